@@ -0,0 +1,327 @@
+//! Generate thin clients for other languages from the protocol schema.
+//!
+//! Each `Method` variant becomes one client method taking the variant's params.
+//! The transport (socket discovery, daemon startup, framing) lives in a
+//! hand-written template per language.
+
+use anyhow::{bail, Context, Result};
+use schemars::schema::{InstanceType, Schema, SchemaObject, SingleOrVec};
+use schemars::Map;
+use serde_json::Value;
+
+use crate::ipc::{method_schema, PROTOCOL_VERSION};
+
+/// A language `lq genclient` can emit a client for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClientLanguage {
+    Python,
+    /// CommonJS module for Node.js
+    Node,
+}
+
+const PYTHON_TEMPLATE: &str = include_str!("templates/client.py");
+const NODE_TEMPLATE: &str = include_str!("templates/client.js");
+
+/// A request method and its parameters, as described by the protocol schema
+#[derive(Debug, Clone, PartialEq)]
+struct MethodSpec {
+    /// Variant name sent on the wire, e.g. `GenericHotspots`
+    name: String,
+    params: Vec<ParamSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ParamSpec {
+    name: String,
+    /// JSON type: `string`, `integer`, `boolean`
+    kind: String,
+    required: bool,
+    default: Option<Value>,
+    description: Option<String>,
+}
+
+/// Source code of a client library for `language`
+pub fn generate_client(language: ClientLanguage) -> Result<String> {
+    let methods = method_specs()?;
+    let (template, rendered) = match language {
+        ClientLanguage::Python => (PYTHON_TEMPLATE, methods.iter().map(python_method).collect::<String>()),
+        ClientLanguage::Node => (NODE_TEMPLATE, methods.iter().map(node_method).collect::<String>()),
+    };
+
+    Ok(template
+        .replace("@@PROTOCOL_VERSION@@", &PROTOCOL_VERSION.to_string())
+        .replace("@@METHODS@@", rendered.trim_end()))
+}
+
+/// Read the methods and their params out of the `Method` schema
+fn method_specs() -> Result<Vec<MethodSpec>> {
+    let root = method_schema();
+    let variants = root
+        .schema
+        .subschemas
+        .as_ref()
+        .and_then(|s| s.one_of.as_ref())
+        .context("Method schema has no variants")?;
+
+    let mut methods = Vec::new();
+    for variant in variants {
+        let object = schema_object(variant)?
+            .object
+            .as_ref()
+            .context("Method variant is not an object")?;
+        let names = object
+            .properties
+            .get("method")
+            .map(schema_object)
+            .transpose()?
+            .and_then(|m| m.enum_values.as_ref())
+            .context("Method variant without a name")?;
+
+        let mut specs = Vec::new();
+        if let Some(params) = object.properties.get("params") {
+            let params = schema_object(params)?
+                .object
+                .as_ref()
+                .context("Method params are not an object")?;
+            for (name, property) in &params.properties {
+                let property = schema_object(property)?;
+                let metadata = property.metadata.as_deref();
+                specs.push(ParamSpec {
+                    name: name.clone(),
+                    kind: json_type(property, &root.definitions)?,
+                    required: params.required.contains(name),
+                    default: metadata.and_then(|m| m.default.clone()),
+                    description: metadata.and_then(|m| m.description.clone()),
+                });
+            }
+        }
+        // Optional params must trail required ones in a function signature
+        specs.sort_by_key(|p| !p.required);
+
+        for name in names.iter().filter_map(Value::as_str) {
+            methods.push(MethodSpec {
+                name: name.to_string(),
+                params: specs.clone(),
+            });
+        }
+    }
+
+    Ok(methods)
+}
+
+fn schema_object(schema: &Schema) -> Result<&SchemaObject> {
+    match schema {
+        Schema::Object(object) => Ok(object),
+        Schema::Bool(_) => bail!("Unexpected boolean schema"),
+    }
+}
+
+/// The primitive JSON type of a property, following `$ref`s and ignoring `null`
+fn json_type(property: &SchemaObject, definitions: &Map<String, Schema>) -> Result<String> {
+    if let Some(reference) = &property.reference {
+        let name = reference.trim_start_matches("#/definitions/");
+        let definition = schema_object(definitions.get(name).with_context(|| format!("Unknown type {}", name))?)?;
+        // String enums are described as `oneOf` single-value enums
+        if let Some(first) = definition.subschemas.as_ref().and_then(|s| s.one_of.as_ref()).and_then(|v| v.first()) {
+            return json_type(schema_object(first)?, definitions);
+        }
+        return json_type(definition, definitions);
+    }
+    // A documented `$ref` is wrapped as `allOf: [{ $ref }]` to carry the description
+    if let Some([only]) = property.subschemas.as_ref().and_then(|s| s.all_of.as_deref()) {
+        return json_type(schema_object(only)?, definitions);
+    }
+
+    let kinds: Vec<InstanceType> = match &property.instance_type {
+        Some(SingleOrVec::Single(kind)) => vec![**kind],
+        Some(SingleOrVec::Vec(kinds)) => kinds.clone(),
+        None => bail!("Property without a type"),
+    };
+    let kind = kinds
+        .into_iter()
+        .find(|k| *k != InstanceType::Null)
+        .context("Property has only a null type")?;
+    Ok(match kind {
+        InstanceType::Boolean => "boolean",
+        InstanceType::Integer => "integer",
+        InstanceType::Number => "number",
+        InstanceType::Object => "object",
+        InstanceType::Array => "array",
+        InstanceType::String | InstanceType::Null => "string",
+    }
+    .to_string())
+}
+
+/// `GenericHotspots` becomes `generic_hotspots`
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+/// `GenericHotspots` becomes `genericHotspots`
+fn camel_case(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+fn python_method(method: &MethodSpec) -> String {
+    let python_type = |kind: &str| match kind {
+        "integer" => "int",
+        "boolean" => "bool",
+        _ => "str",
+    };
+
+    let mut signature = vec!["self".to_string()];
+    for param in &method.params {
+        let kind = python_type(&param.kind);
+        signature.push(match (&param.required, &param.default) {
+            (true, _) => format!("{}: {}", param.name, kind),
+            (false, Some(Value::Bool(b))) => format!("{}: {} = {}", param.name, kind, if *b { "True" } else { "False" }),
+            (false, Some(default)) if !default.is_null() => format!("{}: {} = {}", param.name, kind, default),
+            (false, _) => format!("{}: Optional[{}] = None", param.name, kind),
+        });
+    }
+
+    let mut out = format!(
+        "\n    def {}({}) -> Any:\n        \"\"\"Send a `{}` request{}\"\"\"\n",
+        snake_case(&method.name),
+        signature.join(", "),
+        method.name,
+        param_notes(method),
+    );
+    if method.params.is_empty() {
+        out.push_str(&format!("        return self.request(\"{}\")\n", method.name));
+    } else {
+        let fields: Vec<String> = method
+            .params
+            .iter()
+            .map(|p| match p.name.as_str() {
+                // The daemon resolves files against its own working directory
+                "file" if p.required => "\"file\": os.path.abspath(file)".to_string(),
+                "file" => "\"file\": os.path.abspath(file) if file is not None else None".to_string(),
+                name => format!("\"{}\": {}", name, name),
+            })
+            .collect();
+        out.push_str(&format!(
+            "        return self.request(\"{}\", {{{}}})\n",
+            method.name,
+            fields.join(", ")
+        ));
+    }
+    out
+}
+
+fn node_method(method: &MethodSpec) -> String {
+    let js_type = |kind: &str| match kind {
+        "integer" => "number",
+        "boolean" => "boolean",
+        _ => "string",
+    };
+
+    let mut out = format!("\n  /**\n   * Send a `{}` request\n", method.name);
+    for param in &method.params {
+        let name = if param.required { param.name.clone() } else { format!("[{}]", param.name) };
+        match &param.description {
+            Some(description) => out.push_str(&format!("   * @param {{{}}} {} {}\n", js_type(&param.kind), name, description)),
+            None => out.push_str(&format!("   * @param {{{}}} {}\n", js_type(&param.kind), name)),
+        }
+    }
+    out.push_str("   */\n");
+
+    let signature: Vec<String> = method
+        .params
+        .iter()
+        .map(|p| match &p.default {
+            Some(default) if !p.required && !default.is_null() => format!("{} = {}", camel_case(&p.name), default),
+            _ => camel_case(&p.name),
+        })
+        .collect();
+    out.push_str(&format!("  {}({}) {{\n", camel_case(&method.name), signature.join(", ")));
+
+    if method.params.is_empty() {
+        out.push_str(&format!("    return this.request(\"{}\");\n", method.name));
+    } else {
+        let fields: Vec<String> = method
+            .params
+            .iter()
+            .map(|p| match p.name.as_str() {
+                "file" if p.required => "file: path.resolve(file)".to_string(),
+                "file" => "file: file === undefined ? undefined : path.resolve(file)".to_string(),
+                name if camel_case(name) == name => name.to_string(),
+                name => format!("{}: {}", name, camel_case(name)),
+            })
+            .collect();
+        out.push_str(&format!(
+            "    return this.request(\"{}\", {{ {} }});\n",
+            method.name,
+            fields.join(", ")
+        ));
+    }
+    out.push_str("  }\n");
+    out
+}
+
+/// Descriptions of params, appended to a Python docstring
+fn param_notes(method: &MethodSpec) -> String {
+    let notes: Vec<String> = method
+        .params
+        .iter()
+        .filter_map(|p| p.description.as_ref().map(|d| format!("        {}: {}", p.name, d)))
+        .collect();
+    if notes.is_empty() {
+        String::new()
+    } else {
+        format!("\n\n{}\n        ", notes.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_specs_follow_protocol() {
+        let methods = method_specs().unwrap();
+        let refs = methods.iter().find(|m| m.name == "Refs").unwrap();
+        let params: Vec<_> = refs.params.iter().map(|p| (p.name.as_str(), p.kind.as_str(), p.required)).collect();
+        assert_eq!(
+            params,
+            vec![
+                ("file", "string", true),
+                ("line", "integer", true),
+                ("symbol", "string", true),
+                ("column", "integer", false),
+                ("raw", "boolean", false),
+            ]
+        );
+
+        let churn = methods.iter().find(|m| m.name == "Churn").unwrap();
+        assert!(churn.params.iter().any(|p| p.name == "sort" && p.kind == "string"));
+        assert!(methods.iter().any(|m| m.name == "Status" && m.params.is_empty()));
+
+        let diag = methods.iter().find(|m| m.name == "Diag").unwrap();
+        assert!(diag.params.iter().any(|p| p.name == "severity" && p.kind == "string" && p.required));
+    }
+
+    #[test]
+    fn test_generated_method_names() {
+        let python = generate_client(ClientLanguage::Python).unwrap();
+        assert!(python.contains("    def generic_hotspots(self, top: int) -> Any:"));
+        assert!(python.contains("    def refs(self, file: str, line: int, symbol: str, column: Optional[int] = None, raw: bool = False) -> Any:"));
+        assert!(!python.contains("@@"));
+
+        let node = generate_client(ClientLanguage::Node).unwrap();
+        assert!(node.contains("  genericHotspots(top) {"));
+        assert!(node.contains("  refs(file, line, symbol, column, raw = false) {"));
+        assert!(!node.contains("@@"));
+    }
+}
@@ -0,0 +1,284 @@
+//! `lq mcp`: the daemon's queries as the tools of a Model Context Protocol server.
+//!
+//! Agent runtimes start `lq mcp` in a workspace and talk JSON-RPC 2.0 to it over
+//! stdin and stdout, one message per line. The server answers `initialize`,
+//! `ping`, `tools/list` and `tools/call`; each tool sends one request over a
+//! single daemon connection and returns its `--format json` payload, both as
+//! text and as structured content. A failed query is a tool result flagged
+//! `isError`, so the agent can read why, while malformed messages get JSON-RPC
+//! errors. Notifications, such as `notifications/initialized`, need no answer.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::client::Client;
+use crate::ipc::{Method, PathQuery, ResponseResult};
+
+/// Protocol revisions the server speaks, newest first
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// The tools' names, in the order `tools/list` lists them
+const TOOLS: &[&str] = &["docs", "impl", "refs", "outline", "search"];
+
+/// Matches `lq search`'s default
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// A symbol, by its position or by its qualified path
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SymbolArgs {
+    /// File containing the symbol, relative to the workspace root
+    file: Option<PathBuf>,
+    /// 1-based line of the symbol
+    line: Option<u32>,
+    /// 1-based column, to pick one of several occurrences of the symbol on the line
+    column: Option<u32>,
+    /// Name of the symbol
+    symbol: Option<String>,
+    /// Qualified path such as my_crate::module::Type::method, in place of file, line and symbol
+    path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct OutlineArgs {
+    /// File to outline, relative to the workspace root
+    file: PathBuf,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SearchArgs {
+    /// Name or part of a name; matched fuzzily
+    query: String,
+    /// Maximum number of matches, 20 by default
+    limit: Option<usize>,
+}
+
+/// The tools, as `tools/list` describes them
+fn tools() -> Value {
+    json!([
+        tool::<SymbolArgs>("docs", "Documentation and signature of a Rust symbol, as an editor shows it on hover"),
+        tool::<SymbolArgs>("impl", "Implementations of a Rust trait, trait method or type, and the source of its definition"),
+        tool::<SymbolArgs>("refs", "Every reference to a Rust symbol across the workspace, with the source line of each"),
+        tool::<OutlineArgs>("outline", "The items a Rust file declares, nested as in the source, with their line ranges"),
+        tool::<SearchArgs>("search", "Rust symbols across the workspace whose names match a query"),
+    ])
+}
+
+fn tool<T: JsonSchema>(name: &str, description: &str) -> Value {
+    let mut settings = SchemaSettings::draft07();
+    settings.meta_schema = None;
+    let schema = settings.into_generator().into_root_schema_for::<T>();
+    let mut schema = serde_json::to_value(schema).unwrap_or_default();
+    if let Some(fields) = schema.as_object_mut() {
+        fields.remove("title");
+    }
+    json!({ "name": name, "description": description, "inputSchema": schema })
+}
+
+/// The request the tool `name` makes with `arguments`, with a relative `file`
+/// taken relative to `workspace`
+fn tool_method(name: &str, arguments: Value, workspace: &Path) -> Result<Method> {
+    let absolute = |file: PathBuf| if file.is_relative() { workspace.join(file) } else { file };
+    let query = match name {
+        "docs" => PathQuery::Docs,
+        "impl" => PathQuery::Impl,
+        "refs" => PathQuery::Refs,
+        "outline" => {
+            let args: OutlineArgs = serde_json::from_value(arguments)?;
+            return Ok(Method::Outline { file: absolute(args.file) });
+        }
+        "search" => {
+            let args: SearchArgs = serde_json::from_value(arguments)?;
+            return Ok(Method::Search { query: args.query, limit: args.limit.unwrap_or(DEFAULT_SEARCH_LIMIT) });
+        }
+        _ => anyhow::bail!("Unknown tool {:?}", name),
+    };
+    let args: SymbolArgs = serde_json::from_value(arguments)?;
+    Ok(match args {
+        SymbolArgs { path: Some(path), file: None, line: None, column: None, symbol: None } => Method::AtPath { path, query, raw: false },
+        SymbolArgs { path: None, file: Some(file), line: Some(line), column, symbol: Some(symbol) } => {
+            let file = absolute(file);
+            match query {
+                PathQuery::Docs => Method::Docs { file, line, column, symbol },
+                PathQuery::Impl => Method::Impl { file, line, column, symbol },
+                _ => Method::Refs { file, line, column, symbol, raw: false },
+            }
+        }
+        _ => anyhow::bail!("Expected either file, line and symbol, or a qualified path on its own"),
+    })
+}
+
+/// Serve the tools on stdin and stdout until stdin closes, passing each answer
+/// through `finish` as `lq batch` does
+pub async fn serve(client: &Client, workspace: &Path, finish: impl Fn(&mut Value)) -> Result<()> {
+    let mut session = client.session();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    while let Some(line) = lines.next_line().await.context("Failed to read stdin")? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match handle(&line, workspace) {
+            Handled::Reply(reply) => reply,
+            Handled::Nothing => continue,
+            Handled::Call { id, method } => {
+                // Reported to the agent rather than ending the server, even when the daemon is gone
+                let result = match method {
+                    Ok(method) => session.request(method).await.unwrap_or_else(|e| ResponseResult::Error { error: format!("{:#}", e) }),
+                    Err(e) => ResponseResult::Error { error: format!("Invalid arguments: {:#}", e) },
+                };
+                let result = match result {
+                    ResponseResult::Success { mut result } => {
+                        finish(&mut result);
+                        json!({
+                            "content": [{ "type": "text", "text": serde_json::to_string(&result)? }],
+                            "structuredContent": result,
+                            "isError": false
+                        })
+                    }
+                    ResponseResult::Error { error } => json!({ "content": [{ "type": "text", "text": error }], "isError": true }),
+                };
+                json!({ "jsonrpc": "2.0", "id": id, "result": result })
+            }
+        };
+        let mut message = serde_json::to_string(&reply)?;
+        message.push('\n');
+        stdout.write_all(message.as_bytes()).await.context("Failed to write stdout")?;
+        stdout.flush().await.context("Failed to write stdout")?;
+    }
+    Ok(())
+}
+
+/// What to do about one message
+#[derive(Debug)]
+enum Handled {
+    /// Write this reply
+    Reply(Value),
+    /// Run the tool call `id` making `method`, and reply with its result
+    Call { id: Value, method: Result<Method> },
+    /// A notification, needing no reply
+    Nothing,
+}
+
+fn handle(line: &str, workspace: &Path) -> Handled {
+    let error = |id: Value, code: i64, message: String| {
+        Handled::Reply(json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }))
+    };
+    let message: Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(e) => return error(Value::Null, PARSE_ERROR, format!("Invalid JSON: {}", e)),
+    };
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        // Responses to requests this server never makes are ignored too
+        return match message.get("id") {
+            Some(id) if message.get("result").is_none() && message.get("error").is_none() => {
+                error(id.clone(), INVALID_REQUEST, "Expected a request with a method".to_string())
+            }
+            _ => Handled::Nothing,
+        };
+    };
+    let Some(id) = message.get("id").cloned() else {
+        return Handled::Nothing;
+    };
+    let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+    let result = match method {
+        "initialize" => {
+            let requested = params.get("protocolVersion").and_then(Value::as_str);
+            let version = PROTOCOL_VERSIONS.iter().find(|version| Some(**version) == requested).unwrap_or(&PROTOCOL_VERSIONS[0]);
+            json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "lq", "version": env!("CARGO_PKG_VERSION") },
+                "instructions": "Queries rust-analyzer about the Rust workspace lq was started in. \
+                    Files are relative to the workspace root; lines and columns are 1-based."
+            })
+        }
+        "ping" => json!({}),
+        "tools/list" => json!({ "tools": tools() }),
+        "tools/call" => {
+            let Some(name) = params.get("name").and_then(Value::as_str) else {
+                return error(id, INVALID_PARAMS, "Missing the tool name".to_string());
+            };
+            if !TOOLS.contains(&name) {
+                return error(id, INVALID_PARAMS, format!("Unknown tool {:?}", name));
+            }
+            let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+            return Handled::Call { id, method: tool_method(name, arguments, workspace) };
+        }
+        _ => return error(id, METHOD_NOT_FOUND, format!("Unknown method {:?}", method)),
+    };
+    Handled::Reply(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(line: &str) -> Value {
+        match handle(line, Path::new("/ws")) {
+            Handled::Reply(reply) => reply,
+            other => panic!("expected a reply, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let init = reply(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{}}}"#);
+        assert_eq!(init["id"], 1);
+        assert_eq!(init["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(init["result"]["serverInfo"]["name"], "lq");
+        let newer = reply(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2099-01-01"}}"#);
+        assert_eq!(newer["result"]["protocolVersion"], PROTOCOL_VERSIONS[0]);
+
+        assert!(matches!(handle(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#, Path::new("/ws")), Handled::Nothing));
+        assert_eq!(reply(r#"{"jsonrpc":"2.0","id":"a","method":"ping"}"#)["result"], json!({}));
+        assert_eq!(reply(r#"{"jsonrpc":"2.0","id":2,"method":"resources/list"}"#)["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(reply("{not json")["error"]["code"], PARSE_ERROR);
+    }
+
+    #[test]
+    fn test_tools_list() {
+        let tools = reply(r#"{"jsonrpc":"2.0","id":3,"method":"tools/list"}"#)["result"]["tools"].clone();
+        let names: Vec<&str> = tools.as_array().unwrap().iter().map(|tool| tool["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["docs", "impl", "refs", "outline", "search"]);
+        let search = &tools[4]["inputSchema"];
+        assert_eq!(search["type"], "object");
+        assert_eq!(search["required"], json!(["query"]));
+        assert!(search["properties"]["limit"]["description"].is_string());
+    }
+
+    #[test]
+    fn test_tool_calls() {
+        let call = |params: &str| match handle(&format!(r#"{{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{}}}"#, params), Path::new("/ws")) {
+            Handled::Call { id, method } => {
+                assert_eq!(id, 4);
+                method
+            }
+            other => panic!("expected a call, got {:?}", other),
+        };
+        let refs = call(r#"{"name":"refs","arguments":{"file":"src/lib.rs","line":3,"symbol":"Config"}}"#).unwrap();
+        assert!(matches!(refs, Method::Refs { file, line: 3, column: None, raw: false, .. } if file == Path::new("/ws/src/lib.rs")));
+        let docs = call(r#"{"name":"docs","arguments":{"path":"my_crate::Config"}}"#).unwrap();
+        assert!(matches!(docs, Method::AtPath { query: PathQuery::Docs, .. }));
+        let search = call(r#"{"name":"search","arguments":{"query":"Conf"}}"#).unwrap();
+        assert!(matches!(search, Method::Search { limit: DEFAULT_SEARCH_LIMIT, .. }));
+
+        assert!(call(r#"{"name":"impl","arguments":{"file":"src/lib.rs","symbol":"Config"}}"#).is_err());
+        assert!(call(r#"{"name":"outline","arguments":{"file":"src/lib.rs","depth":2}}"#).is_err());
+        let unknown = reply(r#"{"jsonrpc":"2.0","id":5,"method":"tools/call","params":{"name":"rename"}}"#);
+        assert_eq!(unknown["error"]["code"], INVALID_PARAMS);
+    }
+}
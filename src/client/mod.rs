@@ -0,0 +1,1001 @@
+//! Client for the daemon IPC protocol.
+//!
+//! [`Client`] finds the daemon for a workspace, starts one if none is running,
+//! and exposes a typed method per request so other tools don't have to speak
+//! the wire format themselves. A daemon is only started for a trusted workspace
+//! (see [`crate::config`]).
+
+pub mod generate;
+pub mod mcp;
+pub mod picker;
+pub mod progress;
+pub mod recording;
+pub mod stdio;
+pub mod watch;
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tracing::{debug, info};
+
+use crate::core::reports::{AsyncCallers, CallBounds, ChurnEntry, ChurnSort, ErrorFlow, FunctionCoverage, GenericHotspot, RankedSymbol, RefClosure, RefsDiff, StringMatch, Tour};
+use crate::config::ensure_trusted;
+use crate::daemon::{get_log_path, get_socket_path, is_daemon_running, StartupStatus};
+use crate::edit::EditPreview;
+use crate::ipc::{read_message, read_message_compressed, write_message, write_message_as, Codec, Compression, DaemonStatus, Method, PathQuery, Request, Response, ResponseResult, PROTOCOL_VERSION};
+use crate::ipc::results::{
+    ActionsResult, AsyncCallersResult, BoundsResult, CallsResult, ChurnResult, CoverageMapResult, DefResult, DiagResult, DiffRefsResult, DocsResult, ErrorFlowResult, ExpandResult, GenericHotspotsResult,
+    HierarchyResult, HintsResult, HirResult, HotspotsResult, ImplResult, OutlineResult, PingResult, RefsResult, RenameResult, ResolveResult,
+    RunnablesResult, SearchResult, SsrResult, StopResult, StringsResult, SyntaxResult, TourResult, TransitiveRefsResult, TypeResult,
+};
+use crate::lsp::{Call, CodeAction, Definition, Diagnostic, HintedLine, ItemView, MacroExpansion, OutlineItem, Reference, Runnable, SymbolMatch, Severity, TypeHierarchy};
+use recording::RecordingDir;
+
+/// How a [`Client`] starts and reaches its daemon
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// The `lq` executable used to start a daemon; resolved via `PATH` if relative
+    pub executable: PathBuf,
+    /// How long to wait for a newly started daemon to report that it is listening
+    pub start_timeout: Duration,
+    /// Extra attempts for a request whose connection fails
+    pub retries: u32,
+    /// Save every exchange to this directory
+    pub record: Option<PathBuf>,
+    /// Answer requests from recordings in this directory instead of a daemon
+    pub replay: Option<PathBuf>,
+    /// Ask the daemon for whatever it has after this long instead of a complete answer
+    pub best_effort: Option<Duration>,
+    /// Accept a cached answer computed before the workspace last changed
+    pub allow_stale: bool,
+    /// Have the daemon give up on a request with an error after this long
+    pub timeout: Option<Duration>,
+    /// Start a daemon even if the workspace is not in the trust allowlist
+    pub trust: bool,
+    /// The rust-analyzer a newly started daemon runs, instead of the configured or discovered one
+    pub server_path: Option<PathBuf>,
+    /// Encoding a [`Session`] switches its connection to; single requests always use JSON
+    pub codec: Codec,
+    /// Compression a [`Session`] asks the daemon for on large responses; single requests are never compressed
+    pub compression: Compression,
+    /// Show a spinner on stderr while a request waits for the language server to start and index
+    pub progress: bool,
+    /// The workspace requests naming no file are about, when the daemon serves several
+    pub member: Option<PathBuf>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            executable: PathBuf::from("lq"),
+            start_timeout: Duration::from_secs(5),
+            retries: 2,
+            record: None,
+            replay: None,
+            best_effort: None,
+            allow_stale: false,
+            timeout: None,
+            trust: false,
+            server_path: None,
+            codec: Codec::Json,
+            compression: Compression::None,
+            progress: false,
+            member: None,
+        }
+    }
+}
+
+/// How long an interrupted client waits for the daemon to take its cancellation
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to check whether a daemon told to shut down has exited
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A connection to the daemon serving one workspace
+#[derive(Debug, Clone)]
+pub struct Client {
+    socket_path: PathBuf,
+    options: ClientOptions,
+    record: Option<RecordingDir>,
+    replay: Option<RecordingDir>,
+    /// Ids of the requests waiting for an answer
+    in_flight: Arc<Mutex<Vec<String>>>,
+}
+
+impl Client {
+    /// Connect to the daemon for `workspace`, starting one if none is running
+    pub async fn connect(workspace: &Path) -> Result<Self> {
+        Self::connect_with_options(workspace, ClientOptions::default()).await
+    }
+
+    /// Connect with explicit options; when replaying, no daemon is contacted
+    pub async fn connect_with_options(workspace: &Path, options: ClientOptions) -> Result<Self> {
+        let socket_path = get_socket_path(workspace)?;
+
+        let running = options.replay.is_none()
+            && is_daemon_running(&socket_path).await
+            && !stop_mismatched_daemon(&socket_path, options.start_timeout).await?;
+        if options.replay.is_none() && !running {
+            if !options.trust {
+                ensure_trusted(workspace)?;
+            }
+            let daemon = spawn_daemon(workspace, &options.executable, options.trust, options.server_path.as_deref())?;
+            wait_for_startup(daemon, workspace, options.start_timeout).await?;
+        }
+
+        Ok(Self {
+            socket_path,
+            record: options.record.as_ref().map(|dir| RecordingDir::new(dir, workspace)),
+            replay: options.replay.as_ref().map(|dir| RecordingDir::new(dir, workspace)),
+            options,
+            in_flight: Arc::default(),
+        })
+    }
+
+    /// Talk to the daemon listening on `socket_path`, without starting one
+    pub fn at_socket(socket_path: impl Into<PathBuf>) -> Self {
+        Self {
+            socket_path: socket_path.into(),
+            options: ClientOptions::default(),
+            record: None,
+            replay: None,
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Exit the process on Ctrl-C, as it would without a handler, after telling the
+    /// daemon to stop working on the requests still waiting for an answer
+    pub fn cancel_on_interrupt(&self) {
+        let client = self.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            let ids = std::mem::take(&mut *client.in_flight.lock().unwrap_or_else(|e| e.into_inner()));
+            for id in ids {
+                let request = client.new_request(&Method::Cancel { id });
+                if let Err(e) = tokio::time::timeout(CANCEL_TIMEOUT, client.exchange(&request)).await.unwrap_or_else(|e| Err(e.into())) {
+                    debug!("Failed to cancel request: {}", e);
+                }
+            }
+            std::process::exit(130);
+        });
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Send a request and return the raw result payload.
+    ///
+    /// Connection failures are retried; errors reported by the daemon are not.
+    pub async fn request(&self, method: Method) -> Result<serde_json::Value> {
+        let result = match &self.replay {
+            Some(replay) => replay.load(&method)?,
+            None if self.options.progress => progress::with_progress(self, self.send(&method)).await?,
+            None => self.send(&method).await?,
+        };
+        if let Some(record) = &self.record {
+            record.save(&method, &result)?;
+        }
+
+        match result {
+            ResponseResult::Success { result } => Ok(result),
+            ResponseResult::Error { error } => bail!(error),
+        }
+    }
+
+    /// Open one connection for many requests in a row, instead of one per request
+    pub fn session(&self) -> Session<'_> {
+        Session { client: self, stream: None, codec: Codec::Json, compression: Compression::None }
+    }
+
+    fn new_request(&self, method: &Method) -> Request {
+        Request {
+            id: uuid::Uuid::new_v4().to_string(),
+            method: method.clone(),
+            deadline_ms: self.options.best_effort.map(|d| d.as_millis() as u64),
+            allow_stale: self.options.allow_stale,
+            timeout_ms: self.options.timeout.map(|d| d.as_millis() as u64),
+            workspace: self.options.member.clone(),
+        }
+    }
+
+    /// Note `id` as waiting for an answer until the returned guard is dropped
+    fn track(&self, id: &str) -> InFlight<'_> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).push(id.to_string());
+        InFlight { ids: &self.in_flight, id: id.to_string() }
+    }
+
+    /// Send `request`, made elsewhere, keeping its id and options, and return the
+    /// raw outcome, daemon errors included. Connection failures are retried as for
+    /// [`Client::request`].
+    pub async fn forward(&self, request: &Request) -> Result<ResponseResult> {
+        let result = match &self.replay {
+            Some(replay) => replay.load(&request.method)?,
+            None => self.send_request(request).await?,
+        };
+        if let Some(record) = &self.record {
+            record.save(&request.method, &result)?;
+        }
+        Ok(result)
+    }
+
+    async fn send(&self, method: &Method) -> Result<ResponseResult> {
+        self.send_request(&self.new_request(method)).await
+    }
+
+    async fn send_request(&self, request: &Request) -> Result<ResponseResult> {
+        let _in_flight = self.track(&request.id);
+        // A shutdown may have taken effect even if the response was lost
+        let retries = if matches!(request.method, Method::Shutdown) { 0 } else { self.options.retries };
+
+        let mut attempt = 0;
+        loop {
+            match self.exchange(request).await {
+                Ok(response) => return Ok(response.result),
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    debug!("Retrying request after connection failure (attempt {}): {}", attempt, e);
+                    tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn exchange(&self, request: &Request) -> Result<Response> {
+        let mut stream = UnixStream::connect(&self.socket_path)
+            .await
+            .context("Failed to connect to daemon")?;
+        write_message(&mut stream, request).await?;
+        read_message(&mut stream)
+            .await?
+            .context("Daemon closed the connection without responding")
+    }
+
+    /// Send a request and read its result payload as `T`
+    async fn request_as<T: DeserializeOwned>(&self, method: Method) -> Result<T> {
+        let result = self.request(method).await?;
+        serde_json::from_value(result).context("Malformed result in response")
+    }
+
+    pub async fn docs(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<String>> {
+        let method = Method::Docs {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<DocsResult>(method).await?.docs)
+    }
+
+    /// Docs for the expression or statement from `start` to `end`, both `(line, column)` and inclusive
+    pub async fn docs_range(&self, file: &Path, start: (u32, u32), end: (u32, u32)) -> Result<Option<String>> {
+        let method = Method::DocsRange {
+            file: absolute(file),
+            start_line: start.0,
+            start_column: start.1,
+            end_line: end.0,
+            end_column: end.1,
+        };
+        Ok(self.request_as::<DocsResult>(method).await?.docs)
+    }
+
+    /// Where a symbol is defined, with its source; the first candidate when there are several
+    pub async fn definition(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<Definition>> {
+        let method = Method::Definition {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<DefResult>(method).await?.definition)
+    }
+
+    /// Every candidate definition of a symbol, such as both an item and its re-export
+    pub async fn definitions(&self, file: &Path, line: u32, symbol: &str) -> Result<Vec<Definition>> {
+        let method = Method::Definition {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<DefResult>(method).await?.definitions)
+    }
+
+    #[deprecated(note = "this always answered the definition; use `definition`, or `implementations` for implementations")]
+    pub async fn implementation(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<Definition>> {
+        self.definition(file, line, symbol).await
+    }
+
+    /// Docs for the item a qualified path such as `my_crate::module::Type::method` names
+    pub async fn docs_at_path(&self, path: &str) -> Result<Option<String>> {
+        let method = Method::AtPath { path: path.to_string(), query: PathQuery::Docs, raw: false };
+        Ok(self.request_as::<DocsResult>(method).await?.docs)
+    }
+
+    /// The definition of the item a qualified path names
+    pub async fn definition_at_path(&self, path: &str) -> Result<Option<Definition>> {
+        let method = Method::AtPath { path: path.to_string(), query: PathQuery::Def, raw: false };
+        Ok(self.request_as::<DefResult>(method).await?.definition)
+    }
+
+    /// Every implementation of a trait, trait method or type
+    pub async fn implementations(&self, file: &Path, line: u32, symbol: &str) -> Result<Vec<Definition>> {
+        let method = Method::Impl {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<ImplResult>(method).await?.implementations)
+    }
+
+    /// Where the type of a variable or expression is declared
+    pub async fn type_definition(&self, file: &Path, line: u32, symbol: &str) -> Result<Vec<Definition>> {
+        let method = Method::TypeDefinition {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<TypeResult>(method).await?.definitions)
+    }
+
+    /// Find references; `raw` skips merging and annotation
+    pub async fn references(&self, file: &Path, line: u32, symbol: &str, raw: bool) -> Result<Vec<Reference>> {
+        let method = Method::Refs {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+            raw,
+        };
+        Ok(self.request_as::<RefsResult>(method).await?.references)
+    }
+
+    pub async fn resolve(&self, file: &Path, symbol: &str) -> Result<Option<String>> {
+        let method = Method::Resolve {
+            file: absolute(file),
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<ResolveResult>(method).await?.resolved)
+    }
+
+    pub async fn callers(&self, file: &Path, line: u32, symbol: &str, depth: usize) -> Result<Vec<Call>> {
+        let method = Method::Callers {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+            depth,
+        };
+        Ok(self.request_as::<CallsResult>(method).await?.calls)
+    }
+
+    pub async fn callees(&self, file: &Path, line: u32, symbol: &str, depth: usize) -> Result<Vec<Call>> {
+        let method = Method::Callees {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+            depth,
+        };
+        Ok(self.request_as::<CallsResult>(method).await?.calls)
+    }
+
+    pub async fn outline(&self, file: &Path) -> Result<Vec<OutlineItem>> {
+        Ok(self.request_as::<OutlineResult>(Method::Outline { file: absolute(file) }).await?.outline)
+    }
+
+    /// Lines of `file` with inferred types and parameter names written in; `lines`
+    /// is an inclusive range of 1-based lines, the whole file when `None`
+    pub async fn inlay_hints(&self, file: &Path, lines: Option<(u32, u32)>) -> Result<Vec<HintedLine>> {
+        let method = Method::Hints {
+            file: absolute(file),
+            start_line: lines.map(|(start, _)| start),
+            end_line: lines.map(|(_, end)| end),
+        };
+        Ok(self.request_as::<HintsResult>(method).await?.hints)
+    }
+
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SymbolMatch>> {
+        let method = Method::Search {
+            query: query.to_string(),
+            limit,
+        };
+        Ok(self.request_as::<SearchResult>(method).await?.symbols)
+    }
+
+    /// Diagnostics for `file`, or the whole workspace, at least as severe as `severity`
+    pub async fn diagnostics(&self, file: Option<&Path>, severity: Severity) -> Result<Vec<Diagnostic>> {
+        let method = Method::Diag {
+            file: file.map(absolute),
+            severity,
+        };
+        Ok(self.request_as::<DiagResult>(method).await?.diagnostics)
+    }
+
+    /// The changes renaming a symbol would make; nothing is written
+    pub async fn rename(&self, file: &Path, line: u32, symbol: &str, new_name: &str) -> Result<EditPreview> {
+        let method = Method::Rename {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+            new_name: new_name.to_string(),
+        };
+        Ok(self.request_as::<RenameResult>(method).await?.rename)
+    }
+
+    /// Code actions offered at a symbol, or anywhere on the line without one
+    pub async fn code_actions(&self, file: &Path, line: u32, symbol: Option<&str>) -> Result<Vec<CodeAction>> {
+        let method = Method::Actions {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.map(str::to_string),
+        };
+        Ok(self.request_as::<ActionsResult>(method).await?.actions)
+    }
+
+    /// The recursive expansion of a macro, or of the first macro on the line without a symbol
+    pub async fn expand_macro(&self, file: &Path, line: u32, symbol: Option<&str>) -> Result<Option<MacroExpansion>> {
+        let method = Method::Expand {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.map(str::to_string),
+        };
+        Ok(self.request_as::<ExpandResult>(method).await?.expansion)
+    }
+
+    /// The lowered HIR of the function, const or static body on a line
+    pub async fn view_hir(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        Ok(self.request_as::<HirResult>(Method::Hir { file: absolute(file), line }).await?.hir)
+    }
+
+    /// The syntax tree of the innermost item on a line
+    pub async fn syntax_tree(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        Ok(self.request_as::<SyntaxResult>(Method::Syntax { file: absolute(file), line }).await?.syntax)
+    }
+
+    /// Tests, benchmarks and binaries with their cargo commands, in `file` or the whole workspace
+    pub async fn runnables(&self, file: Option<&Path>) -> Result<Vec<Runnable>> {
+        let method = Method::Runnables {
+            file: file.map(absolute),
+        };
+        Ok(self.request_as::<RunnablesResult>(method).await?.runnables)
+    }
+
+    /// The changes a structural search and replace rule would make across the
+    /// workspace, or within `lines` of `file`; nothing is written
+    pub async fn structural_replace(&self, rule: &str, file: Option<&Path>, lines: Option<(u32, u32)>) -> Result<EditPreview> {
+        let method = Method::Ssr {
+            rule: rule.to_string(),
+            file: file.map(absolute),
+            start_line: lines.map(|(start, _)| start),
+            end_line: lines.map(|(_, end)| end),
+        };
+        Ok(self.request_as::<SsrResult>(method).await?.ssr)
+    }
+
+    pub async fn hierarchy(&self, file: &Path, line: u32, symbol: &str) -> Result<TypeHierarchy> {
+        let method = Method::Hierarchy {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<HierarchyResult>(method).await?.hierarchy)
+    }
+
+    pub async fn generic_hotspots(&self, top: usize) -> Result<Vec<GenericHotspot>> {
+        Ok(self.request_as::<GenericHotspotsResult>(Method::GenericHotspots { top }).await?.hotspots)
+    }
+
+    pub async fn coverage_map(&self) -> Result<Vec<FunctionCoverage>> {
+        Ok(self.request_as::<CoverageMapResult>(Method::CoverageMap).await?.functions.unwrap_or_default())
+    }
+
+    pub async fn hotspots(&self, top: usize, sample: Option<usize>, concurrency: usize) -> Result<Vec<RankedSymbol>> {
+        Ok(self.request_as::<HotspotsResult>(Method::Hotspots { top, sample, concurrency }).await?.hotspots)
+    }
+
+    pub async fn tour(&self, top: usize) -> Result<Tour> {
+        Ok(self.request_as::<TourResult>(Method::Tour { top }).await?.tour)
+    }
+
+    pub async fn churn(&self, top: usize, since: Option<&str>, sort: ChurnSort) -> Result<Vec<ChurnEntry>> {
+        let method = Method::Churn {
+            top,
+            since: since.map(str::to_string),
+            sort,
+        };
+        Ok(self.request_as::<ChurnResult>(method).await?.entries)
+    }
+
+    /// References to the item at qualified path `a` on lines not referencing `b`, and the other way round
+    pub async fn diff_refs(&self, a: &str, b: &str) -> Result<RefsDiff> {
+        let method = Method::DiffRefs {
+            a: a.to_string(),
+            b: Some(b.to_string()),
+            revs: Vec::new(),
+        };
+        Ok(self.request_as::<DiffRefsResult>(method).await?.diff)
+    }
+
+    /// References to the item at qualified `path` at revision `rev_a` compared with
+    /// `rev_b`, or with the working tree
+    pub async fn diff_refs_across(&self, path: &str, rev_a: &str, rev_b: Option<&str>) -> Result<RefsDiff> {
+        let method = Method::DiffRefs {
+            a: path.to_string(),
+            b: None,
+            revs: std::iter::once(rev_a).chain(rev_b).map(str::to_string).collect(),
+        };
+        Ok(self.request_as::<DiffRefsResult>(method).await?.diff)
+    }
+
+    /// The chains of callers of a function up to `depth` levels, and where they cross between async and sync code
+    pub async fn async_callers(&self, file: &Path, line: u32, symbol: &str, depth: usize) -> Result<AsyncCallers> {
+        let method = Method::AsyncCallers {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+            depth,
+        };
+        Ok(self.request_as::<AsyncCallersResult>(method).await?.report)
+    }
+
+    /// The functions referring to a function, and those referring to them, up to `depth` levels
+    pub async fn transitive_refs(&self, file: &Path, line: u32, symbol: &str, depth: usize) -> Result<RefClosure> {
+        let method = Method::TransitiveRefs {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+            depth,
+        };
+        Ok(self.request_as::<TransitiveRefsResult>(method).await?.report)
+    }
+
+    /// The string literals matching `pattern`, or with `log` those that could have printed the line `pattern`
+    pub async fn strings(&self, pattern: &str, log: bool) -> Result<Vec<StringMatch>> {
+        let method = Method::Strings { pattern: pattern.to_string(), log };
+        Ok(self.request_as::<StringsResult>(method).await?.strings)
+    }
+
+    /// The trait bounds of the generic function called at `line` and `column`, and the impls satisfying them
+    pub async fn bounds(&self, file: &Path, line: u32, column: u32) -> Result<CallBounds> {
+        let method = Method::Bounds { file: absolute(file), line, column };
+        Ok(self.request_as::<BoundsResult>(method).await?.report)
+    }
+
+    /// The error type a function returns, the conversions into it, and how its callers handle it
+    pub async fn error_flow(&self, file: &Path, line: u32, symbol: &str) -> Result<ErrorFlow> {
+        let method = Method::ErrorFlow {
+            file: absolute(file),
+            line,
+            column: None,
+            symbol: symbol.to_string(),
+        };
+        Ok(self.request_as::<ErrorFlowResult>(method).await?.report)
+    }
+
+    pub async fn status(&self) -> Result<DaemonStatus> {
+        self.request_as(Method::Status).await
+    }
+
+    /// Check the daemon and its language server respond, timing the round trip
+    pub async fn ping(&self) -> Result<PingResult> {
+        let started = Instant::now();
+        let mut result: PingResult = self.request_as(Method::Ping).await?;
+        result.round_trip_ms = Some(started.elapsed().as_millis() as u64);
+        Ok(result)
+    }
+
+    /// Ask the daemon to exit
+    pub async fn shutdown(&self) -> Result<()> {
+        self.request_as::<StopResult>(Method::Shutdown).await?;
+        Ok(())
+    }
+}
+
+/// One connection to the daemon carrying any number of requests
+pub struct Session<'a> {
+    client: &'a Client,
+    stream: Option<UnixStream>,
+    /// How the open connection's messages are encoded
+    codec: Codec,
+    /// How the open connection's responses may be compressed
+    compression: Compression,
+}
+
+/// A request waiting for its answer; see [`Client::cancel_on_interrupt`]
+struct InFlight<'a> {
+    ids: &'a Mutex<Vec<String>>,
+    id: String,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.ids.lock().unwrap_or_else(|e| e.into_inner()).retain(|id| *id != self.id);
+    }
+}
+
+impl Session<'_> {
+    /// Send a request and return its raw outcome, daemon errors included.
+    ///
+    /// A lost connection is reopened once, unless the request was a shutdown.
+    pub async fn request(&mut self, method: Method) -> Result<ResponseResult> {
+        let client = self.client;
+        let result = match &client.replay {
+            Some(replay) => replay.load(&method)?,
+            None => {
+                let request = client.new_request(&method);
+                let _in_flight = client.track(&request.id);
+                let result = match self.exchange(&request).await {
+                    Ok(response) => response.result,
+                    Err(e) if !matches!(method, Method::Shutdown) => {
+                        debug!("Reconnecting after connection failure: {}", e);
+                        self.exchange(&request).await?.result
+                    }
+                    Err(e) => return Err(e),
+                };
+                // The daemon switches once it has answered
+                if let ResponseResult::Success { .. } = &result {
+                    self.switch(&method);
+                }
+                result
+            }
+        };
+        if let Some(record) = &client.record {
+            record.save(&method, &result)?;
+        }
+        Ok(result)
+    }
+
+    async fn exchange(&mut self, request: &Request) -> Result<Response> {
+        if self.stream.is_none() {
+            self.connect().await?;
+        }
+        let response = self.round_trip(request).await;
+        if response.is_err() {
+            self.stream = None;
+        }
+        response
+    }
+
+    /// Open a connection, switched to the client's codec and compression when the daemon takes them
+    async fn connect(&mut self) -> Result<()> {
+        let open = || async { UnixStream::connect(&self.client.socket_path).await.context("Failed to connect to daemon") };
+        self.stream = Some(open().await?);
+        self.codec = Codec::Json;
+        self.compression = Compression::None;
+        let ClientOptions { codec, compression, .. } = self.client.options;
+        if codec != Codec::Json {
+            self.negotiate(Method::Codec { codec }).await?;
+        }
+        if compression != Compression::None {
+            self.negotiate(Method::Compress { compression, min_bytes: None }).await?;
+        }
+        Ok(())
+    }
+
+    /// Ask the daemon to switch the connection with `method`, and follow if it did
+    async fn negotiate(&mut self, method: Method) -> Result<()> {
+        match self.round_trip(&self.client.new_request(&method)).await {
+            Ok(Response { result: ResponseResult::Success { .. }, .. }) => self.switch(&method),
+            Ok(Response { result: ResponseResult::Error { error }, .. }) => debug!("Not switching with {}: {}", method.name(), error),
+            // A daemon from before the method drops a connection with a request it cannot parse
+            Err(e) => {
+                debug!("Not switching with {}: {}", method.name(), e);
+                self.stream = Some(UnixStream::connect(&self.client.socket_path).await.context("Failed to connect to daemon")?);
+                // The new connection starts over in JSON, uncompressed
+                self.codec = Codec::Json;
+                self.compression = Compression::None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow the switch the daemon made to the connection on answering `method`
+    fn switch(&mut self, method: &Method) {
+        match *method {
+            Method::Codec { codec } => self.codec = codec,
+            Method::Compress { compression, .. } => self.compression = compression,
+            _ => {}
+        }
+    }
+
+    async fn round_trip(&mut self, request: &Request) -> Result<Response> {
+        let stream = self.stream.as_mut().context("Not connected to the daemon")?;
+        write_message_as(stream, request, self.codec).await?;
+        read_message_compressed(stream, self.codec, self.compression).await?.context("Daemon closed the connection without responding")
+    }
+}
+
+/// Stop the daemon on `socket_path` if it runs another `lq` build, whose requests
+/// may not parse as ours, waiting up to `timeout` for it to exit.
+///
+/// Returns whether it was stopped, so that a daemon from this build can take its place.
+async fn stop_mismatched_daemon(socket_path: &Path, timeout: Duration) -> Result<bool> {
+    let daemon = Client::at_socket(socket_path);
+    let status = match daemon.request(Method::Status).await {
+        Ok(status) => status,
+        Err(e) => {
+            debug!("Could not read the daemon's version: {}", e);
+            return Ok(false);
+        }
+    };
+    if DaemonStatus::is_current(&status) {
+        return Ok(false);
+    }
+    info!(
+        "Restarting daemon {} (protocol {}) as {} (protocol {})",
+        status["version"].as_str().filter(|version| !version.is_empty()).unwrap_or("of an older lq"),
+        status["protocol_version"].as_u64().unwrap_or(0),
+        env!("CARGO_PKG_VERSION"),
+        PROTOCOL_VERSION
+    );
+    // The daemon may exit before answering
+    if let Err(e) = daemon.shutdown().await {
+        debug!("Shutdown of the mismatched daemon: {}", e);
+    }
+    let stopped = async {
+        while is_daemon_running(socket_path).await {
+            tokio::time::sleep(STOP_POLL_INTERVAL).await;
+        }
+    };
+    if tokio::time::timeout(timeout, stopped).await.is_err() {
+        bail!("Daemon of another lq version did not stop within {:?}; run `lq stop` and retry", timeout);
+    }
+    Ok(true)
+}
+
+/// Start a daemon for `workspace` in the background, logging to the workspace log file.
+///
+/// The daemon refuses to start for an untrusted workspace unless `trust` is set,
+/// and runs the rust-analyzer at `server_path` when given.
+/// Its stdout is a pipe carrying the startup handshake; see [`wait_for_startup`].
+/// It runs in a session of its own, so that it outlives the terminal it was
+/// started from and the signals sent to that terminal's processes.
+pub fn spawn_daemon(workspace: &Path, executable: &Path, trust: bool, server_path: Option<&Path>) -> Result<Child> {
+    // Capture anything the daemon prints outside of tracing (e.g. panics)
+    let log_path = get_log_path(workspace)?;
+    let log_file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .context("Failed to open log file")?;
+
+    let mut command = Command::new(executable);
+    command.arg("daemon").arg("--workspace").arg(workspace);
+    if trust {
+        command.arg("--trust");
+    }
+    if let Some(server_path) = server_path {
+        command.arg("--server-path").arg(server_path);
+    }
+    // SAFETY: setsid is async-signal-safe, and nothing else runs between fork and exec
+    unsafe {
+        command.pre_exec(|| match libc::setsid() {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(log_file)
+        .spawn()
+        .with_context(|| format!("Failed to spawn daemon using {:?}", executable))
+}
+
+/// Wait for a daemon started by [`spawn_daemon`] to report that it is listening,
+/// failing with its reason if it could not start
+pub async fn wait_for_startup(mut daemon: Child, workspace: &Path, timeout: Duration) -> Result<()> {
+    let stdout = daemon.stdout.take().context("Daemon started without a startup pipe")?;
+    let mut lines = BufReader::new(stdout).lines();
+    let log_path = get_log_path(workspace)?;
+
+    let line = match tokio::time::timeout(timeout, lines.next_line()).await {
+        Ok(line) => line.context("Failed to read the daemon's startup status")?,
+        Err(_) => bail!("Daemon did not start within {:?}; see {}", timeout, log_path.display()),
+    };
+    match line.as_deref().and_then(StartupStatus::parse) {
+        Some(StartupStatus::Ready) => Ok(()),
+        Some(StartupStatus::Failed(reason)) => bail!("Daemon failed to start: {}", reason),
+        None => bail!("Daemon exited during startup; see {}", log_path.display()),
+    }
+}
+
+/// Take a relative `file` among the params of `request`, a request as JSON, relative to `dir`
+pub fn resolve_file(request: &mut serde_json::Value, dir: &Path) {
+    if let Some(file) = request.pointer_mut("/params/file") {
+        if let Some(path) = file.as_str().filter(|path| Path::new(path).is_relative()) {
+            *file = dir.join(path).to_string_lossy().into_owned().into();
+        }
+    }
+}
+
+/// The daemon resolves files against its own working directory, so send absolute paths
+pub fn absolute(file: &Path) -> PathBuf {
+    if file.is_absolute() {
+        file.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{read_message_as, write_message_compressed};
+    use tokio::net::UnixListener;
+
+    /// Answer each connection on `socket_path` with the next canned result
+    fn serve(socket_path: &Path, results: Vec<ResponseResult>) {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        tokio::spawn(async move {
+            for result in results {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request: Request = read_message(&mut stream).await.unwrap().unwrap();
+                write_message(&mut stream, &Response { id: request.id, result }).await.unwrap();
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_typed_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("lq-test.sock");
+        serve(&socket_path, vec![
+            ResponseResult::Success { result: serde_json::json!({ "resolved": "Found symbol `Config`" }) },
+            ResponseResult::Error { error: "Symbol 'Nope' not found near line 1".to_string() },
+        ]);
+
+        let client = Client::at_socket(&socket_path);
+        let resolved = client.resolve(Path::new("/ws/src/lib.rs"), "Config").await.unwrap();
+        assert_eq!(resolved.as_deref(), Some("Found symbol `Config`"));
+
+        let error = client.resolve(Path::new("/ws/src/lib.rs"), "Nope").await.unwrap_err();
+        assert_eq!(error.to_string(), "Symbol 'Nope' not found near line 1");
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_until_answered() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("lq-test.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let client = Client::at_socket(&socket_path);
+        let in_flight = client.in_flight.clone();
+        let daemon = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request: Request = read_message(&mut stream).await.unwrap().unwrap();
+            let tracked = *in_flight.lock().unwrap() == vec![request.id.clone()];
+            let result = ResponseResult::Success { result: serde_json::json!({}) };
+            write_message(&mut stream, &Response { id: request.id, result }).await.unwrap();
+            tracked
+        });
+
+        client.request(Method::Status).await.unwrap();
+        assert!(daemon.await.unwrap());
+        assert!(client.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_session_switches_codec_and_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("lq-test.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let daemon = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request: Request = read_message(&mut stream).await.unwrap().unwrap();
+            assert!(matches!(request.method, Method::Codec { codec: Codec::Msgpack }));
+            let result = ResponseResult::Success { result: serde_json::json!({ "codec": "msgpack" }) };
+            write_message(&mut stream, &Response { id: request.id, result }).await.unwrap();
+
+            let request: Request = read_message_as(&mut stream, Codec::Msgpack).await.unwrap().unwrap();
+            assert!(matches!(request.method, Method::Compress { compression: Compression::Gzip, min_bytes: None }));
+            let result = ResponseResult::Success { result: serde_json::json!({ "compression": "gzip", "min_bytes": 0 }) };
+            write_message_as(&mut stream, &Response { id: request.id, result }, Codec::Msgpack).await.unwrap();
+
+            let request: Request = read_message_as(&mut stream, Codec::Msgpack).await.unwrap().unwrap();
+            let result = ResponseResult::Success { result: serde_json::json!({ "resolved": "Found symbol `Config`" }) };
+            write_message_compressed(&mut stream, &Response { id: request.id, result }, Codec::Msgpack, Compression::Gzip, 0).await.unwrap();
+        });
+
+        let mut client = Client::at_socket(&socket_path);
+        client.options.codec = Codec::Msgpack;
+        client.options.compression = Compression::Gzip;
+        let mut session = client.session();
+        let method = Method::Resolve { file: PathBuf::from("/ws/src/lib.rs"), symbol: "Config".to_string() };
+        let result = session.request(method).await.unwrap();
+        assert!(matches!(result, ResponseResult::Success { result } if result["resolved"] == "Found symbol `Config`"));
+        daemon.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_follows_explicit_switches() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("lq-switch.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let daemon = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request: Request = read_message(&mut stream).await.unwrap().unwrap();
+            assert!(matches!(request.method, Method::Compress { compression: Compression::Zstd, min_bytes: Some(0) }));
+            let result = ResponseResult::Success { result: serde_json::json!({ "compression": "zstd", "min_bytes": 0 }) };
+            write_message(&mut stream, &Response { id: request.id, result }).await.unwrap();
+
+            let request: Request = read_message(&mut stream).await.unwrap().unwrap();
+            assert!(matches!(request.method, Method::Codec { codec: Codec::Msgpack }));
+            let result = ResponseResult::Success { result: serde_json::json!({ "codec": "msgpack" }) };
+            write_message_compressed(&mut stream, &Response { id: request.id, result }, Codec::Json, Compression::Zstd, 0).await.unwrap();
+
+            let request: Request = read_message_as(&mut stream, Codec::Msgpack).await.unwrap().unwrap();
+            let result = ResponseResult::Success { result: serde_json::json!({ "pong": true }) };
+            write_message_compressed(&mut stream, &Response { id: request.id, result }, Codec::Msgpack, Compression::Zstd, 0).await.unwrap();
+        });
+
+        let client = Client::at_socket(&socket_path);
+        let mut session = client.session();
+        session.request(Method::Compress { compression: Compression::Zstd, min_bytes: Some(0) }).await.unwrap();
+        session.request(Method::Codec { codec: Codec::Msgpack }).await.unwrap();
+        let result = session.request(Method::Ping).await.unwrap();
+        assert!(matches!(result, ResponseResult::Success { result } if result["pong"] == true));
+        daemon.await.unwrap();
+    }
+
+    fn status(versions: serde_json::Value) -> ResponseResult {
+        let mut status = serde_json::json!({
+            "status": "running",
+            "workspace": "/ws",
+            "indexing": false,
+            "phase": null,
+            "percentage": null,
+            "message": null,
+            "pid": 1,
+            "uptime_secs": 0,
+            "memory_bytes": null,
+            "server_pid": null,
+            "server_memory_bytes": null
+        });
+        status.as_object_mut().unwrap().extend(versions.as_object().unwrap().clone());
+        ResponseResult::Success { result: status }
+    }
+
+    #[tokio::test]
+    async fn test_stop_mismatched_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("lq-test.sock");
+        // A daemon from before versions were reported
+        serve(&socket_path, vec![status(serde_json::json!({})), ResponseResult::Success { result: serde_json::json!({}) }]);
+        assert!(stop_mismatched_daemon(&socket_path, Duration::from_secs(5)).await.unwrap());
+
+        // A daemon from before this series, whose status lacks fields `DaemonStatus` requires
+        let socket_path = dir.path().join("lq-baseline.sock");
+        let baseline = serde_json::json!({ "status": "running", "workspace": "/ws", "indexing": false });
+        serve(&socket_path, vec![ResponseResult::Success { result: baseline }, ResponseResult::Success { result: serde_json::json!({}) }]);
+        assert!(stop_mismatched_daemon(&socket_path, Duration::from_secs(5)).await.unwrap());
+
+        // An answer that is no status at all
+        let socket_path = dir.path().join("lq-garbled.sock");
+        serve(&socket_path, vec![ResponseResult::Success { result: serde_json::json!("running") }, ResponseResult::Success { result: serde_json::json!({}) }]);
+        assert!(stop_mismatched_daemon(&socket_path, Duration::from_secs(5)).await.unwrap());
+
+        let socket_path = dir.path().join("lq-current.sock");
+        let current = serde_json::json!({ "protocol_version": PROTOCOL_VERSION, "version": env!("CARGO_PKG_VERSION") });
+        serve(&socket_path, vec![status(current)]);
+        assert!(!stop_mismatched_daemon(&socket_path, Duration::from_secs(5)).await.unwrap());
+    }
+}
@@ -0,0 +1,276 @@
+//! An interactive list to pick one of several answers from, such as the matches
+//! of `lq search`, narrowed down as the user types.
+//!
+//! The list is drawn on the terminal itself (`/dev/tty`) rather than stdout, so
+//! only the answer printed afterwards reaches a pipe or a pager. Typing keeps
+//! the items that contain the typed characters in order, best matches first.
+//! The arrow keys or Ctrl-P and Ctrl-N move the selection, Enter picks it, and
+//! Esc or Ctrl-C gives up.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+/// Items shown at once; the list scrolls to keep the selection among them
+const VISIBLE_ITEMS: usize = 10;
+
+/// Width assumed when the terminal does not say
+const DEFAULT_WIDTH: usize = 80;
+
+/// Let the user pick one of `items` on the terminal; `None` if they gave up
+pub fn pick(prompt: &str, items: &[String]) -> Result<Option<usize>> {
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("No terminal to pick from")?;
+    let width = stty(&tty, &["size"])
+        .ok()
+        .and_then(|size| size.split_whitespace().nth(1)?.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH);
+    let _raw = RawMode::enable(&tty)?;
+
+    let mut picker = Picker::new(items);
+    let mut buffer = [0; 64];
+    let outcome = loop {
+        picker.draw(&mut tty, prompt, width)?;
+        let read = tty.read(&mut buffer)?;
+        if read == 0 {
+            break None;
+        }
+        if let Some(outcome) = keys(&buffer[..read]).into_iter().find_map(|key| picker.press(key)) {
+            break outcome;
+        }
+    };
+    // Clear the list, so the answer prints where it was
+    write!(tty, "\r\x1b[J")?;
+    Ok(outcome)
+}
+
+/// How well `item` matches `query`, if it contains the query's characters in
+/// order, ignoring case. Runs of consecutive characters and characters that
+/// start a word score higher, so `hmap` prefers `HashMap` to `hash_map_impl`.
+pub fn fuzzy_score(query: &str, item: &str) -> Option<i64> {
+    let item: Vec<char> = item.chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for wanted in query.chars().flat_map(char::to_lowercase) {
+        let found = (position..item.len()).find(|&index| item[index].to_lowercase().eq(std::iter::once(wanted)))?;
+        let starts_word = found == 0
+            || !item[found - 1].is_alphanumeric()
+            || (item[found - 1].is_lowercase() && item[found].is_uppercase());
+        score += 1;
+        if previous == Some(found.wrapping_sub(1)) {
+            score += 5;
+        }
+        if starts_word {
+            score += 3;
+        }
+        // Characters skipped in between count against the match
+        score -= (found - position) as i64 / 4;
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Backspace,
+    Up,
+    Down,
+    Enter,
+    Cancel,
+}
+
+/// The keys in what one read from the terminal returned. A terminal writes an
+/// escape sequence all at once, so an Esc on its own is the Esc key.
+fn keys(input: &[u8]) -> Vec<Key> {
+    let input = String::from_utf8_lossy(input);
+    let mut chars = input.chars();
+    let mut keys = Vec::new();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' => match chars.next() {
+                None => Key::Cancel,
+                Some('[' | 'O') => {
+                    // Up to the sequence's final byte
+                    let last = chars.by_ref().find(|c| ('\x40'..='\x7e').contains(c));
+                    match last {
+                        Some('A') => Key::Up,
+                        Some('B') => Key::Down,
+                        _ => continue,
+                    }
+                }
+                Some(_) => continue,
+            },
+            '\x03' | '\x07' => Key::Cancel,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\x10' => Key::Up,
+            '\x0e' => Key::Down,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+struct Picker<'a> {
+    items: &'a [String],
+    query: String,
+    /// Index into the matches, not the items
+    selected: usize,
+}
+
+impl<'a> Picker<'a> {
+    fn new(items: &'a [String]) -> Self {
+        Self { items, query: String::new(), selected: 0 }
+    }
+
+    /// Indexes of the items matching the query, best first, ties in list order
+    fn matches(&self) -> Vec<usize> {
+        let mut scored: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| Some((fuzzy_score(&self.query, item)?, index)))
+            .collect();
+        scored.sort_by_key(|&(score, index)| (std::cmp::Reverse(score), index));
+        scored.into_iter().map(|(_, index)| index).collect()
+    }
+
+    /// Handle `key`; once the user is done, the item picked, if any
+    fn press(&mut self, key: Key) -> Option<Option<usize>> {
+        match key {
+            Key::Char(c) => {
+                self.query.push(c);
+                self.selected = 0;
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.selected = 0;
+            }
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => self.selected = (self.selected + 1).min(self.matches().len().saturating_sub(1)),
+            // Nothing to pick while nothing matches
+            Key::Enter => return self.matches().get(self.selected).map(|&index| Some(index)),
+            Key::Cancel => return Some(None),
+        }
+        None
+    }
+
+    /// Draw the prompt and the visible matches, leaving the cursor after the query
+    fn draw(&self, out: &mut impl Write, prompt: &str, width: usize) -> Result<()> {
+        let matches = self.matches();
+        let first = self.selected.saturating_sub(VISIBLE_ITEMS - 1);
+        let prompt_line = format!("{} {}", prompt, self.query);
+        let mut screen = format!("\r\x1b[J{}  [{}/{}]", prompt_line, matches.len(), self.items.len());
+        let shown = &matches[first..matches.len().min(first + VISIBLE_ITEMS)];
+        for (offset, &index) in shown.iter().enumerate() {
+            let marker = if first + offset == self.selected { '>' } else { ' ' };
+            // Cut to the width, so that no line wraps and the cursor can find its way back up
+            let item: String = self.items[index].chars().take(width.saturating_sub(3)).collect();
+            screen.push_str(&format!("\r\n{} {}", marker, item));
+        }
+        if !shown.is_empty() {
+            screen.push_str(&format!("\x1b[{}A", shown.len()));
+        }
+        screen.push_str(&format!("\r\x1b[{}C", prompt_line.chars().count()));
+        if out.write_all(screen.as_bytes()).and_then(|_| out.flush()).is_err() {
+            bail!("Failed to draw on the terminal");
+        }
+        Ok(())
+    }
+}
+
+/// The terminal switched to reading keys as they are pressed, without echoing
+/// them, until dropped
+struct RawMode {
+    tty: File,
+    /// The settings to restore, as `stty -g` prints them
+    saved: String,
+}
+
+impl RawMode {
+    fn enable(tty: &File) -> Result<Self> {
+        let saved = stty(tty, &["-g"])?.trim().to_string();
+        // Ctrl-C arrives as a key rather than a signal, so the settings are always restored
+        stty(tty, &["-icanon", "-echo", "-isig", "min", "1", "time", "0"])?;
+        Ok(Self { tty: tty.try_clone()?, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&self.tty, &[&self.saved]);
+    }
+}
+
+/// Run `stty` on `tty`, returning what it printed
+fn stty(tty: &File, args: &[&str]) -> Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(tty.try_clone()?)
+        .output()
+        .context("Failed to run stty")?;
+    if !output.status.success() {
+        bail!("stty {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert!(fuzzy_score("xyz", "HashMap").is_none());
+        assert!(fuzzy_score("mh", "HashMap").is_none());
+        assert!(fuzzy_score("hmap", "HashMap") > fuzzy_score("hmap", "hash_map_impl"));
+        assert!(fuzzy_score("conf", "Config") > fuzzy_score("conf", "ServerConfig"));
+        assert!(fuzzy_score("sc", "ServerConfig") > fuzzy_score("sc", "Socket"));
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(keys(b"a\xc3\xa9"), [Key::Char('a'), Key::Char('é')]);
+        assert_eq!(keys(b"\x1b[A\x1b[B\x1bOA"), [Key::Up, Key::Down, Key::Up]);
+        assert_eq!(keys(b"\x1b"), [Key::Cancel]);
+        assert_eq!(keys(b"\x1b[1;5C\x7f\r"), [Key::Backspace, Key::Enter]);
+        assert_eq!(keys(b"\x03"), [Key::Cancel]);
+    }
+
+    #[test]
+    fn test_picker() {
+        let items = ["Config".to_string(), "ServerConfig".to_string(), "connect".to_string()];
+        let mut picker = Picker::new(&items);
+        assert_eq!(picker.matches(), [0, 1, 2]);
+        assert_eq!(picker.press(Key::Down), None);
+        assert_eq!(picker.press(Key::Down), None);
+        assert_eq!(picker.press(Key::Down), None);
+        assert_eq!(picker.selected, 2);
+
+        for c in "sconf".chars() {
+            picker.press(Key::Char(c));
+        }
+        assert_eq!(picker.matches(), [1]);
+        picker.press(Key::Char('z'));
+        assert_eq!(picker.press(Key::Enter), None);
+        picker.press(Key::Backspace);
+        assert_eq!(picker.press(Key::Enter), Some(Some(1)));
+        assert_eq!(picker.press(Key::Cancel), Some(None));
+
+        let mut screen = Vec::new();
+        picker.draw(&mut screen, "search>", 8).unwrap();
+        let screen = String::from_utf8(screen).unwrap();
+        assert!(screen.contains("search> sconf  [1/3]\r\n> Serve\x1b[1A"), "{:?}", screen);
+    }
+}
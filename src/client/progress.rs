@@ -0,0 +1,144 @@
+//! A spinner on stderr while a request waits for the daemon's language server
+//! to start and index, such as the first query after the daemon started.
+//!
+//! Nothing is drawn for an answer that arrives quickly. After that, the
+//! spinner follows what the daemon reports in `lq status`, which mirrors the
+//! progress rust-analyzer pushes to it, and clears its line once the answer
+//! arrives. A request that is slow for other reasons draws nothing, since the
+//! daemon is neither starting nor indexing then.
+
+use std::future::Future;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::ipc::{DaemonStatus, Method, ResponseResult};
+
+use super::Client;
+
+/// How long an answer may take before the spinner shows
+const SPINNER_DELAY: Duration = Duration::from_millis(300);
+
+/// How often the spinner moves and the daemon is asked for its status
+const FRAME_INTERVAL: Duration = Duration::from_millis(150);
+
+/// Lines are cut to this width, so that none wraps and `\r` can clear it
+const LINE_WIDTH: usize = 79;
+
+const FRAMES: &[char] = &['|', '/', '-', '\\'];
+
+/// Await `work`, showing the daemon's startup progress meanwhile if it takes long
+pub async fn with_progress<T>(client: &Client, work: impl Future<Output = T>) -> T {
+    tokio::pin!(work);
+    tokio::select! {
+        output = &mut work => return output,
+        _ = tokio::time::sleep(SPINNER_DELAY) => {}
+    }
+
+    let started = Instant::now();
+    let mut frames = tokio::time::interval(FRAME_INTERVAL);
+    let mut frame = 0;
+    let mut drawn = false;
+    let output = loop {
+        tokio::select! {
+            output = &mut work => break output,
+            _ = frames.tick() => {
+                let line = match client.send(&Method::Status).await {
+                    Ok(ResponseResult::Success { result }) => serde_json::from_value::<DaemonStatus>(result)
+                        .ok()
+                        .and_then(|status| progress_line(&status, FRAMES[frame % FRAMES.len()], started.elapsed())),
+                    _ => None,
+                };
+                frame += 1;
+                drawn = match line {
+                    Some(line) => {
+                        draw(&format!("\r\x1b[K{}", line));
+                        true
+                    }
+                    None => {
+                        if drawn {
+                            draw("\r\x1b[K");
+                        }
+                        false
+                    }
+                };
+            }
+        }
+    };
+    if drawn {
+        draw("\r\x1b[K");
+    }
+    output
+}
+
+fn draw(text: &str) {
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(text.as_bytes()).and_then(|_| stderr.flush());
+}
+
+/// The spinner's line for a daemon reporting `status`, `elapsed` into the wait;
+/// none once it is neither starting nor indexing
+fn progress_line(status: &DaemonStatus, frame: char, elapsed: Duration) -> Option<String> {
+    if !matches!(status.status.as_str(), "starting" | "indexing") {
+        return None;
+    }
+    let mut line = format!("{} rust-analyzer: {}", frame, status.phase.as_deref().unwrap_or("starting"));
+    if let Some(percentage) = status.percentage {
+        line.push_str(&format!(" {}%", percentage));
+    }
+    if let Some(message) = &status.message {
+        line.push_str(&format!(" ({})", message));
+    }
+    line.push_str(&format!(" [{}s]", elapsed.as_secs()));
+    if line.chars().count() > LINE_WIDTH {
+        line = line.chars().take(LINE_WIDTH - 3).chain("...".chars()).collect();
+    }
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn status(status: &str, phase: Option<&str>, percentage: Option<u32>, message: Option<&str>) -> DaemonStatus {
+        DaemonStatus {
+            status: status.to_string(),
+            workspace: PathBuf::from("/ws"),
+            indexing: status != "ready",
+            phase: phase.map(str::to_string),
+            percentage,
+            message: message.map(str::to_string),
+            pid: 1,
+            uptime_secs: 0,
+            memory_bytes: None,
+            server_pid: None,
+            server_memory_bytes: None,
+            crash_reason: None,
+            restart_attempts: 0,
+            server_restarts: 0,
+            memory_restarts: 0,
+            next_restart_secs: None,
+            paused_for_build: false,
+            prefetch: Default::default(),
+            protocol_version: 0,
+            version: String::new(),
+            members: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_progress_line() {
+        let elapsed = Duration::from_millis(12_400);
+        assert_eq!(progress_line(&status("starting", None, None, None), '|', elapsed).unwrap(), "| rust-analyzer: starting [12s]");
+        assert_eq!(
+            progress_line(&status("indexing", Some("Indexing"), Some(42), Some("3/7 (serde)")), '/', elapsed).unwrap(),
+            "/ rust-analyzer: Indexing 42% (3/7 (serde)) [12s]"
+        );
+        let long = "x".repeat(100);
+        let line = progress_line(&status("indexing", Some("Loading"), None, Some(&long)), '-', elapsed).unwrap();
+        assert_eq!(line.chars().count(), LINE_WIDTH);
+        assert!(line.ends_with("..."));
+        assert!(progress_line(&status("ready", None, None, None), '|', elapsed).is_none());
+        assert!(progress_line(&status("degraded", None, None, None), '|', elapsed).is_none());
+    }
+}
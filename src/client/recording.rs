@@ -0,0 +1,121 @@
+//! Request/response recordings for deterministic replays.
+//!
+//! Each exchange is stored as `<key>.json` in the recording directory, where the
+//! key hashes the request's method and params. Paths inside the workspace are
+//! stored relative to it, so a recording replays from any checkout. Repeating a
+//! request while recording overwrites the earlier answer.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::ipc::{Method, ResponseResult};
+
+/// One recorded exchange
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub request: Value,
+    pub response: ResponseResult,
+}
+
+/// A directory of recordings for one workspace
+#[derive(Debug, Clone)]
+pub struct RecordingDir {
+    dir: PathBuf,
+    workspace: PathBuf,
+}
+
+impl RecordingDir {
+    pub fn new(dir: impl Into<PathBuf>, workspace: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            workspace: workspace.into(),
+        }
+    }
+
+    /// Store the response to `method`, replacing any earlier recording of it
+    pub fn save(&self, method: &Method, response: &ResponseResult) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create recording directory {:?}", self.dir))?;
+        let recording = Recording {
+            request: self.normalize(method)?,
+            response: response.clone(),
+        };
+        let path = self.path_for(&recording.request);
+        std::fs::write(&path, serde_json::to_string_pretty(&recording)?)
+            .with_context(|| format!("Failed to write recording {:?}", path))
+    }
+
+    /// The recorded response to `method`
+    pub fn load(&self, method: &Method) -> Result<ResponseResult> {
+        let request = self.normalize(method)?;
+        let path = self.path_for(&request);
+        let contents = std::fs::read_to_string(&path).with_context(|| {
+            format!("No recorded response for {} (expected {:?})", serde_json::to_string(&request).unwrap_or_default(), path)
+        })?;
+        let recording: Recording = serde_json::from_str(&contents)
+            .with_context(|| format!("Malformed recording {:?}", path))?;
+        Ok(recording.response)
+    }
+
+    fn path_for(&self, request: &Value) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(request.to_string().as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        self.dir.join(format!("{}.json", &hash[..16]))
+    }
+
+    /// The method as JSON with workspace paths made relative
+    fn normalize(&self, method: &Method) -> Result<Value> {
+        let mut request = serde_json::to_value(method)?;
+        relativize(&mut request, &self.workspace);
+        Ok(request)
+    }
+}
+
+fn relativize(value: &mut Value, workspace: &Path) {
+    match value {
+        Value::String(s) => {
+            if let Ok(relative) = Path::new(s.as_str()).strip_prefix(workspace) {
+                *s = relative.to_string_lossy().into_owned();
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| relativize(v, workspace)),
+        Value::Object(fields) => fields.values_mut().for_each(|v| relativize(v, workspace)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docs(file: &str) -> Method {
+        Method::Docs {
+            file: PathBuf::from(file),
+            line: 3,
+            column: None,
+            symbol: "Config".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_replay_from_another_checkout() {
+        let dir = tempfile::tempdir().unwrap();
+        let response = ResponseResult::Success { result: serde_json::json!({ "docs": "A config" }) };
+
+        RecordingDir::new(dir.path(), "/home/a/project")
+            .save(&docs("/home/a/project/src/lib.rs"), &response)
+            .unwrap();
+
+        let replay = RecordingDir::new(dir.path(), "/ci/checkout");
+        match replay.load(&docs("/ci/checkout/src/lib.rs")).unwrap() {
+            ResponseResult::Success { result } => assert_eq!(result["docs"], "A config"),
+            ResponseResult::Error { error } => panic!("unexpected error: {}", error),
+        }
+        assert!(replay.load(&docs("/ci/checkout/src/main.rs")).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! `lq stdio`: the daemon's protocol on stdin and stdout, for editors and
+//! wrappers that would rather start one process than find and connect to the
+//! daemon's socket.
+//!
+//! Messages are framed as on the socket, a 4-byte big-endian length followed by
+//! a JSON [`Request`] or [`Response`], so a client of the socket only swaps its
+//! transport. Requests are answered concurrently, each forwarded on its own
+//! connection with its id, and responses are written as they come, to be
+//! matched up by id; a `Cancel` naming a request still in flight stops it. A
+//! relative `file` is taken relative to the workspace root. Unlike the socket,
+//! the streams stay uncompressed JSON throughout, so `Codec` and `Compress`
+//! requests are refused.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::client::{resolve_file, Client};
+use crate::ipc::{read_message, write_message, Method, Request, Response, ResponseResult};
+
+/// Answer requests read from stdin on stdout until stdin closes and every
+/// answer is written, passing each answer through `finish` as `lq batch` does
+pub async fn serve(client: &Client, workspace: &Path, finish: impl Fn(&mut Value)) -> Result<()> {
+    // Read apart from answering, so that no message is cut short when an answer comes first
+    let (messages_tx, mut messages) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut stdin = tokio::io::stdin();
+        loop {
+            let message = read_message::<_, Value>(&mut stdin).await.context("Failed to read stdin");
+            let done = !matches!(message, Ok(Some(_)));
+            if messages_tx.send(message).await.is_err() || done {
+                break;
+            }
+        }
+    });
+
+    let mut stdout = tokio::io::stdout();
+    let mut pending = FuturesUnordered::new();
+    let mut reading = true;
+    loop {
+        let response = tokio::select! {
+            message = messages.recv(), if reading => match message {
+                Some(Ok(Some(message))) => match request(message, workspace) {
+                    Ok(request) => {
+                        pending.push(answer(client, request));
+                        continue;
+                    }
+                    Err(response) => response,
+                },
+                Some(Ok(None)) | None => {
+                    reading = false;
+                    continue;
+                }
+                Some(Err(e)) => return Err(e),
+            },
+            Some(response) = pending.next() => response,
+            else => break,
+        };
+        let response = match response {
+            Response { id, result: ResponseResult::Success { mut result } } => {
+                finish(&mut result);
+                Response { id, result: ResponseResult::Success { result } }
+            }
+            response => response,
+        };
+        write_message(&mut stdout, &response).await.context("Failed to write stdout")?;
+    }
+    Ok(())
+}
+
+/// The request in `message`, or the error response to write instead
+fn request(mut message: Value, workspace: &Path) -> Result<Request, Response> {
+    let id = message.get("id").and_then(Value::as_str).unwrap_or_default().to_string();
+    let error = |error: String| Response { id: id.clone(), result: ResponseResult::Error { error } };
+    if let Some(method) = message.get_mut("method") {
+        resolve_file(method, workspace);
+    }
+    let request: Request = serde_json::from_value(message).map_err(|e| error(format!("Invalid request: {}", e)))?;
+    if matches!(request.method, Method::Codec { .. } | Method::Compress { .. }) {
+        return Err(error(format!("lq stdio speaks uncompressed JSON only; {} requests are for the socket", request.method.name())));
+    }
+    Ok(request)
+}
+
+async fn answer(client: &Client, request: Request) -> Response {
+    // Reported to the caller rather than ending the session, even when the daemon is gone
+    let result = client
+        .forward(&request)
+        .await
+        .unwrap_or_else(|e| ResponseResult::Error { error: format!("{:#}", e) });
+    Response { id: request.id, result }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_request() {
+        let request = request(json!({ "id": "7", "method": { "method": "Outline", "params": { "file": "src/lib.rs" } } }), Path::new("/ws")).unwrap();
+        assert_eq!(request.id, "7");
+        assert!(matches!(request.method, Method::Outline { file } if file == Path::new("/ws/src/lib.rs")));
+
+        let error = request_error(json!({ "id": "8", "method": { "method": "Outline" } }));
+        assert_eq!(error.id, "8");
+        assert!(matches!(error.result, ResponseResult::Error { error } if error.starts_with("Invalid request")));
+
+        let error = request_error(json!({ "id": "9", "method": { "method": "Codec", "params": { "codec": "msgpack" } } }));
+        assert!(matches!(error.result, ResponseResult::Error { error } if error.contains("JSON only")));
+    }
+
+    fn request_error(message: Value) -> Response {
+        request(message, Path::new("/ws")).unwrap_err()
+    }
+}
@@ -0,0 +1,135 @@
+// Client for the lq daemon, generated by `lq genclient node`.
+//
+// Speaks the daemon's IPC protocol (version @@PROTOCOL_VERSION@@) over its Unix
+// socket, starting a daemon for the workspace if none is running. Result
+// payloads are described by `lq schema <command>`. Regenerate after upgrading lq.
+
+"use strict";
+
+const { spawn } = require("child_process");
+const crypto = require("crypto");
+const fs = require("fs");
+const net = require("net");
+const os = require("os");
+const path = require("path");
+
+const PROTOCOL_VERSION = @@PROTOCOL_VERSION@@;
+
+/** An error reported by the daemon */
+class LqError extends Error {}
+
+/** The socket the daemon for `workspace` listens on */
+function socketPath(workspace) {
+  const digest = crypto.createHash("sha256").update(fs.realpathSync(workspace)).digest("hex");
+  const socketDir = process.env.XDG_RUNTIME_DIR
+    ? path.join(process.env.XDG_RUNTIME_DIR, "lq")
+    : path.join(process.env.TMPDIR || "/tmp", `lq-${process.getuid()}`);
+  return path.join(socketDir, `lq-${digest.slice(0, 8)}.sock`);
+}
+
+function isRunning(socket) {
+  return new Promise((resolve) => {
+    const connection = net.connect(socket);
+    connection.on("connect", () => {
+      connection.end();
+      resolve(true);
+    });
+    connection.on("error", () => resolve(false));
+  });
+}
+
+/** Read the daemon's one-line startup status: `ready` or `failed: <reason>` */
+function waitForStartup(daemon, timeout) {
+  return new Promise((resolve, reject) => {
+    let output = "";
+    let settled = false;
+    const finish = (error) => {
+      if (settled) {
+        return;
+      }
+      settled = true;
+      clearTimeout(timer);
+      daemon.stdout.destroy();
+      daemon.unref();
+      if (error) {
+        reject(error);
+      } else {
+        resolve();
+      }
+    };
+    const timer = setTimeout(() => finish(new LqError(`Daemon did not start within ${timeout}ms`)), timeout);
+
+    daemon.stdout.on("data", (chunk) => {
+      output += chunk;
+      const newline = output.indexOf("\n");
+      if (newline < 0) {
+        return;
+      }
+      const line = output.slice(0, newline);
+      if (line === "ready") {
+        finish();
+      } else if (line.startsWith("failed: ")) {
+        finish(new LqError(`Daemon failed to start: ${line.slice("failed: ".length)}`));
+      } else {
+        finish(new LqError("Daemon exited during startup"));
+      }
+    });
+    daemon.stdout.on("end", () => finish(new LqError("Daemon exited during startup")));
+    daemon.on("error", finish);
+  });
+}
+
+class Client {
+  constructor(socket) {
+    this.socketPath = socket;
+  }
+
+  /** Connect to the daemon for `workspace`, starting one if none is running */
+  static async connect(workspace = process.cwd(), { executable = "lq", startTimeout = 5000 } = {}) {
+    workspace = fs.realpathSync(workspace);
+    const socket = socketPath(workspace);
+    if (!(await isRunning(socket))) {
+      const daemon = spawn(executable, ["daemon", "--workspace", workspace], {
+        detached: true,
+        stdio: ["ignore", "pipe", "ignore"],
+      });
+      await waitForStartup(daemon, startTimeout);
+    }
+    return new Client(socket);
+  }
+
+  /** Send one request and return its result payload */
+  request(method, params) {
+    const call = { method };
+    if (params !== undefined) {
+      call.params = params;
+    }
+    const body = Buffer.from(JSON.stringify({ id: crypto.randomUUID(), method: call }));
+    const header = Buffer.alloc(4);
+    header.writeUInt32BE(body.length);
+
+    return new Promise((resolve, reject) => {
+      const connection = net.connect(this.socketPath);
+      let buffer = Buffer.alloc(0);
+      connection.on("connect", () => connection.write(Buffer.concat([header, body])));
+      connection.on("data", (chunk) => {
+        buffer = Buffer.concat([buffer, chunk]);
+        if (buffer.length < 4 || buffer.length < 4 + buffer.readUInt32BE(0)) {
+          return;
+        }
+        connection.end();
+        const response = JSON.parse(buffer.subarray(4, 4 + buffer.readUInt32BE(0)));
+        if ("error" in response) {
+          reject(new LqError(response.error));
+        } else {
+          resolve(response.result);
+        }
+      });
+      connection.on("error", reject);
+      connection.on("end", () => reject(new LqError("Daemon closed the connection without responding")));
+    });
+  }
+@@METHODS@@
+}
+
+module.exports = { Client, LqError, PROTOCOL_VERSION, socketPath };
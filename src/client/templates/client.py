@@ -0,0 +1,96 @@
+"""Client for the lq daemon, generated by `lq genclient python`.
+
+Speaks the daemon's IPC protocol (version @@PROTOCOL_VERSION@@) over its Unix
+socket, starting a daemon for the workspace if none is running. Result
+payloads are described by `lq schema <command>`. Regenerate after upgrading lq.
+"""
+
+import hashlib
+import json
+import os
+import select
+import socket
+import struct
+import subprocess
+import uuid
+from typing import Any, Optional
+
+PROTOCOL_VERSION = @@PROTOCOL_VERSION@@
+
+
+class LqError(Exception):
+    """An error reported by the daemon"""
+
+
+def socket_path(workspace: str) -> str:
+    """The socket the daemon for `workspace` listens on"""
+    digest = hashlib.sha256(os.path.realpath(workspace).encode()).hexdigest()
+    if os.environ.get("XDG_RUNTIME_DIR"):
+        socket_dir = os.path.join(os.environ["XDG_RUNTIME_DIR"], "lq")
+    else:
+        socket_dir = os.path.join(os.environ.get("TMPDIR", "/tmp"), "lq-%d" % os.getuid())
+    return os.path.join(socket_dir, "lq-%s.sock" % digest[:8])
+
+
+class Client:
+    def __init__(self, workspace: Optional[str] = None, executable: str = "lq", start_timeout: float = 5.0):
+        self.workspace = os.path.realpath(workspace or os.getcwd())
+        self.socket_path = socket_path(self.workspace)
+        if not self._is_running():
+            daemon = subprocess.Popen(
+                [executable, "daemon", "--workspace", self.workspace],
+                stdin=subprocess.DEVNULL,
+                stdout=subprocess.PIPE,
+                stderr=subprocess.DEVNULL,
+                start_new_session=True,
+            )
+            _wait_for_startup(daemon, start_timeout)
+
+    def _is_running(self) -> bool:
+        try:
+            with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as sock:
+                sock.connect(self.socket_path)
+            return True
+        except OSError:
+            return False
+
+    def request(self, method: str, params: Optional[dict] = None) -> Any:
+        """Send one request and return its result payload"""
+        call = {"method": method}
+        if params is not None:
+            call["params"] = params
+        body = json.dumps({"id": str(uuid.uuid4()), "method": call}).encode()
+
+        with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as sock:
+            sock.connect(self.socket_path)
+            sock.sendall(struct.pack(">I", len(body)) + body)
+            (length,) = struct.unpack(">I", _read_exact(sock, 4))
+            response = json.loads(_read_exact(sock, length))
+
+        if "error" in response:
+            raise LqError(response["error"])
+        return response["result"]
+@@METHODS@@
+
+
+def _wait_for_startup(daemon: subprocess.Popen, timeout: float) -> None:
+    """Read the daemon's one-line startup status: `ready` or `failed: <reason>`"""
+    readable, _, _ = select.select([daemon.stdout], [], [], timeout)
+    line = daemon.stdout.readline().decode().rstrip("\n") if readable else None
+    daemon.stdout.close()
+    if line is None:
+        raise LqError("Daemon did not start within %ss" % timeout)
+    if line.startswith("failed: "):
+        raise LqError("Daemon failed to start: " + line[len("failed: "):])
+    if line != "ready":
+        raise LqError("Daemon exited during startup")
+
+
+def _read_exact(sock: socket.socket, length: int) -> bytes:
+    data = b""
+    while len(data) < length:
+        chunk = sock.recv(length - len(data))
+        if not chunk:
+            raise LqError("Daemon closed the connection without responding")
+        data += chunk
+    return data
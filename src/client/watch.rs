@@ -0,0 +1,105 @@
+//! `--watch`: waiting for the workspace sources to change, so a query can be
+//! asked again.
+//!
+//! The same files count as in the daemon's cache fingerprint: Rust sources and
+//! manifests, outside hidden directories and build output. An editor saving
+//! a file usually touches it several times in a row, so changes are collected
+//! until none has come for [`SETTLE_TIME`].
+
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::core::workspace::SKIPPED_DIRS;
+
+/// How long the sources have to stay unchanged before a change is reported
+pub const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Watches a workspace's sources
+pub struct SourceWatcher {
+    workspace: PathBuf,
+    events: mpsc::UnboundedReceiver<Event>,
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl SourceWatcher {
+    pub fn new(workspace: &Path) -> Result<Self> {
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                let _ = sender.send(event);
+            }
+        })
+        .context("Failed to create a file watcher")?;
+        watcher
+            .watch(workspace, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", workspace.display()))?;
+        // Events carry resolved paths
+        let workspace = workspace.canonicalize().unwrap_or_else(|_| workspace.to_path_buf());
+        Ok(Self { workspace, events, _watcher: watcher })
+    }
+
+    /// Wait until a source file has changed and the changes have settled; the changed files
+    pub async fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        while changed.is_empty() {
+            let Some(event) = self.events.recv().await else {
+                return changed;
+            };
+            self.collect(event, &mut changed);
+        }
+        while let Ok(Some(event)) = tokio::time::timeout(SETTLE_TIME, self.events.recv()).await {
+            self.collect(event, &mut changed);
+        }
+        changed
+    }
+
+    fn collect(&self, event: Event, changed: &mut Vec<PathBuf>) {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        for path in event.paths {
+            if is_watched_source(&self.workspace, &path) && !changed.contains(&path) {
+                changed.push(path);
+            }
+        }
+    }
+}
+
+/// Whether a change to `path` can change an answer about `workspace`
+fn is_watched_source(workspace: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(workspace) else {
+        return false;
+    };
+    let skipped = relative.parent().is_some_and(|dir| {
+        dir.components().any(|component| match component {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref())
+            }
+            _ => false,
+        })
+    });
+    let source = path.extension().is_some_and(|ext| ext == "rs") || path.file_name().is_some_and(|name| name == "Cargo.toml");
+    source && !skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_watched_source() {
+        let ws = Path::new("/ws");
+        assert!(is_watched_source(ws, Path::new("/ws/src/lib.rs")));
+        assert!(is_watched_source(ws, Path::new("/ws/crates/core/Cargo.toml")));
+        assert!(!is_watched_source(ws, Path::new("/ws/README.md")));
+        assert!(!is_watched_source(ws, Path::new("/ws/target/debug/build/out.rs")));
+        assert!(!is_watched_source(ws, Path::new("/ws/.git/hooks/x.rs")));
+        assert!(!is_watched_source(ws, Path::new("/elsewhere/lib.rs")));
+    }
+}
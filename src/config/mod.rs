@@ -0,0 +1,740 @@
+//! Global configuration and the workspace trust allowlist.
+//!
+//! Starting a daemon runs rust-analyzer, which executes the workspace's build
+//! scripts and proc macros. Daemons therefore only start for workspaces listed
+//! in `trusted_workspaces` (or below one), unless `--trust` is passed.
+//!
+//! The file lives at `~/.config/language-query/config.toml` on Linux (the
+//! platform config directory elsewhere), or wherever `LQ_CONFIG` points.
+//! A workspace may also carry a `.lq.toml` with settings for its own daemon.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::core::workspace::{cargo_manifests, find_workspace_root};
+use crate::lsp::{Priority, ResourceLimits};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Workspaces allowed to start a daemon, including everything beneath them
+    pub trusted_workspaces: Vec<PathBuf>,
+    /// Sandbox for every workspace, overriding any in a workspace's `.lq.toml`
+    pub sandbox: Option<SandboxConfig>,
+    /// CPU and memory limits for every workspace, overriding any in a workspace's `.lq.toml`
+    pub limits: Option<ResourceLimits>,
+    /// Scheduling priority for every workspace, overriding any in a workspace's `.lq.toml`
+    pub priority: Option<Priority>,
+    /// Prefetching for every workspace, overriding any in a workspace's `.lq.toml`
+    pub prefetch: Option<PrefetchConfig>,
+    /// Retrying empty answers for every workspace, overriding any in a workspace's `.lq.toml`
+    pub retry_empty: Option<RetryConfig>,
+    /// Event hooks for every workspace; each one set here overrides the same one in a workspace's `.lq.toml`
+    pub hooks: Option<HooksConfig>,
+    /// Query presets for every workspace, overriding any of the same name in a workspace's `.lq.toml`
+    #[serde(rename = "preset")]
+    pub presets: BTreeMap<String, Preset>,
+    /// Flags added to every run of a command, keyed by command name, overriding
+    /// those of the same command in a workspace's `.lq.toml`
+    pub defaults: BTreeMap<String, Vec<String>>,
+    /// Commands that the JSON answer of a command is piped through in place of its
+    /// text output, keyed by command name, overriding those of the same command in a
+    /// workspace's `.lq.toml`
+    #[serde(rename = "formatter")]
+    pub formatters: BTreeMap<String, Vec<String>>,
+    /// Redaction rules for every workspace, applied along with a workspace's own
+    pub redact: Option<RedactConfig>,
+    /// Usage metrics kept on this machine; only the global config can turn them on
+    pub metrics: Option<MetricsConfig>,
+    /// Language servers by language, overriding those of the same language in a workspace's `.lq.toml`
+    pub servers: BTreeMap<String, ServerConfig>,
+    /// Daemon lifetime and startup for every workspace; each setting here overrides the same one in a
+    /// workspace's `.lq.toml`
+    pub daemon: Option<DaemonConfig>,
+}
+
+/// Settings from a workspace's own `.lq.toml`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceConfig {
+    pub sandbox: Option<SandboxConfig>,
+    pub limits: Option<ResourceLimits>,
+    pub priority: Option<Priority>,
+    pub prefetch: Option<PrefetchConfig>,
+    pub retry_empty: Option<RetryConfig>,
+    pub hooks: Option<HooksConfig>,
+    #[serde(rename = "preset")]
+    pub presets: BTreeMap<String, Preset>,
+    pub defaults: BTreeMap<String, Vec<String>>,
+    #[serde(rename = "formatter")]
+    pub formatters: BTreeMap<String, Vec<String>>,
+    pub links: Option<LinksConfig>,
+    pub redact: Option<RedactConfig>,
+    pub servers: BTreeMap<String, ServerConfig>,
+    pub daemon: Option<DaemonConfig>,
+    /// Serve the Cargo workspaces below this directory from one daemon
+    pub monorepo: Option<MonorepoConfig>,
+}
+
+/// A wrapper command that confines the language server, such as `firejail` or `sandbox-exec`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// Program and arguments placed before the server command; `{workspace}` is
+    /// replaced by the workspace root
+    pub command: Vec<String>,
+}
+
+impl SandboxConfig {
+    /// The wrapper command for `workspace`, placeholders filled in
+    pub fn command_for(&self, workspace: &Path) -> Vec<String> {
+        let workspace = workspace.to_string_lossy();
+        self.command.iter().map(|arg| arg.replace("{workspace}", &workspace)).collect()
+    }
+}
+
+/// Languages with a language server lq can drive, as named in `[servers.<language>]`
+pub const SERVED_LANGUAGES: &[&str] = &["rust"];
+
+/// The language server of one language, as `[servers.<language>]`. Besides
+/// how it is started, it holds the rust-analyzer settings a workspace commonly
+/// needs to build at all; any other setting can go under `settings`, in
+/// rust-analyzer's own nesting, such as `settings.cargo.extraEnv`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The server's executable, instead of the one found on `PATH`; may start with `~`
+    pub command: Option<String>,
+    /// Arguments the server is started with
+    pub args: Vec<String>,
+    /// Cargo features to analyze the workspace with
+    pub features: Option<Vec<String>>,
+    /// Analyze with every Cargo feature enabled, instead of `features`
+    pub all_features: Option<bool>,
+    pub no_default_features: Option<bool>,
+    /// The target triple to analyze for, such as `wasm32-unknown-unknown`
+    pub target: Option<String>,
+    /// Whether proc macros are expanded; rust-analyzer expands them unless this is false
+    pub proc_macros: Option<bool>,
+    /// The cargo command run for diagnostics, such as `clippy`
+    pub check_command: Option<String>,
+    /// Further `initializationOptions`, which the settings above override
+    pub settings: Option<toml::Table>,
+}
+
+impl ServerConfig {
+    /// The configured executable, `~` expanded
+    pub fn executable(&self) -> Option<PathBuf> {
+        self.command.as_deref().map(|command| expand_home(Path::new(command)))
+    }
+
+    /// The settings as rust-analyzer's `initializationOptions`; none when nothing is set
+    pub fn initialization_options(&self) -> Result<Option<serde_json::Value>> {
+        let mut options = match &self.settings {
+            Some(settings) => serde_json::to_value(settings).context("Invalid [servers.rust.settings]")?,
+            None => serde_json::Value::Object(Default::default()),
+        };
+        let features = match (self.all_features, &self.features) {
+            (Some(true), _) => Some(serde_json::json!("all")),
+            (_, Some(features)) => Some(serde_json::json!(features)),
+            _ => None,
+        };
+        let typed = [
+            (&["cargo", "features"][..], features),
+            (&["cargo", "noDefaultFeatures"], self.no_default_features.map(Into::into)),
+            (&["cargo", "target"], self.target.clone().map(Into::into)),
+            (&["procMacro", "enable"], self.proc_macros.map(Into::into)),
+            (&["check", "command"], self.check_command.clone().map(Into::into)),
+        ];
+        for (path, value) in typed {
+            if let Some(value) = value {
+                set_setting(&mut options, path, value)?;
+            }
+        }
+        Ok(options.as_object().is_some_and(|options| !options.is_empty()).then_some(options))
+    }
+}
+
+/// Set the setting at `path` in `options`, creating the tables on the way
+fn set_setting(options: &mut serde_json::Value, path: &[&str], value: serde_json::Value) -> Result<()> {
+    let (last, parents) = path.split_last().context("Empty setting path")?;
+    let mut table = options;
+    for key in parents {
+        table = table
+            .as_object_mut()
+            .with_context(|| format!("[servers.rust.settings] has a value where table `{}` belongs", key))?
+            .entry(*key)
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+    }
+    table
+        .as_object_mut()
+        .with_context(|| format!("[servers.rust.settings] has a value where the table holding `{}` belongs", last))?
+        .insert(last.to_string(), value);
+    Ok(())
+}
+
+/// The Cargo workspaces a monorepo's daemon serves
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MonorepoConfig {
+    /// Workspace roots relative to the monorepo root; empty serves every Cargo workspace below it
+    pub members: Vec<PathBuf>,
+}
+
+/// How long a daemon waits for its language server, and how long it lives
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    /// Minutes without requests after which the daemon exits; unset or 0 keeps it until `lq stop`
+    pub idle_timeout_minutes: Option<u64>,
+    /// Seconds the language server has to initialize and finish its first indexing
+    pub ready_timeout_secs: Option<u64>,
+    /// Restarts of a crashing language server to attempt before giving up
+    pub max_restarts: Option<u32>,
+    /// The daemon's log filter when `RUST_LOG` is unset, such as `debug`
+    pub log_level: Option<String>,
+}
+
+impl DaemonConfig {
+    /// These settings, with any unset one taken from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            idle_timeout_minutes: self.idle_timeout_minutes.or(fallback.idle_timeout_minutes),
+            ready_timeout_secs: self.ready_timeout_secs.or(fallback.ready_timeout_secs),
+            max_restarts: self.max_restarts.or(fallback.max_restarts),
+            log_level: self.log_level.or(fallback.log_level),
+        }
+    }
+}
+
+/// Whether the daemon warms its cache with the queries likely to follow a `docs` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrefetchConfig {
+    pub enabled: bool,
+    /// Prefetches allowed to run at once; any beyond that are skipped, not queued
+    pub max_concurrent: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent: 2,
+        }
+    }
+}
+
+/// Whether the daemon asks again when a query comes back empty shortly after
+/// the language server became ready, as its caches may still be warming
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub enabled: bool,
+    /// Empty answers this long after the server became ready are retried
+    pub within_secs: u64,
+    /// How long to wait before the retry
+    pub delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            within_secs: 30,
+            delay_ms: 1000,
+        }
+    }
+}
+
+/// Commands the daemon runs on workspace events, each given the event as JSON on stdin.
+///
+/// Each command is a program and its arguments, run in the workspace root.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Once the language server is ready, and again after each restart
+    pub on_ready: Option<Vec<String>>,
+    /// When the diagnostics of one or more files change
+    pub on_diagnostics_changed: Option<Vec<String>>,
+    /// When the language server stops or fails to start, and after each failed restart
+    pub on_crash: Option<Vec<String>>,
+}
+
+impl HooksConfig {
+    /// These hooks, with any unset one taken from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            on_ready: self.on_ready.or(fallback.on_ready),
+            on_diagnostics_changed: self.on_diagnostics_changed.or(fallback.on_diagnostics_changed),
+            on_crash: self.on_crash.or(fallback.on_crash),
+        }
+    }
+}
+
+/// Links from results to the repository host, as with `--link-template`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinksConfig {
+    /// Link to a line on the repository host, such as
+    /// `https://github.com/org/repo/blob/{rev}/{path}#L{line}`; `{path}` is
+    /// relative to the repository root
+    pub template: Option<String>,
+}
+
+/// Source lines to mask in results before they are printed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactConfig {
+    /// Globs of workspace paths whose lines are all masked, as with `--exclude`
+    pub paths: Vec<String>,
+    /// Regexes of lines to mask in any file
+    pub patterns: Vec<String>,
+}
+
+/// Whether daemons count their requests per method and time them, for `lq metrics dump`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+}
+
+/// A named query, run as `lq run <name> <args>`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    /// The command to run, such as `refs`
+    pub method: String,
+    /// Arguments of the command, placed before those given to `lq run`
+    pub args: Vec<String>,
+    /// Globs of workspace paths whose results are dropped, as with `--exclude`
+    pub exclude: Vec<String>,
+    /// Output format, unless one is given on the command line
+    pub format: Option<String>,
+}
+
+impl WorkspaceConfig {
+    /// Load `<workspace>/.lq.toml`; a missing file is an empty config
+    pub fn load(workspace: &Path) -> Result<Self> {
+        let path = workspace.join(".lq.toml");
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).with_context(|| format!("Invalid config file {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read config file {:?}", path)),
+        }
+    }
+}
+
+/// The sandbox wrapper for `workspace`'s language server; empty when none is configured.
+///
+/// The global config wins so that a checkout's own `.lq.toml` cannot loosen it.
+pub fn sandbox_command(workspace: &Path) -> Result<Vec<String>> {
+    let sandbox = match Config::load()?.sandbox {
+        Some(sandbox) => Some(sandbox),
+        None => WorkspaceConfig::load(workspace)?.sandbox,
+    };
+    Ok(sandbox.map(|s| s.command_for(workspace)).unwrap_or_default())
+}
+
+/// The CPU and memory limits for `workspace`'s language server, with the same
+/// precedence as [`sandbox_command`]
+pub fn resource_limits(workspace: &Path) -> Result<ResourceLimits> {
+    let limits = match Config::load()?.limits {
+        Some(limits) => Some(limits),
+        None => WorkspaceConfig::load(workspace)?.limits,
+    };
+    Ok(limits.unwrap_or_default())
+}
+
+/// The scheduling priority of `workspace`'s daemon and language server, with the
+/// same precedence as [`sandbox_command`]
+pub fn priority_config(workspace: &Path) -> Result<Priority> {
+    let priority = match Config::load()?.priority {
+        Some(priority) => Some(priority),
+        None => WorkspaceConfig::load(workspace)?.priority,
+    };
+    Ok(priority.unwrap_or_default())
+}
+
+/// Prefetch settings for `workspace`'s daemon, with the same precedence as [`sandbox_command`]
+pub fn prefetch_config(workspace: &Path) -> Result<PrefetchConfig> {
+    let prefetch = match Config::load()?.prefetch {
+        Some(prefetch) => Some(prefetch),
+        None => WorkspaceConfig::load(workspace)?.prefetch,
+    };
+    Ok(prefetch.unwrap_or_default())
+}
+
+/// Empty-answer retries for `workspace`'s daemon, with the same precedence as [`sandbox_command`]
+pub fn retry_config(workspace: &Path) -> Result<RetryConfig> {
+    let retry = match Config::load()?.retry_empty {
+        Some(retry) => Some(retry),
+        None => WorkspaceConfig::load(workspace)?.retry_empty,
+    };
+    Ok(retry.unwrap_or_default())
+}
+
+/// Event hooks for `workspace`'s daemon; a hook set in the global config wins
+/// over the same hook in the workspace's `.lq.toml`, while the others still apply
+pub fn hooks_config(workspace: &Path) -> Result<HooksConfig> {
+    let global = Config::load()?.hooks.unwrap_or_default();
+    let workspace = WorkspaceConfig::load(workspace)?.hooks.unwrap_or_default();
+    Ok(global.or(workspace))
+}
+
+/// Query presets for `workspace`; a preset in the global config wins over one
+/// of the same name in the workspace's `.lq.toml`
+pub fn presets(workspace: &Path) -> Result<BTreeMap<String, Preset>> {
+    let mut presets = WorkspaceConfig::load(workspace)?.presets;
+    presets.extend(Config::load()?.presets);
+    Ok(presets)
+}
+
+/// Default flags per command for `workspace`, with the same precedence as [`presets`]
+pub fn default_flags(workspace: &Path) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut defaults = WorkspaceConfig::load(workspace)?.defaults;
+    defaults.extend(Config::load()?.defaults);
+    Ok(defaults)
+}
+
+/// Output formatters per command for `workspace`, with the same precedence as
+/// [`presets`]. They run on every query, so those of the workspace's `.lq.toml`
+/// only count once it is `trusted`.
+pub fn formatters(workspace: &Path, trusted: bool) -> Result<BTreeMap<String, Vec<String>>> {
+    let config = Config::load()?;
+    let mut formatters = if trusted || config.is_trusted(workspace) {
+        WorkspaceConfig::load(workspace)?.formatters
+    } else {
+        BTreeMap::new()
+    };
+    formatters.extend(config.formatters);
+    Ok(formatters)
+}
+
+/// The link template of `workspace`'s results when no `--link-template` is
+/// given, from its `.lq.toml` since it names the workspace's own repository
+pub fn link_template(workspace: &Path) -> Result<Option<String>> {
+    Ok(WorkspaceConfig::load(workspace)?.links.and_then(|links| links.template))
+}
+
+/// The redaction rules for `workspace`'s results: those of the global config
+/// and of its `.lq.toml` together, so neither can lift the other's
+pub fn redact_config(workspace: &Path) -> Result<RedactConfig> {
+    let mut redact = Config::load()?.redact.unwrap_or_default();
+    let local = WorkspaceConfig::load(workspace)?.redact.unwrap_or_default();
+    redact.paths.extend(local.paths);
+    redact.patterns.extend(local.patterns);
+    Ok(redact)
+}
+
+/// The language server settings for `workspace`; `[servers.rust]` in the
+/// global config wins over the one in its `.lq.toml`. Languages lq cannot
+/// serve are an error rather than ignored, so a typo does not go unnoticed.
+pub fn server_config(workspace: &Path) -> Result<ServerConfig> {
+    let mut servers = WorkspaceConfig::load(workspace)?.servers;
+    servers.extend(Config::load()?.servers);
+    served(servers)
+}
+
+/// The Rust server among `servers`, unless one is for a language lq cannot serve
+fn served(mut servers: BTreeMap<String, ServerConfig>) -> Result<ServerConfig> {
+    if let Some(language) = servers.keys().find(|language| !SERVED_LANGUAGES.contains(&language.as_str())) {
+        bail!("[servers.{}] is not supported: lq serves {} only", language, SERVED_LANGUAGES.join(", "));
+    }
+    Ok(servers.remove("rust").unwrap_or_default())
+}
+
+/// Daemon settings for `workspace`; a setting in the global config wins over
+/// the same one in its `.lq.toml`, while the others still apply
+pub fn daemon_config(workspace: &Path) -> Result<DaemonConfig> {
+    let global = Config::load()?.daemon.unwrap_or_default();
+    let workspace = WorkspaceConfig::load(workspace)?.daemon.unwrap_or_default();
+    Ok(global.or(workspace))
+}
+
+/// The monorepo `dir` lies in: the nearest directory at or above it, up to a
+/// repository root, whose `.lq.toml` has a `[monorepo]` section
+pub fn monorepo_root(dir: &Path) -> Option<PathBuf> {
+    for ancestor in dir.ancestors() {
+        if WorkspaceConfig::load(ancestor).is_ok_and(|config| config.monorepo.is_some()) {
+            return Some(ancestor.to_path_buf());
+        }
+        if ancestor.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// The roots of the Cargo workspaces the daemon for `root` serves, sorted: those
+/// its `[monorepo]` section lists, or every one below it when the list is empty;
+/// `root` alone when it has no such section
+pub fn monorepo_members(root: &Path) -> Result<Vec<PathBuf>> {
+    let Some(monorepo) = WorkspaceConfig::load(root)?.monorepo else {
+        return Ok(vec![root.to_path_buf()]);
+    };
+    let mut members: Vec<PathBuf> = if monorepo.members.is_empty() {
+        cargo_manifests(root)
+            .iter()
+            .filter_map(|manifest| manifest.parent())
+            .map(find_workspace_root)
+            .filter(|member| member.starts_with(root))
+            .collect()
+    } else {
+        monorepo.members.iter().map(|member| root.join(member)).collect()
+    };
+    if let Some(member) = members.iter().find(|member| !member.join("Cargo.toml").is_file()) {
+        bail!("[monorepo] member {} has no Cargo.toml", member.display());
+    }
+    members.sort();
+    members.dedup();
+    if members.is_empty() {
+        bail!("[monorepo] in {} finds no Cargo workspace below it", root.display());
+    }
+    Ok(members)
+}
+
+/// Metrics settings for every daemon on this machine, from the global config
+/// alone: a workspace cannot opt its users in
+pub fn metrics_config() -> Result<MetricsConfig> {
+    Ok(Config::load()?.metrics.unwrap_or_default())
+}
+
+impl Config {
+    /// Load the global config; a missing file is an empty config
+    pub fn load() -> Result<Self> {
+        Self::load_from(&config_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).with_context(|| format!("Invalid config file {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read config file {:?}", path)),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&config_path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create config directory")?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write config file {:?}", path))
+    }
+
+    /// Whether `workspace` is, or is inside, a trusted workspace
+    pub fn is_trusted(&self, workspace: &Path) -> bool {
+        let workspace = canonical(workspace);
+        self.trusted_workspaces
+            .iter()
+            .any(|trusted| workspace.starts_with(canonical(&expand_home(trusted))))
+    }
+
+    /// Add `workspace` to the allowlist
+    pub fn trust(&mut self, workspace: &Path) {
+        let workspace = canonical(workspace);
+        if !self.trusted_workspaces.contains(&workspace) {
+            self.trusted_workspaces.push(workspace);
+        }
+    }
+}
+
+/// Where the global config is read from
+pub fn config_path() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os("LQ_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+    let dirs = directories::ProjectDirs::from("", "", "language-query")
+        .context("Failed to find the config directory")?;
+    Ok(dirs.config_dir().join("config.toml"))
+}
+
+/// Fail with instructions unless `workspace` is trusted
+pub fn ensure_trusted(workspace: &Path) -> Result<()> {
+    if Config::load()?.is_trusted(workspace) {
+        return Ok(());
+    }
+    bail!(
+        "Workspace {} is not trusted. Starting a daemon runs rust-analyzer, which executes the \
+         workspace's build scripts and proc macros. Run `lq trust` in the workspace to allow this \
+         permanently, or pass --trust for this query.",
+        workspace.display()
+    )
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Entries may be written by hand as `~/src/project`
+fn expand_home(path: &Path) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(&path.to_string_lossy()).as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trusted_workspaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let trusted = dir.path().join("trusted");
+        let nested = trusted.join("crates/core");
+        let other = dir.path().join("other");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+
+        let path = dir.path().join("config.toml");
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+
+        let mut config = Config::default();
+        config.trust(&trusted);
+        config.trust(&trusted);
+        config.save_to(&path).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.trusted_workspaces.len(), 1);
+        assert!(config.is_trusted(&trusted));
+        assert!(config.is_trusted(&nested));
+        assert!(!config.is_trusted(&other));
+    }
+
+    #[test]
+    fn test_workspace_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(WorkspaceConfig::load(dir.path()).unwrap(), WorkspaceConfig::default());
+
+        std::fs::write(
+            dir.path().join(".lq.toml"),
+            "[sandbox]\ncommand = [\"firejail\", \"--quiet\", \"--net=none\", \"--whitelist={workspace}\"]\n",
+        )
+        .unwrap();
+        let sandbox = WorkspaceConfig::load(dir.path()).unwrap().sandbox.unwrap();
+        assert_eq!(
+            sandbox.command_for(Path::new("/ws")),
+            vec!["firejail", "--quiet", "--net=none", "--whitelist=/ws"]
+        );
+    }
+
+    #[test]
+    fn test_hooks_merge_per_hook() {
+        let global: Config = toml::from_str("[hooks]\non_crash = [\"notify-send\", \"lq crashed\"]\n").unwrap();
+        let workspace: WorkspaceConfig = toml::from_str(
+            "[hooks]\non_ready = [\"./ready.sh\"]\non_crash = [\"./crash.sh\"]\n",
+        )
+        .unwrap();
+        let hooks = global.hooks.unwrap().or(workspace.hooks.unwrap());
+        assert_eq!(hooks.on_ready, Some(vec!["./ready.sh".to_string()]));
+        assert_eq!(hooks.on_crash, Some(vec!["notify-send".to_string(), "lq crashed".to_string()]));
+        assert_eq!(hooks.on_diagnostics_changed, None);
+    }
+
+    #[test]
+    fn test_presets() {
+        let workspace: WorkspaceConfig = toml::from_str(
+            "preset.api-refs = { method = \"refs\", exclude = [\"tests/**\"], format = \"jsonl\" }\n\
+             [preset.errors]\nmethod = \"diag\"\nargs = [\"--severity\", \"error\"]\n",
+        )
+        .unwrap();
+        let api_refs = &workspace.presets["api-refs"];
+        assert_eq!(api_refs.method, "refs");
+        assert_eq!((api_refs.exclude.as_slice(), api_refs.format.as_deref()), (["tests/**".to_string()].as_slice(), Some("jsonl")));
+        assert!(api_refs.args.is_empty());
+        assert_eq!(workspace.presets["errors"].args, ["--severity", "error"]);
+    }
+
+    #[test]
+    fn test_formatters() {
+        let config: Config = toml::from_str("[formatter]\nrefs = [\"jq\", \"-r\", \".references[].path\"]\n").unwrap();
+        assert_eq!(config.formatters["refs"], ["jq", "-r", ".references[].path"]);
+    }
+
+    #[test]
+    fn test_default_flags() {
+        let config: Config = toml::from_str("[defaults]\nrefs = [\"--format\", \"jsonl\"]\ncoverage-map = [\"--check\"]\n").unwrap();
+        assert_eq!(config.defaults["refs"], ["--format", "jsonl"]);
+        assert_eq!(config.defaults["coverage-map"], ["--check"]);
+    }
+
+    #[test]
+    fn test_servers_and_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".lq.toml"),
+            "[servers.rust]\ncommand = \"~/bin/rust-analyzer\"\nargs = [\"--log-file\", \"/tmp/ra.log\"]\n\n\
+             [daemon]\nidle_timeout_minutes = 30\nmax_restarts = 2\n",
+        )
+        .unwrap();
+        let workspace = WorkspaceConfig::load(dir.path()).unwrap();
+        let rust = &workspace.servers["rust"];
+        assert!(rust.executable().unwrap().ends_with("bin/rust-analyzer"));
+        assert!(!rust.executable().unwrap().starts_with("~"));
+        assert_eq!(rust.args, ["--log-file", "/tmp/ra.log"]);
+
+        let global: Config = toml::from_str("[daemon]\nmax_restarts = 8\nlog_level = \"debug\"\n").unwrap();
+        let daemon = global.daemon.unwrap().or(workspace.daemon.unwrap());
+        assert_eq!((daemon.idle_timeout_minutes, daemon.max_restarts, daemon.ready_timeout_secs), (Some(30), Some(8), None));
+        assert_eq!(daemon.log_level.as_deref(), Some("debug"));
+
+        assert_eq!(served(workspace.servers.clone()).unwrap(), workspace.servers["rust"]);
+        let python: Config = toml::from_str("[servers.python]\ncommand = \"pylsp\"\n").unwrap();
+        let error = served(python.servers).unwrap_err().to_string();
+        assert!(error.contains("[servers.python] is not supported"), "{}", error);
+    }
+
+    #[test]
+    fn test_initialization_options() {
+        assert_eq!(ServerConfig::default().initialization_options().unwrap(), None);
+
+        let config: Config = toml::from_str(
+            "[servers.rust]\nfeatures = [\"foo\"]\ntarget = \"wasm32-unknown-unknown\"\nproc_macros = false\ncheck_command = \"clippy\"\n\
+             \n[servers.rust.settings]\ncargo = { target = \"x86_64-unknown-linux-gnu\", extraEnv = { FOO = \"1\" } }\n",
+        )
+        .unwrap();
+        let options = config.servers["rust"].initialization_options().unwrap().unwrap();
+        assert_eq!(
+            options,
+            serde_json::json!({
+                "cargo": { "features": ["foo"], "target": "wasm32-unknown-unknown", "extraEnv": { "FOO": "1" } },
+                "procMacro": { "enable": false },
+                "check": { "command": "clippy" },
+            })
+        );
+
+        let all = ServerConfig { all_features: Some(true), features: Some(vec!["foo".to_string()]), ..Default::default() };
+        assert_eq!(all.initialization_options().unwrap().unwrap(), serde_json::json!({ "cargo": { "features": "all" } }));
+
+        let clash: Config = toml::from_str("[servers.rust]\ntarget = \"wasm32-unknown-unknown\"\nsettings = { cargo = 1 }\n").unwrap();
+        assert!(clash.servers["rust"].initialization_options().is_err());
+    }
+
+    #[test]
+    fn test_monorepo() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path();
+        std::fs::create_dir(root.join(".git")).unwrap();
+        for dir in ["services/api/crates/x/src", "web/src"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::fs::write(root.join("services/api/Cargo.toml"), "[workspace]\nmembers = [\"crates/x\"]\n").unwrap();
+        std::fs::write(root.join("services/api/crates/x/Cargo.toml"), "[package]\nname = \"x\"\n").unwrap();
+        std::fs::write(root.join("web/Cargo.toml"), "[package]\nname = \"web\"\n").unwrap();
+        assert_eq!(monorepo_root(&root.join("web/src")), None);
+        assert_eq!(monorepo_members(root).unwrap(), [root]);
+
+        std::fs::write(root.join(".lq.toml"), "[monorepo]\n").unwrap();
+        assert_eq!(monorepo_root(&root.join("web/src")).as_deref(), Some(root));
+        assert_eq!(monorepo_members(root).unwrap(), [root.join("services/api"), root.join("web")]);
+
+        std::fs::write(root.join(".lq.toml"), "[monorepo]\nmembers = [\"web\"]\n").unwrap();
+        assert_eq!(monorepo_members(root).unwrap(), [root.join("web")]);
+        std::fs::write(root.join(".lq.toml"), "[monorepo]\nmembers = [\"docs\"]\n").unwrap();
+        assert!(monorepo_members(root).unwrap_err().to_string().contains("has no Cargo.toml"));
+    }
+}
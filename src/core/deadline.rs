@@ -0,0 +1,56 @@
+//! Deadlines for best-effort requests.
+//!
+//! A request sent with `--best-effort` must be answered within a time budget.
+//! Single queries give up when the deadline passes; reports that issue many
+//! queries stop and return what they collected so far.
+
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// The point in time by which a request should be answered, if any
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Wait as long as it takes for a complete answer
+    pub fn none() -> Self {
+        Self(None)
+    }
+
+    pub fn after(duration: Duration) -> Self {
+        Self(Some(Instant::now() + duration))
+    }
+
+    pub fn expired(&self) -> bool {
+        self.0.is_some_and(|at| Instant::now() >= at)
+    }
+
+    /// Run `future` to completion, or give up with `None` once the deadline has passed
+    pub async fn within<F: Future>(&self, future: F) -> Option<F::Output> {
+        match self.0 {
+            // Don't start work that can no longer finish in time
+            Some(_) if self.expired() => None,
+            Some(at) => tokio::time::timeout_at(at, future).await.ok(),
+            None => Some(future.await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_within() {
+        let slow = tokio::time::sleep(Duration::from_secs(5));
+        assert_eq!(Deadline::after(Duration::from_millis(10)).within(slow).await, None);
+        assert_eq!(Deadline::after(Duration::from_secs(5)).within(async { 1 }).await, Some(1));
+        assert_eq!(Deadline::none().within(async { 2 }).await, Some(2));
+
+        let expired = Deadline::after(Duration::ZERO);
+        assert!(expired.expired());
+        assert_eq!(expired.within(async { 3 }).await, None);
+    }
+}
@@ -1,3 +1,9 @@
+pub mod deadline;
+pub mod reports;
 pub mod service;
+pub mod symbol_path;
+pub mod text_search;
+pub mod workspace;
 
-pub use service::LanguageQueryService;
\ No newline at end of file
+pub use deadline::Deadline;
+pub use service::LanguageQueryService;
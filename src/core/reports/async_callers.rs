@@ -0,0 +1,252 @@
+//! Where async and sync code meet along the call chains into a function.
+//!
+//! The incoming call hierarchy is walked a few levels up and split into
+//! chains, from the direct caller outwards. Each call is classified by whether
+//! the caller and the callee are declared `async`, and, where async code is
+//! called from a sync function, by the call site's text: `block_on` runs it to
+//! completion, `spawn` hands it to an executor, and `.await` means the call sits
+//! in an async block or closure. Like the other reports this reads source text,
+//! so a call spread over several lines may be classified by its first line only.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reports::scan::{function_declarations, FnDecl};
+use crate::core::{Deadline, LanguageQueryService};
+use crate::lsp::{Call, CallDirection, CallSite};
+
+/// The call chains into a function, and where they cross between async and sync code
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AsyncCallers {
+    /// The function asked about
+    pub function: String,
+    /// Whether it is declared `async`
+    pub is_async: bool,
+    /// Every chain of callers up to the requested depth, those with a crossing first
+    pub chains: Vec<CallChain>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallChain {
+    /// From the direct caller outwards
+    pub callers: Vec<ChainCaller>,
+    /// The outermost caller is not async, so the chain is entered from sync code
+    pub sync_entry: bool,
+}
+
+impl CallChain {
+    /// Whether a call along the chain crosses between async and sync code
+    pub fn crosses(&self) -> bool {
+        self.callers.iter().any(|caller| caller.crossing.is_some())
+    }
+}
+
+/// A function in a call chain
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChainCaller {
+    pub name: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the function name
+    pub line: u32,
+    pub is_async: bool,
+    /// Where it calls the function before it in the chain
+    pub site: Option<CallSite>,
+    /// How that call crosses between async and sync code, if it does
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crossing: Option<Crossing>,
+}
+
+/// A call between async and sync code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Crossing {
+    /// Async code run to completion from a sync function with `block_on`
+    BlockOn,
+    /// Async code handed to an executor from a sync function with `spawn`
+    Spawn,
+    /// Async code awaited in an async block or closure of a sync function
+    AsyncBlock,
+    /// Async code called from a sync function without awaiting it, so its future is returned or stored
+    Unawaited,
+    /// Sync code called from an async function, blocking the executor thread while it runs
+    SyncInAsync,
+}
+
+impl Crossing {
+    fn label(self) -> &'static str {
+        match self {
+            Crossing::BlockOn => "block_on",
+            Crossing::Spawn => "spawn",
+            Crossing::AsyncBlock => "async block",
+            Crossing::Unawaited => "unawaited",
+            Crossing::SyncInAsync => "sync in async",
+        }
+    }
+}
+
+impl LanguageQueryService {
+    /// The chains of callers of the function named by `symbol`, up to `depth` levels,
+    /// and where they cross between async and sync code
+    #[allow(clippy::too_many_arguments)]
+    pub async fn async_callers(
+        &self,
+        file: &Path,
+        line: u32,
+        column: Option<u32>,
+        symbol: &str,
+        depth: usize,
+        deadline: Deadline,
+    ) -> Result<AsyncCallers> {
+        let mut declarations = Declarations::default();
+        let is_async = match self.get_definition(file, line, column, symbol).await?.first() {
+            Some(definition) => declarations.is_async(&definition.file, definition.start_line, symbol).await,
+            None => false,
+        };
+        let calls = self
+            .call_hierarchy(file, line, column, symbol, CallDirection::Incoming, depth, deadline)
+            .await?;
+
+        let mut paths = Vec::new();
+        call_paths(&calls, &mut Vec::new(), &mut paths);
+        let mut chains = Vec::new();
+        for path in paths {
+            let mut callers = Vec::new();
+            let mut callee_async = is_async;
+            for call in path {
+                let caller_async = declarations.is_async(&call.file, call.line, &call.name).await;
+                let site = call.sites.first().cloned();
+                let crossing = crossing(callee_async, caller_async, site.as_ref().map_or("", |site| &site.text));
+                callers.push(ChainCaller {
+                    name: call.name.clone(),
+                    path: call.path.clone(),
+                    line: call.line,
+                    is_async: caller_async,
+                    site,
+                    crossing,
+                });
+                callee_async = caller_async;
+            }
+            let sync_entry = callers.last().is_some_and(|caller| !caller.is_async);
+            chains.push(CallChain { callers, sync_entry });
+        }
+        chains.sort_by_key(|chain| !chain.crosses());
+
+        Ok(AsyncCallers { function: symbol.to_string(), is_async, chains })
+    }
+}
+
+/// Every path from a direct caller to an outermost one in the hierarchy `calls`
+fn call_paths<'a>(calls: &'a [Call], current: &mut Vec<&'a Call>, paths: &mut Vec<Vec<&'a Call>>) {
+    for call in calls {
+        current.push(call);
+        if call.calls.is_empty() {
+            paths.push(current.clone());
+        } else {
+            call_paths(&call.calls, current, paths);
+        }
+        current.pop();
+    }
+}
+
+/// How a call from a function declared `caller_async` to one declared `callee_async`
+/// on the source line `site` crosses between async and sync code
+fn crossing(callee_async: bool, caller_async: bool, site: &str) -> Option<Crossing> {
+    match (callee_async, caller_async) {
+        (true, false) if site.contains("block_on") => Some(Crossing::BlockOn),
+        (true, false) if site.contains("spawn") => Some(Crossing::Spawn),
+        (true, false) if site.contains(".await") => Some(Crossing::AsyncBlock),
+        (true, false) => Some(Crossing::Unawaited),
+        // Moved off the executor already
+        (false, true) if site.contains("spawn_blocking") => None,
+        (false, true) => Some(Crossing::SyncInAsync),
+        _ => None,
+    }
+}
+
+/// The function declarations of the files read so far
+#[derive(Default)]
+struct Declarations(HashMap<PathBuf, Vec<FnDecl>>);
+
+impl Declarations {
+    /// Whether the function `name` declared on `line` of `file` is `async`
+    async fn is_async(&mut self, file: &Path, line: u32, name: &str) -> bool {
+        if !self.0.contains_key(file) {
+            let contents = tokio::fs::read_to_string(file).await.unwrap_or_default();
+            self.0.insert(file.to_path_buf(), function_declarations(&contents));
+        }
+        self.0[file].iter().any(|decl| decl.is_async && decl.line == line && decl.name == name)
+    }
+}
+
+impl fmt::Display for AsyncCallers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let crossing = self.chains.iter().filter(|chain| chain.crosses()).count();
+        writeln!(
+            f,
+            "{}{}: {} chain(s) of callers, {} crossing between async and sync code",
+            if self.is_async { "async " } else { "" },
+            self.function,
+            self.chains.len(),
+            crossing
+        )?;
+        for (index, chain) in self.chains.iter().enumerate() {
+            let entry = if chain.sync_entry { "entered from sync code" } else { "entered from async code" };
+            writeln!(f, "\nChain {} ({}):", index + 1, entry)?;
+            for caller in &chain.callers {
+                let kind = if caller.is_async { "async" } else { "sync" };
+                write!(f, "  {} ({}:{}) {}", caller.name, caller.path.display(), caller.line, kind)?;
+                if let Some(crossing) = caller.crossing {
+                    write!(f, " [{}]", crossing.label())?;
+                }
+                writeln!(f)?;
+                if let Some(site) = &caller.site {
+                    writeln!(f, "    > {}:{}: {}", site.path.display(), site.line, site.text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossing() {
+        assert_eq!(crossing(true, true, "load().await"), None);
+        assert_eq!(crossing(true, false, "rt.block_on(load())"), Some(Crossing::BlockOn));
+        assert_eq!(crossing(true, false, "tokio::spawn(load());"), Some(Crossing::Spawn));
+        assert_eq!(crossing(true, false, "let x = load().await;"), Some(Crossing::AsyncBlock));
+        assert_eq!(crossing(true, false, "futures.push(load());"), Some(Crossing::Unawaited));
+        assert_eq!(crossing(false, true, "parse(&body)"), Some(Crossing::SyncInAsync));
+        assert_eq!(crossing(false, true, "spawn_blocking(move || parse(&body))"), None);
+        assert_eq!(crossing(false, false, "parse(&body)"), None);
+    }
+
+    #[test]
+    fn test_call_paths() {
+        let call = |name: &str, calls: Vec<Call>| Call {
+            name: name.to_string(),
+            kind: "Function".to_string(),
+            detail: None,
+            file: PathBuf::from("/ws/src/lib.rs"),
+            path: PathBuf::from("src/lib.rs"),
+            line: 1,
+            column: 1,
+            sites: Vec::new(),
+            calls,
+        };
+        let hierarchy = vec![call("load", vec![call("main", Vec::new()), call("serve", Vec::new())]), call("test", Vec::new())];
+        let mut paths = Vec::new();
+        call_paths(&hierarchy, &mut Vec::new(), &mut paths);
+        let names: Vec<Vec<&str>> = paths.iter().map(|path| path.iter().map(|call| call.name.as_str()).collect()).collect();
+        assert_eq!(names, vec![vec!["load", "main"], vec!["load", "serve"], vec!["test"]]);
+    }
+}
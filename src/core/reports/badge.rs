@@ -0,0 +1,101 @@
+//! Usage badges: how often an item is referenced, as the JSON that shields.io's
+//! endpoint badge reads.
+//!
+//! A library author serves the JSON from any URL, e.g. a file committed by CI,
+//! and points `https://img.shields.io/endpoint?url=...` at it. The count comes
+//! from a plain `refs` answer, so a badge needs no request of its own, and
+//! `--exclude` keeps tests or examples out of it like any other answer.
+
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::lsp::Reference;
+
+/// The version of the endpoint schema shields.io expects
+const SCHEMA_VERSION: u32 = 1;
+
+/// A shields.io endpoint badge
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Badge {
+    /// Always 1
+    pub schema_version: u32,
+    /// Text on the left of the badge
+    pub label: String,
+    /// Text on the right of the badge: the count, shortened above a thousand
+    pub message: String,
+    /// Named shields.io color, brighter for more references
+    pub color: String,
+}
+
+impl Badge {
+    /// A badge counting `references`, each use on a line counted once
+    pub fn references(label: &str, references: &[Reference]) -> Self {
+        let count = references.iter().map(|reference| reference.occurrences).sum();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            label: label.to_string(),
+            message: compact(count),
+            color: color(count).to_string(),
+        }
+    }
+}
+
+/// `count` as badges usually show it: 950, 1.2k, 12k, 3.4M
+fn compact(count: usize) -> String {
+    let scaled = |unit: f64, suffix: &str| {
+        let value = count as f64 / unit;
+        if value < 10.0 {
+            format!("{:.1}{}", (value * 10.0).floor() / 10.0, suffix).replace(".0", "")
+        } else {
+            format!("{}{}", value.floor(), suffix)
+        }
+    };
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=999_999 => scaled(1e3, "k"),
+        _ => scaled(1e6, "M"),
+    }
+}
+
+fn color(count: usize) -> &'static str {
+    match count {
+        0 => "lightgrey",
+        1..=9 => "yellow",
+        10..=99 => "green",
+        _ => "brightgreen",
+    }
+}
+
+impl fmt::Display for Badge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {}", self.label, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact() {
+        assert_eq!(compact(0), "0");
+        assert_eq!(compact(999), "999");
+        assert_eq!(compact(1_000), "1k");
+        assert_eq!(compact(1_299), "1.2k");
+        assert_eq!(compact(12_500), "12k");
+        assert_eq!(compact(999_999), "999k");
+        assert_eq!(compact(3_450_000), "3.4M");
+    }
+
+    #[test]
+    fn test_endpoint_payload() {
+        let badge = Badge::references("used by", &[]);
+        assert_eq!(
+            serde_json::to_value(&badge).unwrap(),
+            serde_json::json!({ "schemaVersion": 1, "label": "used by", "message": "0", "color": "lightgrey" })
+        );
+    }
+}
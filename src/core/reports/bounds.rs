@@ -0,0 +1,443 @@
+//! The trait bounds a generic call has to satisfy, and what satisfies them.
+//!
+//! Hovering the called function gives its signature, with the types rust-analyzer
+//! inferred for the generic parameters at that call when it shows them. The
+//! bounds are read from the signature's generic parameters, its `where` clause
+//! and its `impl Trait` arguments. Each bound's trait is then found from the
+//! function's declaration, and goto-implementation lists the types implementing
+//! it. A bound counts as satisfied when an impl names the inferred type, and as
+//! unsatisfied when every impl names another; blanket impls, impls a macro
+//! generates and auto traits without impls leave it undecided.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reports::scan::type_declarations;
+use crate::core::{Deadline, LanguageQueryService};
+use crate::lsp::position::symbol_columns;
+use crate::lsp::type_hierarchy::{last_segment, parse_impl_header};
+use crate::lsp::Definition;
+
+/// Upper bound on the impls listed per bound; the count covers them all
+const MAX_LISTED_IMPLS: usize = 20;
+
+/// The trait bounds of the function called at a position
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallBounds {
+    /// The function called
+    pub function: String,
+    /// Its signature as the server shows it, on one line
+    pub signature: String,
+    pub bounds: Vec<Bound>,
+}
+
+/// A trait bound on one generic parameter or `impl Trait` argument
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Bound {
+    /// The bounded parameter: a type parameter, or the name of an `impl Trait` argument
+    pub param: String,
+    /// The trait, as written in the signature
+    #[serde(rename = "trait")]
+    pub trait_name: String,
+    /// The type inferred for the parameter at this call, when the server shows it
+    pub type_argument: Option<String>,
+    /// Where the trait is declared, relative to the workspace root when inside it
+    pub trait_path: Option<PathBuf>,
+    /// 1-based line of the trait declaration
+    pub trait_line: Option<u32>,
+    /// Whether an impl names the inferred type; `None` when that cannot be told
+    pub satisfied: Option<bool>,
+    /// The impl for the inferred type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub satisfied_by: Option<BoundImpl>,
+    /// Impls of the trait found, of which `impls` lists the first few
+    pub impl_count: usize,
+    pub impls: Vec<BoundImpl>,
+}
+
+/// An impl of a bound's trait
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BoundImpl {
+    /// The implementing type, when the impl header or derive names it
+    pub self_type: Option<String>,
+    /// Path relative to the workspace root when inside it
+    pub path: PathBuf,
+    /// 1-based line
+    pub line: u32,
+    /// The trimmed source line
+    pub text: String,
+    /// Implements the trait for type parameters, so it may cover any type
+    pub blanket: bool,
+}
+
+impl LanguageQueryService {
+    /// The trait bounds of the function called at 1-based `line` and `column`,
+    /// with the traits' declarations and the impls that satisfy them
+    pub async fn call_bounds(&self, file: &Path, line: u32, column: u32, deadline: Deadline) -> Result<CallBounds> {
+        let contents = tokio::fs::read_to_string(file)
+            .await
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let text = contents.lines().nth(line.saturating_sub(1) as usize).unwrap_or_default();
+        let function = identifier_at(text, column).with_context(|| format!("No identifier at {}:{}", line, column))?;
+
+        let hover = self
+            .hover_at(file, line, column)
+            .await?
+            .with_context(|| format!("The server knows nothing about '{}'", function))?;
+        let signature = hover_signature(&hover).with_context(|| format!("'{}' is not a function", function))?;
+        let definition = self.get_definition(file, line, Some(column), &function).await?.into_iter().next();
+
+        let mut bounds = Vec::new();
+        for (param, trait_name) in signature_bounds(&signature) {
+            let type_argument = substitution(&hover, &param);
+            let mut bound = Bound {
+                param,
+                trait_name,
+                type_argument,
+                trait_path: None,
+                trait_line: None,
+                satisfied: None,
+                satisfied_by: None,
+                impl_count: 0,
+                impls: Vec::new(),
+            };
+            let expired = match &definition {
+                Some(definition) => deadline.within(self.resolve_bound(definition, &mut bound)).await.is_none(),
+                None => false,
+            };
+            bounds.push(bound);
+            if expired {
+                break;
+            }
+        }
+
+        Ok(CallBounds { function, signature: one_line(&signature), bounds })
+    }
+
+    /// Find the declaration and impls of `bound`'s trait, named in the function's `definition`
+    async fn resolve_bound(&self, definition: &Definition, bound: &mut Bound) {
+        let name = trait_ident(&bound.trait_name);
+        let Some(offset) = definition.code.lines().position(|line| !symbol_columns(line, name).is_empty()) else {
+            return;
+        };
+        let line = definition.code_start_line + offset as u32;
+        let Some(declaration) = self
+            .get_definition(&definition.file, line, None, name)
+            .await
+            .ok()
+            .and_then(|found| found.into_iter().next())
+        else {
+            return;
+        };
+        bound.trait_path = Some(declaration.path.clone());
+        bound.trait_line = Some(declaration.start_line);
+
+        let implementations = self
+            .get_impls(&declaration.file, declaration.start_line, None, name)
+            .await
+            .unwrap_or_default();
+        let impls: Vec<BoundImpl> = implementations.iter().map(|implementation| bound_impl(implementation, name)).collect();
+        bound.impl_count = impls.len();
+        if let Some(type_argument) = &bound.type_argument {
+            let wanted = type_ident(type_argument);
+            bound.satisfied_by = impls
+                .iter()
+                .find(|found| found.self_type.as_deref().is_some_and(|self_type| type_ident(self_type) == wanted))
+                .cloned();
+            // A blanket impl, or one whose type cannot be read, may cover the type
+            let undecided = impls.is_empty() || impls.iter().any(|found| found.blanket || found.self_type.is_none());
+            bound.satisfied = match (&bound.satisfied_by, undecided) {
+                (Some(_), _) => Some(true),
+                (None, true) => None,
+                (None, false) => Some(false),
+            };
+        }
+        bound.impls = impls.into_iter().take(MAX_LISTED_IMPLS).collect();
+    }
+}
+
+/// An impl found by goto-implementation: an `impl` block, or a type deriving the trait `name`
+fn bound_impl(implementation: &Definition, name: &str) -> BoundImpl {
+    let offset = implementation.start_line.saturating_sub(implementation.code_start_line) as usize;
+    let text = implementation.code.lines().nth(offset).unwrap_or_default().trim().to_string();
+    let header = implementation.code.lines().map(str::trim).find(|line| line.starts_with("impl") || line.starts_with("unsafe impl"));
+    let (self_type, blanket) = match header.and_then(|header| parse_impl_header(header).map(|parsed| (header, parsed))) {
+        Some((header, parsed)) => {
+            let blanket = impl_params(header).iter().any(|param| *param == parsed.self_type.trim_start_matches('&').trim());
+            (Some(parsed.self_type), blanket)
+        }
+        None => {
+            let derived = implementation
+                .code
+                .lines()
+                .any(|line| line.trim_start().starts_with("#[derive(") && !symbol_columns(line, name).is_empty());
+            let declared = type_declarations(&implementation.code).into_iter().next().map(|decl| decl.name);
+            (declared.filter(|_| derived), false)
+        }
+    };
+    BoundImpl { self_type, path: implementation.path.clone(), line: implementation.start_line, text, blanket }
+}
+
+/// The identifier covering 1-based UTF-16 `column` of `line`
+fn identifier_at(line: &str, column: u32) -> Option<String> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut units = 0;
+    let cursor = line.char_indices().find_map(|(index, c)| {
+        units += c.len_utf16() as u32;
+        (units >= column).then_some(index)
+    })?;
+    if !line[cursor..].starts_with(is_ident) {
+        return None;
+    }
+    let start = line[..cursor].rfind(|c: char| !is_ident(c)).map_or(0, |index| index + 1);
+    let end = line[cursor..].find(|c: char| !is_ident(c)).map_or(line.len(), |index| cursor + index);
+    Some(line[start..end].to_string())
+}
+
+/// The function signature in hover text: the code block declaring a `fn`
+fn hover_signature(hover: &str) -> Option<String> {
+    code_blocks(hover)
+        .into_iter()
+        .find(|block| block.contains("fn "))
+        .map(|block| block.trim().to_string())
+}
+
+/// The type the server inferred for generic parameter `param` at the call, from
+/// a `T = u8` line of the hover text
+fn substitution(hover: &str, param: &str) -> Option<String> {
+    code_blocks(hover).into_iter().flat_map(|block| block.lines()).find_map(|line| {
+        let (name, ty) = line.trim().split_once(" = ")?;
+        (name == param).then(|| ty.trim_end_matches(',').trim().to_string())
+    })
+}
+
+/// The contents of the fenced code blocks in markdown
+fn code_blocks(markdown: &str) -> Vec<&str> {
+    markdown
+        .split("```")
+        .skip(1)
+        .step_by(2)
+        .map(|block| block.split_once('\n').map_or("", |(_, code)| code))
+        .collect()
+}
+
+/// The `(parameter, trait)` pairs a function signature bounds, in the order written
+fn signature_bounds(signature: &str) -> Vec<(String, String)> {
+    let mut bounds = Vec::new();
+    let mut add = |param: &str, traits: &str| {
+        for bound in split_top_level(traits, '+') {
+            // Lifetimes and `?Sized` relax rather than require
+            if !bound.is_empty() && !bound.starts_with('\'') && !bound.starts_with('?') {
+                bounds.push((param.trim().to_string(), bound.to_string()));
+            }
+        }
+    };
+
+    let Some(start) = signature.find("fn ") else {
+        return bounds;
+    };
+    let rest = &signature[start + 3..];
+    let name_end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+    let mut rest = &rest[name_end..];
+    if rest.starts_with('<') {
+        let Some(close) = closing(rest, 0) else {
+            return bounds;
+        };
+        for param in split_top_level(&rest[1..close], ',') {
+            if param.starts_with("const ") {
+                continue;
+            }
+            if let Some((param, traits)) = param.split_once(':') {
+                add(param, traits);
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    if let Some(open) = rest.find('(') {
+        let Some(close) = closing(rest, open) else {
+            return bounds;
+        };
+        for param in split_top_level(&rest[open + 1..close], ',') {
+            if let Some((name, ty)) = param.split_once(':') {
+                if let Some(traits) = ty.trim().strip_prefix("impl ") {
+                    add(name.trim_start_matches("mut "), traits);
+                }
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    if let Some(index) = rest.find("where") {
+        for predicate in split_top_level(&rest[index + "where".len()..], ',') {
+            if let Some((param, traits)) = split_top_level(predicate, ':').split_first().map(|(param, traits)| (*param, traits.join(":"))) {
+                if !traits.is_empty() {
+                    add(param, &traits);
+                }
+            }
+        }
+    }
+    bounds
+}
+
+/// The names of the generic parameters of an `impl<...>` header
+fn impl_params(header: &str) -> Vec<&str> {
+    let Some(rest) = header.trim_start_matches("unsafe ").strip_prefix("impl") else {
+        return Vec::new();
+    };
+    if !rest.starts_with('<') {
+        return Vec::new();
+    }
+    let Some(close) = closing(rest, 0) else {
+        return Vec::new();
+    };
+    split_top_level(&rest[1..close], ',')
+        .into_iter()
+        .map(|param| param.split(':').next().unwrap_or(param).trim())
+        .filter(|param| !param.starts_with('\'') && !param.starts_with("const "))
+        .collect()
+}
+
+/// The index of the bracket closing the one at `open`
+fn closing(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut previous = ' ';
+    for (index, c) in text.char_indices().skip_while(|(index, _)| *index < open) {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            // The arrow of `Fn() -> T` closes nothing
+            '>' if previous == '-' => {}
+            ')' | ']' | '>' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+/// Split at every `separator` outside brackets, trimming the parts
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut previous = ' ';
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            '>' if previous == '-' => {}
+            ')' | ']' | '>' => depth = depth.saturating_sub(1),
+            // `::` in a path is no separator
+            ':' if previous == ':' || text[index + 1..].starts_with(':') => {}
+            c if c == separator && depth == 0 => {
+                parts.push(text[start..index].trim());
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    parts.push(text[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// The trait's name as declared: `std::convert::Into<String>` becomes `Into`, `FnMut(u8)` becomes `FnMut`
+fn trait_ident(bound: &str) -> &str {
+    let name = last_segment(bound);
+    &name[..name.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(name.len())]
+}
+
+/// A type's name for comparing it with an impl's self type: `&mut Vec<u8>` becomes `Vec`
+fn type_ident(ty: &str) -> &str {
+    let ty = ty.trim().trim_start_matches('&').trim_start();
+    trait_ident(ty.strip_prefix("mut ").unwrap_or(ty))
+}
+
+/// `text` with its line breaks and indentation folded into single spaces
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for CallBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.signature)?;
+        if self.bounds.is_empty() {
+            return writeln!(f, "\n{} has no trait bounds", self.function);
+        }
+        for bound in &self.bounds {
+            write!(f, "\n{}: {}", bound.param, bound.trait_name)?;
+            if let Some(type_argument) = &bound.type_argument {
+                write!(f, " with {} = {}", bound.param, type_argument)?;
+            }
+            match bound.satisfied {
+                Some(true) => writeln!(f, " [satisfied]")?,
+                Some(false) => writeln!(f, " [NOT satisfied]")?,
+                None => writeln!(f)?,
+            }
+            if let (Some(path), Some(line)) = (&bound.trait_path, bound.trait_line) {
+                writeln!(f, "  trait declared at {}:{}", path.display(), line)?;
+            }
+            if let Some(found) = &bound.satisfied_by {
+                writeln!(f, "  satisfied by {}:{}: {}", found.path.display(), found.line, found.text)?;
+                continue;
+            }
+            if bound.impl_count > 0 {
+                writeln!(f, "  implemented by ({} of {}):", bound.impls.len(), bound.impl_count)?;
+                for found in &bound.impls {
+                    writeln!(f, "    {}:{}: {}", found.path.display(), found.line, found.text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_bounds() {
+        let signature = "pub fn render<T: Display + Clone, F, 'a>(item: &'a T, mut out: impl io::Write, f: F) -> String\nwhere\n    F: Fn(&T) -> String + Send,\n    Vec<T>: Debug,";
+        let bounds = signature_bounds(signature);
+        let bounds: Vec<(&str, &str)> = bounds.iter().map(|(param, bound)| (param.as_str(), bound.as_str())).collect();
+        assert_eq!(
+            bounds,
+            vec![
+                ("T", "Display"),
+                ("T", "Clone"),
+                ("out", "io::Write"),
+                ("F", "Fn(&T) -> String"),
+                ("F", "Send"),
+                ("Vec<T>", "Debug"),
+            ]
+        );
+        assert!(signature_bounds("fn plain<T: ?Sized + 'static>(x: &T)").is_empty());
+    }
+
+    #[test]
+    fn test_hover_parts() {
+        let hover = "```rust\nmy_crate::view\n```\n\n```rust\npub fn render<T>(item: T)\nwhere\n    T: Display,\n```\n\n```rust\nT = Point\n```\n\n---\n\nRenders an item.";
+        assert_eq!(hover_signature(hover).as_deref(), Some("pub fn render<T>(item: T)\nwhere\n    T: Display,"));
+        assert_eq!(substitution(hover, "T").as_deref(), Some("Point"));
+        assert_eq!(substitution(hover, "U"), None);
+        assert_eq!(hover_signature("```rust\nstruct Point\n```"), None);
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(identifier_at("    let s = render(&p);", 14).as_deref(), Some("render"));
+        assert_eq!(identifier_at("    let s = render(&p);", 3), None);
+        assert_eq!(trait_ident("std::convert::Into<String>"), "Into");
+        assert_eq!(trait_ident("FnMut(u8) -> u8"), "FnMut");
+        assert_eq!(type_ident("&mut Vec<u8>"), "Vec");
+        assert_eq!(impl_params("impl<T: Display, 'a> Render for T {"), vec!["T"]);
+    }
+}
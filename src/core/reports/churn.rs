@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reports::ranking::DEFAULT_CONCURRENCY;
+use crate::core::{Deadline, LanguageQueryService};
+
+/// How to order a churn report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ChurnSort {
+    /// Commits × references
+    #[default]
+    Risk,
+    /// Commits touching the declaring file
+    Churn,
+    /// References to the item
+    Refs,
+}
+
+/// An item with its file's commit count and its reference count
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChurnEntry {
+    pub name: String,
+    pub kind: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the declaration
+    pub line: u32,
+    /// Commits that touched the declaring file
+    pub commits: usize,
+    pub references: usize,
+    /// `commits × references`
+    pub risk: usize,
+}
+
+impl LanguageQueryService {
+    /// Flag items that are both frequently changed and widely referenced.
+    ///
+    /// `since` is passed to `git log --since` (e.g. `"6 months ago"`).
+    pub async fn churn_report(&self, top: usize, since: Option<&str>, sort: ChurnSort) -> Result<Vec<ChurnEntry>> {
+        let churn = file_churn(self.workspace_path(), since).await?;
+
+        let mut entries: Vec<ChurnEntry> = self
+            .hotspots(usize::MAX, None, DEFAULT_CONCURRENCY, Deadline::none())
+            .await?
+            .into_iter()
+            .map(|symbol| {
+                let commits = churn.get(&symbol.path).copied().unwrap_or(0);
+                ChurnEntry {
+                    risk: commits * symbol.references,
+                    commits,
+                    references: symbol.references,
+                    name: symbol.name,
+                    kind: symbol.kind,
+                    path: symbol.path,
+                    line: symbol.line,
+                }
+            })
+            .collect();
+
+        entries.sort_by(|a, b| {
+            let key = |e: &ChurnEntry| match sort {
+                ChurnSort::Risk => (e.risk, e.commits, e.references),
+                ChurnSort::Churn => (e.commits, e.risk, e.references),
+                ChurnSort::Refs => (e.references, e.risk, e.commits),
+            };
+            key(b)
+                .cmp(&key(a))
+                .then_with(|| a.path.cmp(&b.path))
+                .then_with(|| a.line.cmp(&b.line))
+        });
+        entries.truncate(top);
+
+        Ok(entries)
+    }
+}
+
+/// Count commits per file (relative to the workspace) using `git log`
+async fn file_churn(workspace: &Path, since: Option<&str>) -> Result<HashMap<PathBuf, usize>> {
+    let mut command = tokio::process::Command::new("git");
+    command
+        .arg("-C")
+        .arg(workspace)
+        .args(["log", "--relative", "--name-only", "--format="]);
+    if let Some(since) = since {
+        command.arg(format!("--since={}", since));
+    }
+
+    let output = command.output().await.context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git log failed (is the workspace a git repository?): {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(count_file_commits(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Count occurrences of each path in `git log --name-only --format=` output
+fn count_file_commits(log: &str) -> HashMap<PathBuf, usize> {
+    let mut counts = HashMap::new();
+    for line in log.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        *counts.entry(PathBuf::from(line)).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_file_commits() {
+        let log = "src/lib.rs\nsrc/main.rs\n\nsrc/lib.rs\n\nREADME.md\n";
+        let counts = count_file_commits(log);
+        assert_eq!(counts[Path::new("src/lib.rs")], 2);
+        assert_eq!(counts[Path::new("src/main.rs")], 1);
+        assert_eq!(counts[Path::new("README.md")], 1);
+    }
+}
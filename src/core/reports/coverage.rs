@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::core::reports::scan::{function_declarations, test_regions, TestRegion};
+use crate::core::workspace::rust_source_files;
+use crate::core::{Deadline, LanguageQueryService};
+
+/// A public function and the tests that reference it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FunctionCoverage {
+    pub name: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the declaration
+    pub line: u32,
+    pub tests: Vec<TestReference>,
+}
+
+/// A test that references a function
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TestReference {
+    /// Test function name, or `None` when the reference is in test code outside a test function
+    pub name: Option<String>,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the reference
+    pub line: u32,
+}
+
+impl LanguageQueryService {
+    /// Map each public, non-test function to the tests that reference it.
+    ///
+    /// A reference counts as coming from a test when it is inside a `#[cfg(test)]`
+    /// item, a `#[test]` function, or a file under the workspace's `tests/` directory.
+    /// Functions not reached before `deadline` are left out.
+    pub async fn coverage_map(&self, deadline: Deadline) -> Result<Vec<FunctionCoverage>> {
+        let mut report = Vec::new();
+
+        'files: for file in rust_source_files(self.workspace_path()) {
+            let Ok(contents) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+            let own_test_regions = test_regions(&contents);
+            let path = self.relative_path(&file);
+            if is_integration_test(&path) {
+                continue;
+            }
+
+            let public_fns = function_declarations(&contents)
+                .into_iter()
+                .filter(|f| f.is_pub && !own_test_regions.iter().any(|r| r.contains(f.line)));
+
+            for decl in public_fns {
+                let Some(references) = deadline.within(self.get_refs(&file, decl.line, None, &decl.name)).await else {
+                    break 'files;
+                };
+                let references = match references {
+                    Ok(references) => references,
+                    Err(e) => {
+                        debug!("Skipping {} at {:?}:{}: {}", decl.name, file, decl.line, e);
+                        continue;
+                    }
+                };
+
+                let mut tests: Vec<TestReference> = Vec::new();
+                for reference in references {
+                    let regions = match tokio::fs::read_to_string(&reference.file).await {
+                        Ok(contents) => test_regions(&contents),
+                        Err(_) => continue,
+                    };
+                    if let Some(test) = classify_reference(&reference.path, reference.line, &regions) {
+                        if !tests.iter().any(|t| t.path == test.path && t.name == test.name) {
+                            tests.push(test);
+                        }
+                    }
+                }
+
+                report.push(FunctionCoverage {
+                    name: decl.name,
+                    path: path.clone(),
+                    line: decl.line,
+                    tests,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Whether a workspace-relative path is an integration test file
+fn is_integration_test(path: &std::path::Path) -> bool {
+    path.components().any(|c| c.as_os_str() == "tests")
+}
+
+/// Decide if a reference lives in test code, and which test it belongs to
+fn classify_reference(path: &std::path::Path, line: u32, regions: &[TestRegion]) -> Option<TestReference> {
+    // Prefer the innermost named test function over an enclosing test module
+    let named = regions.iter().find(|r| r.name.is_some() && r.contains(line));
+    let in_region = named.or_else(|| regions.iter().find(|r| r.contains(line)));
+
+    if in_region.is_none() && !is_integration_test(path) {
+        return None;
+    }
+
+    Some(TestReference {
+        name: in_region.and_then(|r| r.name.clone()),
+        path: path.to_path_buf(),
+        line,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_classify_reference() {
+        let regions = vec![
+            TestRegion { name: None, start_line: 10, end_line: 30 },
+            TestRegion { name: Some("test_add".to_string()), start_line: 12, end_line: 15 },
+        ];
+
+        let in_test = classify_reference(Path::new("src/lib.rs"), 13, &regions).unwrap();
+        assert_eq!(in_test.name.as_deref(), Some("test_add"));
+
+        let in_module = classify_reference(Path::new("src/lib.rs"), 20, &regions).unwrap();
+        assert_eq!(in_module.name, None);
+
+        assert!(classify_reference(Path::new("src/lib.rs"), 5, &regions).is_none());
+        assert!(classify_reference(Path::new("tests/it.rs"), 5, &[]).is_some());
+    }
+}
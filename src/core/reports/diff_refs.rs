@@ -0,0 +1,202 @@
+//! Comparing the references of two items, or of one item at two revisions.
+//!
+//! Migrating call sites from an old API to a new one leaves the lines that
+//! still use only the old one as stragglers. Between two items the lines are
+//! matched by file and line number; between revisions, where lines move, by
+//! file and trimmed text. Another revision is checked out as a git worktree
+//! under the workspace's `target/` directory, so a sandbox allowed into the
+//! workspace also covers it, and gets its own language server for the query.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::core::LanguageQueryService;
+use crate::lsp::Reference;
+
+/// Where the worktrees of other revisions are checked out, below the workspace
+const REVISIONS_DIR: &str = "target/lq-revisions";
+
+/// References on lines only one side of a comparison has
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RefsDiff {
+    /// What the first side is, e.g. `old_api::connect` or `old_api::connect@v1.2`
+    pub a: String,
+    /// What the second side is
+    pub b: String,
+    /// References on lines only the first side has, e.g. call sites not yet migrated
+    pub only_a: Vec<Reference>,
+    /// References on lines only the second side has
+    pub only_b: Vec<Reference>,
+    /// Number of lines both sides reference
+    pub common: usize,
+}
+
+impl LanguageQueryService {
+    /// The references to the items at qualified paths `a` and `b`, on lines not
+    /// referencing the other
+    pub async fn diff_refs(&self, a: &str, b: &str) -> Result<RefsDiff> {
+        let refs_a = self.refs_at_path(a).await?;
+        let refs_b = self.refs_at_path(b).await?;
+        Ok(diff(a, b, refs_a, refs_b, |r| (r.path.clone(), r.line.to_string())))
+    }
+
+    /// The references to the item at qualified `path` at revision `rev_a`, on
+    /// lines not referencing it at `rev_b`, or in the working tree without one
+    pub async fn diff_refs_across(&self, path: &str, rev_a: &str, rev_b: Option<&str>) -> Result<RefsDiff> {
+        let refs_a = self.refs_at_revision(path, rev_a).await?;
+        let refs_b = match rev_b {
+            Some(rev) => self.refs_at_revision(path, rev).await?,
+            None => self.refs_at_path(path).await?,
+        };
+        let label = |rev: Option<&str>| match rev {
+            Some(rev) => format!("{}@{}", path, rev),
+            None => path.to_string(),
+        };
+        Ok(diff(&label(Some(rev_a)), &label(rev_b), refs_a, refs_b, |r| (r.path.clone(), r.text.clone())))
+    }
+
+    /// References to the item at qualified `path`; none if no item matches
+    async fn refs_at_path(&self, path: &str) -> Result<Vec<Reference>> {
+        let location = self.locate_path(path).await?;
+        self.get_refs(&location.file, location.line, Some(location.column), &location.name).await
+    }
+
+    async fn refs_at_revision(&self, path: &str, rev: &str) -> Result<Vec<Reference>> {
+        let checkout = Checkout::new(self.workspace_path(), rev).await?;
+        info!("Starting a language server for {} at {:?}", rev, checkout.workspace);
+        let service = LanguageQueryService::unsupervised(&checkout.workspace, self.options().clone()).await?;
+        service.refs_at_path(path).await
+    }
+}
+
+/// Split two reference lists by the lines, as identified by `key`, only one of them has
+fn diff(
+    a: &str,
+    b: &str,
+    refs_a: Vec<Reference>,
+    refs_b: Vec<Reference>,
+    key: impl Fn(&Reference) -> (PathBuf, String),
+) -> RefsDiff {
+    let keys_a: HashSet<_> = refs_a.iter().map(&key).collect();
+    let keys_b: HashSet<_> = refs_b.iter().map(&key).collect();
+    RefsDiff {
+        a: a.to_string(),
+        b: b.to_string(),
+        common: keys_a.intersection(&keys_b).count(),
+        only_a: refs_a.into_iter().filter(|r| !keys_b.contains(&key(r))).collect(),
+        only_b: refs_b.into_iter().filter(|r| !keys_a.contains(&key(r))).collect(),
+    }
+}
+
+/// A detached git worktree of another revision, removed again when dropped
+struct Checkout {
+    /// A directory in the repository the worktree belongs to
+    repository: PathBuf,
+    dir: PathBuf,
+    /// The workspace within the worktree; below `dir` when the workspace is below the repository root
+    workspace: PathBuf,
+}
+
+impl Checkout {
+    async fn new(workspace: &Path, rev: &str) -> Result<Self> {
+        let commit = git(workspace, &["rev-parse", "--verify", &format!("{}^{{commit}}", rev)])
+            .await
+            .with_context(|| format!("Unknown revision {}", rev))?;
+        let prefix = git(workspace, &["rev-parse", "--show-prefix"]).await?;
+        let dir = workspace.join(REVISIONS_DIR).join(&commit);
+        if dir.exists() {
+            // Left behind by a daemon that was killed mid-query
+            let _ = git(workspace, &["worktree", "remove", "--force", &dir.to_string_lossy()]).await;
+        }
+        git(workspace, &["worktree", "add", "--detach", &dir.to_string_lossy(), &commit]).await?;
+        Ok(Self {
+            repository: workspace.to_path_buf(),
+            workspace: dir.join(prefix),
+            dir,
+        })
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        let removed = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&self.repository)
+            .args(["worktree", "remove", "--force"])
+            .arg(&self.dir)
+            .status();
+        if !removed.is_ok_and(|status| status.success()) {
+            warn!("Failed to remove the worktree at {:?}", self.dir);
+        }
+    }
+}
+
+/// Run git in `dir` and return its trimmed output
+async fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed (is the workspace a git repository?): {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::Anchor;
+
+    fn reference(path: &str, line: u32, text: &str) -> Reference {
+        Reference {
+            file: PathBuf::from("/ws").join(path),
+            path: PathBuf::from(path),
+            line,
+            column: 5,
+            end_line: line,
+            end_column: 9,
+            text: text.to_string(),
+            occurrences: 1,
+            macro_generated: false,
+            anchor: Anchor::default(),
+            url: None,
+            context_before: Vec::new(),
+            context_after: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff_by_line() {
+        let old = vec![reference("src/a.rs", 3, "old(x);"), reference("src/b.rs", 7, "old(y) + new(y)")];
+        let new = vec![reference("src/b.rs", 7, "old(y) + new(y)"), reference("src/c.rs", 1, "new(z);")];
+        let diff = diff("old", "new", old, new, |r| (r.path.clone(), r.line.to_string()));
+        assert_eq!(diff.only_a.len(), 1);
+        assert_eq!(diff.only_a[0].path, Path::new("src/a.rs"));
+        assert_eq!(diff.only_b[0].path, Path::new("src/c.rs"));
+        assert_eq!(diff.common, 1);
+    }
+
+    #[test]
+    fn test_diff_by_text_ignores_moved_lines() {
+        let before = vec![reference("src/a.rs", 3, "old(x);"), reference("src/a.rs", 9, "old(w);")];
+        let after = vec![reference("src/a.rs", 12, "old(x);")];
+        let diff = diff("old@v1", "old", before, after, |r| (r.path.clone(), r.text.clone()));
+        assert_eq!(diff.only_a.len(), 1);
+        assert_eq!(diff.only_a[0].text, "old(w);");
+        assert!(diff.only_b.is_empty());
+        assert_eq!(diff.common, 1);
+    }
+}
@@ -0,0 +1,460 @@
+//! How errors leave a function and travel through its callers.
+//!
+//! The function's return type is read from its declaration. When it returns a
+//! `Result`, the error type is taken as written, or from the type alias when
+//! the `Result` names only the value, as `io::Result<T>` does, and its
+//! declaration is found with a type-definition query. Its `impl From<_>` blocks
+//! are the conversions `?` can make into it. Each reference from a caller is
+//! then read for a `?` propagating the error, and where the caller returns a
+//! different error type, for the `From` impl that converts it. Like the other
+//! reports this reads source text: a call spread over several lines may be
+//! misread, and calls in closures count towards the enclosing function.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reports::scan::function_declarations;
+use crate::core::{Deadline, LanguageQueryService};
+use crate::lsp::Definition;
+
+/// The error type a function returns, the conversions into it, and where its callers handle it
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorFlow {
+    /// The function asked about
+    pub function: String,
+    /// Its declared return type, or `None` when it returns `()`
+    pub return_type: Option<String>,
+    /// The error type of the `Result` it returns, if it returns one
+    pub error_type: Option<ErrorType>,
+    /// The `impl From<_>` blocks converting other errors into the error type
+    pub conversions: Vec<Conversion>,
+    /// The calls of the function, in the order the references were found
+    pub sites: Vec<ErrorSite>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorType {
+    /// As written in the return type or the `Result` alias
+    pub name: String,
+    /// Where it is declared, relative to the workspace root, when the server found it
+    pub path: Option<PathBuf>,
+    /// 1-based line of the declaration
+    pub line: Option<u32>,
+}
+
+/// An `impl From<_>` block, the conversion `?` applies between two error types
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Conversion {
+    /// The type converted from
+    pub from: String,
+    /// The type converted into
+    pub into: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the impl
+    pub line: u32,
+}
+
+/// A call of the function and what the caller does with its error
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorSite {
+    /// The function the call is in
+    pub caller: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the call
+    pub line: u32,
+    /// The trimmed source line
+    pub text: String,
+    /// The error is propagated with `?`
+    pub propagated: bool,
+    /// The error type the caller returns, if it returns a `Result`
+    pub caller_error: Option<String>,
+    /// `?` converts the error into a different one, the caller's
+    pub converted: bool,
+    /// The `From` impl making that conversion, when one names the error type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<Conversion>,
+}
+
+/// An error type found in a signature, and its declaration
+struct ResolvedError {
+    name: String,
+    definition: Option<Definition>,
+}
+
+impl ResolvedError {
+    fn is(&self, other: &ResolvedError) -> bool {
+        match (&self.definition, &other.definition) {
+            (Some(a), Some(b)) => a.file == b.file && a.start_line == b.start_line,
+            _ => last_segment(&self.name) == last_segment(&other.name),
+        }
+    }
+}
+
+impl LanguageQueryService {
+    /// The error type the function named by `symbol` returns, the `From` conversions
+    /// into it, and how each of its callers handles it
+    pub async fn error_flow(
+        &self,
+        file: &Path,
+        line: u32,
+        column: Option<u32>,
+        symbol: &str,
+        deadline: Deadline,
+    ) -> Result<ErrorFlow> {
+        let definition = self
+            .get_definition(file, line, column, symbol)
+            .await?
+            .into_iter()
+            .next()
+            .with_context(|| format!("No definition found for '{}'", symbol))?;
+        let contents = tokio::fs::read_to_string(&definition.file)
+            .await
+            .with_context(|| format!("Failed to read {}", definition.file.display()))?;
+        let return_type = signature_at(&contents, definition.start_line).and_then(|signature| return_type(&signature));
+        let error = self.error_type_at(&definition.file, &contents, definition.start_line).await;
+
+        let mut conversions_into: HashMap<(PathBuf, u32), Vec<Conversion>> = HashMap::new();
+        let conversions = match error.as_ref().and_then(|error| error.definition.as_ref().map(|definition| (error, definition))) {
+            Some((error, definition)) => {
+                let conversions = self.conversions_into(definition, &error.name).await;
+                conversions_into.insert((definition.file.clone(), definition.start_line), conversions.clone());
+                conversions
+            }
+            None => Vec::new(),
+        };
+
+        let references = self.get_refs(file, line, column, symbol).await?;
+        let mut files: HashMap<PathBuf, String> = HashMap::new();
+        let mut caller_errors: HashMap<(PathBuf, u32), Option<ResolvedError>> = HashMap::new();
+        let mut sites = Vec::new();
+        for reference in references {
+            if reference.file == definition.file && reference.line == definition.start_line {
+                continue;
+            }
+            if !files.contains_key(&reference.file) {
+                let contents = tokio::fs::read_to_string(&reference.file).await.unwrap_or_default();
+                files.insert(reference.file.clone(), contents);
+            }
+            let contents = &files[&reference.file];
+            let Some(caller) = function_declarations(contents).into_iter().rev().find(|decl| decl.line <= reference.line) else {
+                continue;
+            };
+
+            let key = (reference.file.clone(), caller.line);
+            if !caller_errors.contains_key(&key) {
+                let Some(caller_error) = deadline.within(self.error_type_at(&reference.file, contents, caller.line)).await else {
+                    break;
+                };
+                caller_errors.insert(key.clone(), caller_error);
+            }
+            let caller_error = caller_errors[&key].as_ref();
+
+            let propagated = propagates(&reference.text, symbol);
+            let converted = propagated
+                && matches!((&error, caller_error), (Some(error), Some(caller_error)) if !error.is(caller_error));
+            let mut conversion = None;
+            if let (true, Some(error), Some(caller_error)) = (converted, &error, caller_error) {
+                if let Some(definition) = &caller_error.definition {
+                    let key = (definition.file.clone(), definition.start_line);
+                    if !conversions_into.contains_key(&key) {
+                        let Some(found) = deadline.within(self.conversions_into(definition, &caller_error.name)).await else {
+                            break;
+                        };
+                        conversions_into.insert(key.clone(), found);
+                    }
+                    conversion = conversions_into[&key]
+                        .iter()
+                        .find(|conversion| last_segment(&conversion.from) == last_segment(&error.name))
+                        .cloned();
+                }
+            }
+
+            sites.push(ErrorSite {
+                caller: caller.name,
+                path: reference.path,
+                line: reference.line,
+                text: reference.text,
+                propagated,
+                caller_error: caller_error.map(|caller_error| caller_error.name.clone()),
+                converted,
+                conversion,
+            });
+        }
+
+        let error_type = error.map(|error| ErrorType {
+            path: error.definition.as_ref().map(|definition| definition.path.clone()),
+            line: error.definition.as_ref().map(|definition| definition.start_line),
+            name: error.name,
+        });
+        Ok(ErrorFlow { function: symbol.to_string(), return_type, error_type, conversions, sites })
+    }
+
+    /// The error type returned by the function declared on `line` of `file`, following
+    /// a `Result` alias to the type it stands for
+    async fn error_type_at(&self, file: &Path, contents: &str, line: u32) -> Option<ResolvedError> {
+        let signature = signature_at(contents, line)?;
+        let args = result_args(&return_type(&signature)?)?;
+        let (name, file, line) = match args.as_slice() {
+            [_, error] => (error.clone(), file.to_path_buf(), line_naming(contents, line, error)),
+            [_] => {
+                let alias_line = line_naming(contents, line, "Result");
+                let alias = self.get_definition(file, alias_line, None, "Result").await.ok()?.into_iter().next()?;
+                let error = result_args(&alias_target(&alias.code)?)?.into_iter().nth(1)?;
+                let alias_contents = tokio::fs::read_to_string(&alias.file).await.ok()?;
+                let line = line_naming(&alias_contents, alias.code_start_line, &error);
+                (error, alias.file, line)
+            }
+            _ => return None,
+        };
+        let definition = self
+            .get_type_definition(&file, line, None, last_segment(&name))
+            .await
+            .ok()
+            .and_then(|definitions| definitions.into_iter().next());
+        Some(ResolvedError { name, definition })
+    }
+
+    /// The `impl From<_>` blocks of the type declared at `definition`
+    async fn conversions_into(&self, definition: &Definition, name: &str) -> Vec<Conversion> {
+        let implementations = self
+            .get_impls(&definition.file, definition.start_line, None, last_segment(name))
+            .await
+            .unwrap_or_default();
+        implementations
+            .into_iter()
+            .filter_map(|implementation| {
+                let (from, into) = from_impl(&implementation.code)?;
+                Some(Conversion { from, into, path: implementation.path, line: implementation.start_line })
+            })
+            .collect()
+    }
+}
+
+/// The signature of the function declared on 1-based `line`, joined onto one line,
+/// up to its body or the closing `;`
+fn signature_at(contents: &str, line: u32) -> Option<String> {
+    let mut signature = String::new();
+    for text in contents.lines().skip(line.checked_sub(1)? as usize).take(20) {
+        if let Some(end) = text.find(['{', ';']) {
+            signature.push_str(&text[..end]);
+            break;
+        }
+        signature.push_str(text.trim());
+        signature.push(' ');
+    }
+    signature.contains("fn ").then(|| signature.trim().to_string())
+}
+
+/// The return type in a function signature, as written
+fn return_type(signature: &str) -> Option<String> {
+    let start = signature.find("fn ")?;
+    let open = start + signature[start..].find('(')?;
+    // Generic parameters may hold `Fn() -> T` bounds, so the parameters are skipped first
+    let generics = &signature[start..open];
+    let open = match generics.find('<') {
+        Some(angle) => {
+            let close = closing(signature, start + angle)?;
+            close + signature[close..].find('(')?
+        }
+        None => open,
+    };
+    let rest = signature[closing(signature, open)? + 1..].trim_start();
+    let ty = rest.strip_prefix("->")?;
+    let ty = ty.split(" where ").next().unwrap_or(ty).trim();
+    (!ty.is_empty()).then(|| ty.to_string())
+}
+
+/// The index of the bracket closing the one at `open`
+fn closing(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut previous = ' ';
+    for (index, c) in text.char_indices().skip_while(|(index, _)| *index < open) {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            // The arrow of `Fn() -> T` closes nothing
+            '>' if previous == '-' => {}
+            ')' | ']' | '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+/// The generic arguments of a `Result` type, split at the top level
+fn result_args(ty: &str) -> Option<Vec<String>> {
+    let open = ty.find('<')?;
+    if last_segment(&ty[..open]) != "Result" {
+        return None;
+    }
+    let inner = &ty[open + 1..closing(ty, open)?];
+    let mut args = Vec::new();
+    let mut depth = 0usize;
+    let mut previous = ' ';
+    let mut start = 0;
+    for (index, c) in inner.char_indices() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            '>' if previous == '-' => {}
+            ')' | ']' | '>' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                args.push(inner[start..index].trim().to_string());
+                start = index + 1;
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    args.push(inner[start..].trim().to_string());
+    args.retain(|arg| !arg.is_empty());
+    Some(args)
+}
+
+/// The type a `type Result<T> = ...;` alias stands for
+fn alias_target(code: &str) -> Option<String> {
+    let alias = &code[code.find("type Result")?..];
+    let target = &alias[alias.find('=')? + 1..];
+    Some(target[..target.find(';')?].split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// The source and target types of an `impl From<Source> for Target` block
+fn from_impl(code: &str) -> Option<(String, String)> {
+    let header = code.lines().map(str::trim).find(|line| line.starts_with("impl"))?;
+    let open = header.find("From<")? + "From".len();
+    let close = closing(header, open)?;
+    let into = header[close + 1..].trim_start().strip_prefix("for ")?;
+    let into = into.split([' ', '{']).next()?;
+    Some((header[open + 1..close].trim().to_string(), into.to_string()))
+}
+
+/// The last path segment of a type, without its generic arguments
+fn last_segment(ty: &str) -> &str {
+    let path = ty.split('<').next().unwrap_or(ty).trim();
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+/// The 1-based line, from `line` on, that names the type `ty`; `line` itself if none does
+fn line_naming(contents: &str, line: u32, ty: &str) -> u32 {
+    let name = last_segment(ty);
+    contents
+        .lines()
+        .enumerate()
+        .skip(line.saturating_sub(1) as usize)
+        .take(20)
+        .find(|(_, text)| text.contains(name))
+        .map_or(line, |(index, _)| index as u32 + 1)
+}
+
+/// Whether the call of `symbol` on the source line `text` propagates its error with `?`
+fn propagates(text: &str, symbol: &str) -> bool {
+    let Some(start) = text.find(&format!("{}(", symbol)) else {
+        return false;
+    };
+    let open = start + symbol.len();
+    let Some(close) = closing(text, open) else {
+        return false;
+    };
+    let rest = text[close + 1..].trim_start();
+    rest.strip_prefix(".await").unwrap_or(rest).trim_start().starts_with('?')
+}
+
+impl fmt::Display for ErrorFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} -> {}", self.function, self.return_type.as_deref().unwrap_or("()"))?;
+        let Some(error) = &self.error_type else {
+            return writeln!(f, "Does not return a Result");
+        };
+        write!(f, "Error type: {}", error.name)?;
+        if let (Some(path), Some(line)) = (&error.path, error.line) {
+            write!(f, " ({}:{})", path.display(), line)?;
+        }
+        writeln!(f)?;
+
+        if !self.conversions.is_empty() {
+            writeln!(f, "\nConversions into {}:", error.name)?;
+            for conversion in &self.conversions {
+                writeln!(f, "  from {} ({}:{})", conversion.from, conversion.path.display(), conversion.line)?;
+            }
+        }
+
+        let propagated = self.sites.iter().filter(|site| site.propagated).count();
+        writeln!(f, "\n{} call site(s), {} propagating with `?`:", self.sites.len(), propagated)?;
+        for site in &self.sites {
+            write!(f, "  {} ({}:{})", site.caller, site.path.display(), site.line)?;
+            if let Some(caller_error) = &site.caller_error {
+                write!(f, " returns {}", caller_error)?;
+            }
+            writeln!(f)?;
+            writeln!(f, "    > {}", site.text)?;
+            match &site.conversion {
+                Some(conversion) => writeln!(
+                    f,
+                    "    `?` converts with impl From<{}> for {} ({}:{})",
+                    conversion.from,
+                    conversion.into,
+                    conversion.path.display(),
+                    conversion.line
+                )?,
+                None if site.converted => writeln!(f, "    `?` converts into {}", site.caller_error.as_deref().unwrap_or("?"))?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_return_type() {
+        let source = "pub fn load<F: Fn() -> u8>(\n    path: &Path,\n    f: F,\n) -> Result<Vec<u8>, LoadError>\nwhere\n    F: Clone,\n{\n";
+        let signature = signature_at(source, 1).unwrap();
+        assert_eq!(return_type(&signature).as_deref(), Some("Result<Vec<u8>, LoadError>"));
+        assert_eq!(return_type("fn run(cb: impl Fn() -> u8)"), None);
+        assert_eq!(return_type("async fn read() -> io::Result<String>").as_deref(), Some("io::Result<String>"));
+        assert_eq!(signature_at("struct A;\n", 1), None);
+    }
+
+    #[test]
+    fn test_result_args() {
+        assert_eq!(result_args("Result<Vec<u8>, LoadError>").unwrap(), vec!["Vec<u8>", "LoadError"]);
+        assert_eq!(result_args("std::result::Result<(u8, u8), Box<dyn Error>>").unwrap(), vec!["(u8, u8)", "Box<dyn Error>"]);
+        assert_eq!(result_args("anyhow::Result<()>").unwrap(), vec!["()"]);
+        assert_eq!(result_args("Option<u8>"), None);
+        assert_eq!(
+            alias_target("/// Results of io\npub type Result<T> = result::Result<T, Error>;").as_deref(),
+            Some("result::Result<T, Error>")
+        );
+    }
+
+    #[test]
+    fn test_from_impl() {
+        let code = "#[automatically_derived]\nimpl From<io::Error> for LoadError {\n    fn from(e: io::Error) -> Self { Self::Io(e) }\n}";
+        assert_eq!(from_impl(code), Some(("io::Error".to_string(), "LoadError".to_string())));
+        assert_eq!(from_impl("impl fmt::Display for LoadError {"), None);
+        assert_eq!(last_segment("std::io::Error"), "Error");
+        assert_eq!(last_segment("Box<dyn Error>"), "Box");
+    }
+
+    #[test]
+    fn test_propagates() {
+        assert!(propagates("let bytes = load(&path, |x| x)?;", "load"));
+        assert!(propagates("fetch(url).await?", "fetch"));
+        assert!(!propagates("match load(&path) {", "load"));
+        assert!(!propagates("paths.iter().map(load).collect::<Result<_>>()?", "load"));
+    }
+}
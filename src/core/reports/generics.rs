@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::core::reports::scan::function_declarations;
+use crate::core::workspace::rust_source_files;
+use crate::core::{Deadline, LanguageQueryService};
+
+/// Upper bound on call sites hovered per function when counting instantiations
+const MAX_HOVER_SAMPLES: usize = 25;
+
+/// A generic function ranked by how much monomorphization it likely causes
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GenericHotspot {
+    pub name: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the declaration
+    pub line: u32,
+    pub references: usize,
+    /// Distinct instantiations observed via hover at sampled call sites
+    pub instantiations: usize,
+    /// `references × instantiations`
+    pub score: usize,
+}
+
+impl LanguageQueryService {
+    /// Rank generic functions by `references × distinct instantiations`.
+    ///
+    /// This is a heuristic: declarations are found textually, and each call site's
+    /// hover text stands in for the instantiation rust-analyzer inferred there.
+    /// Functions not reached before `deadline` are left out.
+    pub async fn generic_hotspots(&self, top: usize, deadline: Deadline) -> Result<Vec<GenericHotspot>> {
+        let mut hotspots = Vec::new();
+
+        'files: for file in rust_source_files(self.workspace_path()) {
+            let Ok(contents) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+
+            for generic_fn in function_declarations(&contents).into_iter().filter(|f| f.is_generic) {
+                let Some(references) = deadline.within(self.get_refs(&file, generic_fn.line, None, &generic_fn.name)).await else {
+                    break 'files;
+                };
+                let references = match references {
+                    Ok(references) => references,
+                    Err(e) => {
+                        debug!("Skipping {} at {:?}:{}: {}", generic_fn.name, file, generic_fn.line, e);
+                        continue;
+                    }
+                };
+
+                if references.is_empty() {
+                    continue;
+                }
+
+                let mut instantiations = HashSet::new();
+                for reference in references.iter().take(MAX_HOVER_SAMPLES) {
+                    if let Some(Ok(Some(hover))) = deadline.within(self.hover_at(&reference.file, reference.line, reference.column)).await {
+                        instantiations.insert(hover);
+                    }
+                }
+                let instantiations = instantiations.len().max(1);
+
+                let path = self.relative_path(&file);
+
+                hotspots.push(GenericHotspot {
+                    name: generic_fn.name,
+                    path,
+                    line: generic_fn.line,
+                    references: references.len(),
+                    instantiations,
+                    score: references.len() * instantiations,
+                });
+            }
+        }
+
+        hotspots.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+                .then_with(|| a.line.cmp(&b.line))
+        });
+        hotspots.truncate(top);
+
+        Ok(hotspots)
+    }
+}
@@ -0,0 +1,197 @@
+//! Migration checklists: the remaining usages of a deprecated item, grouped by
+//! crate and module, as markdown to work through.
+//!
+//! The usages are a plain `refs` answer, so building a checklist needs no
+//! request of its own. A tracking file written earlier is carried forward:
+//! usages it lists that are gone from the new answer are kept, checked off,
+//! under "Done", so the file shows progress across runs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::symbol_path::file_module_path;
+use crate::lsp::Reference;
+use crate::output::Permalinks;
+
+/// The remaining usages of an item
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Migration {
+    /// The item being migrated off, as given on the command line
+    pub item: String,
+    /// Number of usages left
+    pub remaining: usize,
+    /// By crate, then module, both in alphabetical order
+    pub groups: Vec<UsageGroup>,
+    /// Usages a previous checklist listed that are gone now, as checklist text
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub done: Vec<String>,
+}
+
+/// The usages in one module
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UsageGroup {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    /// Full module path, starting with the crate
+    pub module: String,
+    pub usages: Vec<Usage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Usage {
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line
+    pub line: u32,
+    /// The trimmed source line
+    pub text: String,
+    /// Link to the line on the repository host, with `--link-template`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl Migration {
+    /// Group `references` to `item`; `previous` is the markdown of an earlier checklist to carry forward
+    pub fn new(item: &str, references: Vec<Reference>, permalinks: Option<&Permalinks>, previous: Option<&str>) -> Self {
+        let mut modules: BTreeMap<(String, String), Vec<Usage>> = BTreeMap::new();
+        let remaining = references.len();
+        for reference in references {
+            let segments = file_module_path(&reference.file);
+            let krate = segments.first().cloned().unwrap_or_default();
+            modules.entry((krate, segments.join("::"))).or_default().push(Usage {
+                url: permalinks.map(|links| links.url(&reference.path, reference.line)),
+                path: reference.path,
+                line: reference.line,
+                text: reference.text,
+            });
+        }
+        let groups: Vec<UsageGroup> = modules
+            .into_iter()
+            .map(|((krate, module), usages)| UsageGroup { krate, module, usages })
+            .collect();
+
+        let mut done = Vec::new();
+        for line in previous.unwrap_or_default().lines() {
+            let Some(entry) = line.strip_prefix("- [ ] ").or_else(|| line.strip_prefix("- [x] ")) else {
+                continue;
+            };
+            let still_used = groups
+                .iter()
+                .flat_map(|group| &group.usages)
+                .any(|usage| entry_key(entry) == entry_key(&usage_entry(usage)));
+            if !still_used && !done.iter().any(|d: &String| entry_key(d) == entry_key(entry)) {
+                done.push(entry.to_string());
+            }
+        }
+
+        Self { item: item.to_string(), remaining, groups, done }
+    }
+
+    /// The checklist as markdown, one `- [ ]` item per usage
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Migrating off `{}`\n", self.item);
+        let crates = self.groups.iter().map(|group| &group.krate).collect::<BTreeSet<_>>().len();
+        let _ = writeln!(out, "{} usage(s) remaining in {} crate(s).", self.remaining, crates);
+
+        let mut krate = None;
+        for group in &self.groups {
+            if krate != Some(&group.krate) {
+                let _ = writeln!(out, "\n## {}", group.krate);
+                krate = Some(&group.krate);
+            }
+            let _ = writeln!(out, "\n### `{}`\n", group.module);
+            for usage in &group.usages {
+                let _ = writeln!(out, "- [ ] {}", usage_entry(usage));
+            }
+        }
+
+        if !self.done.is_empty() {
+            let _ = writeln!(out, "\n## Done\n");
+            for entry in &self.done {
+                let _ = writeln!(out, "- [x] {}", entry);
+            }
+        }
+        out
+    }
+}
+
+/// A usage as checklist text, without the checkbox
+fn usage_entry(usage: &Usage) -> String {
+    let location = format!("{}:{}", usage.path.display(), usage.line);
+    match &usage.url {
+        Some(url) => format!("[{}]({}) `{}`", location, url, usage.text),
+        None => format!("{} `{}`", location, usage.text),
+    }
+}
+
+/// What identifies a usage across runs: its file and its code, since line
+/// numbers and permalink commits change as the migration goes on
+fn entry_key(entry: &str) -> (&str, &str) {
+    let location = entry.trim_start_matches('[');
+    let path = location.split(':').next().unwrap_or_default();
+    let code = match (entry.find('`'), entry.rfind('`')) {
+        (Some(start), Some(end)) if start < end => &entry[start + 1..end],
+        _ => "",
+    };
+    (path, code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::Anchor;
+    use std::path::Path;
+
+    fn reference(workspace: &Path, path: &str, line: u32, text: &str) -> Reference {
+        Reference {
+            file: workspace.join(path),
+            path: PathBuf::from(path),
+            line,
+            column: 1,
+            end_line: line,
+            end_column: 4,
+            text: text.to_string(),
+            occurrences: 1,
+            macro_generated: false,
+            anchor: Anchor::default(),
+            url: None,
+            context_before: Vec::new(),
+            context_after: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_checklist_groups_by_module_and_carries_done_forward() {
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(workspace.path().join("Cargo.toml"), "[package]\nname = \"shapes\"\n").unwrap();
+        let ws = workspace.path();
+        let references = vec![
+            reference(ws, "src/render/mod.rs", 12, "old_area(&s)"),
+            reference(ws, "src/lib.rs", 3, "old_area(&c)"),
+            reference(ws, "src/render/mod.rs", 40, "old_area(&t)"),
+        ];
+        let previous = "- [ ] src/lib.rs:3 `old_area(&c)`\n- [ ] src/io.rs:7 `old_area(&x)`\n";
+        let migration = Migration::new("shapes::old_area", references, None, Some(previous));
+
+        let modules: Vec<&str> = migration.groups.iter().map(|group| group.module.as_str()).collect();
+        assert_eq!(modules, vec!["shapes", "shapes::render"]);
+        assert_eq!(migration.groups[1].usages.len(), 2);
+        assert_eq!(migration.done, vec!["src/io.rs:7 `old_area(&x)`"]);
+
+        let markdown = migration.to_markdown();
+        assert!(markdown.contains("3 usage(s) remaining in 1 crate(s)."));
+        assert!(markdown.contains("### `shapes::render`\n\n- [ ] src/render/mod.rs:12 `old_area(&s)`"));
+        assert!(markdown.ends_with("## Done\n\n- [x] src/io.rs:7 `old_area(&x)`\n"));
+    }
+
+    #[test]
+    fn test_entry_key_ignores_links() {
+        assert_eq!(entry_key("[src/lib.rs:9](https://example.com/abc123/src/lib.rs#L9) `f()`"), ("src/lib.rs", "f()"));
+        assert_eq!(entry_key("src/lib.rs:12 `f()`"), ("src/lib.rs", "f()"));
+    }
+}
@@ -0,0 +1,28 @@
+pub mod async_callers;
+pub mod badge;
+pub mod bounds;
+pub mod churn;
+pub mod coverage;
+pub mod diff_refs;
+pub mod error_flow;
+pub mod generics;
+pub mod migration;
+pub mod ranking;
+pub mod ref_closure;
+pub mod scan;
+pub mod strings;
+pub mod tour;
+
+pub use async_callers::AsyncCallers;
+pub use badge::Badge;
+pub use bounds::CallBounds;
+pub use churn::{ChurnEntry, ChurnSort};
+pub use coverage::{FunctionCoverage, TestReference};
+pub use diff_refs::RefsDiff;
+pub use error_flow::ErrorFlow;
+pub use generics::GenericHotspot;
+pub use migration::Migration;
+pub use ranking::RankedSymbol;
+pub use ref_closure::RefClosure;
+pub use strings::StringMatch;
+pub use tour::Tour;
@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::core::reports::scan::{function_declarations, test_regions, type_declarations};
+use crate::core::workspace::rust_source_files;
+use crate::core::{Deadline, LanguageQueryService};
+
+/// Default number of reference queries in flight while ranking
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// A public item and how many times it is referenced
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RankedSymbol {
+    pub name: String,
+    /// Declaring keyword, e.g. `fn` or `struct`
+    pub kind: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the declaration
+    pub line: u32,
+    pub references: usize,
+}
+
+/// A public item found by scanning, before references are counted
+#[derive(Debug, Clone)]
+pub(crate) struct Candidate {
+    pub file: PathBuf,
+    pub name: String,
+    pub kind: String,
+    pub line: u32,
+}
+
+impl LanguageQueryService {
+    /// Rank public, non-test items across the workspace by reference count
+    pub async fn most_referenced(&self, top: usize, deadline: Deadline) -> Result<Vec<RankedSymbol>> {
+        self.hotspots(top, None, DEFAULT_CONCURRENCY, deadline).await
+    }
+
+    /// Rank public, non-test items by reference count.
+    ///
+    /// When `sample` is set, only that many candidates (spread evenly across the
+    /// workspace) are counted. At most `concurrency` reference queries run at once.
+    /// Candidates not counted before `deadline` are left out.
+    pub async fn hotspots(
+        &self,
+        top: usize,
+        sample: Option<usize>,
+        concurrency: usize,
+        deadline: Deadline,
+    ) -> Result<Vec<RankedSymbol>> {
+        let mut candidates = self.public_items().await;
+        if let Some(sample) = sample {
+            candidates = sample_evenly(candidates, sample);
+        }
+
+        let mut ranked: Vec<RankedSymbol> = stream::iter(candidates)
+            .map(|candidate| async move {
+                match deadline.within(self.get_refs(&candidate.file, candidate.line, None, &candidate.name)).await? {
+                    Ok(references) => Some(RankedSymbol {
+                        path: self.relative_path(&candidate.file),
+                        name: candidate.name,
+                        kind: candidate.kind,
+                        line: candidate.line,
+                        references: references.len(),
+                    }),
+                    Err(e) => {
+                        debug!("Skipping {} at {:?}:{}: {}", candidate.name, candidate.file, candidate.line, e);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .filter_map(|ranked| async move { ranked })
+            .collect()
+            .await;
+
+        sort_ranked(&mut ranked);
+        ranked.truncate(top);
+        Ok(ranked)
+    }
+
+    /// Public functions and types declared outside test code
+    pub(crate) async fn public_items(&self) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+
+        for file in rust_source_files(self.workspace_path()) {
+            let Ok(contents) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+            let regions = test_regions(&contents);
+            let in_tests = |line: u32| regions.iter().any(|r| r.contains(line));
+
+            for decl in function_declarations(&contents) {
+                if decl.is_pub && !in_tests(decl.line) {
+                    candidates.push(Candidate {
+                        file: file.clone(),
+                        name: decl.name,
+                        kind: "fn".to_string(),
+                        line: decl.line,
+                    });
+                }
+            }
+            for decl in type_declarations(&contents) {
+                if decl.is_pub && !in_tests(decl.line) {
+                    candidates.push(Candidate {
+                        file: file.clone(),
+                        name: decl.name,
+                        kind: decl.kind.to_string(),
+                        line: decl.line,
+                    });
+                }
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Pick `count` items spread evenly across `items`, keeping their order
+fn sample_evenly<T>(items: Vec<T>, count: usize) -> Vec<T> {
+    if count == 0 {
+        return Vec::new();
+    }
+    if items.len() <= count {
+        return items;
+    }
+
+    let stride = items.len() as f64 / count as f64;
+    let mut wanted = (0..count).map(|i| (i as f64 * stride) as usize).peekable();
+    items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, item)| {
+            if wanted.peek() == Some(&index) {
+                wanted.next();
+                Some(item)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Most referenced first, ties broken by location for stable output
+pub(crate) fn sort_ranked(ranked: &mut [RankedSymbol]) {
+    ranked.sort_by(|a, b| {
+        b.references
+            .cmp(&a.references)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.line.cmp(&b.line))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_evenly() {
+        let items: Vec<u32> = (0..10).collect();
+        assert_eq!(sample_evenly(items.clone(), 5), vec![0, 2, 4, 6, 8]);
+        assert_eq!(sample_evenly(items.clone(), 3), vec![0, 3, 6]);
+        assert_eq!(sample_evenly(items.clone(), 20), items);
+        assert!(sample_evenly(items, 0).is_empty());
+    }
+}
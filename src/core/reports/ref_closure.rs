@@ -0,0 +1,269 @@
+//! The functions that ultimately depend on a function, by following references.
+//!
+//! The references to the function are attributed to the innermost function or
+//! method of their file's outline that spans them, and the references to each
+//! of those functions are followed in turn, up to a depth. Unlike the incoming
+//! call hierarchy, a reference need not be a call, so a function passed as a
+//! callback or stored in a table counts as used by the function doing so.
+//! References outside any function, such as imports and constants, are listed
+//! for the function asked about and not followed. A function already on the
+//! path is not followed again, so recursion ends the walk.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::core::reports::scan::function_declarations;
+use crate::core::{Deadline, LanguageQueryService};
+use crate::lsp::{Call, CallSite, OutlineItem, Reference};
+
+/// Outline kinds whose references are followed
+const FUNCTION_KINDS: &[&str] = &["Function", "Method"];
+
+/// The functions referring to a function, and those referring to them, up to a depth
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RefClosure {
+    /// The function asked about
+    pub function: String,
+    /// The functions referring to it, each with the references inside it as its
+    /// sites and the functions referring to it in turn as its calls
+    pub referrers: Vec<Call>,
+    /// References to it outside any function, such as imports and constants
+    pub unenclosed: Vec<Reference>,
+}
+
+impl RefClosure {
+    /// The distinct functions anywhere in the closure
+    pub fn function_count(&self) -> usize {
+        let mut seen = HashSet::new();
+        count_functions(&self.referrers, &mut seen);
+        seen.len()
+    }
+}
+
+fn count_functions<'a>(calls: &'a [Call], seen: &mut HashSet<(&'a Path, u32)>) {
+    for call in calls {
+        seen.insert((&call.path, call.line));
+        count_functions(&call.calls, seen);
+    }
+}
+
+impl LanguageQueryService {
+    /// The functions referring to the function named by `symbol`, followed
+    /// `depth` levels up, and its references outside any function
+    #[allow(clippy::too_many_arguments)]
+    pub async fn ref_closure(
+        &self,
+        file: &Path,
+        line: u32,
+        column: Option<u32>,
+        symbol: &str,
+        depth: usize,
+        deadline: Deadline,
+    ) -> Result<RefClosure> {
+        let mut walk = ClosureWalk { deadline, files: HashMap::new(), path: HashSet::new() };
+        let (referrers, unenclosed) = self.referrers(file, line, column, symbol, depth.max(1), &mut walk).await?;
+        Ok(RefClosure { function: symbol.to_string(), referrers, unenclosed })
+    }
+
+    /// The functions referring to `symbol`, and its references outside any function
+    async fn referrers(
+        &self,
+        file: &Path,
+        line: u32,
+        column: Option<u32>,
+        symbol: &str,
+        depth: usize,
+        walk: &mut ClosureWalk,
+    ) -> Result<(Vec<Call>, Vec<Reference>)> {
+        let Some(references) = walk.deadline.within(self.get_refs(file, line, column, symbol)).await else {
+            return Ok((Vec::new(), Vec::new()));
+        };
+        let mut referrers: Vec<Call> = Vec::new();
+        let mut unenclosed = Vec::new();
+        for reference in references? {
+            let Some(function) = self.enclosing_function(&reference.file, reference.line, walk).await else {
+                unenclosed.push(reference);
+                continue;
+            };
+            // The declaration's own name
+            if function.name == symbol && function.line == reference.line {
+                walk.path.insert((function.file.clone(), function.name.clone()));
+                continue;
+            }
+            let site = CallSite { path: reference.path.clone(), line: reference.line, column: reference.column, text: reference.text.clone() };
+            match referrers.iter_mut().find(|call| call.file == function.file && call.line == function.line) {
+                Some(call) => call.sites.push(site),
+                None => referrers.push(Call { sites: vec![site], ..function }),
+            }
+        }
+        referrers.retain(|call| !walk.path.contains(&(call.file.clone(), call.name.clone())));
+
+        if depth > 1 {
+            for call in &mut referrers {
+                let key = (call.file.clone(), call.name.clone());
+                walk.path.insert(key.clone());
+                match Box::pin(self.referrers(&call.file, call.line, Some(call.column), &call.name, depth - 1, walk)).await {
+                    Ok((children, _)) => call.calls = children,
+                    Err(e) => debug!("Not following references to {} at {:?}:{}: {}", call.name, call.file, call.line, e),
+                }
+                walk.path.remove(&key);
+            }
+        }
+        Ok((referrers, unenclosed))
+    }
+
+    /// The innermost function of `file` spanning 1-based `line`
+    async fn enclosing_function(&self, file: &Path, line: u32, walk: &mut ClosureWalk) -> Option<Call> {
+        if !walk.files.contains_key(file) {
+            let contents = tokio::fs::read_to_string(file).await.unwrap_or_default();
+            let outline = self.outline(file).await.unwrap_or_default();
+            walk.files.insert(file.to_path_buf(), functions(&outline, &contents));
+        }
+        // Listed outermost first, so the last one spanning the line is the innermost
+        let function = walk.files[file].iter().rev().find(|function| function.start_line <= line && line <= function.end_line)?;
+        Some(Call {
+            name: function.name.clone(),
+            kind: function.kind.clone(),
+            detail: function.detail.clone(),
+            file: file.to_path_buf(),
+            path: self.relative_path(file),
+            line: function.line,
+            column: function.column,
+            sites: Vec::new(),
+            calls: Vec::new(),
+        })
+    }
+}
+
+/// State carried through one walk of the references
+struct ClosureWalk {
+    deadline: Deadline,
+    /// The functions of the files read so far
+    files: HashMap<PathBuf, Vec<Function>>,
+    /// The functions being followed, by file and name
+    path: HashSet<(PathBuf, String)>,
+}
+
+/// A function or method of a file's outline
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Function {
+    name: String,
+    kind: String,
+    detail: Option<String>,
+    /// 1-based lines it spans, doc comments and attributes included
+    start_line: u32,
+    end_line: u32,
+    /// 1-based line and column of its name
+    line: u32,
+    column: u32,
+}
+
+/// The functions and methods of `outline`, outermost first, where `contents`
+/// declares their names; those declared by macros have no name to query and are left out
+fn functions(outline: &[OutlineItem], contents: &str) -> Vec<Function> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let declarations = function_declarations(contents);
+    let mut found = Vec::new();
+    let mut items: Vec<&OutlineItem> = outline.iter().rev().collect();
+    while let Some(item) = items.pop() {
+        items.extend(item.children.iter().rev());
+        if !FUNCTION_KINDS.contains(&item.kind.as_str()) {
+            continue;
+        }
+        let declaration = declarations
+            .iter()
+            .find(|declaration| declaration.name == item.name && (item.start_line..=item.end_line).contains(&declaration.line));
+        let Some(declaration) = declaration else {
+            continue;
+        };
+        let text = lines.get(declaration.line as usize - 1).copied().unwrap_or_default();
+        let Some(offset) = text.find(&format!("fn {}", item.name)) else {
+            continue;
+        };
+        found.push(Function {
+            name: item.name.clone(),
+            kind: item.kind.clone(),
+            detail: item.detail.clone(),
+            start_line: item.start_line,
+            end_line: item.end_line,
+            line: declaration.line,
+            column: text[..offset + 3].chars().count() as u32 + 1,
+        });
+    }
+    found
+}
+
+impl fmt::Display for RefClosure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.referrers.is_empty() && self.unenclosed.is_empty() {
+            return writeln!(f, "No references found for {}", self.function);
+        }
+        writeln!(
+            f,
+            "{}: referenced from {} function(s) directly, {} in all",
+            self.function,
+            self.referrers.len(),
+            self.function_count()
+        )?;
+        for call in &self.referrers {
+            write!(f, "{}", call)?;
+        }
+        if !self.unenclosed.is_empty() {
+            writeln!(f, "\nOutside functions:")?;
+            for reference in &self.unenclosed {
+                writeln!(f, "  {}:{}: {}", reference.path.display(), reference.line, reference.text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(kind: &str, name: &str, lines: (u32, u32), children: Vec<OutlineItem>) -> OutlineItem {
+        OutlineItem { name: name.to_string(), kind: kind.to_string(), detail: None, start_line: lines.0, end_line: lines.1, children }
+    }
+
+    #[test]
+    fn test_functions() {
+        let contents = "impl Loader {\n    /// Docs\n    pub fn load(&self) {\n        fn inner() {}\n    }\n}\nconst LIMIT: usize = 3;\nhandler!(serve);\n";
+        let outline = vec![
+            item("Object", "impl Loader", (1, 6), vec![item("Method", "load", (2, 5), vec![item("Function", "inner", (4, 4), Vec::new())])]),
+            item("Constant", "LIMIT", (7, 7), Vec::new()),
+            item("Function", "serve", (8, 8), Vec::new()),
+        ];
+        let found = functions(&outline, contents);
+        let names: Vec<(&str, u32, u32)> = found.iter().map(|function| (function.name.as_str(), function.line, function.column)).collect();
+        assert_eq!(names, [("load", 3, 12), ("inner", 4, 12)]);
+    }
+
+    #[test]
+    fn test_function_count() {
+        let call = |name: &str, line: u32, calls: Vec<Call>| Call {
+            name: name.to_string(),
+            kind: "Function".to_string(),
+            detail: None,
+            file: PathBuf::from("/ws/src/lib.rs"),
+            path: PathBuf::from("src/lib.rs"),
+            line,
+            column: 4,
+            sites: Vec::new(),
+            calls,
+        };
+        let closure = RefClosure {
+            function: "parse".to_string(),
+            referrers: vec![call("load", 10, vec![call("main", 2, Vec::new())]), call("reload", 20, vec![call("main", 2, Vec::new())])],
+            unenclosed: Vec::new(),
+        };
+        assert_eq!(closure.function_count(), 3);
+        assert!(closure.to_string().starts_with("parse: referenced from 2 function(s) directly, 3 in all\nload (src/lib.rs:10)\n"));
+    }
+}
@@ -0,0 +1,494 @@
+//! Lightweight textual scanning of Rust sources.
+//!
+//! Reports use these helpers to pick candidate items before asking the language
+//! server about them. They are deliberately approximate: no macro expansion, and
+//! braces inside string literals can confuse region detection.
+
+/// A function declaration found by scanning source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnDecl {
+    pub name: String,
+    /// 1-based line of the declaration
+    pub line: u32,
+    /// Declared with `pub` (any visibility restriction included)
+    pub is_pub: bool,
+    /// Declares at least one type or const generic parameter
+    pub is_generic: bool,
+    /// Declared with `async`
+    pub is_async: bool,
+}
+
+/// A type-like item declaration (struct, enum, union, trait, or type alias)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeDecl {
+    /// The declaring keyword, e.g. `struct`
+    pub kind: &'static str,
+    pub name: String,
+    /// 1-based line of the declaration
+    pub line: u32,
+    pub is_pub: bool,
+}
+
+/// A region of a file that belongs to test code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestRegion {
+    /// Name of the test function, or `None` for a whole `#[cfg(test)]` module
+    pub name: Option<String>,
+    /// 1-based first line (the attribute line)
+    pub start_line: u32,
+    /// 1-based last line (the closing brace)
+    pub end_line: u32,
+}
+
+impl TestRegion {
+    pub fn contains(&self, line: u32) -> bool {
+        (self.start_line..=self.end_line).contains(&line)
+    }
+}
+
+/// Find `fn` declarations in Rust source text
+pub fn function_declarations(contents: &str) -> Vec<FnDecl> {
+    let mut found = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim_start().starts_with("//") {
+            continue;
+        }
+
+        let mut search_from = 0;
+        while let Some(offset) = line[search_from..].find("fn ") {
+            let start = search_from + offset;
+            search_from = start + 3;
+
+            // `fn` must be its own token, not the tail of an identifier
+            let preceded_by_ident = line[..start]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_');
+            if preceded_by_ident {
+                continue;
+            }
+
+            let rest = line[start + 3..].trim_start();
+            let name: String = rest
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect();
+            if name.is_empty() {
+                continue;
+            }
+
+            let after_name = rest[name.len()..].trim_start();
+            let is_generic = after_name
+                .strip_prefix('<')
+                .is_some_and(has_type_params);
+
+            found.push(FnDecl {
+                name,
+                line: index as u32 + 1,
+                is_pub: is_pub_prefix(&line[..start]),
+                is_generic,
+                is_async: line[..start].split_whitespace().any(|word| word == "async"),
+            });
+        }
+    }
+
+    found
+}
+
+/// Find struct, enum, union, trait, and type alias declarations in Rust source text
+pub fn type_declarations(contents: &str) -> Vec<TypeDecl> {
+    const KINDS: &[&str] = &["struct", "enum", "union", "trait", "type"];
+    let mut found = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("//") {
+            continue;
+        }
+
+        let words: Vec<&str> = trimmed.split_whitespace().collect();
+        for (position, word) in words.iter().enumerate() {
+            let Some(kind) = KINDS.iter().find(|k| *k == word) else {
+                continue;
+            };
+            // Only modifiers may precede the keyword on a declaration line
+            let prefix = &words[..position];
+            let is_decl = prefix
+                .iter()
+                .all(|w| *w == "pub" || w.starts_with("pub(") || *w == "unsafe" || *w == "auto");
+            if !is_decl {
+                break;
+            }
+
+            let name: String = words
+                .get(position + 1)
+                .map(|w| w.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect())
+                .unwrap_or_default();
+            if !name.is_empty() {
+                found.push(TypeDecl {
+                    kind,
+                    name,
+                    line: index as u32 + 1,
+                    is_pub: prefix.iter().any(|w| *w == "pub" || w.starts_with("pub(")),
+                });
+            }
+            break;
+        }
+    }
+
+    found
+}
+
+/// Find `#[cfg(test)]` items and `#[test]` functions in Rust source text
+pub fn test_regions(contents: &str) -> Vec<TestRegion> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut regions = Vec::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let is_test_fn = trimmed == "#[test]" || (trimmed.starts_with("#[") && trimmed.ends_with("::test]"));
+        let is_test_mod = trimmed.starts_with("#[cfg(test)]");
+        if !is_test_fn && !is_test_mod {
+            continue;
+        }
+
+        let Some(end) = item_end(&lines, index) else {
+            continue;
+        };
+
+        let name = if is_test_fn {
+            lines[index..=end]
+                .iter()
+                .find_map(|l| function_declarations(l).into_iter().next())
+                .map(|decl| decl.name)
+        } else {
+            None
+        };
+
+        regions.push(TestRegion {
+            name,
+            start_line: index as u32 + 1,
+            end_line: end as u32 + 1,
+        });
+    }
+
+    regions
+}
+
+/// A string literal found by scanning source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringLiteral {
+    /// 1-based line of its first character
+    pub line: u32,
+    /// 1-based column of its first character, the prefix of a raw or byte string
+    pub column: u32,
+    /// The literal as written, prefix and quotes included
+    pub source: String,
+    /// The text it spells: escapes resolved, except in a raw string
+    pub value: String,
+}
+
+/// Find string literals in Rust source text: plain, raw, byte and C strings.
+/// Comments and character literals are skipped.
+pub fn string_literals(contents: &str) -> Vec<StringLiteral> {
+    let chars: Vec<char> = contents.chars().collect();
+    let mut found = Vec::new();
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut index = 0;
+
+    while index < chars.len() {
+        let start = index;
+        let (body, raw) = match chars[index] {
+            '/' if chars.get(index + 1) == Some(&'/') => {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(index + 1) == Some(&'*') => {
+                // Block comments nest
+                let mut depth = 0;
+                while index < chars.len() {
+                    match (chars[index], chars.get(index + 1)) {
+                        ('/', Some('*')) => {
+                            depth += 1;
+                            index += 2;
+                        }
+                        ('*', Some('/')) => {
+                            depth -= 1;
+                            index += 2;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        ('\n', _) => {
+                            index += 1;
+                            line += 1;
+                            line_start = index;
+                        }
+                        _ => index += 1,
+                    }
+                }
+                continue;
+            }
+            '\'' => {
+                // A character literal, such as '"' or '\'', rather than a lifetime
+                if chars.get(index + 1) == Some(&'\\') {
+                    index += 3;
+                    while index < chars.len() && chars[index] != '\'' {
+                        index += 1;
+                    }
+                    index += 1;
+                } else if chars.get(index + 2) == Some(&'\'') {
+                    index += 3;
+                } else {
+                    index += 1;
+                }
+                continue;
+            }
+            '"' => (index + 1, None),
+            c if c.is_alphabetic() || c == '_' => {
+                while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_') {
+                    index += 1;
+                }
+                let prefix: String = chars[start..index].iter().collect();
+                let hashes = chars[index..].iter().take_while(|c| **c == '#').count();
+                match prefix.as_str() {
+                    "r" | "br" | "cr" if chars.get(index + hashes) == Some(&'"') => (index + hashes + 1, Some(hashes)),
+                    "b" | "c" if chars.get(index) == Some(&'"') => (index + 1, None),
+                    _ => continue,
+                }
+            }
+            '\n' => {
+                index += 1;
+                line += 1;
+                line_start = index;
+                continue;
+            }
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+
+        let (literal_line, column) = (line, (start - line_start) as u32 + 1);
+        index = body;
+        let end = loop {
+            match chars.get(index) {
+                None => break chars.len(),
+                Some('\\') if raw.is_none() => {
+                    if chars.get(index + 1) == Some(&'\n') {
+                        line += 1;
+                        line_start = index + 2;
+                    }
+                    index += 2;
+                    continue;
+                }
+                Some('"') if raw.is_none_or(|hashes| chars[index + 1..].iter().take(hashes).all(|c| *c == '#') && index + hashes < chars.len()) => {
+                    break index;
+                }
+                Some('\n') => {
+                    line += 1;
+                    line_start = index + 1;
+                }
+                Some(_) => {}
+            }
+            index += 1;
+        };
+        index = (end + 1 + raw.unwrap_or(0)).min(chars.len());
+        let text: String = chars[body..end].iter().collect();
+        found.push(StringLiteral {
+            line: literal_line,
+            column,
+            source: chars[start..index].iter().collect(),
+            value: if raw.is_some() { text } else { unescape(&text) },
+        });
+    }
+
+    found
+}
+
+/// The text a plain string literal's `body` spells
+fn unescape(body: &str) -> String {
+    let mut value = String::with_capacity(body.len());
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('r') => value.push('\r'),
+            Some('0') => value.push('\0'),
+            Some('x') => {
+                let code: String = chars.by_ref().take(2).collect();
+                value.extend(u8::from_str_radix(&code, 16).ok().map(char::from));
+            }
+            Some('u') => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                value.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+            }
+            // A line continuation skips the next line's indentation
+            Some('\n') => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            }
+            Some(other) => value.push(other),
+            None => {}
+        }
+    }
+    value
+}
+
+/// Index of the line closing the braced item that starts at or after `start`
+fn item_end(lines: &[&str], start: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    let mut opened = false;
+
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        let code = line.split("//").next().unwrap_or_default();
+        for c in code.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                // An item without a body, e.g. `#[cfg(test)] mod tests;`
+                ';' if !opened => return Some(index),
+                _ => {}
+            }
+        }
+        if opened && depth <= 0 {
+            return Some(index);
+        }
+    }
+
+    None
+}
+
+/// Whether the text before `fn` on a declaration line makes it public
+fn is_pub_prefix(prefix: &str) -> bool {
+    prefix.split_whitespace().any(|word| word == "pub" || word.starts_with("pub("))
+}
+
+/// Whether a generic parameter list (text following `<`) declares any type or const parameter
+fn has_type_params(generics: &str) -> bool {
+    let params = generics.split('>').next().unwrap_or_default();
+    params
+        .split(',')
+        .map(str::trim)
+        .any(|param| !param.is_empty() && !param.starts_with('\''))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_function_declarations() {
+        let source = r#"
+pub fn plain(x: i32) -> i32 { x }
+pub fn generic<T: Clone>(x: T) -> T { x }
+    fn  spaced <T>(x: T) {}
+// fn commented<T>(x: T) {}
+fn lifetime<'a>(x: &'a str) -> &'a str { x }
+pub(crate) async fn mixed<'a, T>(x: &'a T) -> &'a T { x }
+"#;
+        let found: Vec<_> = function_declarations(source)
+            .into_iter()
+            .map(|f| (f.name, f.line, f.is_pub, f.is_generic, f.is_async))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("plain".to_string(), 2, true, false, false),
+                ("generic".to_string(), 3, true, true, false),
+                ("spaced".to_string(), 4, false, true, false),
+                ("lifetime".to_string(), 6, false, false, false),
+                ("mixed".to_string(), 7, true, true, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignores_fn_inside_identifiers() {
+        let source = "let cfn <T> = 1;\nfn_call<T>();";
+        assert!(function_declarations(source).is_empty());
+    }
+
+    #[test]
+    fn test_type_declarations() {
+        let source = r#"
+pub struct Config {
+enum State { A, B }
+pub(crate) trait Backend: Send {
+    type Output;
+let structure = 1;
+"#;
+        let found: Vec<_> = type_declarations(source)
+            .into_iter()
+            .map(|t| (t.kind, t.name, t.line, t.is_pub))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("struct", "Config".to_string(), 2, true),
+                ("enum", "State".to_string(), 3, false),
+                ("trait", "Backend".to_string(), 4, true),
+                ("type", "Output".to_string(), 5, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_test_regions() {
+        let source = r#"pub fn add(a: i32, b: i32) -> i32 { a + b }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        assert_eq!(add(1, 2), 3);
+    }
+
+    #[tokio::test]
+    async fn test_async() {}
+}
+"#;
+        let regions = test_regions(source);
+        assert_eq!(
+            regions,
+            vec![
+                TestRegion { name: None, start_line: 3, end_line: 14 },
+                TestRegion { name: Some("test_add".to_string()), start_line: 7, end_line: 10 },
+                TestRegion { name: Some("test_async".to_string()), start_line: 12, end_line: 13 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let source = "fn f<'a>(x: &'a str) {\n    // \"not this\"\n    /* nor /* \"this\" */ */ let q = '\"';\n    \
+                      log!(\"loaded {} items\\n\", n, '\\'');\n    let p = br#\"a \"quoted\" path\"#;\n    \"two \\\n     lines\"; c\"end\"\n}\n";
+        let literals = string_literals(source);
+        let found: Vec<(u32, u32, &str, &str)> = literals
+            .iter()
+            .map(|literal| (literal.line, literal.column, literal.source.as_str(), literal.value.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (4, 10, "\"loaded {} items\\n\"", "loaded {} items\n"),
+                (5, 13, "br#\"a \"quoted\" path\"#", "a \"quoted\" path"),
+                (6, 5, "\"two \\\n     lines\"", "two lines"),
+                (7, 14, "c\"end\"", "end"),
+            ]
+        );
+    }
+}
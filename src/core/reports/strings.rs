@@ -0,0 +1,259 @@
+//! String literals matching a pattern, each placed in its module and function.
+//!
+//! Literals are found by scanning the workspace's Rust files, so those a macro
+//! builds are missed. A file's module path follows from where it sits under
+//! `src`, and the document symbols of a file with matches add its inline
+//! modules and the items around the literal. With `--log`, the pattern is a
+//! line of output rather than a regular expression, and a literal matches when
+//! it could have printed it: its text between `{}` placeholders appears in the
+//! line, in order.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex_automata::meta::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reports::scan::{string_literals, StringLiteral};
+use crate::core::workspace::rust_source_files;
+use crate::core::{Deadline, LanguageQueryService};
+use crate::lsp::OutlineItem;
+
+/// Outline kinds a literal can be inside of that run code
+const FUNCTION_KINDS: &[&str] = &["Function", "Method"];
+
+/// Literal characters, whitespace aside, a format string needs to be taken for
+/// a log line's source; `"{}: {}"` could print almost anything
+const MIN_LOG_TEXT: usize = 4;
+
+/// Directories whose files are each the root of a crate of their own
+const CRATE_ROOT_DIRS: &[&str] = &["tests", "examples", "benches"];
+
+/// A string literal and where it sits in the code
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StringMatch {
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line and column where the literal starts
+    pub line: u32,
+    pub column: u32,
+    /// The literal as written, quotes included
+    pub text: String,
+    /// Module path, such as `crate::daemon::cache`
+    pub module: String,
+    /// Items around the literal below its module, outermost first, such as an
+    /// impl and one of its methods
+    pub items: Vec<String>,
+    /// The innermost function or method around the literal, if any
+    pub function: Option<String>,
+    /// Link to the line on the repository host, with `--link-template`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl LanguageQueryService {
+    /// The string literals of the workspace whose text `pattern` matches, or,
+    /// with `log`, those that could have printed the line `pattern`. Files not
+    /// reached before `deadline` are left out.
+    pub async fn string_matches(&self, pattern: &str, log: bool, deadline: Deadline) -> Result<Vec<StringMatch>> {
+        let regex = if log {
+            None
+        } else {
+            Some(Regex::new(pattern).with_context(|| format!("Invalid pattern {:?}", pattern))?)
+        };
+        let matches = |literal: &StringLiteral| match &regex {
+            Some(regex) => regex.is_match(&literal.value),
+            None => could_print(&literal.value, pattern),
+        };
+
+        let mut found = Vec::new();
+        for file in rust_source_files(self.workspace_path()) {
+            let Ok(contents) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+            let literals: Vec<StringLiteral> = string_literals(&contents).into_iter().filter(|literal| matches(literal)).collect();
+            if literals.is_empty() {
+                continue;
+            }
+            let Some(outline) = deadline.within(self.outline(&file)).await else {
+                break;
+            };
+            let outline = outline.unwrap_or_default();
+            let path = self.relative_path(&file);
+            for literal in literals {
+                let (modules, items, function) = enclosing_items(&outline, literal.line);
+                let module = std::iter::once(module_path(&path)).chain(modules).collect::<Vec<_>>().join("::");
+                found.push(StringMatch {
+                    path: path.clone(),
+                    line: literal.line,
+                    column: literal.column,
+                    text: literal.source,
+                    module,
+                    items,
+                    function,
+                    url: None,
+                });
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// The module path of the file at `path`, relative to the workspace root
+fn module_path(path: &Path) -> String {
+    let path = path.with_extension("");
+    let components: Vec<&str> = path.iter().filter_map(|component| component.to_str()).collect();
+    let mut modules = match components.iter().rposition(|component| *component == "src") {
+        Some(src) if components.get(src + 1) == Some(&"bin") => components.get(src + 3..).unwrap_or_default(),
+        Some(src) => &components[src + 1..],
+        None => match components.iter().rposition(|component| CRATE_ROOT_DIRS.contains(component)) {
+            Some(dir) => components.get(dir + 2..).unwrap_or_default(),
+            None => &[],
+        },
+    };
+    if let [parents @ .., "mod"] = modules {
+        modules = parents;
+    }
+    if let ["lib" | "main"] = modules {
+        modules = &[];
+    }
+    std::iter::once("crate").chain(modules.iter().copied()).collect::<Vec<_>>().join("::")
+}
+
+/// The inline modules, the other items and the innermost function of `outline`
+/// spanning 1-based `line`, outermost first
+fn enclosing_items(outline: &[OutlineItem], line: u32) -> (Vec<String>, Vec<String>, Option<String>) {
+    let mut modules = Vec::new();
+    let mut items = Vec::new();
+    let mut function = None;
+    let mut level = outline;
+    while let Some(item) = level.iter().find(|item| item.start_line <= line && line <= item.end_line) {
+        // Items inside a function are not reachable by path
+        if item.kind == "Module" && items.is_empty() {
+            modules.push(item.name.clone());
+        } else {
+            items.push(item.name.clone());
+        }
+        if FUNCTION_KINDS.contains(&item.kind.as_str()) {
+            function = Some(item.name.clone());
+        }
+        level = &item.children;
+    }
+    (modules, items, function)
+}
+
+/// The literal text of the format string `format`, split at its placeholders
+fn format_parts(format: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.next_if_eq(&'{').is_some() => parts.last_mut().unwrap().push('{'),
+            '}' if chars.next_if_eq(&'}').is_some() => parts.last_mut().unwrap().push('}'),
+            '{' => {
+                chars.by_ref().find(|c| *c == '}');
+                parts.push(String::new());
+            }
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Whether the format string `format` could have printed the text in `line`
+fn could_print(format: &str, line: &str) -> bool {
+    let parts = format_parts(format);
+    let text: usize = parts.iter().map(|part| part.chars().filter(|c| !c.is_whitespace()).count()).sum();
+    if text < MIN_LOG_TEXT {
+        return false;
+    }
+    let mut rest = line;
+    for part in &parts {
+        // A trailing newline is the logger's, not part of the line
+        let part = part.trim_end_matches('\n');
+        let Some(offset) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[offset + part.len()..];
+    }
+    true
+}
+
+impl fmt::Display for StringMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}  ({}", self.path.display(), self.line, self.text, self.module)?;
+        for item in &self.items {
+            write!(f, " > {}", item)?;
+        }
+        write!(f, ")")?;
+        if let Some(url) = &self.url {
+            write!(f, " <{}>", url)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(kind: &str, name: &str, lines: (u32, u32), children: Vec<OutlineItem>) -> OutlineItem {
+        OutlineItem { name: name.to_string(), kind: kind.to_string(), detail: None, start_line: lines.0, end_line: lines.1, children }
+    }
+
+    #[test]
+    fn test_module_path() {
+        assert_eq!(module_path(Path::new("src/lib.rs")), "crate");
+        assert_eq!(module_path(Path::new("src/daemon/mod.rs")), "crate::daemon");
+        assert_eq!(module_path(Path::new("crates/cli/src/daemon/cache.rs")), "crate::daemon::cache");
+        assert_eq!(module_path(Path::new("src/bin/lq.rs")), "crate");
+        assert_eq!(module_path(Path::new("src/bin/lq/main.rs")), "crate");
+        assert_eq!(module_path(Path::new("src/bin/lq/args.rs")), "crate::args");
+        assert_eq!(module_path(Path::new("tests/cli.rs")), "crate");
+        assert_eq!(module_path(Path::new("tests/common/mod.rs")), "crate");
+        assert_eq!(module_path(Path::new("build.rs")), "crate");
+    }
+
+    #[test]
+    fn test_enclosing_items() {
+        let outline = vec![item(
+            "Module",
+            "tests",
+            (1, 20),
+            vec![item("Object", "impl Loader", (2, 10), vec![item("Method", "load", (3, 9), Vec::new())])],
+        )];
+        let (modules, items, function) = enclosing_items(&outline, 5);
+        assert_eq!(modules, ["tests"]);
+        assert_eq!(items, ["impl Loader", "load"]);
+        assert_eq!(function.as_deref(), Some("load"));
+        assert_eq!(enclosing_items(&outline, 15), (vec!["tests".to_string()], Vec::new(), None));
+    }
+
+    #[test]
+    fn test_could_print() {
+        assert_eq!(format_parts("loaded {} items from {path:?} {{cached}}"), ["loaded ", " items from ", " {cached}"]);
+        let line = "2026-10-15T10:02:11Z WARN loaded 12 items from \"/srv/data\" {cached}";
+        assert!(could_print("loaded {} items from {path:?} {{cached}}", line));
+        assert!(could_print("loaded {} items from {:?} {{cached}}\n", line));
+        assert!(!could_print("items from {} loaded {}", line));
+        assert!(!could_print("{}: {}", line));
+    }
+
+    #[test]
+    fn test_display() {
+        let found = StringMatch {
+            path: PathBuf::from("src/daemon/cache.rs"),
+            line: 40,
+            column: 19,
+            text: "\"cache is stale\"".to_string(),
+            module: "crate::daemon::cache".to_string(),
+            items: vec!["impl ResponseCache".to_string(), "load".to_string()],
+            function: Some("load".to_string()),
+            url: None,
+        };
+        assert_eq!(found.to_string(), "src/daemon/cache.rs:40: \"cache is stale\"  (crate::daemon::cache > impl ResponseCache > load)");
+    }
+}
@@ -0,0 +1,262 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reports::ranking::RankedSymbol;
+use crate::core::reports::scan::{function_declarations, type_declarations};
+use crate::core::workspace::{cargo_manifests, rust_source_files};
+use crate::core::{Deadline, LanguageQueryService};
+
+/// Number of modules listed in the "largest modules" section
+const LARGEST_MODULES: usize = 10;
+
+/// An onboarding overview of the workspace
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Tour {
+    /// Workspace directory name
+    pub workspace: String,
+    pub crates: Vec<CrateInfo>,
+    pub entry_points: Vec<EntryPoint>,
+    pub largest_modules: Vec<ModuleSize>,
+    pub most_referenced: Vec<RankedSymbol>,
+}
+
+/// A crate in the workspace and its dependencies
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CrateInfo {
+    pub name: String,
+    /// Manifest path relative to the workspace root
+    pub manifest: PathBuf,
+    /// Dependencies on other crates of this workspace
+    pub workspace_dependencies: Vec<String>,
+    /// Number of dependencies from outside the workspace
+    pub external_dependencies: usize,
+}
+
+/// A file where execution or the public API starts
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EntryPoint {
+    /// `library`, `binary`, `example`, or `build script`
+    pub kind: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+}
+
+/// Size of a single source file
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ModuleSize {
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    pub lines: usize,
+    /// Number of functions and types declared in the file
+    pub items: usize,
+}
+
+impl LanguageQueryService {
+    /// Assemble an onboarding tour of the workspace; `deadline` cuts the most-referenced ranking short
+    pub async fn tour(&self, top: usize, deadline: Deadline) -> Result<Tour> {
+        let workspace = self.workspace_path();
+        let crates = read_crates(workspace);
+
+        let mut entry_points = Vec::new();
+        for manifest in cargo_manifests(workspace) {
+            let Some(crate_dir) = manifest.parent() else {
+                continue;
+            };
+            for (kind, path) in crate_entry_points(crate_dir) {
+                entry_points.push(EntryPoint {
+                    kind: kind.to_string(),
+                    path: self.relative_path(&path),
+                });
+            }
+        }
+
+        let mut largest_modules = Vec::new();
+        for file in rust_source_files(workspace) {
+            let Ok(contents) = tokio::fs::read_to_string(&file).await else {
+                continue;
+            };
+            largest_modules.push(ModuleSize {
+                path: self.relative_path(&file),
+                lines: contents.lines().count(),
+                items: function_declarations(&contents).len() + type_declarations(&contents).len(),
+            });
+        }
+        largest_modules.sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.path.cmp(&b.path)));
+        largest_modules.truncate(LARGEST_MODULES);
+
+        Ok(Tour {
+            workspace: workspace
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            crates,
+            entry_points,
+            largest_modules,
+            most_referenced: self.most_referenced(top, deadline).await?,
+        })
+    }
+}
+
+impl Tour {
+    /// Render the tour as a markdown document
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Tour of `{}`\n", self.workspace);
+
+        let _ = writeln!(out, "## Crates\n");
+        for krate in &self.crates {
+            let _ = writeln!(
+                out,
+                "- **{}** (`{}`) - {} external dependencies",
+                krate.name,
+                krate.manifest.display(),
+                krate.external_dependencies
+            );
+        }
+        if self.crates.iter().any(|c| !c.workspace_dependencies.is_empty()) {
+            let _ = writeln!(out, "\n```mermaid\ngraph TD");
+            for krate in &self.crates {
+                for dependency in &krate.workspace_dependencies {
+                    let _ = writeln!(out, "    {} --> {}", krate.name, dependency);
+                }
+            }
+            let _ = writeln!(out, "```");
+        }
+
+        let _ = writeln!(out, "\n## Entry points\n");
+        for entry in &self.entry_points {
+            let _ = writeln!(out, "- `{}` ({})", entry.path.display(), entry.kind);
+        }
+
+        let _ = writeln!(out, "\n## Largest modules\n");
+        let _ = writeln!(out, "| Module | Lines | Items |\n|---|---:|---:|");
+        for module in &self.largest_modules {
+            let _ = writeln!(out, "| `{}` | {} | {} |", module.path.display(), module.lines, module.items);
+        }
+
+        let _ = writeln!(out, "\n## Most referenced items\n");
+        let _ = writeln!(out, "| Item | Kind | Location | References |\n|---|---|---|---:|");
+        for symbol in &self.most_referenced {
+            let _ = writeln!(
+                out,
+                "| `{}` | {} | `{}:{}` | {} |",
+                symbol.name,
+                symbol.kind,
+                symbol.path.display(),
+                symbol.line,
+                symbol.references
+            );
+        }
+
+        out
+    }
+}
+
+/// Read every crate manifest in the workspace and link path dependencies between them
+fn read_crates(workspace: &Path) -> Vec<CrateInfo> {
+    let mut crates = Vec::new();
+    let mut dependency_names = Vec::new();
+
+    for manifest in cargo_manifests(workspace) {
+        let Ok(contents) = std::fs::read_to_string(&manifest) else {
+            continue;
+        };
+        let Ok(value) = contents.parse::<toml::Table>() else {
+            continue;
+        };
+        let Some(name) = value
+            .get("package")
+            .and_then(|p| p.get("name"))
+            .and_then(|n| n.as_str())
+        else {
+            continue;
+        };
+
+        let mut dependencies = Vec::new();
+        for section in ["dependencies", "dev-dependencies", "build-dependencies"] {
+            if let Some(table) = value.get(section).and_then(|d| d.as_table()) {
+                dependencies.extend(table.keys().cloned());
+            }
+        }
+        dependencies.sort();
+        dependencies.dedup();
+
+        crates.push(CrateInfo {
+            name: name.to_string(),
+            manifest: manifest.strip_prefix(workspace).unwrap_or(&manifest).to_path_buf(),
+            workspace_dependencies: Vec::new(),
+            external_dependencies: 0,
+        });
+        dependency_names.push(dependencies);
+    }
+
+    let members: Vec<String> = crates.iter().map(|c| c.name.clone()).collect();
+    for (krate, dependencies) in crates.iter_mut().zip(dependency_names) {
+        let (internal, external): (Vec<String>, Vec<String>) = dependencies
+            .into_iter()
+            .partition(|d| members.contains(d) || members.contains(&d.replace('-', "_")));
+        krate.workspace_dependencies = internal;
+        krate.external_dependencies = external.len();
+    }
+
+    crates
+}
+
+/// Conventional Cargo target locations within a crate directory
+fn crate_entry_points(crate_dir: &Path) -> Vec<(&'static str, PathBuf)> {
+    let mut entries = Vec::new();
+
+    for (kind, relative) in [("library", "src/lib.rs"), ("binary", "src/main.rs"), ("build script", "build.rs")] {
+        let path = crate_dir.join(relative);
+        if path.exists() {
+            entries.push((kind, path));
+        }
+    }
+
+    for (kind, dir) in [("binary", "src/bin"), ("example", "examples")] {
+        let Ok(read_dir) = std::fs::read_dir(crate_dir.join(dir)) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = read_dir
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "rs") || p.join("main.rs").exists())
+            .collect();
+        paths.sort();
+        entries.extend(paths.into_iter().map(|p| (kind, p)));
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_crates_links_workspace_members() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("core")).unwrap();
+        std::fs::create_dir_all(root.join("cli")).unwrap();
+        std::fs::write(root.join("core/Cargo.toml"), "[package]\nname = \"my-core\"\n\n[dependencies]\nserde = \"1\"\n").unwrap();
+        std::fs::write(
+            root.join("cli/Cargo.toml"),
+            "[package]\nname = \"my-cli\"\n\n[dependencies]\nmy-core = { path = \"../core\" }\nclap = \"4\"\n",
+        )
+        .unwrap();
+
+        let crates = read_crates(root);
+        let cli = crates.iter().find(|c| c.name == "my-cli").unwrap();
+        assert_eq!(cli.workspace_dependencies, vec!["my-core".to_string()]);
+        assert_eq!(cli.external_dependencies, 1);
+        let core = crates.iter().find(|c| c.name == "my-core").unwrap();
+        assert!(core.workspace_dependencies.is_empty());
+    }
+}
@@ -1,51 +1,384 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use anyhow::{Result, bail};
+use tracing::debug;
 
-use crate::lsp::{LspConnection, RustAnalyzerConnection};
+use crate::core::symbol_path::{item_path, parse_path, path_matches, pick_item};
+use crate::core::text_search::{declaration_docs, find_declarations, find_references};
+use crate::core::workspace::rust_source_files;
+use crate::core::Deadline;
+use crate::edit::{apply_text_edits, EditPreview};
+use crate::lsp::{normalize_references, rank_symbols, Call, CallDirection, CodeAction, Definition, Diagnostic, HintedLine, IndexingStatus, InlayHint, ItemView, LspConnection, MacroExpansion, OutlineItem, Reference, Runnable, RustAnalyzerConnection, ServerHealth, ServerOptions, Severity, SupervisedConnection, SymbolMatch, TextEdit, TypeHierarchy};
 
 pub struct LanguageQueryService {
     lsp: Box<dyn LspConnection>,
     workspace: PathBuf,
+    options: ServerOptions,
 }
 
 impl LanguageQueryService {
     pub async fn new(workspace: &Path) -> Result<Self> {
-        // Check if we have a Rust project
-        if workspace.join("Cargo.toml").exists() {
-            let lsp = Box::new(RustAnalyzerConnection::new(workspace).await?);
-            Ok(Self {
-                lsp,
-                workspace: workspace.to_path_buf(),
-            })
-        } else {
-            bail!("Not a Rust project (no Cargo.toml found). Only Rust projects are currently supported.")
+        Self::with_options(workspace, ServerOptions::default()).await
+    }
+    
+    pub async fn with_options(workspace: &Path, options: ServerOptions) -> Result<Self> {
+        Self::check_workspace(workspace)?;
+        let lsp = Box::new(SupervisedConnection::start(workspace, options.clone()).await);
+        Ok(Self {
+            lsp,
+            workspace: workspace.to_path_buf(),
+            options,
+        })
+    }
+    
+    /// Fail unless `workspace` is a project the language server can analyze
+    pub fn check_workspace(workspace: &Path) -> Result<()> {
+        if !workspace.join("Cargo.toml").exists() {
+            bail!("Not a Rust project (no Cargo.toml found). Only Rust projects are currently supported.");
         }
+        Ok(())
+    }
+    
+    /// A service for a short-lived analysis, such as of another revision: its
+    /// language server is not restarted after a crash and stops when the service is dropped
+    pub async fn unsupervised(workspace: &Path, options: ServerOptions) -> Result<Self> {
+        Self::check_workspace(workspace)?;
+        // Its progress is not that of the workspace's own server
+        let options = ServerOptions { progress: None, ..options };
+        Ok(Self {
+            lsp: Box::new(RustAnalyzerConnection::with_options(workspace, options.clone()).await?),
+            workspace: workspace.to_path_buf(),
+            options,
+        })
+    }
+    
+    /// How the language server was started
+    pub fn options(&self) -> &ServerOptions {
+        &self.options
     }
     
     pub fn workspace_path(&self) -> &Path {
         &self.workspace
     }
     
-    pub async fn get_docs(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<String>> {
-        self.lsp.hover(file, line, symbol).await
+    /// Strip the workspace root from a path for display
+    pub fn relative_path(&self, file: &Path) -> PathBuf {
+        file.strip_prefix(&self.workspace).unwrap_or(file).to_path_buf()
+    }
+    
+    /// Process id of the language server, if it runs as a child process
+    pub fn server_pid(&self) -> Option<u32> {
+        self.lsp.server_pid()
     }
     
-    pub async fn get_impl(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<String>> {
-        self.lsp.implementation(file, line, symbol).await
+    /// Kill the language server so that it is started afresh, for `reason`;
+    /// false if it is not running or is not supervised
+    pub fn restart_server(&self, reason: &str) -> bool {
+        self.lsp.restart(reason)
     }
     
-    pub async fn get_refs(&self, file: &Path, line: u32, symbol: &str) -> Result<Vec<String>> {
-        self.lsp.references(file, line, symbol).await
+    /// Whether the language server is up, and why not if it crashed
+    pub fn server_health(&self) -> ServerHealth {
+        self.lsp.health()
+    }
+    
+    /// Whether the language server is down, so that docs, references, resolve and
+    /// search fall back to text search
+    pub fn is_degraded(&self) -> bool {
+        self.lsp.health().is_degraded()
+    }
+    
+    /// Current indexing progress of the language server
+    pub fn indexing_status(&self) -> IndexingStatus {
+        self.lsp.indexing_status()
+    }
+    
+    pub async fn get_docs(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Option<String>> {
+        if self.is_degraded() {
+            return Ok(declaration_docs(&self.workspace, file, symbol).map(|(_, docs)| docs));
+        }
+        self.lsp.hover(file, line, column, symbol).await
+    }
+    
+    /// Every candidate definition of the item named by `symbol`
+    pub async fn get_definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        self.lsp.definition(file, line, column, symbol).await
+    }
+    
+    /// Find references, merging hits that share a file and line
+    pub async fn get_refs(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Reference>> {
+        if self.is_degraded() {
+            return Ok(normalize_references(find_references(&self.workspace, symbol)));
+        }
+        Ok(normalize_references(self.lsp.references(file, line, column, symbol).await?))
+    }
+    
+    /// Find references exactly as the language server reports them, without annotations
+    pub async fn get_refs_raw(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Reference>> {
+        if self.is_degraded() {
+            return Ok(find_references(&self.workspace, symbol));
+        }
+        let mut references = self.lsp.references(file, line, column, symbol).await?;
+        for reference in &mut references {
+            reference.macro_generated = false;
+        }
+        Ok(references)
     }
     
     pub async fn resolve_symbol(&self, file: &Path, symbol: &str) -> Result<Option<String>> {
+        if self.is_degraded() {
+            return Ok(declaration_docs(&self.workspace, file, symbol).map(|(path, docs)| {
+                format!("Found symbol `{}` in {}:\n\n{}", symbol, path.display(), docs)
+            }));
+        }
         self.lsp.resolve_symbol(file, symbol).await
     }
+    
+    pub async fn hover_at(&self, file: &Path, line: u32, column: u32) -> Result<Option<String>> {
+        self.lsp.hover_at(file, line, column).await
+    }
+    
+    /// Docs for the expression or statement a selection covers
+    pub async fn hover_range(&self, file: &Path, start: (u32, u32), end: (u32, u32)) -> Result<Option<String>> {
+        self.lsp.hover_range(file, start, end).await
+    }
+    
+    /// The items declared in a file, nested by containment
+    pub async fn outline(&self, file: &Path) -> Result<Vec<OutlineItem>> {
+        self.lsp.document_symbols(file).await
+    }
+    
+    /// Every implementation of the trait, trait method or type named by `symbol`, in file order
+    pub async fn get_impls(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        let mut implementations = self.lsp.implementations(file, line, column, symbol).await?;
+        implementations.sort_by(|a, b| a.path.cmp(&b.path).then(a.start_line.cmp(&b.start_line)));
+        Ok(implementations)
+    }
+    
+    /// Where the type of the variable or expression named by `symbol` is declared
+    pub async fn get_type_definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        self.lsp.type_definition(file, line, column, symbol).await
+    }
+    
+    /// What renaming the symbol to `new_name` would change, without touching any file
+    pub async fn rename(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str, new_name: &str) -> Result<EditPreview> {
+        EditPreview::new(self.lsp.rename(file, line, column, symbol, new_name).await?)
+    }
+    
+    /// Code actions and quick fixes offered at `symbol` on `line`, or anywhere on the line
+    pub async fn code_actions(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Vec<CodeAction>> {
+        self.lsp.code_actions(file, line, column, symbol).await
+    }
+    
+    /// The recursive expansion of the macro at `symbol` on `line`, or of the first macro on it
+    pub async fn expand_macro(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Option<MacroExpansion>> {
+        self.lsp.expand_macro(file, line, column, symbol).await
+    }
+    
+    /// The lowered HIR of the innermost function, const or static body on `line`
+    pub async fn view_hir(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        self.lsp.view_hir(file, line).await
+    }
+    
+    /// The syntax tree of the innermost item on `line`
+    pub async fn syntax_tree(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        self.lsp.syntax_tree(file, line).await
+    }
+    
+    /// What the structural search and replace `rule` would change across the workspace,
+    /// or within `lines` of `file`, without touching any file
+    pub async fn structural_replace(&self, rule: &str, file: Option<&Path>, lines: Option<(u32, u32)>) -> Result<EditPreview> {
+        EditPreview::new(self.lsp.structural_replace(rule, file, lines).await?)
+    }
+    
+    /// The tests, benchmarks and binaries declared in `file`, or in every Rust file
+    /// of the workspace; files not reached before `deadline` are left out
+    pub async fn runnables(&self, file: Option<&Path>, deadline: Deadline) -> Result<Vec<Runnable>> {
+        if let Some(file) = file {
+            return self.lsp.runnables(file).await;
+        }
+        let mut runnables: Vec<Runnable> = Vec::new();
+        for file in rust_source_files(&self.workspace) {
+            let Some(found) = deadline.within(self.lsp.runnables(&file)).await else {
+                break;
+            };
+            match found {
+                Ok(found) => {
+                    for runnable in found {
+                        // Package-wide commands come back for every file in the package
+                        if !runnables.iter().any(|r| r.command == runnable.command) {
+                            runnables.push(runnable);
+                        }
+                    }
+                }
+                Err(e) => debug!("No runnables for {:?}: {}", file, e),
+            }
+        }
+        Ok(runnables)
+    }
+    
+    /// Lines of `file` with their inlay hints written in: every line of `lines`
+    /// when a range is given, otherwise only the lines that have hints
+    pub async fn inlay_hints(&self, file: &Path, lines: Option<(u32, u32)>) -> Result<Vec<HintedLine>> {
+        let hints = self.lsp.inlay_hints(file, lines).await?;
+        let contents = std::fs::read_to_string(file)?;
+        hinted_lines(&self.relative_path(file), &contents, hints, lines)
+    }
+    
+    /// The `limit` workspace symbols best matching `query`
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SymbolMatch>> {
+        let symbols = if self.is_degraded() {
+            let query = query.to_lowercase();
+            find_declarations(&self.workspace, |name| name.to_lowercase().contains(&query))
+        } else {
+            self.lsp.workspace_symbols(query).await?
+        };
+        let mut symbols = rank_symbols(query, symbols);
+        symbols.truncate(limit);
+        Ok(symbols)
+    }
+    
+    /// The item a qualified path such as `my_crate::module::Type::method` names
+    pub async fn locate_path(&self, path: &str) -> Result<SymbolMatch> {
+        let query = parse_path(path)?;
+        let name = query.last().copied().unwrap_or_default();
+        let mut candidates = Vec::new();
+        for symbol in self.search(name, usize::MAX).await?.into_iter().filter(|symbol| symbol.name == name) {
+            // Without an outline only the file's own module path is known
+            let outline = self.outline(&symbol.file).await.unwrap_or_default();
+            let item = item_path(&symbol, &outline);
+            if path_matches(&query, &item) {
+                candidates.push((item, symbol));
+            }
+        }
+        pick_item(path, candidates)
+    }
+    
+    /// Diagnostics for `file`, or for the whole workspace, at least as severe as `severity`
+    pub async fn diagnostics(&self, file: Option<&Path>, severity: Severity) -> Result<Vec<Diagnostic>> {
+        let mut diagnostics = self.lsp.diagnostics(file).await?;
+        diagnostics.retain(|d| d.severity <= severity);
+        Ok(diagnostics)
+    }
+    
+    /// Traits a type implements, or supertraits and implementors of a trait
+    pub async fn type_hierarchy(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<TypeHierarchy> {
+        self.lsp.type_hierarchy(file, line, column, symbol).await
+    }
+    
+    /// Walk the call hierarchy of a function up to `depth` levels.
+    ///
+    /// Functions already on the current path are not expanded again, so recursion
+    /// shows up as a leaf instead of repeating. Levels not reached before
+    /// `deadline` are left unexpanded.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn call_hierarchy(
+        &self,
+        file: &Path,
+        line: u32,
+        column: Option<u32>,
+        symbol: &str,
+        direction: CallDirection,
+        depth: usize,
+        deadline: Deadline,
+    ) -> Result<Vec<Call>> {
+        let mut walk = CallWalk {
+            direction,
+            deadline,
+            path: HashSet::new(),
+        };
+        walk.path.insert((file.to_path_buf(), symbol.to_string()));
+        self.expand_calls(file, line, column, symbol, depth.max(1), &mut walk).await
+    }
+    
+    async fn expand_calls(
+        &self,
+        file: &Path,
+        line: u32,
+        column: Option<u32>,
+        symbol: &str,
+        depth: usize,
+        walk: &mut CallWalk,
+    ) -> Result<Vec<Call>> {
+        let (direction, deadline) = (walk.direction, walk.deadline);
+        let calls = match direction {
+            CallDirection::Incoming => deadline.within(self.lsp.incoming_calls(file, line, column, symbol)).await,
+            CallDirection::Outgoing => deadline.within(self.lsp.outgoing_calls(file, line, column, symbol)).await,
+        };
+        let Some(calls) = calls else {
+            return Ok(Vec::new());
+        };
+        let mut calls = calls?;
+        
+        if depth > 1 {
+            for call in &mut calls {
+                let key = (call.file.clone(), call.name.clone());
+                if !walk.path.insert(key.clone()) {
+                    continue;
+                }
+                match Box::pin(self.expand_calls(&call.file, call.line, Some(call.column), &call.name, depth - 1, walk)).await {
+                    Ok(children) => call.calls = children,
+                    Err(e) => debug!("Not expanding {} at {:?}:{}: {}", call.name, call.file, call.line, e),
+                }
+                walk.path.remove(&key);
+            }
+        }
+        
+        Ok(calls)
+    }
+}
+
+/// Write each hint into its source line, the way an editor displays it
+fn hinted_lines(path: &Path, contents: &str, hints: Vec<InlayHint>, lines: Option<(u32, u32)>) -> Result<Vec<HintedLine>> {
+    let mut hinted = Vec::new();
+    for (index, source) in contents.lines().enumerate() {
+        let line = index as u32 + 1;
+        let line_hints: Vec<InlayHint> = hints.iter().filter(|hint| hint.line == line).cloned().collect();
+        let in_range = match lines {
+            Some((start, end)) => (start..=end).contains(&line),
+            None => !line_hints.is_empty(),
+        };
+        if !in_range {
+            continue;
+        }
+        
+        let inserts: Vec<TextEdit> = line_hints
+            .iter()
+            .map(|hint| TextEdit {
+                start_line: 1,
+                start_column: hint.column,
+                end_line: 1,
+                end_column: hint.column,
+                new_text: format!(
+                    "{}{}{}",
+                    if hint.padding_left { " " } else { "" },
+                    hint.label,
+                    if hint.padding_right { " " } else { "" }
+                ),
+            })
+            .collect();
+        hinted.push(HintedLine {
+            path: path.to_path_buf(),
+            line,
+            text: apply_text_edits(source, &inserts)?,
+            hints: line_hints,
+        });
+    }
+    Ok(hinted)
+}
+
+/// State shared across one call hierarchy walk
+struct CallWalk {
+    direction: CallDirection,
+    deadline: Deadline,
+    /// Functions on the path from the root to the call being expanded
+    path: HashSet<(PathBuf, String)>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lsp::InlayHintKind;
     use tempfile::TempDir;
     
     fn ensure_rust_analyzer() {
@@ -145,7 +478,7 @@ pub fn create_map() -> HashMap<String, i32> {
         // No need to wait - the service waits for ready internally
         
         // Get docs for TestStruct
-        let result = service.get_docs(&lib_file, 4, "TestStruct").await;
+        let result = service.get_docs(&lib_file, 4, None, "TestStruct").await;
         
         match result {
             Ok(Some(docs)) => {
@@ -166,10 +499,10 @@ pub fn create_map() -> HashMap<String, i32> {
         let service = LanguageQueryService::new(temp_dir.path()).await.unwrap();
         
         // Try to get implementation of TestStruct at the struct definition
-        let result = service.get_impl(&lib_file, 4, "TestStruct").await.unwrap();
+        let result = service.get_definition(&lib_file, 4, None, "TestStruct").await.unwrap();
         
-        if let Some(implementation) = result {
-            let redacted = redact_temp_path(&implementation, temp_dir.path());
+        if let Some(implementation) = result.first() {
+            let redacted = redact_temp_path(&implementation.to_string(), temp_dir.path());
             insta::assert_snapshot!("test_impl_command", redacted);
         } else {
             // It's OK if go-to-definition returns None for struct definition
@@ -185,7 +518,7 @@ pub fn create_map() -> HashMap<String, i32> {
         let service = LanguageQueryService::new(temp_dir.path()).await.unwrap();
         
         // Find references to TestStruct
-        let result = service.get_refs(&lib_file, 4, "TestStruct").await;
+        let result = service.get_refs(&lib_file, 4, None, "TestStruct").await;
         
         match result {
             Ok(refs) => {
@@ -193,7 +526,7 @@ pub fn create_map() -> HashMap<String, i32> {
                 
                 // Redact temp paths in all references
                 let redacted_refs: Vec<String> = refs.iter()
-                    .map(|r| redact_temp_path(r, temp_dir.path()))
+                    .map(|r| redact_temp_path(&r.to_string(), temp_dir.path()))
                     .collect();
                 
                 insta::assert_snapshot!("test_refs_command", redacted_refs.join("\n"));
@@ -219,4 +552,32 @@ pub fn create_map() -> HashMap<String, i32> {
             panic!("Expected to resolve HashMap");
         }
     }
+    
+    #[test]
+    fn test_hinted_lines() {
+        let hint = |line, column, label: &str, kind, padding_right| InlayHint {
+            line,
+            column,
+            label: label.to_string(),
+            kind,
+            padding_left: false,
+            padding_right,
+        };
+        let contents = "fn main() {\n    let s = add(\"é\".len(), 2);\n}\n";
+        let hints = vec![
+            hint(2, 10, ": usize", InlayHintKind::Type, false),
+            hint(2, 17, "a:", InlayHintKind::Parameter, true),
+            hint(2, 28, "b:", InlayHintKind::Parameter, true),
+        ];
+        let path = Path::new("src/main.rs");
+        
+        let lines = hinted_lines(path, contents, hints.clone(), None).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].to_string(), "src/main.rs:2:     let s: usize = add(a: \"é\".len(), b: 2);");
+        assert_eq!(lines[0].hints, hints);
+        
+        let lines = hinted_lines(path, contents, hints, Some((1, 2))).unwrap();
+        let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec!["fn main() {", "    let s: usize = add(a: \"é\".len(), b: 2);"]);
+    }
 }
\ No newline at end of file
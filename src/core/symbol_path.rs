@@ -0,0 +1,214 @@
+//! Qualified paths such as `my_crate::module::Type::method`, resolved without a
+//! file or line.
+//!
+//! Workspace symbol search finds every item named by the path's last segment,
+//! but reports only the type or trait each one is declared in. The rest of an
+//! item's path comes from where it is declared: the crate and module its file
+//! is for, and the inline modules around it in the file's outline. A path that
+//! starts with `crate` must match an item's path from the crate root; any other
+//! path matches its end, so the crate and leading modules can be left out and
+//! `Circle::area` finds `area` in every `impl Circle`. Items in trait impls lose
+//! ties to inherent items and trait declarations, which is where a path like
+//! that usually points.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::lsp::{OutlineItem, SymbolMatch};
+
+/// The full path of a declared item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemPath {
+    /// The crate, modules, declaring type or trait, and the item's name
+    pub segments: Vec<String>,
+    /// Whether the item is declared in an `impl Trait for Type` block
+    pub in_trait_impl: bool,
+}
+
+impl fmt::Display for ItemPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segments.join("::"))
+    }
+}
+
+/// The segments of `path`, checked to be a qualified path
+pub fn parse_path(path: &str) -> Result<Vec<&str>> {
+    let segments: Vec<&str> = path.trim_start_matches("::").split("::").collect();
+    let is_segment = |segment: &&str| !segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if !segments.iter().all(is_segment) || segments.last() == Some(&"crate") {
+        bail!("Invalid path {:?}; expected something like my_crate::module::Type::method", path);
+    }
+    Ok(segments)
+}
+
+/// The path of `symbol`, given the outline of the file it is declared in
+pub fn item_path(symbol: &SymbolMatch, outline: &[OutlineItem]) -> ItemPath {
+    let mut segments = file_module_path(&symbol.file);
+    let mut in_trait_impl = false;
+    // Descend through the items with a child around the symbol; the symbol itself has none
+    let encloses = |item: &&OutlineItem| item.children.iter().any(|child| child.start_line <= symbol.line && symbol.line <= child.end_line);
+    let mut items = outline;
+    while let Some(parent) = items.iter().find(encloses) {
+        match parent.kind.as_str() {
+            "Module" => segments.push(parent.name.clone()),
+            "Object" if parent.name.contains(" for ") => in_trait_impl = true,
+            _ => {}
+        }
+        items = &parent.children;
+    }
+    if let Some(container) = &symbol.container {
+        // Generic types are reported with their parameters, as in `Stack<T>`
+        let container = container.split('<').next().unwrap_or_default().trim();
+        if segments.last().map(String::as_str) != Some(container) {
+            segments.push(container.to_string());
+        }
+    }
+    segments.push(symbol.name.clone());
+    ItemPath { segments, in_trait_impl }
+}
+
+/// Whether the `query` segments name the item at `item`
+pub fn path_matches(query: &[&str], item: &ItemPath) -> bool {
+    let segments: Vec<&str> = item.segments.iter().map(String::as_str).collect();
+    match query.split_first() {
+        Some((&"crate", rest)) => segments.get(1..) == Some(rest),
+        _ => segments.ends_with(query),
+    }
+}
+
+/// The one item `path` names among `candidates`, or an error listing them
+pub fn pick_item(path: &str, mut candidates: Vec<(ItemPath, SymbolMatch)>) -> Result<SymbolMatch> {
+    candidates.sort_by(|a, b| (&a.1.file, a.1.line, a.1.column).cmp(&(&b.1.file, b.1.line, b.1.column)));
+    candidates.dedup_by(|a, b| (&a.1.file, a.1.line, a.1.column) == (&b.1.file, b.1.line, b.1.column));
+    if candidates.iter().any(|(item, _)| !item.in_trait_impl) {
+        candidates.retain(|(item, _)| !item.in_trait_impl);
+    }
+    match candidates.len() {
+        0 => bail!("No item named {} in the workspace", path),
+        1 => Ok(candidates.remove(0).1),
+        _ => {
+            let listed: Vec<String> = candidates
+                .iter()
+                .map(|(item, symbol)| format!("  {} ({}:{})", item, symbol.path.display(), symbol.line))
+                .collect();
+            bail!("{} names {} items; qualify it further or give a file and line:\n{}", path, candidates.len(), listed.join("\n"))
+        }
+    }
+}
+
+/// The crate and modules of a source file, from where it sits in its package
+pub fn file_module_path(file: &Path) -> Vec<String> {
+    let Some(package) = file.ancestors().skip(1).find(|dir| dir.join("Cargo.toml").is_file()) else {
+        return Vec::new();
+    };
+    let crate_name = package_name(package).replace('-', "_");
+    let parts: Vec<&str> = file
+        .strip_prefix(package)
+        .unwrap_or(file)
+        .iter()
+        .filter_map(|part| part.to_str())
+        .map(|part| part.strip_suffix(".rs").unwrap_or(part))
+        .collect();
+    match parts[..] {
+        ["src", "lib" | "main"] => vec![crate_name],
+        // Binaries, tests, examples and benches are crates of their own
+        ["src", "bin", name] | ["src", "bin", name, "main"] => vec![name.replace('-', "_")],
+        ["tests" | "examples" | "benches", name, ..] => vec![name.replace('-', "_")],
+        ["src", ref modules @ ..] => std::iter::once(crate_name)
+            .chain(modules.iter().filter(|&&module| module != "mod").map(|module| module.to_string()))
+            .collect(),
+        _ => vec![crate_name],
+    }
+}
+
+/// The package name in `package`'s manifest, or the directory name without one
+fn package_name(package: &Path) -> String {
+    std::fs::read_to_string(package.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| manifest.parse::<toml::Table>().ok())
+        .and_then(|manifest| manifest.get("package")?.get("name")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| package.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn symbol(file: PathBuf, line: u32, container: Option<&str>) -> SymbolMatch {
+        SymbolMatch {
+            name: "area".to_string(),
+            kind: "Function".to_string(),
+            container: container.map(str::to_string),
+            path: file.file_name().unwrap().into(),
+            file,
+            line,
+            column: 8,
+            url: None,
+        }
+    }
+
+    fn outline_item(kind: &str, name: &str, lines: (u32, u32), children: Vec<OutlineItem>) -> OutlineItem {
+        OutlineItem {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            detail: None,
+            start_line: lines.0,
+            end_line: lines.1,
+            children,
+        }
+    }
+
+    #[test]
+    fn test_item_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"geo-kit\"\n").unwrap();
+        let lib = dir.path().join("src/lib.rs");
+        let outline = vec![outline_item("Module", "units", (1, 9), vec![
+            outline_item("Struct", "Circle", (2, 2), Vec::new()),
+            outline_item("Object", "impl Circle", (3, 8), vec![outline_item("Method", "area", (4, 7), Vec::new())]),
+        ])];
+        assert_eq!(item_path(&symbol(lib.clone(), 5, Some("Circle")), &outline).to_string(), "geo_kit::units::Circle::area");
+        assert_eq!(item_path(&symbol(lib, 12, None), &outline).to_string(), "geo_kit::area");
+
+        let shapes = dir.path().join("src/shapes/mod.rs");
+        let outline = vec![outline_item("Object", "impl Shape for Circle", (1, 5), vec![outline_item("Method", "area", (2, 4), Vec::new())])];
+        let item = item_path(&symbol(shapes, 3, Some("Circle")), &outline);
+        assert_eq!((item.to_string(), item.in_trait_impl), ("geo_kit::shapes::Circle::area".to_string(), true));
+        assert_eq!(item_path(&symbol(dir.path().join("src/bin/geo-cli.rs"), 1, None), &[]).to_string(), "geo_cli::area");
+    }
+
+    #[test]
+    fn test_path_matches() {
+        let item = ItemPath { segments: ["geo_kit", "shapes", "Circle", "area"].map(String::from).to_vec(), in_trait_impl: false };
+        assert!(path_matches(&["Circle", "area"], &item));
+        assert!(path_matches(&["geo_kit", "shapes", "Circle", "area"], &item));
+        assert!(path_matches(&["crate", "shapes", "Circle", "area"], &item));
+        assert!(!path_matches(&["crate", "Circle", "area"], &item));
+        assert!(!path_matches(&["units", "Circle", "area"], &item));
+
+        assert_eq!(parse_path("geo_kit::Circle::area").unwrap(), ["geo_kit", "Circle", "area"]);
+        assert!(parse_path("geo_kit::").is_err());
+        assert!(parse_path("Vec<u8>::len").is_err());
+    }
+
+    #[test]
+    fn test_pick_item() {
+        let inherent = ItemPath { segments: vec!["Circle".to_string(), "area".to_string()], in_trait_impl: false };
+        let trait_impl = ItemPath { in_trait_impl: true, ..inherent.clone() };
+        let picked = pick_item("Circle::area", vec![
+            (trait_impl.clone(), symbol("src/shapes.rs".into(), 19, Some("Circle"))),
+            (inherent.clone(), symbol("src/shapes.rs".into(), 8, Some("Circle"))),
+        ]);
+        assert_eq!(picked.unwrap().line, 8);
+
+        let error = pick_item("Circle::area", vec![
+            (inherent.clone(), symbol("src/shapes.rs".into(), 8, Some("Circle"))),
+            (inherent, symbol("src/units.rs".into(), 3, Some("Circle"))),
+        ]);
+        assert!(error.unwrap_err().to_string().contains("Circle::area (units.rs:3)"));
+        assert!(pick_item("Circle::perimeter", Vec::new()).is_err());
+    }
+}
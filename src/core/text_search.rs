@@ -0,0 +1,211 @@
+//! Plain-text answers for when the language server is down.
+//!
+//! References are whole-word matches and declarations are recognized by their
+//! leading keyword, so results include false positives (same-named items,
+//! comments, strings) and miss anything generated by macros. The daemon flags
+//! such answers as degraded.
+
+use std::path::{Path, PathBuf};
+
+use crate::core::workspace::rust_source_files;
+use crate::lsp::render::{fenced, item_excerpt, line_text, relative_path, DEFAULT_EXCERPT_LINES};
+use crate::lsp::{Anchor, Reference, SymbolMatch};
+
+/// Item keywords and the LSP symbol kind names they correspond to
+const DECLARATION_KINDS: &[(&str, &str)] = &[
+    ("fn", "Function"),
+    ("struct", "Struct"),
+    ("enum", "Enum"),
+    ("union", "Struct"),
+    ("trait", "Interface"),
+    ("type", "TypeParameter"),
+    ("const", "Constant"),
+    ("static", "Variable"),
+    ("mod", "Module"),
+    ("macro_rules!", "Function"),
+];
+
+/// Every whole-word occurrence of `symbol` in the workspace's Rust files
+pub fn find_references(workspace: &Path, symbol: &str) -> Vec<Reference> {
+    let mut references = Vec::new();
+    for file in rust_source_files(workspace) {
+        let Ok(contents) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        let path = relative_path(workspace, &file);
+        let lines: Vec<&str> = contents.lines().collect();
+        for (index, line) in lines.iter().enumerate() {
+            for column in word_matches(line, symbol) {
+                references.push(Reference {
+                    file: file.clone(),
+                    path: path.clone(),
+                    line: index as u32 + 1,
+                    column: column + 1,
+                    end_line: index as u32 + 1,
+                    end_column: column + symbol.chars().count() as u32 + 1,
+                    text: line_text(&lines, index),
+                    occurrences: 1,
+                    macro_generated: false,
+                    anchor: Anchor::at(&lines, index as u32 + 1, column + 1),
+                    url: None,
+                    context_before: Vec::new(),
+                    context_after: Vec::new(),
+                });
+            }
+        }
+    }
+    references
+}
+
+/// Items declared anywhere in the workspace whose name satisfies `matches`
+pub fn find_declarations(workspace: &Path, matches: impl Fn(&str) -> bool) -> Vec<SymbolMatch> {
+    let mut declarations = Vec::new();
+    for file in rust_source_files(workspace) {
+        let Ok(contents) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        let path = relative_path(workspace, &file);
+        for (index, line) in contents.lines().enumerate() {
+            let Some((kind, name, column)) = parse_declaration(line) else {
+                continue;
+            };
+            if matches(name) {
+                declarations.push(SymbolMatch {
+                    name: name.to_string(),
+                    kind: kind.to_string(),
+                    container: None,
+                    file: file.clone(),
+                    path: path.clone(),
+                    line: index as u32 + 1,
+                    column: column as u32 + 1,
+                    url: None,
+                });
+            }
+        }
+    }
+    declarations
+}
+
+/// Hover-style text for the declaration of `symbol`: its source and doc comment.
+///
+/// Declarations in `file` are preferred, since that is where the name was seen.
+pub fn declaration_docs(workspace: &Path, file: &Path, symbol: &str) -> Option<(PathBuf, String)> {
+    let mut declarations = find_declarations(workspace, |name| name == symbol);
+    declarations.sort_by_key(|d| d.file != file);
+    let declaration = declarations.into_iter().next()?;
+
+    let contents = std::fs::read_to_string(&declaration.file).ok()?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = declaration.line as usize - 1;
+
+    let docs: Vec<&str> = lines[..start]
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with("///") || line.starts_with("#["))
+        .filter_map(|line| line.strip_prefix("///"))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect();
+    let docs: Vec<&str> = docs.into_iter().rev().collect();
+
+    let mut text = fenced("rust", &item_excerpt(&lines, start, DEFAULT_EXCERPT_LINES));
+    if !docs.is_empty() {
+        text.push_str(&format!("\n\n---\n\n{}", docs.join("\n")));
+    }
+    Some((declaration.path, text))
+}
+
+/// Keyword, name and 0-based column of the name of an item declared on `line`
+fn parse_declaration(line: &str) -> Option<(&'static str, &str, usize)> {
+    let mut rest = line.trim_start();
+    if let Some(after) = rest.strip_prefix("pub") {
+        rest = match after.trim_start().strip_prefix('(') {
+            Some(scoped) => scoped.split_once(')')?.1,
+            None if after.starts_with(char::is_whitespace) => after,
+            None => return None,
+        }
+        .trim_start();
+    }
+    loop {
+        let stripped = ["async ", "unsafe ", "default ", "extern \"C\" ", "extern "]
+            .iter()
+            .find_map(|qualifier| rest.strip_prefix(qualifier));
+        match stripped {
+            // `const fn` is a function, but `const NAME` is a constant
+            Some(after) => rest = after.trim_start(),
+            None if rest.starts_with("const fn ") => rest = &rest["const ".len()..],
+            None => break,
+        }
+    }
+
+    for (keyword, kind) in DECLARATION_KINDS {
+        let Some(after) = rest.strip_prefix(keyword) else {
+            continue;
+        };
+        if !keyword.ends_with('!') && !after.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let name_start = after.trim_start();
+        let name_len = name_start.find(|c: char| !is_identifier_char(c)).unwrap_or(name_start.len());
+        if name_len == 0 {
+            return None;
+        }
+        let column = line.len() - name_start.len();
+        return Some((kind, &name_start[..name_len], line[..column].chars().count()));
+    }
+    None
+}
+
+/// 0-based character columns where `word` appears on `line` as a whole identifier
+fn word_matches(line: &str, word: &str) -> Vec<u32> {
+    line.match_indices(word)
+        .filter(|&(start, _)| {
+            let before = line[..start].chars().next_back();
+            let after = line[start + word.len()..].chars().next();
+            !before.is_some_and(is_identifier_char) && !after.is_some_and(is_identifier_char)
+        })
+        .map(|(start, _)| line[..start].chars().count() as u32)
+        .collect()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_declaration() {
+        assert_eq!(parse_declaration("pub struct Square(pub f64);"), Some(("Struct", "Square", 11)));
+        assert_eq!(parse_declaration("    pub(crate) async fn load() {"), Some(("Function", "load", 24)));
+        assert_eq!(parse_declaration("pub const fn new() -> Self {"), Some(("Function", "new", 13)));
+        assert_eq!(parse_declaration("const LIMIT: usize = 3;"), Some(("Constant", "LIMIT", 6)));
+        assert_eq!(parse_declaration("macro_rules! lq {"), Some(("Function", "lq", 13)));
+        assert_eq!(parse_declaration("let fn_name = 1;"), None);
+        assert_eq!(parse_declaration("public fn x() {}"), None);
+    }
+
+    #[test]
+    fn test_text_search() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("lib.rs"),
+            "/// A square\n#[derive(Debug)]\npub struct Square(pub f64);\nfn area(s: &Square) -> f64 { s.0 }\nstruct Squared;\n",
+        )
+        .unwrap();
+
+        let references = find_references(dir.path(), "Square");
+        let lines: Vec<(u32, u32)> = references.iter().map(|r| (r.line, r.column)).collect();
+        assert_eq!(lines, vec![(3, 12), (4, 13)]);
+
+        let declarations = find_declarations(dir.path(), |name| name.starts_with("Square"));
+        let names: Vec<&str> = declarations.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["Square", "Squared"]);
+
+        let (path, docs) = declaration_docs(dir.path(), &dir.path().join("lib.rs"), "Square").unwrap();
+        assert_eq!(path, PathBuf::from("lib.rs"));
+        assert_eq!(docs, "```rust\npub struct Square(pub f64);\n```\n\n---\n\nA square");
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+/// Directories that never contain workspace sources worth analyzing
+pub const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+/// The root of the Cargo workspace `dir` lies in, so that every directory of
+/// a workspace is served by the same daemon: the nearest directory above
+/// `dir` whose `Cargo.toml` has a `[workspace]` table, else the nearest with a
+/// `Cargo.toml` at all. The search stops at a repository root (a `.git`
+/// entry), which is the answer when there is no manifest on the way up;
+/// `dir` itself is when there is neither.
+pub fn find_workspace_root(dir: &Path) -> PathBuf {
+    let mut package = None;
+    for ancestor in dir.ancestors() {
+        let manifest = ancestor.join("Cargo.toml");
+        if let Ok(contents) = std::fs::read_to_string(&manifest) {
+            let table: Option<toml::Table> = toml::from_str(&contents).ok();
+            if table.is_some_and(|table| table.contains_key("workspace")) {
+                return ancestor.to_path_buf();
+            }
+            package.get_or_insert(ancestor);
+        }
+        if ancestor.join(".git").exists() {
+            return package.unwrap_or(ancestor).to_path_buf();
+        }
+    }
+    package.unwrap_or(dir).to_path_buf()
+}
+
+/// Collect all Rust source files under the workspace root, sorted by path.
+///
+/// Hidden directories and build output are skipped.
+pub fn rust_source_files(root: &Path) -> Vec<PathBuf> {
+    collect_files(root, &|path| path.extension().is_some_and(|ext| ext == "rs"))
+}
+
+/// Collect all `Cargo.toml` manifests under the workspace root, sorted by path
+pub fn cargo_manifests(root: &Path) -> Vec<PathBuf> {
+    collect_files(root, &|path| path.file_name().is_some_and(|name| name == "Cargo.toml"))
+}
+
+fn collect_files(root: &Path, matches: &dyn Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_matching(root, matches, &mut files);
+    files.sort();
+    files
+}
+
+fn collect_matching(dir: &Path, matches: &dyn Fn(&Path) -> bool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        
+        if path.is_dir() {
+            if name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref()) {
+                continue;
+            }
+            collect_matching(&path, matches, files);
+        } else if matches(&path) {
+            files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_workspace_root() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path();
+        std::fs::create_dir(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("crates/a/src/nested")).unwrap();
+        std::fs::create_dir_all(root.join("tools/b/src")).unwrap();
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("crates/a/Cargo.toml"), "[package]\nname = \"a\"\n").unwrap();
+        std::fs::write(root.join("tools/b/Cargo.toml"), "[package]\nname = \"b\"\n").unwrap();
+
+        // Without a workspace manifest each package is its own root
+        assert_eq!(find_workspace_root(&root.join("crates/a/src/nested")), root.join("crates/a"));
+        assert_eq!(find_workspace_root(&root.join("docs")), root);
+
+        std::fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"crates/*\"]\n").unwrap();
+        assert_eq!(find_workspace_root(&root.join("crates/a/src/nested")), root);
+        assert_eq!(find_workspace_root(&root.join("docs")), root);
+        assert_eq!(find_workspace_root(root), root);
+
+        // A workspace of its own below the repository root wins over the outer one
+        std::fs::write(root.join("tools/b/Cargo.toml"), "[package]\nname = \"b\"\n\n[workspace]\n").unwrap();
+        assert_eq!(find_workspace_root(&root.join("tools/b/src")), root.join("tools/b"));
+
+        let outside = tempfile::tempdir().unwrap();
+        assert_eq!(find_workspace_root(outside.path()), outside.path());
+    }
+}
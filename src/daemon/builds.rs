@@ -0,0 +1,123 @@
+//! Pausing the language server's indexing while the user builds.
+//!
+//! Cargo holds an exclusive lock on `<target>/<profile>/.cargo-lock` for as
+//! long as a build runs. While one is held and the server is still indexing,
+//! the daemon stops the server with `SIGSTOP` and continues it once the lock is
+//! released; queries wait for indexing anyway, so they only wait longer. Builds
+//! the server runs itself, such as build scripts and `cargo check` on save,
+//! hold the same lock and are told apart by the cargo process descending from
+//! the server: pausing the server then would stall the build it waits for.
+
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, Signal, System};
+
+/// How often the daemon checks for a running build
+pub const BUILD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The target directory cargo builds `workspace` into, as `cargo metadata`
+/// reports it so that `build.target-dir` in `.cargo/config.toml` is honoured
+pub fn target_dir(workspace: &Path) -> PathBuf {
+    metadata_target_dir(workspace).unwrap_or_else(|| match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => workspace.join(dir),
+        None => workspace.join("target"),
+    })
+}
+
+/// The `target_directory` of `cargo metadata` run in `workspace`, if cargo could tell
+fn metadata_target_dir(workspace: &Path) -> Option<PathBuf> {
+    let output = std::process::Command::new("cargo")
+        .current_dir(workspace)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    metadata["target_directory"].as_str().map(PathBuf::from)
+}
+
+/// Whether a cargo build holds the lock of any profile directory in `target`
+pub fn build_lock_held(target: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(target) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let Ok(lock) = File::open(entry.path().join(".cargo-lock")) else {
+            return false;
+        };
+        // Released as soon as `lock` is dropped
+        matches!(lock.try_lock_shared(), Err(TryLockError::WouldBlock))
+    })
+}
+
+/// Whether a cargo build not started by the language server `server_pid` is running
+pub fn user_build_running(target: &Path, server_pid: u32) -> bool {
+    build_lock_held(target) && !server_runs_cargo(server_pid)
+}
+
+/// Whether `server_pid` has a cargo process among its descendants
+fn server_runs_cargo(server_pid: u32) -> bool {
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::All, ProcessRefreshKind::new());
+    let server = Pid::from_u32(server_pid);
+    system.processes().values().filter(|process| process.name() == "cargo").any(|process| {
+        let mut parent = process.parent();
+        while let Some(pid) = parent {
+            if pid == server {
+                return true;
+            }
+            parent = system.process(pid).and_then(|process| process.parent());
+        }
+        false
+    })
+}
+
+/// Stop or continue process `pid`; false if the signal could not be sent
+pub fn set_paused(pid: u32, paused: bool) -> bool {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), ProcessRefreshKind::new());
+    let signal = if paused { Signal::Stop } else { Signal::Continue };
+    system
+        .process(pid)
+        .and_then(|process| process.kill_with(signal))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_dir() {
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(
+            workspace.path().join("Cargo.toml"),
+            "[package]\nname = \"builds\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(workspace.path().join("src")).unwrap();
+        std::fs::write(workspace.path().join("src/lib.rs"), "").unwrap();
+        std::fs::create_dir(workspace.path().join(".cargo")).unwrap();
+        std::fs::write(workspace.path().join(".cargo/config.toml"), "[build]\ntarget-dir = \"out\"\n").unwrap();
+
+        assert_eq!(target_dir(workspace.path()), workspace.path().canonicalize().unwrap().join("out"));
+    }
+
+    #[test]
+    fn test_build_lock_held() {
+        let target = tempfile::tempdir().unwrap();
+        assert!(!build_lock_held(target.path()));
+
+        std::fs::create_dir(target.path().join("debug")).unwrap();
+        let lock = File::create(target.path().join("debug/.cargo-lock")).unwrap();
+        assert!(!build_lock_held(target.path()));
+
+        lock.lock().unwrap();
+        assert!(build_lock_held(target.path()));
+        lock.unlock().unwrap();
+        assert!(!build_lock_held(target.path()));
+    }
+}
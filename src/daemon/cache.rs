@@ -0,0 +1,307 @@
+//! Cached responses, so `--allow-stale` requests can be answered immediately.
+//!
+//! Complete answers are stored with a fingerprint of the workspace sources at
+//! the time they were computed. An answer stays fresh while the fingerprint is
+//! unchanged; once a source file or manifest changes it is stale, and is only
+//! handed out when the request accepts that. Answers computed ahead of time by
+//! the prefetcher are marked, so the status can report how many were used.
+//!
+//! The answers are also kept on disk, in a file per workspace under the user's
+//! cache directory, so a daemon started after `lq stop` or a reboot serves the
+//! answers for unchanged sources while rust-analyzer is still indexing. The
+//! fingerprint is only comparable between runs of the same build, so a file
+//! written by another version of lq is ignored.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+use tracing::{debug, info};
+
+use crate::core::workspace::{cargo_manifests, rust_source_files};
+use crate::ipc::Method;
+
+/// What the cache holds for a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lookup {
+    /// Computed from the current workspace sources
+    Fresh(Value),
+    /// Computed before the workspace last changed
+    Stale(Value),
+    Missing,
+}
+
+/// How often a daemon writes answers computed since the last save to disk
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+struct Entry {
+    result: Value,
+    fingerprint: u64,
+    /// Prefetched and not yet asked for
+    prefetched: bool,
+}
+
+/// The cache as it is written to disk
+#[derive(Serialize, Deserialize)]
+struct SavedCache {
+    /// The lq version that wrote the file
+    version: String,
+    entries: Vec<SavedEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntry {
+    key: String,
+    fingerprint: u64,
+    result: Value,
+}
+
+#[derive(Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    /// The file the answers are saved to, if they are kept on disk
+    path: Option<PathBuf>,
+    /// Whether answers were stored since the last save
+    changed: AtomicBool,
+    /// Keys with a background recomputation in flight
+    refreshing: Mutex<HashSet<String>>,
+    /// Notified whenever a background recomputation finishes
+    refreshed: Notify,
+    /// Prefetched answers that were later asked for
+    prefetch_hits: AtomicU64,
+}
+
+impl ResponseCache {
+    /// A cache saved to `path`, starting with the answers saved there before.
+    /// A file that cannot be read only means starting empty.
+    pub fn persisted(path: PathBuf) -> Self {
+        let entries = match load(&path) {
+            Ok(entries) => {
+                info!("Loaded {} cached answers from {:?}", entries.len(), path);
+                entries
+            }
+            Err(e) => {
+                debug!("Starting with an empty cache: {:#}", e);
+                HashMap::new()
+            }
+        };
+        Self { entries: Mutex::new(entries), path: Some(path), ..Default::default() }
+    }
+
+    /// Write the answers to disk, if any were stored since the last save
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let entries = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, entry)| SavedEntry { key: key.clone(), fingerprint: entry.fingerprint, result: entry.result.clone() })
+            .collect();
+        let saved = SavedCache { version: env!("CARGO_PKG_VERSION").to_string(), entries };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create cache directory")?;
+        }
+        // Renamed into place, so a daemon starting meanwhile never reads half a file
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_vec(&saved)?).with_context(|| format!("Failed to write cache file {:?}", partial))?;
+        std::fs::rename(&partial, path).with_context(|| format!("Failed to write cache file {:?}", path))
+    }
+
+    /// The cache key for `method`, or `None` if its answer must not be cached.
+    ///
+    /// Churn and references across revisions depend on git history rather than
+    /// the sources, diagnostics arrive
+    /// from the server on their own schedule, and status and shutdown are about
+    /// the daemon itself.
+    pub fn key(method: &Method) -> Option<String> {
+        match method {
+            Method::Churn { .. } | Method::Diag { .. } | Method::Status | Method::Ping | Method::Stats | Method::Cancel { .. } | Method::Codec { .. } | Method::Compress { .. } | Method::Shutdown => None,
+            Method::DiffRefs { revs, .. } if !revs.is_empty() => None,
+            _ => serde_json::to_string(method).ok(),
+        }
+    }
+
+    pub fn lookup(&self, key: &str, fingerprint: u64) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) if entry.fingerprint == fingerprint => {
+                if std::mem::take(&mut entry.prefetched) {
+                    self.prefetch_hits.fetch_add(1, Ordering::Relaxed);
+                }
+                Lookup::Fresh(entry.result.clone())
+            }
+            Some(entry) => Lookup::Stale(entry.result.clone()),
+            None => Lookup::Missing,
+        }
+    }
+
+    /// Whether a fresh answer for `key` is cached, without counting it as asked for
+    pub fn is_fresh(&self, key: &str, fingerprint: u64) -> bool {
+        self.entries.lock().unwrap().get(key).is_some_and(|entry| entry.fingerprint == fingerprint)
+    }
+
+    pub fn store(&self, key: String, fingerprint: u64, result: Value) {
+        self.entries.lock().unwrap().insert(key, Entry { result, fingerprint, prefetched: false });
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Store an answer nobody has asked for yet
+    pub fn store_prefetched(&self, key: String, fingerprint: u64, result: Value) {
+        self.entries.lock().unwrap().insert(key, Entry { result, fingerprint, prefetched: true });
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn prefetch_hits(&self) -> u64 {
+        self.prefetch_hits.load(Ordering::Relaxed)
+    }
+
+    /// Claim the refresh of `key`; false if one is already running
+    pub fn start_refresh(&self, key: &str) -> bool {
+        self.refreshing.lock().unwrap().insert(key.to_string())
+    }
+
+    pub fn finish_refresh(&self, key: &str) {
+        self.refreshing.lock().unwrap().remove(key);
+        self.refreshed.notify_waiters();
+    }
+
+    /// Wait until no background recomputation of `key` is in flight
+    pub async fn wait_for_refresh(&self, key: &str) {
+        loop {
+            // Registered before checking, so a refresh finishing in between still wakes it
+            let refreshed = self.refreshed.notified();
+            if !self.refreshing.lock().unwrap().contains(key) {
+                return;
+            }
+            refreshed.await;
+        }
+    }
+}
+
+/// The answers saved at `path` by this version of lq
+fn load(path: &Path) -> Result<HashMap<String, Entry>> {
+    let contents = std::fs::read(path).with_context(|| format!("Failed to read cache file {:?}", path))?;
+    let saved: SavedCache = serde_json::from_slice(&contents).with_context(|| format!("Invalid cache file {:?}", path))?;
+    if saved.version != env!("CARGO_PKG_VERSION") {
+        anyhow::bail!("Cache file {:?} was written by lq {}", path, saved.version);
+    }
+    Ok(saved
+        .entries
+        .into_iter()
+        .map(|entry| (entry.key, Entry { result: entry.result, fingerprint: entry.fingerprint, prefetched: false }))
+        .collect())
+}
+
+/// Where the answers of the daemon listening on `socket_path` are saved:
+/// `~/.cache/language-query/responses` on Linux, the platform cache directory
+/// elsewhere, in a file named like the socket
+pub fn cache_path(socket_path: &Path) -> Result<PathBuf> {
+    let dirs = directories::ProjectDirs::from("", "", "language-query")
+        .context("Failed to find the cache directory")?;
+    let name = socket_path.file_stem().context("Invalid socket path")?;
+    Ok(dirs.cache_dir().join("responses").join(name).with_extension("json"))
+}
+
+/// A hash of the path, size, and modification time of every source file and manifest
+pub fn workspace_fingerprint(workspace: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for file in rust_source_files(workspace).into_iter().chain(cargo_manifests(workspace)) {
+        file.hash(&mut hasher);
+        if let Ok(metadata) = std::fs::metadata(&file) {
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{Codec, Compression};
+
+    #[test]
+    fn test_answer_goes_stale_when_sources_change() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "pub fn a() {}\n").unwrap();
+
+        let cache = ResponseCache::default();
+        let key = ResponseCache::key(&Method::CoverageMap).unwrap();
+        let before = workspace_fingerprint(dir.path());
+        assert_eq!(cache.lookup(&key, before), Lookup::Missing);
+
+        cache.store(key.clone(), before, serde_json::json!({ "functions": [] }));
+        assert!(matches!(cache.lookup(&key, workspace_fingerprint(dir.path())), Lookup::Fresh(_)));
+
+        std::fs::write(dir.path().join("lib.rs"), "pub fn a() {}\npub fn b() {}\n").unwrap();
+        assert!(matches!(cache.lookup(&key, workspace_fingerprint(dir.path())), Lookup::Stale(_)));
+
+        assert!(ResponseCache::key(&Method::Status).is_none());
+        assert!(ResponseCache::key(&Method::Ping).is_none());
+        assert!(ResponseCache::key(&Method::Cancel { id: "1".to_string() }).is_none());
+        assert!(ResponseCache::key(&Method::Codec { codec: Codec::Msgpack }).is_none());
+        assert!(ResponseCache::key(&Method::Compress { compression: Compression::Gzip, min_bytes: None }).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_prefetched_answer_counts_one_hit() {
+        let cache = std::sync::Arc::new(ResponseCache::default());
+        let key = ResponseCache::key(&Method::CoverageMap).unwrap();
+        assert!(cache.start_refresh(&key));
+
+        let waiter = tokio::spawn({
+            let cache = cache.clone();
+            let key = key.clone();
+            async move {
+                cache.wait_for_refresh(&key).await;
+                cache.lookup(&key, 1)
+            }
+        });
+        cache.store_prefetched(key.clone(), 1, serde_json::json!({ "functions": [] }));
+        assert!(cache.is_fresh(&key, 1));
+        assert_eq!(cache.prefetch_hits(), 0);
+        cache.finish_refresh(&key);
+
+        assert!(matches!(waiter.await.unwrap(), Lookup::Fresh(_)));
+        assert!(matches!(cache.lookup(&key, 1), Lookup::Fresh(_)));
+        assert_eq!(cache.prefetch_hits(), 1);
+    }
+
+    #[test]
+    fn test_answers_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lq-12345678.json");
+        let key = ResponseCache::key(&Method::CoverageMap).unwrap();
+
+        let cache = ResponseCache::persisted(path.clone());
+        assert_eq!(cache.lookup(&key, 1), Lookup::Missing);
+        cache.save().unwrap();
+        assert!(!path.exists());
+        cache.store_prefetched(key.clone(), 1, serde_json::json!({ "functions": [] }));
+        cache.save().unwrap();
+
+        let restarted = ResponseCache::persisted(path.clone());
+        assert_eq!(restarted.lookup(&key, 1), Lookup::Fresh(serde_json::json!({ "functions": [] })));
+        assert!(matches!(restarted.lookup(&key, 2), Lookup::Stale(_)));
+        // Loaded answers were asked for before the restart, if at all
+        assert_eq!(restarted.prefetch_hits(), 0);
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, saved.replace(env!("CARGO_PKG_VERSION"), "0.0.0-other")).unwrap();
+        assert_eq!(ResponseCache::persisted(path).lookup(&key, 1), Lookup::Missing);
+    }
+}
@@ -0,0 +1,231 @@
+//! User commands run on workspace events.
+//!
+//! The daemon checks the language server's health and diagnostics once a
+//! second, and runs the configured hook when it sees one of these changes:
+//!
+//! - `on_ready`: the server is up, at startup and again after each restart
+//! - `on_diagnostics_changed`: the diagnostics of one or more files changed
+//! - `on_crash`: the server stopped or could not start, and after each failed restart
+//!
+//! Each hook gets the event as one line of JSON on stdin, with `event` and
+//! `workspace` fields plus those of the event. Hooks run in the background and
+//! are killed after [`HOOK_TIMEOUT`]; a failing hook is logged and otherwise ignored.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
+
+use crate::config::HooksConfig;
+use crate::lsp::{Diagnostic, ServerHealth, Severity};
+
+/// How often the daemon looks for events
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a hook may run before it is killed
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HookEvent {
+    Ready {
+        server_pid: Option<u32>,
+        /// Whether the server came back after a crash
+        restarted: bool,
+    },
+    DiagnosticsChanged {
+        /// Files whose diagnostics changed, relative to the workspace root
+        files: Vec<PathBuf>,
+        /// The current diagnostics of those files
+        diagnostics: Vec<Diagnostic>,
+        /// Errors in the whole workspace
+        errors: usize,
+        /// Warnings in the whole workspace
+        warnings: usize,
+    },
+    Crash {
+        reason: String,
+        restart_attempts: u32,
+        /// False once restarts are exhausted
+        restarting: bool,
+    },
+}
+
+/// What the daemon saw of the language server at one point in time
+pub struct Observation {
+    pub health: ServerHealth,
+    pub server_pid: Option<u32>,
+    /// Every diagnostic in the workspace; `None` while they cannot be read
+    pub diagnostics: Option<Vec<Diagnostic>>,
+}
+
+/// Turns successive observations into the events between them
+#[derive(Default)]
+pub struct Observer {
+    /// Whether the server was down, and when it was due to restart, as last seen;
+    /// each failed restart schedules the next one
+    health: Option<(bool, Option<Instant>)>,
+    diagnostics: BTreeMap<PathBuf, Vec<Diagnostic>>,
+}
+
+impl Observer {
+    pub fn observe(&mut self, observation: Observation) -> Vec<HookEvent> {
+        let mut events = Vec::new();
+
+        let health = &observation.health;
+        let seen = (health.is_degraded(), health.next_restart);
+        match self.health {
+            _ if seen.0 && self.health != Some(seen) => events.push(HookEvent::Crash {
+                reason: health.crash_reason.clone().unwrap_or_default(),
+                restart_attempts: health.restart_attempts,
+                restarting: health.next_restart.is_some(),
+            }),
+            None if !seen.0 => events.push(HookEvent::Ready { server_pid: observation.server_pid, restarted: false }),
+            Some((true, ..)) if !seen.0 => events.push(HookEvent::Ready { server_pid: observation.server_pid, restarted: true }),
+            _ => {}
+        }
+        self.health = Some(seen);
+
+        if let Some(diagnostics) = observation.diagnostics {
+            let mut by_file: BTreeMap<PathBuf, Vec<Diagnostic>> = BTreeMap::new();
+            for diagnostic in diagnostics {
+                by_file.entry(diagnostic.path.clone()).or_default().push(diagnostic);
+            }
+            let mut files: Vec<PathBuf> = self.diagnostics.keys().chain(by_file.keys()).cloned().collect();
+            files.sort();
+            files.dedup();
+            files.retain(|file| self.diagnostics.get(file) != by_file.get(file));
+
+            if !files.is_empty() {
+                let count = |severity| by_file.values().flatten().filter(|d| d.severity == severity).count();
+                events.push(HookEvent::DiagnosticsChanged {
+                    diagnostics: files.iter().filter_map(|file| by_file.get(file)).flatten().cloned().collect(),
+                    files,
+                    errors: count(Severity::Error),
+                    warnings: count(Severity::Warning),
+                });
+                self.diagnostics = by_file;
+            }
+        }
+        events
+    }
+}
+
+/// The configured hooks of one workspace
+pub struct Hooks {
+    workspace: PathBuf,
+    config: HooksConfig,
+}
+
+impl Hooks {
+    pub fn new(workspace: &Path, config: HooksConfig) -> Self {
+        Self {
+            workspace: workspace.to_path_buf(),
+            config,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.config.on_ready.is_none() && self.config.on_diagnostics_changed.is_none() && self.config.on_crash.is_none()
+    }
+
+    /// Run the hook for `event` in the background, if one is configured
+    pub fn fire(&self, event: &HookEvent) {
+        let (name, command) = match event {
+            HookEvent::Ready { .. } => ("on_ready", &self.config.on_ready),
+            HookEvent::DiagnosticsChanged { .. } => ("on_diagnostics_changed", &self.config.on_diagnostics_changed),
+            HookEvent::Crash { .. } => ("on_crash", &self.config.on_crash),
+        };
+        let Some(command) = command.clone().filter(|command| !command.is_empty()) else {
+            return;
+        };
+        let mut payload = serde_json::to_value(event).unwrap_or_default();
+        payload["workspace"] = serde_json::json!(self.workspace);
+        let workspace = self.workspace.clone();
+        tokio::spawn(async move {
+            match run_hook(&command, &workspace, &payload.to_string()).await {
+                Ok(()) => debug!("Ran {} hook", name),
+                Err(e) => warn!("The {} hook failed: {:#}", name, e),
+            }
+        });
+    }
+}
+
+async fn run_hook(command: &[String], workspace: &Path, payload: &str) -> Result<()> {
+    let mut child = tokio::process::Command::new(&command[0])
+        .args(&command[1..])
+        .current_dir(workspace)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", command[0]))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that exits without reading its input is fine
+        let _ = stdin.write_all(format!("{}\n", payload).as_bytes()).await;
+    }
+    let output = tokio::time::timeout(HOOK_TIMEOUT, child.wait_with_output())
+        .await
+        .with_context(|| format!("Killed after {:?}", HOOK_TIMEOUT))??;
+    if !output.status.success() {
+        bail!("{:?} exited with {}: {}", command[0], output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(path: &str, severity: Severity) -> Diagnostic {
+        Diagnostic {
+            file: Path::new("/ws").join(path),
+            path: PathBuf::from(path),
+            line: 1,
+            column: 1,
+            severity,
+            code: None,
+            source: Some("rustc".to_string()),
+            message: "oops".to_string(),
+        }
+    }
+
+    fn observation(crash: Option<(&str, Instant)>, diagnostics: Option<Vec<Diagnostic>>) -> Observation {
+        Observation {
+            health: ServerHealth {
+                crash_reason: crash.map(|(reason, _)| reason.to_string()),
+                restart_attempts: 0,
+                next_restart: crash.map(|(_, at)| at),
+                restarts: 0,
+            },
+            server_pid: crash.is_none().then_some(42),
+            diagnostics,
+        }
+    }
+
+    #[test]
+    fn test_observer_events() {
+        let mut observer = Observer::default();
+        assert_eq!(observer.observe(observation(None, Some(Vec::new()))), [HookEvent::Ready { server_pid: Some(42), restarted: false }]);
+        assert_eq!(observer.observe(observation(None, Some(Vec::new()))), []);
+
+        let events = observer.observe(observation(None, Some(vec![diagnostic("src/lib.rs", Severity::Error), diagnostic("src/main.rs", Severity::Warning)])));
+        assert!(matches!(&events[..], [HookEvent::DiagnosticsChanged { files, errors: 1, warnings: 1, .. }] if files.len() == 2));
+        let events = observer.observe(observation(None, Some(vec![diagnostic("src/main.rs", Severity::Warning)])));
+        assert!(matches!(&events[..], [HookEvent::DiagnosticsChanged { files, diagnostics, errors: 0, .. }] if files == &[PathBuf::from("src/lib.rs")] && diagnostics.is_empty()));
+
+        let restart_at = Instant::now();
+        let events = observer.observe(observation(Some(("killed", restart_at)), None));
+        assert!(matches!(&events[..], [HookEvent::Crash { reason, restarting: true, .. }] if reason == "killed"));
+        assert_eq!(observer.observe(observation(Some(("killed", restart_at)), None)), []);
+        let events = observer.observe(observation(Some(("no binary", restart_at + Duration::from_secs(2))), None));
+        assert!(matches!(&events[..], [HookEvent::Crash { reason, .. }] if reason == "no binary"));
+        assert_eq!(observer.observe(observation(None, Some(vec![diagnostic("src/main.rs", Severity::Warning)]))), [HookEvent::Ready { server_pid: Some(42), restarted: true }]);
+    }
+}
@@ -0,0 +1,367 @@
+//! The parts of the daemon that are not about answering queries: accepting
+//! connections, framing and decoding requests, cancelling and timing them out,
+//! and stopping on request.
+//!
+//! A [`Host`] runs these for any [`RequestHandler`] and over any transport, so
+//! that a tool such as an editor plugin or an HTTP frontend can serve the
+//! daemon's protocol itself. The daemon hosts its
+//! [`Workspaces`](crate::daemon::router::Workspaces) on a Unix socket; a tool
+//! can host them, or the [`DaemonState`](crate::daemon::server::DaemonState) of
+//! a single workspace, on a stream of its own with
+//! [`Host::serve_connection`], or without a transport at all with
+//! [`Host::answer`].
+//!
+//! `Cancel`, `Codec`, `Compress` and `Shutdown` requests are answered by the
+//! host and never reach the handler. A shutdown only stops [`Host::serve`]; what else to stop
+//! is up to whoever hosts it.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::future::{abortable, AbortHandle, Aborted};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::watch;
+use tracing::{debug, error, info};
+
+use crate::core::Deadline;
+use crate::ipc::results::{CancelResult, CodecResult, CompressResult, StopResult};
+use crate::ipc::{read_message_as, write_message_compressed, Codec, Compression, Method, Request, Response, ResponseResult, DEFAULT_COMPRESS_MIN_BYTES};
+
+/// What answers the requests a [`Host`] receives
+#[async_trait]
+pub trait RequestHandler: Send + Sync + 'static {
+    /// The payload answering `request`, computed by `deadline`. Dropping the
+    /// future stops the work, which is how requests are cancelled.
+    async fn handle(self: Arc<Self>, request: Request, deadline: Deadline) -> Result<Value>;
+
+    /// What keeps requests from being answered right now, such as `"server
+    /// still indexing"`, told with a request that timed out
+    fn busy(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Called once `method` is answered, after `elapsed`, with whether it succeeded
+    fn answered(&self, _method: &Method, _elapsed: Duration, _succeeded: bool) {}
+}
+
+/// A transport that clients connect to
+#[async_trait]
+pub trait Listener: Send {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// The next client to connect
+    async fn accept(&mut self) -> io::Result<Self::Stream>;
+}
+
+#[async_trait]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    async fn accept(&mut self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).await.map(|(stream, _addr)| stream)
+    }
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&mut self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).await.map(|(stream, _addr)| stream)
+    }
+}
+
+/// Serves the daemon's protocol for a handler, on as many connections as it is given
+pub struct Host<H> {
+    handler: Arc<H>,
+    /// Requests being answered, by id, so that a client can cancel them
+    in_flight: Mutex<HashMap<String, AbortHandle>>,
+    /// Set once a shutdown was requested
+    stopping: watch::Sender<bool>,
+}
+
+impl<H: RequestHandler> Host<H> {
+    pub fn new(handler: Arc<H>) -> Arc<Self> {
+        Arc::new(Self {
+            handler,
+            in_flight: Mutex::new(HashMap::new()),
+            stopping: watch::channel(false).0,
+        })
+    }
+
+    pub fn handler(&self) -> &Arc<H> {
+        &self.handler
+    }
+
+    /// Accept clients on `listener` and serve each on its own task, until a
+    /// shutdown is requested
+    pub async fn serve<L: Listener>(self: &Arc<Self>, mut listener: L) -> Result<()> {
+        let mut stopping = self.stopping.subscribe();
+        loop {
+            tokio::select! {
+                result = listener.accept() => match result {
+                    Ok(stream) => {
+                        let host = self.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = host.serve_connection(stream).await {
+                                error!("Error handling client: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => error!("Failed to accept connection: {}", e),
+                },
+                _ = stopping.wait_for(|stopping| *stopping) => {
+                    info!("Shutting down daemon");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Answer the requests of one client on `stream`, one at a time, until it
+    /// disconnects or asks for a shutdown
+    pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(&self, mut stream: S) -> Result<()> {
+        let mut codec = Codec::Json;
+        let (mut compression, mut min_bytes) = (Compression::None, DEFAULT_COMPRESS_MIN_BYTES);
+        loop {
+            let Some(request): Option<Request> = read_message_as(&mut stream, codec).await
+                .context("Failed to parse request")? else {
+                debug!("Client disconnected");
+                return Ok(());
+            };
+            debug!("Received request: {:?}", request.method);
+
+            let is_shutdown = matches!(request.method, Method::Shutdown);
+            let switch_to = match request.method {
+                Method::Codec { codec } => Some(codec),
+                _ => None,
+            };
+            let compress = match request.method {
+                Method::Compress { compression, min_bytes } => Some((compression, min_bytes.unwrap_or(DEFAULT_COMPRESS_MIN_BYTES))),
+                _ => None,
+            };
+            let (response, cancelled) = self.respond(request).await;
+
+            // A client that cancelled has usually gone already
+            if let Err(e) = write_message_compressed(&mut stream, &response, codec, compression, min_bytes).await {
+                if cancelled {
+                    debug!("Client left after cancelling: {}", e);
+                    return Ok(());
+                }
+                return Err(e);
+            }
+            if !matches!(response.result, ResponseResult::Success { .. }) {
+                continue;
+            }
+            if let Some(switch_to) = switch_to {
+                debug!("Switching connection to {:?}", switch_to);
+                codec = switch_to;
+            }
+            if let Some(compress) = compress {
+                debug!("Compressing responses as {:?} above {} bytes", compress.0, compress.1);
+                (compression, min_bytes) = compress;
+            }
+            // Stopped once the client has its answer
+            if is_shutdown {
+                info!("Received shutdown request");
+                self.stop();
+                return Ok(());
+            }
+        }
+    }
+
+    /// The response to `request`, for a host without a transport. `Codec` and
+    /// `Compress` requests change nothing then.
+    pub async fn answer(&self, request: Request) -> Response {
+        let is_shutdown = matches!(request.method, Method::Shutdown);
+        let (response, _) = self.respond(request).await;
+        if is_shutdown && matches!(response.result, ResponseResult::Success { .. }) {
+            self.stop();
+        }
+        response
+    }
+
+    /// Make [`Host::serve`] return, as a `Shutdown` request does
+    pub fn stop(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    /// How many requests are being answered, on any connection
+    pub fn requests_in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Wait until a shutdown is requested
+    pub async fn stopped(&self) {
+        let _ = self.stopping.subscribe().wait_for(|stopping| *stopping).await;
+    }
+
+    /// The response to `request`, and whether it was cancelled
+    async fn respond(&self, request: Request) -> (Response, bool) {
+        let id = request.id.clone();
+        let method = request.method.clone();
+        let deadline = request.deadline_ms
+            .map(|ms| Deadline::after(Duration::from_millis(ms)))
+            .unwrap_or_default();
+        let timeout = request.timeout_ms.map(Duration::from_millis);
+
+        // Dropping the answer's future stops the work it waits on
+        let started = Instant::now();
+        let (answer, abort) = abortable(self.dispatch(request, deadline));
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).insert(id.clone(), abort);
+        let outcome = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, answer).await {
+                Ok(outcome) => outcome,
+                Err(_) => Ok(Err(self.timed_out(timeout))),
+            },
+            None => answer.await,
+        };
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+        self.handler.answered(&method, started.elapsed(), matches!(outcome, Ok(Ok(_))));
+
+        let cancelled = matches!(outcome, Err(Aborted));
+        let result = match outcome {
+            Ok(Ok(result)) => ResponseResult::Success { result },
+            Ok(Err(e)) => ResponseResult::Error { error: e.to_string() },
+            Err(Aborted) => ResponseResult::Error { error: "Request cancelled".to_string() },
+        };
+        (Response { id, result }, cancelled)
+    }
+
+    /// Answer the requests about the connection here, and pass on the rest
+    async fn dispatch(&self, request: Request, deadline: Deadline) -> Result<Value> {
+        let result = match request.method {
+            Method::Cancel { id } => serde_json::to_value(CancelResult { cancelled: self.cancel(&id) }),
+            // Switched to by `serve_connection` once answered
+            Method::Codec { codec } => serde_json::to_value(CodecResult { codec }),
+            Method::Compress { compression, min_bytes } => {
+                serde_json::to_value(CompressResult { compression, min_bytes: min_bytes.unwrap_or(DEFAULT_COMPRESS_MIN_BYTES) })
+            }
+            Method::Shutdown => serde_json::to_value(StopResult { shutdown: true }),
+            _ => return self.handler.clone().handle(request, deadline).await,
+        };
+        Ok(result?)
+    }
+
+    /// Stop answering the request `id`; whether it was still in flight
+    fn cancel(&self, id: &str) -> bool {
+        match self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(id) {
+            Some(abort) => {
+                info!("Cancelling request {}", id);
+                abort.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The error for a request that took longer than `timeout`, with what kept the handler busy
+    fn timed_out(&self, timeout: Duration) -> anyhow::Error {
+        let mut message = if timeout.subsec_millis() == 0 {
+            format!("Request timed out after {}s", timeout.as_secs())
+        } else {
+            format!("Request timed out after {:.1}s", timeout.as_secs_f64())
+        };
+        if let Some(busy) = self.handler.busy() {
+            message.push_str(", ");
+            message.push_str(busy);
+        }
+        anyhow::anyhow!(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{read_message, read_message_compressed, write_message};
+    use serde_json::json;
+    use std::path::PathBuf;
+    use tokio::io::AsyncReadExt;
+
+    /// Echoes the file of an `Outline` request, after `delay`
+    struct Echo {
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl RequestHandler for Echo {
+        async fn handle(self: Arc<Self>, request: Request, _deadline: Deadline) -> Result<Value> {
+            tokio::time::sleep(self.delay).await;
+            match request.method {
+                Method::Outline { file } => Ok(json!({ "file": file })),
+                method => anyhow::bail!("Unexpected {}", method.name()),
+            }
+        }
+
+        fn busy(&self) -> Option<&'static str> {
+            Some("server still starting")
+        }
+    }
+
+    fn request(id: &str, method: Method, timeout_ms: Option<u64>) -> Request {
+        Request { id: id.to_string(), method, deadline_ms: None, allow_stale: false, timeout_ms, workspace: None }
+    }
+
+    fn outline() -> Method {
+        Method::Outline { file: PathBuf::from("/ws/src/lib.rs") }
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection() {
+        let host = Host::new(Arc::new(Echo { delay: Duration::ZERO }));
+        let (mut client, server) = tokio::io::duplex(4096);
+        let serving = tokio::spawn({
+            let host = host.clone();
+            async move { host.serve_connection(server).await }
+        });
+
+        write_message(&mut client, &request("1", outline(), None)).await.unwrap();
+        let response: Response = read_message(&mut client).await.unwrap().unwrap();
+        assert_eq!(response.id, "1");
+        assert!(matches!(response.result, ResponseResult::Success { result } if result == json!({ "file": "/ws/src/lib.rs" })));
+
+        // Compressed only after the answer to the switch
+        let compress = Method::Compress { compression: Compression::Gzip, min_bytes: Some(0) };
+        write_message(&mut client, &request("2", compress, None)).await.unwrap();
+        let response: Response = read_message(&mut client).await.unwrap().unwrap();
+        assert!(matches!(response.result, ResponseResult::Success { result } if result == json!({ "compression": "gzip", "min_bytes": 0 })));
+        write_message(&mut client, &request("3", outline(), None)).await.unwrap();
+        let mut len = [0; 4];
+        client.read_exact(&mut len).await.unwrap();
+        let mut message = vec![0; (u32::from_be_bytes(len) & !(1 << 31)) as usize];
+        client.read_exact(&mut message).await.unwrap();
+        assert!(u32::from_be_bytes(len) & 1 << 31 != 0);
+
+        write_message(&mut client, &request("4", Method::Shutdown, None)).await.unwrap();
+        let response: Response = read_message_compressed(&mut client, Codec::Json, Compression::Gzip).await.unwrap().unwrap();
+        assert!(matches!(response.result, ResponseResult::Success { .. }));
+        serving.await.unwrap().unwrap();
+        host.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_timeout() {
+        let host = Host::new(Arc::new(Echo { delay: Duration::from_secs(60) }));
+        let slow = tokio::spawn({
+            let host = host.clone();
+            async move { host.answer(request("slow", outline(), None)).await }
+        });
+        // Cancelled only once it is in flight
+        while host.in_flight.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let cancel = host.answer(request("c", Method::Cancel { id: "slow".to_string() }, None)).await;
+        assert!(matches!(cancel.result, ResponseResult::Success { result } if result == json!({ "cancelled": true })));
+        let slow = slow.await.unwrap();
+        assert!(matches!(slow.result, ResponseResult::Error { error } if error == "Request cancelled"));
+
+        let timed_out = host.answer(request("t", outline(), Some(100))).await;
+        assert!(matches!(timed_out.result, ResponseResult::Error { error } if error == "Request timed out after 0.1s, server still starting"));
+    }
+}
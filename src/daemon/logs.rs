@@ -0,0 +1,87 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use super::get_socket_path;
+use crate::{out, outln};
+
+/// How often `follow_log` checks the log file for new output
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Get the log file path for a given workspace (next to its socket)
+pub fn get_log_path(workspace: &Path) -> Result<PathBuf> {
+    Ok(get_socket_path(workspace)?.with_extension("log"))
+}
+
+/// Return the last `count` lines of `contents`
+pub fn last_lines(contents: &str, count: usize) -> Vec<&str> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = lines.len().saturating_sub(count);
+    lines[start..].to_vec()
+}
+
+/// Print the last `count` lines of the log file
+pub fn print_log_tail(log_path: &Path, count: usize) -> Result<u64> {
+    let contents = std::fs::read(log_path)
+        .with_context(|| format!("No daemon log found at {}", log_path.display()))?;
+    
+    for line in last_lines(&String::from_utf8_lossy(&contents), count) {
+        outln!("{}", line);
+    }
+    
+    Ok(contents.len() as u64)
+}
+
+/// Keep printing output appended to the log file, starting at `offset`.
+///
+/// Runs until interrupted. If the file is truncated or recreated, output
+/// restarts from the beginning.
+pub async fn follow_log(log_path: &Path, mut offset: u64) -> Result<()> {
+    loop {
+        tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+        
+        let Ok(mut file) = std::fs::File::open(log_path) else {
+            continue;
+        };
+        let len = file.metadata()?.len();
+        
+        if len < offset {
+            offset = 0;
+        }
+        if len == offset {
+            continue;
+        }
+        
+        file.seek(SeekFrom::Start(offset))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+        offset += appended.len() as u64;
+        
+        out!("{}", String::from_utf8_lossy(&appended));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_last_lines() {
+        let contents = "one\ntwo\nthree\n";
+        assert_eq!(last_lines(contents, 2), vec!["two", "three"]);
+        assert_eq!(last_lines(contents, 10), vec!["one", "two", "three"]);
+        assert!(last_lines(contents, 0).is_empty());
+    }
+    
+    #[test]
+    fn test_log_path_is_next_to_socket() {
+        let workspace = Path::new("/some/workspace");
+        let socket = get_socket_path(workspace).unwrap();
+        let log = get_log_path(workspace).unwrap();
+        assert_eq!(log.parent(), socket.parent());
+        assert_eq!(log.file_stem(), socket.file_stem());
+        assert_eq!(log.extension().unwrap(), "log");
+    }
+}
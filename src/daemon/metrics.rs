@@ -0,0 +1,198 @@
+//! Usage metrics: how often each method is asked for and how long its answers take,
+//! kept on this machine.
+//!
+//! Off unless the global config sets `[metrics] enabled = true`, so the person
+//! administering a machine opts in rather than a repository. Nothing is sent
+//! anywhere. Each daemon adds its counts to a file of its own in the metrics
+//! directory, named like its socket, so daemons never write the same file, and
+//! `lq metrics dump` sums the files for whoever collects them. Only method
+//! names and timings are kept, never paths, symbols or workspaces.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// How often a daemon adds what it recorded to its file
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The requests for one method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MethodUsage {
+    pub count: u64,
+    /// Requests answered with an error, cancelled or timed out
+    pub errors: u64,
+    /// Time spent answering all of them, in milliseconds
+    pub total_ms: u64,
+    /// The slowest answer, in milliseconds
+    pub max_ms: u64,
+}
+
+impl MethodUsage {
+    pub fn mean_ms(&self) -> u64 {
+        self.total_ms.checked_div(self.count).unwrap_or(0)
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.count += other.count;
+        self.errors += other.errors;
+        self.total_ms += other.total_ms;
+        self.max_ms = self.max_ms.max(other.max_ms);
+    }
+}
+
+/// Requests per method name, as the protocol spells it (`Refs`, `Docs`, ...)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    /// When the first of these requests was recorded, in seconds since the Unix epoch
+    pub since: Option<u64>,
+    pub methods: BTreeMap<String, MethodUsage>,
+}
+
+impl Usage {
+    fn record(&mut self, method: &str, elapsed: Duration, succeeded: bool) {
+        if self.since.is_none() {
+            self.since = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|now| now.as_secs());
+        }
+        let ms = elapsed.as_millis() as u64;
+        self.methods.entry(method.to_string()).or_default().add(&MethodUsage {
+            count: 1,
+            errors: u64::from(!succeeded),
+            total_ms: ms,
+            max_ms: ms,
+        });
+    }
+
+    /// Add the requests of `other`
+    pub fn merge(&mut self, other: &Usage) {
+        self.since = match (self.since, other.since) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        for (method, usage) in &other.methods {
+            self.methods.entry(method.clone()).or_default().add(usage);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty()
+    }
+
+    /// The usage in the file at `path`; a missing file has none
+    fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents).with_context(|| format!("Invalid metrics file {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read metrics file {:?}", path)),
+        }
+    }
+}
+
+/// A daemon's requests, kept in memory until flushed to its file
+pub struct MetricsRecorder {
+    path: PathBuf,
+    pending: std::sync::Mutex<Usage>,
+}
+
+impl MetricsRecorder {
+    /// A recorder for the daemon listening on `socket_path`
+    pub fn for_socket(socket_path: &Path) -> Result<Self> {
+        let name = socket_path.file_stem().context("Invalid socket path")?;
+        Ok(Self::new(metrics_dir()?.join(name).with_extension("json")))
+    }
+
+    fn new(path: PathBuf) -> Self {
+        Self { path, pending: std::sync::Mutex::new(Usage::default()) }
+    }
+
+    pub fn record(&self, method: &str, elapsed: Duration, succeeded: bool) {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).record(method, elapsed, succeeded);
+    }
+
+    /// Add the requests recorded since the last flush to the file
+    pub fn flush(&self) -> Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut usage = Usage::load(&self.path)?;
+        usage.merge(&pending);
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create metrics directory")?;
+        }
+        // Renamed into place, so a dump never reads half a file
+        let partial = self.path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_vec(&usage)?)
+            .with_context(|| format!("Failed to write metrics file {:?}", partial))?;
+        std::fs::rename(&partial, &self.path).with_context(|| format!("Failed to write metrics file {:?}", self.path))
+    }
+}
+
+/// Where daemons keep their metrics: `~/.local/share/language-query/metrics` on
+/// Linux, the platform data directory elsewhere
+pub fn metrics_dir() -> Result<PathBuf> {
+    let dirs = directories::ProjectDirs::from("", "", "language-query")
+        .context("Failed to find the data directory")?;
+    Ok(dirs.data_local_dir().join("metrics"))
+}
+
+/// The usage recorded by every daemon that wrote to `dir`
+pub fn collect(dir: &Path) -> Result<Usage> {
+    let mut usage = Usage::default();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(usage),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read metrics directory {:?}", dir)),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "json") {
+            usage.merge(&Usage::load(&path)?);
+        }
+    }
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_merge() {
+        let mut usage = Usage::default();
+        usage.record("Refs", Duration::from_millis(30), true);
+        usage.record("Refs", Duration::from_millis(90), false);
+        usage.record("Docs", Duration::from_millis(5), true);
+        assert_eq!(usage.methods["Refs"], MethodUsage { count: 2, errors: 1, total_ms: 120, max_ms: 90 });
+        assert_eq!(usage.methods["Refs"].mean_ms(), 60);
+
+        let mut older = Usage { since: Some(1), ..Default::default() };
+        older.record("Docs", Duration::from_millis(7), true);
+        usage.merge(&older);
+        assert_eq!(usage.since, Some(1));
+        assert_eq!(usage.methods["Docs"], MethodUsage { count: 2, errors: 0, total_ms: 12, max_ms: 7 });
+    }
+
+    #[test]
+    fn test_flush_and_collect() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = MetricsRecorder::new(dir.path().join("lq-aaaaaaaa.json"));
+        let second = MetricsRecorder::new(dir.path().join("lq-bbbbbbbb.json"));
+        first.record("Refs", Duration::from_millis(10), true);
+        first.flush().unwrap();
+        first.record("Refs", Duration::from_millis(20), true);
+        first.flush().unwrap();
+        // Nothing recorded since is nothing to write
+        first.flush().unwrap();
+        second.record("Refs", Duration::from_millis(30), true);
+        second.flush().unwrap();
+
+        let usage = collect(dir.path()).unwrap();
+        assert_eq!(usage.methods["Refs"], MethodUsage { count: 3, errors: 0, total_ms: 60, max_ms: 30 });
+        assert!(collect(&dir.path().join("missing")).unwrap().is_empty());
+    }
+}
@@ -1,16 +1,69 @@
+pub mod builds;
+pub mod cache;
+pub mod hooks;
+pub mod host;
+pub mod logs;
+pub mod metrics;
+pub mod pidfile;
+pub mod prefetch;
+pub mod process;
+pub mod retry;
+pub mod router;
 pub mod server;
+pub mod stats;
+pub mod startup;
 
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
 use sha2::{Sha256, Digest};
 
-pub use server::DaemonServer;
+pub use host::{Host, Listener, RequestHandler};
+pub use logs::get_log_path;
+pub use router::Workspaces;
+pub use server::{DaemonServer, DaemonState};
+pub use startup::{redirect_output, report_startup, StartupStatus};
+
+/// Get the directory holding all daemon sockets: `$XDG_RUNTIME_DIR/lq`, else
+/// `lq-<uid>` in the temp directory. Anyone who can connect to a daemon can read
+/// its workspace through queries, so only the user may enter the directory.
+pub fn get_socket_dir() -> Result<PathBuf> {
+    let socket_dir = match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("lq"),
+        None => std::env::temp_dir().join(format!("lq-{}", current_uid())),
+    };
+    private_dir(&socket_dir)?;
+    Ok(socket_dir)
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+/// Create `dir` unless it exists, and make it accessible to this user only;
+/// one that another user owns, as anyone may create in the temp directory, is refused
+fn private_dir(dir: &Path) -> Result<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create socket directory {}", dir.display()))?;
+    let metadata = std::fs::symlink_metadata(dir)
+        .with_context(|| format!("Failed to read socket directory {}", dir.display()))?;
+    if !metadata.is_dir() || metadata.uid() != current_uid() {
+        bail!("Socket directory {} is not a directory of yours; remove it, or set XDG_RUNTIME_DIR", dir.display());
+    }
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+            .with_context(|| format!("Failed to restrict socket directory {}", dir.display()))?;
+    }
+    Ok(())
+}
 
 /// Get the socket path for a given workspace
 pub fn get_socket_path(workspace: &Path) -> Result<PathBuf> {
-    let socket_dir = std::env::temp_dir().join("language-query");
-    std::fs::create_dir_all(&socket_dir)
-        .context("Failed to create socket directory")?;
+    let socket_dir = get_socket_dir()?;
     
     // Create a unique socket name based on workspace path
     let mut hasher = Sha256::new();
@@ -33,7 +86,48 @@ pub async fn is_daemon_running(socket_path: &Path) -> bool {
         Err(_) => {
             // Socket exists but can't connect, clean it up
             let _ = std::fs::remove_file(socket_path);
+            let _ = std::fs::remove_file(pidfile::pid_path(socket_path));
             false
         }
     }
-}
\ No newline at end of file
+}
+
+/// List the sockets of all daemons on this machine, sorted by path
+pub fn list_daemon_sockets() -> Result<Vec<PathBuf>> {
+    let mut sockets: Vec<PathBuf> = std::fs::read_dir(get_socket_dir()?)
+        .context("Failed to read socket directory")?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with("lq-") && name.ends_with(".sock")
+        })
+        .collect();
+    sockets.sort();
+    Ok(sockets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_private_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().mode() & 0o777;
+
+        let created = dir.path().join("lq");
+        private_dir(&created).unwrap();
+        assert_eq!(mode(&created), 0o700);
+
+        let open = dir.path().join("open");
+        std::fs::create_dir(&open).unwrap();
+        std::fs::set_permissions(&open, std::fs::Permissions::from_mode(0o777)).unwrap();
+        private_dir(&open).unwrap();
+        assert_eq!(mode(&open), 0o700);
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(private_dir(&file).is_err());
+    }
+}
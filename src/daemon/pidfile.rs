@@ -0,0 +1,58 @@
+//! The PID file a daemon keeps next to its socket, `lq-<hash>.pid`, so that a
+//! daemon can be found and signalled even when it no longer answers on the socket.
+//!
+//! The file is written once the daemon listens and removed when it exits; one
+//! left behind by a daemon that was killed is removed along with its socket.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// The PID file of the daemon listening on `socket_path`
+pub fn pid_path(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("pid")
+}
+
+/// The PID the daemon on `socket_path` recorded, if it did
+pub fn read_pid(socket_path: &Path) -> Option<u32> {
+    std::fs::read_to_string(pid_path(socket_path)).ok()?.trim().parse().ok()
+}
+
+/// The PID file of this process, removed when dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Record this process as the daemon on `socket_path`
+    pub fn create(socket_path: &Path) -> Result<Self> {
+        let path = pid_path(socket_path);
+        std::fs::write(&path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("lq-0123abcd.sock");
+        assert_eq!(pid_path(&socket), dir.path().join("lq-0123abcd.pid"));
+        assert_eq!(read_pid(&socket), None);
+
+        let pid_file = PidFile::create(&socket).unwrap();
+        assert_eq!(read_pid(&socket), Some(std::process::id()));
+        drop(pid_file);
+        assert!(!pid_path(&socket).exists());
+    }
+}
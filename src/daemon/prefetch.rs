@@ -0,0 +1,104 @@
+//! Speculative answers to the queries likely to follow a `docs` request.
+//!
+//! An agent reading up on a symbol typically asks for its docs, then its
+//! definition, then its references. Once `docs` is answered the daemon
+//! computes `def` and `refs` for the same location or qualified path in the
+//! background and caches them, so the follow-ups are served from the cache. At most
+//! `max_concurrent` prefetches run at once; any beyond that are skipped rather
+//! than queued, so prefetching never builds up a backlog in front of real queries.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::config::PrefetchConfig;
+use crate::ipc::{Method, PathQuery, PrefetchStats};
+
+pub struct Prefetcher {
+    config: PrefetchConfig,
+    running: AtomicUsize,
+    issued: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl Prefetcher {
+    pub fn new(config: PrefetchConfig) -> Self {
+        Self {
+            config,
+            running: AtomicUsize::new(0),
+            issued: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Claim a slot for one prefetch; false, and counted as skipped, when all are taken
+    pub fn try_start(&self) -> bool {
+        let claimed = self
+            .running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| (running < self.config.max_concurrent).then_some(running + 1))
+            .is_ok();
+        let counter = if claimed { &self.issued } else { &self.skipped };
+        counter.fetch_add(1, Ordering::Relaxed);
+        claimed
+    }
+
+    pub fn finish(&self) {
+        self.running.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// The counters for `lq status`, given how many prefetched answers were asked for
+    pub fn stats(&self, hits: u64) -> PrefetchStats {
+        PrefetchStats {
+            enabled: self.config.enabled,
+            issued: self.issued.load(Ordering::Relaxed),
+            hits,
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The queries worth answering ahead of time once `method` is answered
+pub fn follow_ups(method: &Method) -> Vec<Method> {
+    match method {
+        Method::Docs { file, line, column, symbol } => vec![
+            Method::Definition { file: file.clone(), line: *line, column: *column, symbol: symbol.clone() },
+            Method::Refs { file: file.clone(), line: *line, column: *column, symbol: symbol.clone(), raw: false },
+        ],
+        Method::AtPath { path, query: PathQuery::Docs, .. } => vec![
+            Method::AtPath { path: path.clone(), query: PathQuery::Def, raw: false },
+            Method::AtPath { path: path.clone(), query: PathQuery::Refs, raw: false },
+        ],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_are_bounded() {
+        let prefetcher = Prefetcher::new(PrefetchConfig { enabled: true, max_concurrent: 1 });
+        assert!(prefetcher.try_start());
+        assert!(!prefetcher.try_start());
+        prefetcher.finish();
+        assert!(prefetcher.try_start());
+
+        let stats = prefetcher.stats(1);
+        assert_eq!((stats.issued, stats.hits, stats.skipped), (2, 1, 1));
+    }
+
+    #[test]
+    fn test_follow_ups() {
+        let docs = Method::Docs { file: "src/lib.rs".into(), line: 3, column: None, symbol: "area".to_string() };
+        let methods: Vec<&str> = follow_ups(&docs).iter().map(|method| match method {
+            Method::Definition { .. } => "def",
+            Method::Refs { raw: false, .. } => "refs",
+            _ => "other",
+        }).collect();
+        assert_eq!(methods, ["def", "refs"]);
+        assert!(follow_ups(&Method::Status).is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+//! Retries of answers that come back empty right after the language server
+//! became ready.
+//!
+//! For a while after rust-analyzer finishes indexing, hover and reference
+//! queries can come back empty while its caches warm, and the same query a
+//! moment later has an answer. With `[retry_empty]` enabled, a lookup that
+//! comes back empty within `within_secs` of readiness is asked once more after
+//! `delay_ms`, and the second answer, empty or not, is flagged `retried`.
+
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::config::RetryConfig;
+use crate::ipc::Method;
+
+/// Fields holding what a lookup found; an answer with all of them empty found nothing
+const FOUND_FIELDS: &[&str] = &["docs", "definitions", "implementations", "references"];
+
+/// Whether `result`, the answer to `method` `ready_for` after the server
+/// became ready, should be asked for again
+pub fn worth_retrying(config: &RetryConfig, method: &Method, result: &Value, ready_for: Option<Duration>) -> bool {
+    config.enabled
+        && matches!(method, Method::Docs { .. } | Method::DocsRange { .. } | Method::Definition { .. } | Method::Impl { .. } | Method::TypeDefinition { .. } | Method::Refs { .. } | Method::AtPath { .. })
+        && ready_for.is_some_and(|ready_for| ready_for < Duration::from_secs(config.within_secs))
+        && is_empty(result)
+}
+
+fn is_empty(result: &Value) -> bool {
+    let found: Vec<&Value> = FOUND_FIELDS.iter().filter_map(|field| result.get(field)).collect();
+    !found.is_empty() && found.iter().all(|value| value.is_null() || value.as_array().is_some_and(Vec::is_empty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_worth_retrying() {
+        let config = RetryConfig { enabled: true, ..Default::default() };
+        let refs = Method::Refs { file: PathBuf::from("/ws/src/lib.rs"), line: 1, column: Some(1), symbol: "a".to_string(), raw: false };
+        let soon = Some(Duration::from_secs(2));
+        assert!(worth_retrying(&config, &refs, &json!({ "references": [] }), soon));
+        assert!(!worth_retrying(&config, &refs, &json!({ "references": [{ "file": "/ws/src/lib.rs" }] }), soon));
+        assert!(!worth_retrying(&config, &refs, &json!({ "references": [] }), Some(Duration::from_secs(60))));
+        assert!(!worth_retrying(&config, &refs, &json!({ "references": [] }), None));
+        assert!(!worth_retrying(&RetryConfig::default(), &refs, &json!({ "references": [] }), soon));
+
+        let docs = Method::AtPath { path: "crate::a".to_string(), query: crate::ipc::PathQuery::Docs, raw: false };
+        assert!(worth_retrying(&config, &docs, &json!({ "docs": null }), soon));
+        assert!(!worth_retrying(&config, &docs, &json!({ "docs": "Does a" }), soon));
+        assert!(!worth_retrying(&config, &Method::Outline { file: PathBuf::from("/ws/src/lib.rs") }, &json!({ "items": [] }), soon));
+    }
+}
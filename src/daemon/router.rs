@@ -0,0 +1,296 @@
+//! Serving several Cargo workspaces from one daemon, as for a monorepo whose
+//! `.lq.toml` has a `[monorepo]` section.
+//!
+//! Each workspace is a member with a [`DaemonState`] of its own, and so its own
+//! language server, started by the first request about it, and its own cache.
+//! A request goes to the member holding the file it names, else to the member
+//! the client runs in. `Status`, `Stats`, and `Search` and whole-workspace `Diag`, asked
+//! from outside every member cover them all, with paths relative to the root.
+//! A daemon for a single workspace has it as its only member, which answers
+//! every request.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::core::Deadline;
+use crate::daemon::host::RequestHandler;
+use crate::daemon::metrics::MetricsRecorder;
+use crate::daemon::server::DaemonState;
+use crate::daemon::stats::Stats;
+use crate::ipc::{DaemonStatus, Method, Request};
+use crate::lsp::render::relative_path;
+use crate::lsp::{rank_symbols, SymbolMatch};
+
+/// The workspaces one daemon serves, and the handler that routes requests among them
+pub struct Workspaces {
+    root: PathBuf,
+    members: Vec<Arc<DaemonState>>,
+    /// Requests counted for `lq metrics dump`, when enabled
+    metrics: Option<MetricsRecorder>,
+    /// When the last request was answered, or the daemon started
+    last_request: Mutex<Instant>,
+}
+
+/// Which members answer a request
+enum Route<'a> {
+    One(&'a Arc<DaemonState>),
+    All,
+}
+
+impl Workspaces {
+    /// The daemon for `root`, serving `members`, which lie at or below it
+    pub fn new(root: &Path, members: Vec<DaemonState>) -> Self {
+        Self {
+            root: root.canonicalize().unwrap_or_else(|_| root.to_path_buf()),
+            members: members.into_iter().map(Arc::new).collect(),
+            metrics: None,
+            last_request: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Count requests in `metrics`
+    pub fn with_metrics(self, metrics: Option<MetricsRecorder>) -> Self {
+        Self { metrics, ..self }
+    }
+
+    pub fn members(&self) -> &[Arc<DaemonState>] {
+        &self.members
+    }
+
+    /// How long since the last request was answered
+    pub fn idle_for(&self) -> Duration {
+        self.last_request.lock().unwrap_or_else(|e| e.into_inner()).elapsed()
+    }
+
+    /// Add the requests counted so far to the metrics file, if metrics are enabled
+    pub fn flush_metrics(&self) {
+        if let Some(Err(e)) = self.metrics.as_ref().map(MetricsRecorder::flush) {
+            warn!("Failed to save metrics: {:#}", e);
+        }
+    }
+
+    /// The innermost member `path` lies in
+    fn member_for(&self, path: &Path) -> Option<&Arc<DaemonState>> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.members
+            .iter()
+            .filter(|member| path.starts_with(member.workspace()))
+            .max_by_key(|member| member.workspace().components().count())
+    }
+
+    fn route(&self, request: &Request) -> Result<Route<'_>> {
+        if let [member] = self.members.as_slice() {
+            return Ok(Route::One(member));
+        }
+        if let Some(file) = request_file(&request.method) {
+            return self
+                .member_for(&file)
+                .map(Route::One)
+                .with_context(|| format!("{} is in none of the workspaces this daemon serves: {}", file.display(), self.listed()));
+        }
+        if let Some(member) = request.workspace.as_deref().and_then(|workspace| self.member_for(workspace)) {
+            return Ok(Route::One(member));
+        }
+        match request.method {
+            Method::Status | Method::Stats | Method::Search { .. } | Method::Diag { file: None, .. } => Ok(Route::All),
+            _ => bail!(
+                "{} is about one workspace, and {} serves several; ask from within one of them: {}",
+                request.method.name(),
+                self.root.display(),
+                self.listed()
+            ),
+        }
+    }
+
+    /// The members, relative to the root, for messages
+    fn listed(&self) -> String {
+        let members: Vec<String> = self
+            .members
+            .iter()
+            .map(|member| relative_path(&self.root, member.workspace()).display().to_string())
+            .collect();
+        members.join(", ")
+    }
+
+    /// Ask every member and answer as one
+    async fn handle_all(&self, request: Request, deadline: Deadline) -> Result<Value> {
+        if let Method::Stats = request.method {
+            let mut stats = Stats::default();
+            for member in &self.members {
+                stats.merge(member.stats());
+            }
+            return Ok(serde_json::to_value(stats.result())?);
+        }
+        let answers = self.members.iter().map(|member| member.clone().handle(request.clone(), deadline));
+        let answers = futures::future::try_join_all(answers).await?;
+        match request.method {
+            Method::Status => self.status(answers),
+            Method::Search { query, limit } => {
+                let mut merged = self.merged(answers, "symbols");
+                let symbols: Vec<SymbolMatch> = serde_json::from_value(merged["symbols"].take())?;
+                let mut symbols = rank_symbols(&query, symbols);
+                symbols.truncate(limit);
+                merged["symbols"] = serde_json::to_value(symbols)?;
+                Ok(merged)
+            }
+            _ => Ok(self.merged(answers, "diagnostics")),
+        }
+    }
+
+    /// The `field` lists of `answers` joined, their paths made relative to the
+    /// root, along with any caveat such as `"partial"` one of them carries
+    fn merged(&self, answers: Vec<Value>, field: &str) -> Value {
+        let mut items = Vec::new();
+        let mut merged = serde_json::Map::new();
+        for answer in answers {
+            let Value::Object(fields) = answer else {
+                continue;
+            };
+            for (key, value) in fields {
+                match value {
+                    Value::Array(list) if key == field => items.extend(list),
+                    Value::Bool(true) => {
+                        merged.insert(key, Value::Bool(true));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for item in &mut items {
+            if let Some(file) = item["file"].as_str() {
+                item["path"] = serde_json::json!(relative_path(&self.root, Path::new(file)));
+            }
+        }
+        merged.insert(field.to_string(), Value::Array(items));
+        Value::Object(merged)
+    }
+
+    /// The daemon's status: that of the member furthest from ready, with every
+    /// member's own under `members`, and the memory and restarts of all their servers
+    fn status(&self, answers: Vec<Value>) -> Result<Value> {
+        let members = answers
+            .into_iter()
+            .map(serde_json::from_value::<DaemonStatus>)
+            .collect::<Result<Vec<_>, _>>()?;
+        let behind = |status: &DaemonStatus| ["ready", "indexing", "starting", "degraded"].iter().position(|s| *s == status.status);
+        let furthest = members.iter().max_by_key(|status| behind(status)).cloned().context("The daemon serves no workspace")?;
+        let memory: Vec<u64> = members.iter().filter_map(|status| status.server_memory_bytes).collect();
+        Ok(serde_json::to_value(DaemonStatus {
+            workspace: self.root.clone(),
+            server_memory_bytes: (!memory.is_empty()).then(|| memory.iter().sum()),
+            server_restarts: members.iter().map(|status| status.server_restarts).sum(),
+            memory_restarts: members.iter().map(|status| status.memory_restarts).sum(),
+            members,
+            ..furthest
+        })?)
+    }
+}
+
+/// The file a request is about, if it names one
+fn request_file(method: &Method) -> Option<PathBuf> {
+    let method = serde_json::to_value(method).ok()?;
+    method.pointer("/params/file")?.as_str().map(PathBuf::from)
+}
+
+#[async_trait]
+impl RequestHandler for Workspaces {
+    async fn handle(self: Arc<Self>, request: Request, deadline: Deadline) -> Result<Value> {
+        match self.route(&request)? {
+            Route::One(member) => member.clone().handle(request, deadline).await,
+            Route::All => self.handle_all(request, deadline).await,
+        }
+    }
+
+    fn busy(&self) -> Option<&'static str> {
+        self.members.iter().find_map(|member| member.busy())
+    }
+
+    fn answered(&self, method: &Method, elapsed: Duration, succeeded: bool) {
+        *self.last_request.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        if let Some(metrics) = &self.metrics {
+            metrics.record(method.name(), elapsed, succeeded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PrefetchConfig;
+    use crate::lsp::ServerOptions;
+
+    fn workspaces(root: &Path, members: &[&str]) -> Workspaces {
+        let members = members
+            .iter()
+            .map(|member| {
+                let dir = root.join(member);
+                std::fs::create_dir_all(dir.join("src")).unwrap();
+                std::fs::write(dir.join("Cargo.toml"), "[workspace]\n").unwrap();
+                DaemonState::new(&dir, ServerOptions::default(), PrefetchConfig::default()).unwrap()
+            })
+            .collect();
+        Workspaces::new(root, members)
+    }
+
+    fn request(method: Method, workspace: Option<PathBuf>) -> Request {
+        Request { id: "1".to_string(), method, deadline_ms: None, allow_stale: false, timeout_ms: None, workspace }
+    }
+
+    fn routed(workspaces: &Workspaces, request: &Request) -> Option<PathBuf> {
+        match workspaces.route(request).unwrap() {
+            Route::One(member) => Some(member.workspace().to_path_buf()),
+            Route::All => None,
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let workspaces = workspaces(&root, &["services/api", "services/api/tools", "web"]);
+        let outline = |file: &str| Method::Outline { file: root.join(file) };
+
+        assert_eq!(routed(&workspaces, &request(outline("services/api/src/lib.rs"), None)), Some(root.join("services/api")));
+        assert_eq!(routed(&workspaces, &request(outline("services/api/tools/src/main.rs"), None)), Some(root.join("services/api/tools")));
+        // The file wins over the workspace the client runs in
+        assert_eq!(routed(&workspaces, &request(outline("web/src/lib.rs"), Some(root.join("services/api")))), Some(root.join("web")));
+        let error = workspaces.route(&request(outline("docs/notes.rs"), None)).err().unwrap().to_string();
+        assert!(error.contains("none of the workspaces") && error.contains("services/api/tools, web"), "{}", error);
+
+        let search = Method::Search { query: "Config".to_string(), limit: 5 };
+        assert_eq!(routed(&workspaces, &request(search.clone(), Some(root.join("web/src")))), Some(root.join("web")));
+        assert_eq!(routed(&workspaces, &request(search, Some(root.clone()))), None);
+        assert_eq!(routed(&workspaces, &request(Method::Status, None)), None);
+        assert!(workspaces.route(&request(Method::Tour { top: 5 }, None)).is_err());
+    }
+
+    #[test]
+    fn test_single_member_answers_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let workspaces = workspaces(&root, &["."]);
+        let request = request(Method::Outline { file: PathBuf::from("/elsewhere/lib.rs") }, None);
+        assert_eq!(routed(&workspaces, &request), Some(root));
+    }
+
+    #[test]
+    fn test_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let workspaces = workspaces(&root, &["a"]);
+        let answers = vec![
+            serde_json::json!({ "diagnostics": [{ "file": root.join("a/src/lib.rs"), "path": "src/lib.rs" }] }),
+            serde_json::json!({ "diagnostics": [{ "file": root.join("b/src/main.rs"), "path": "src/main.rs" }], "partial": true }),
+        ];
+        let merged = workspaces.merged(answers, "diagnostics");
+        assert_eq!(merged["partial"], true);
+        let paths: Vec<&str> = merged["diagnostics"].as_array().unwrap().iter().map(|d| d["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["a/src/lib.rs", "b/src/main.rs"]);
+    }
+}
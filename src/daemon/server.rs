@@ -1,60 +1,315 @@
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use anyhow::{Result, Context};
-use tokio::net::{UnixListener, UnixStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::{info, error, debug};
+use std::time::{Duration, Instant};
+use anyhow::{bail, Result, Context};
+use async_trait::async_trait;
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+use tracing::{info, error, debug, warn};
 
-use crate::core::LanguageQueryService;
-use crate::ipc::{Request, Response, Method, ResponseResult};
+use crate::config::{HooksConfig, MetricsConfig, PrefetchConfig, RetryConfig};
+use crate::core::workspace::rust_source_files;
+use crate::core::{Deadline, LanguageQueryService};
+use crate::daemon::builds::{set_paused, target_dir, user_build_running, BUILD_POLL_INTERVAL};
+use crate::daemon::cache::{cache_path, workspace_fingerprint, Lookup, ResponseCache, SAVE_INTERVAL};
+use crate::daemon::hooks::{Hooks, Observation, Observer, POLL_INTERVAL};
+use crate::daemon::host::{Host, RequestHandler};
+use crate::daemon::get_socket_path;
+use crate::daemon::metrics::{MetricsRecorder, FLUSH_INTERVAL};
+use crate::daemon::pidfile::PidFile;
+use crate::daemon::prefetch::{follow_ups, Prefetcher};
+use crate::daemon::process::process_memory;
+use crate::daemon::retry::worth_retrying;
+use crate::daemon::stats::{CacheOutcome, Stats};
+use crate::daemon::router::Workspaces;
+use crate::lsp::{CallDirection, IndexingStatus, Priority, ProgressState, ServerOptions, Severity, TypeHierarchy};
+use crate::ipc::{DaemonStatus, PathQuery, Request, Method, PROTOCOL_VERSION};
+use crate::ipc::results::{
+    ActionsResult, AsyncCallersResult, BoundsResult, CallsResult, ChurnResult, CoverageMapResult, DefResult, DiagResult, DiffRefsResult, DocsResult, ErrorFlowResult, ExpandResult, GenericHotspotsResult,
+    HierarchyResult, HintsResult, HirResult, HotspotsResult, ImplResult, OutlineResult, PingResult, RefsResult, RenameResult, ResolveResult,
+    ResultFlags, RunnablesResult, SearchResult, SsrResult, StringsResult, SyntaxResult, TourResult, TransitiveRefsResult, TypeResult,
+};
+
+/// How long `Ping` waits for the language server's hover before calling it unresponsive
+const PING_HOVER_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the language server's memory is checked against `restart_above`
+const MEMORY_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// How often background tasks check whether a request has started the language server
+const STARTED_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct DaemonServer {
-    service: Arc<LanguageQueryService>,
+    host: Arc<Host<Workspaces>>,
+    /// The hooks of each member, in the order of [`Workspaces::members`]
+    hooks: Vec<Hooks>,
+    pause_during_builds: bool,
+    /// Resident memory above which the language server is restarted
+    restart_above: Option<u64>,
     socket_path: PathBuf,
     listener: UnixListener,
+    /// Removed, like the socket, when the daemon exits
+    pid_file: PidFile,
+    /// How long the daemon may go without requests before it exits
+    idle_timeout: Option<Duration>,
+}
+
+/// State shared by all client connections of a daemon about one workspace,
+/// and the handler that answers their requests. A tool can host it in-process
+/// with a [`Host`](crate::daemon::host::Host) of its own.
+pub struct DaemonState {
+    /// Set once the language server has started; until then only cached answers are served
+    service: tokio::sync::OnceCell<LanguageQueryService>,
+    workspace: PathBuf,
+    options: ServerOptions,
+    /// The language server's progress, followed while it starts
+    progress: watch::Receiver<ProgressState>,
+    cache: ResponseCache,
+    prefetcher: Prefetcher,
+    retry: RetryConfig,
+    started_at: Instant,
+    /// PID of the language server while it is stopped for a build
+    paused_server: std::sync::Mutex<Option<u32>>,
+    /// How requests have been answered, for `lq stats`
+    stats: Stats,
+    /// Times the language server was restarted for going over `restart_above`
+    memory_restarts: AtomicU32,
+}
+
+impl DaemonState {
+    /// State for the workspace at `workspace`, caching answers in memory only.
+    /// The language server starts with the first request that needs it.
+    pub fn new(workspace: &Path, options: ServerOptions, prefetch: PrefetchConfig) -> Result<Self> {
+        let workspace = workspace.canonicalize()
+            .context("Failed to canonicalize workspace path")?;
+        LanguageQueryService::check_workspace(&workspace)?;
+        let (progress_tx, progress) = watch::channel(ProgressState::default());
+        Ok(Self {
+            service: tokio::sync::OnceCell::new(),
+            workspace,
+            options: ServerOptions { progress: Some(Arc::new(progress_tx)), ..options },
+            progress,
+            cache: ResponseCache::default(),
+            prefetcher: Prefetcher::new(prefetch),
+            retry: RetryConfig::default(),
+            started_at: Instant::now(),
+            paused_server: std::sync::Mutex::new(None),
+            stats: Stats::default(),
+            memory_restarts: AtomicU32::new(0),
+        })
+    }
+    
+    /// The canonical root of the workspace
+    pub fn workspace(&self) -> &Path {
+        &self.workspace
+    }
+    
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+    
+    /// The service, waiting for the language server to start if it has not yet
+    async fn service(&self) -> Result<&LanguageQueryService> {
+        self.service
+            .get_or_try_init(|| LanguageQueryService::with_options(&self.workspace, self.options.clone()))
+            .await
+    }
+    
+    /// The service once a request has started the language server, without starting it
+    async fn started_service(&self) -> &LanguageQueryService {
+        loop {
+            if let Some(service) = self.service.get() {
+                return service;
+            }
+            tokio::time::sleep(STARTED_POLL_INTERVAL).await;
+        }
+    }
+    
+    /// Whether answers computed now are complete: the language server has
+    /// started, is done indexing, and is up
+    fn is_settled(&self) -> bool {
+        self.service
+            .get()
+            .is_some_and(|service| !service.indexing_status().indexing && !service.is_degraded())
+    }
+    
+    /// Stop or continue the language server; a server that changed since it was
+    /// paused has been restarted and runs already
+    fn pause_server(&self, pause: Option<u32>) {
+        let mut paused = self.paused_server.lock().unwrap_or_else(|e| e.into_inner());
+        if *paused == pause {
+            return;
+        }
+        match (*paused, pause) {
+            (_, Some(pid)) => {
+                info!("Pausing the language server while a cargo build runs");
+                set_paused(pid, true);
+            }
+            (Some(pid), None) => {
+                info!("Resuming the language server");
+                set_paused(pid, false);
+            }
+            (None, None) => {}
+        }
+        *paused = pause;
+    }
+    
+    /// Write the answers cached since the last save to disk
+    fn save_cache(&self) {
+        if let Err(e) = self.cache.save() {
+            warn!("Failed to save cached answers: {:#}", e);
+        }
+    }
+    
+    fn is_server_paused(&self) -> bool {
+        self.paused_server.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+    }
+}
+
+#[async_trait]
+impl RequestHandler for DaemonState {
+    async fn handle(self: Arc<Self>, request: Request, deadline: Deadline) -> Result<serde_json::Value> {
+        let timer = self.stats.start(request.method.name());
+        let result = handle_request(request.id, request.method, deadline, request.allow_stale, &self).await;
+        timer.finish(result.is_ok());
+        result
+    }
+    
+    fn busy(&self) -> Option<&'static str> {
+        let service = self.service.get();
+        if self.is_server_paused() {
+            Some("server paused while a cargo build runs")
+        } else if service.is_none() {
+            Some("server still starting")
+        } else if service.is_some_and(|service| service.indexing_status().indexing) {
+            Some("server still indexing")
+        } else if service.is_some_and(LanguageQueryService::is_degraded) {
+            Some("server down")
+        } else {
+            None
+        }
+    }
+}
+
+/// Bind `socket_path` under a 0077 umask, so that the socket is created 0600
+/// rather than narrowed after another user could already have connected
+fn bind_private(socket_path: &Path) -> Result<UnixListener> {
+    // SAFETY: umask has no preconditions and cannot fail; the daemon creates
+    // no other files while it starts, so no other thread sees the narrower mask
+    let previous = unsafe { libc::umask(0o077) };
+    let listener = UnixListener::bind(socket_path);
+    // SAFETY: as above, restoring the mask the process had
+    unsafe { libc::umask(previous) };
+    listener.context("Failed to bind to socket")
 }
 
 impl DaemonServer {
-    pub async fn new(workspace: &Path, socket_path: PathBuf) -> Result<Self> {
+    /// The daemon for `workspace`, serving each of `members` as a workspace of
+    /// its own, or `workspace` itself when there are none
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        workspace: &Path,
+        members: &[PathBuf],
+        socket_path: PathBuf,
+        options: ServerOptions,
+        priority: Priority,
+        prefetch: PrefetchConfig,
+        retry: RetryConfig,
+        hooks: HooksConfig,
+        metrics: MetricsConfig,
+    ) -> Result<Self> {
         // Remove existing socket if it exists
         if socket_path.exists() {
             std::fs::remove_file(&socket_path)
                 .context("Failed to remove existing socket")?;
         }
         
-        let listener = UnixListener::bind(&socket_path)
-            .context("Failed to bind to socket")?;
+        let listener = bind_private(&socket_path)?;
         
         info!("Daemon listening on: {:?}", socket_path);
+        let pid_file = PidFile::create(&socket_path)?;
+        let restart_above = options.limits.restart_above_bytes()?;
         
-        // Ensure we have an absolute path for the workspace
-        let absolute_workspace = workspace.canonicalize()
-            .context("Failed to canonicalize workspace path")?;
+        let members = match members {
+            [] => vec![workspace.to_path_buf()],
+            members => members.to_vec(),
+        };
+        let mut states = Vec::new();
+        for member in &members {
+            let state = DaemonState::new(member, options.clone(), prefetch.clone())?;
+            // A member's answers are saved where a daemon of its own would save them
+            let cache = match members.len() {
+                1 => cache_path(&socket_path)?,
+                _ => cache_path(&get_socket_path(&state.workspace)?)?,
+            };
+            states.push(DaemonState { cache: ResponseCache::persisted(cache), retry: retry.clone(), ..state });
+        }
+        
+        // Lowered before the language server starts, so that it inherits the priority
+        priority.apply_to_process(std::process::id())?;
         
-        let service = Arc::new(LanguageQueryService::new(&absolute_workspace).await?);
+        let metrics = match metrics.enabled {
+            true => Some(MetricsRecorder::for_socket(&socket_path)?),
+            false => None,
+        };
         
         Ok(Self {
-            service,
+            hooks: states.iter().map(|state| Hooks::new(&state.workspace, hooks.clone())).collect(),
+            host: Host::new(Arc::new(Workspaces::new(workspace, states).with_metrics(metrics))),
+            pause_during_builds: priority.pause_during_builds,
+            restart_above,
             socket_path,
             listener,
+            pid_file,
+            idle_timeout: None,
         })
     }
     
+    /// Exit once no request has come for `idle_timeout`, rather than running until stopped
+    pub fn with_idle_timeout(self, idle_timeout: Option<Duration>) -> Self {
+        Self { idle_timeout, ..self }
+    }
+    
     pub async fn run(self) -> Result<()> {
-        let service = self.service.clone();
+        let workspaces = self.host.handler().clone();
         let socket_path = self.socket_path.clone();
         
-        // Handle shutdown signal
-        let shutdown = Arc::new(tokio::sync::Notify::new());
-        let shutdown_clone = shutdown.clone();
+        // The language server of a lone workspace starts once the daemon runs, so that
+        // answers kept from before a restart are served while it indexes; those of several
+        // start with the first request about each, rather than all at once
+        if let [state] = workspaces.members() {
+            let starting = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = starting.service().await {
+                    error!("Failed to start the language server: {:#}", e);
+                }
+            });
+        }
+        for (state, hooks) in workspaces.members().iter().zip(self.hooks) {
+            tokio::spawn(save_cache(state.clone()));
+            if !hooks.is_empty() {
+                tokio::spawn(run_hooks(state.clone(), hooks));
+            }
+            if self.pause_during_builds {
+                tokio::spawn(pause_during_builds(state.clone()));
+            }
+            if let Some(limit) = self.restart_above {
+                tokio::spawn(restart_on_memory(state.clone(), limit));
+            }
+        }
+        tokio::spawn(flush_metrics(workspaces.clone()));
+        if let Some(idle_timeout) = self.idle_timeout {
+            tokio::spawn(stop_when_idle(self.host.clone(), idle_timeout));
+        }
         
+        // Handle shutdown signal
+        let host = self.host.clone();
         tokio::spawn(async move {
             use futures::stream::StreamExt;
-            match signal_hook_tokio::Signals::new(&[signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT]) {
+            match signal_hook_tokio::Signals::new([signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT]) {
                 Ok(mut signals) => {
                     if let Some(_signal) = signals.next().await {
                         info!("Received shutdown signal");
-                        shutdown_clone.notify_one();
+                        host.stop();
                     }
                 }
                 Err(e) => {
@@ -63,129 +318,518 @@ impl DaemonServer {
             }
         });
         
-        loop {
-            tokio::select! {
-                result = self.listener.accept() => {
-                    match result {
-                        Ok((stream, _addr)) => {
-                            let service = service.clone();
-                            tokio::spawn(async move {
-                                if let Err(e) = handle_client(stream, service).await {
-                                    error!("Error handling client: {}", e);
-                                }
-                            });
-                        }
-                        Err(e) => {
-                            error!("Failed to accept connection: {}", e);
-                        }
-                    }
-                }
-                _ = shutdown.notified() => {
-                    info!("Shutting down daemon");
-                    break;
-                }
-            }
-        }
+        self.host.serve(self.listener).await?;
         
         // Cleanup
-        drop(self.listener);
+        for state in workspaces.members() {
+            state.pause_server(None);
+            state.save_cache();
+        }
+        workspaces.flush_metrics();
         let _ = std::fs::remove_file(&socket_path);
+        drop(self.pid_file);
         
         Ok(())
     }
 }
 
-async fn handle_client(mut stream: UnixStream, service: Arc<LanguageQueryService>) -> Result<()> {
-    let mut buffer = vec![0; 65536]; // 64KB buffer
-    
+/// Watch the language server for the events hooks run on, for as long as the daemon runs
+async fn run_hooks(state: Arc<DaemonState>, hooks: Hooks) {
+    let service = state.started_service().await;
+    let mut observer = Observer::default();
     loop {
-        // Read length prefix (4 bytes)
-        let mut len_buf = [0u8; 4];
-        match stream.read_exact(&mut len_buf).await {
-            Ok(_) => {},
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                debug!("Client disconnected");
-                return Ok(());
-            }
-            Err(e) => return Err(e.into()),
+        let observation = Observation {
+            health: service.server_health(),
+            server_pid: service.server_pid(),
+            diagnostics: service.diagnostics(None, Severity::Hint).await.ok(),
+        };
+        for event in observer.observe(observation) {
+            hooks.fire(&event);
         }
-        
-        let msg_len = u32::from_be_bytes(len_buf) as usize;
-        if msg_len > buffer.len() {
-            buffer.resize(msg_len, 0);
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Stop the daemon once no request has been answered for `idle_timeout` and none is being answered
+async fn stop_when_idle(host: Arc<Host<Workspaces>>, idle_timeout: Duration) {
+    loop {
+        let idle = host.handler().idle_for();
+        if idle >= idle_timeout && host.requests_in_flight() == 0 {
+            info!("No requests for {}s, shutting down", idle.as_secs());
+            host.stop();
+            return;
         }
-        
-        // Read message
-        stream.read_exact(&mut buffer[..msg_len]).await?;
-        
-        // Parse request
-        let request: Request = serde_json::from_slice(&buffer[..msg_len])
-            .context("Failed to parse request")?;
-        
-        debug!("Received request: {:?}", request.method);
-        
-        let is_shutdown = matches!(request.method, Method::Shutdown);
-        
-        // Handle request
-        let response = match handle_request(request.id.clone(), request.method, &service).await {
-            Ok(result) => Response {
-                id: request.id,
-                result: ResponseResult::Success { result },
-            },
-            Err(e) => Response {
-                id: request.id,
-                result: ResponseResult::Error { error: e.to_string() },
-            },
+        // Sleep until the timeout would be up if no request came meanwhile
+        tokio::time::sleep(idle_timeout.saturating_sub(idle).max(Duration::from_secs(1))).await;
+    }
+}
+
+/// Save the requests counted for metrics every so often, for as long as the daemon runs
+async fn flush_metrics(workspaces: Arc<Workspaces>) {
+    loop {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        workspaces.flush_metrics();
+    }
+}
+
+/// Save the answers cached since the last save every so often, for as long as the daemon runs
+async fn save_cache(state: Arc<DaemonState>) {
+    loop {
+        tokio::time::sleep(SAVE_INTERVAL).await;
+        state.save_cache();
+    }
+}
+
+/// Stop the language server while it indexes during a build of the workspace, for as long as the daemon runs
+async fn pause_during_builds(state: Arc<DaemonState>) {
+    let service = state.started_service().await;
+    let target = target_dir(service.workspace_path());
+    loop {
+        let pause = service
+            .server_pid()
+            .filter(|_| service.indexing_status().indexing)
+            .filter(|&pid| user_build_running(&target, pid));
+        state.pause_server(pause);
+        tokio::time::sleep(BUILD_POLL_INTERVAL).await;
+    }
+}
+
+/// Restart the language server whenever its resident memory goes above `limit` bytes, for as long as the daemon runs
+async fn restart_on_memory(state: Arc<DaemonState>, limit: u64) {
+    let service = state.started_service().await;
+    loop {
+        tokio::time::sleep(MEMORY_POLL_INTERVAL).await;
+        let Some(memory) = service.server_pid().and_then(process_memory) else {
+            continue;
         };
-        
-        // Send response
-        let response_bytes = serde_json::to_vec(&response)?;
-        let len_bytes = (response_bytes.len() as u32).to_be_bytes();
-        stream.write_all(&len_bytes).await?;
-        stream.write_all(&response_bytes).await?;
-        stream.flush().await?;
-        
-        // Check if this was a shutdown request
-        if matches!(response.result, ResponseResult::Success { .. }) && is_shutdown {
-            info!("Received shutdown request");
-            // Give client time to receive response
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            std::process::exit(0);
+        if memory <= limit {
+            continue;
+        }
+        let reason = format!("using {} MiB of memory, above its {} MiB limit", memory >> 20, limit >> 20);
+        warn!("Restarting the language server of {:?}: {}", state.workspace, reason);
+        if service.restart_server(&reason) {
+            state.memory_restarts.fetch_add(1, Ordering::Relaxed);
         }
     }
 }
 
+/// Answer `method` from the cache when possible, otherwise compute and cache the answer.
+///
+/// A stale cached answer is returned when the request allows it, and also in
+/// place of a partial answer when the deadline passes. Answers from text search
+/// while the language server is down are flagged `"degraded"` and never cached.
 async fn handle_request(
-    _id: String,
+    id: String,
     method: Method,
-    service: &LanguageQueryService,
+    deadline: Deadline,
+    allow_stale: bool,
+    state: &Arc<DaemonState>,
 ) -> Result<serde_json::Value> {
+    let Some(key) = ResponseCache::key(&method) else {
+        return answer(id, method, deadline, state).await;
+    };
+    
+    let name = method.name();
+    let fingerprint = workspace_fingerprint(&state.workspace);
+    let cached = match state.cache.lookup(&key, fingerprint) {
+        Lookup::Fresh(result) => {
+            state.stats.record_cache(name, CacheOutcome::Hit);
+            return Ok(result);
+        }
+        Lookup::Stale(result) if allow_stale => {
+            state.stats.record_cache(name, CacheOutcome::Stale);
+            refresh_in_background(state.clone(), key, id, method);
+            return Ok(flagged(result, "stale"));
+        }
+        Lookup::Stale(result) => Some(result),
+        Lookup::Missing => None,
+    };
+    
+    // A prefetch or refresh already computing this answer is closer to done than a new computation
+    if deadline.within(state.cache.wait_for_refresh(&key)).await.is_some() {
+        if let Lookup::Fresh(result) = state.cache.lookup(&key, fingerprint) {
+            state.stats.record_cache(name, CacheOutcome::Hit);
+            return Ok(result);
+        }
+    }
+    
+    let service = state.service().await?;
+    if service.is_degraded() {
+        return Ok(flagged(answer(id, method, deadline, state).await?, "degraded"));
+    }
+    let prefetch = follow_ups(&method);
+    let retry = method.clone();
+    let mut result = answer(id.clone(), method, deadline, state).await?;
+    let mut retried = false;
+    let ready_for = state.progress.borrow().ready_for();
+    if worth_retrying(&state.retry, &retry, &result, ready_for) {
+        debug!("Empty answer right after the server became ready, retrying: {}", retry.name());
+        let delay = Duration::from_millis(state.retry.delay_ms);
+        if deadline.within(tokio::time::sleep(delay)).await.is_some() {
+            result = answer(id, retry, deadline, state).await?;
+            retried = true;
+        }
+    }
+    if deadline.expired() {
+        return Ok(match cached {
+            Some(stale) => {
+                state.stats.record_cache(name, CacheOutcome::Stale);
+                flagged(stale, "stale")
+            }
+            None => {
+                state.stats.record_cache(name, CacheOutcome::Miss);
+                flagged(result, "partial")
+            }
+        });
+    }
+    state.stats.record_cache(name, CacheOutcome::Miss);
+    // Answers computed while indexing may be incomplete
+    if !service.indexing_status().indexing {
+        state.cache.store(key, fingerprint, result.clone());
+    }
+    prefetch_in_background(state, prefetch);
+    Ok(if retried { flagged(result, "retried") } else { result })
+}
+
+/// Answer and cache `methods` ahead of time, as far as the prefetch limits allow
+fn prefetch_in_background(state: &Arc<DaemonState>, methods: Vec<Method>) {
+    if !state.prefetcher.enabled() || methods.is_empty() {
+        return;
+    }
+    let fingerprint = workspace_fingerprint(&state.workspace);
+    for method in methods {
+        let Some(key) = ResponseCache::key(&method) else {
+            continue;
+        };
+        if state.cache.is_fresh(&key, fingerprint) || !state.prefetcher.try_start() {
+            continue;
+        }
+        if !state.cache.start_refresh(&key) {
+            state.prefetcher.finish();
+            continue;
+        }
+        let state = state.clone();
+        tokio::spawn(async move {
+            match answer("prefetch".to_string(), method, Deadline::none(), &state).await {
+                Ok(result) if state.is_settled() => {
+                    state.cache.store_prefetched(key.clone(), fingerprint, result)
+                }
+                Ok(_) => {}
+                Err(e) => debug!("Failed to prefetch answer: {}", e),
+            }
+            state.cache.finish_refresh(&key);
+            state.prefetcher.finish();
+        });
+    }
+}
+
+/// Recompute a stale cached answer, unless a refresh of it is already running
+fn refresh_in_background(state: Arc<DaemonState>, key: String, id: String, method: Method) {
+    if !state.cache.start_refresh(&key) {
+        return;
+    }
+    tokio::spawn(async move {
+        let fingerprint = workspace_fingerprint(&state.workspace);
+        match answer(id, method, Deadline::none(), &state).await {
+            Ok(result) if state.is_settled() => {
+                state.cache.store(key.clone(), fingerprint, result)
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Failed to refresh cached answer: {}", e),
+        }
+        state.cache.finish_refresh(&key);
+    });
+}
+
+/// Mark a payload with a `"partial"`, `"stale"` or `"degraded"` caveat
+fn flagged(mut result: serde_json::Value, flag: &str) -> serde_json::Value {
+    if let Some(fields) = result.as_object_mut() {
+        fields.insert(flag.to_string(), serde_json::json!(true));
+    }
+    result
+}
+
+/// The wire form of a typed result
+fn payload<T: serde::Serialize>(result: T) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(result)?)
+}
+
+/// Build the response payload for `method`.
+///
+/// Single queries cut off by `deadline` answer as if nothing was found; reports
+/// return the items collected before it passed. Requests about the daemon
+/// itself are answered while the language server is still starting.
+async fn answer(
+    id: String,
+    method: Method,
+    deadline: Deadline,
+    state: &DaemonState,
+) -> Result<serde_json::Value> {
+    let service = match method {
+        Method::Status => return payload(status(state)),
+        Method::Ping => return payload(ping(state, deadline).await),
+        Method::Stats => return payload(state.stats.result()),
+        // Answered by the daemon's host
+        Method::Cancel { .. } | Method::Codec { .. } | Method::Compress { .. } | Method::Shutdown => bail!("{} is not a query", method.name()),
+        _ => state.service().await?,
+    };
     match method {
-        Method::Docs { file, line, symbol } => {
-            let result = service.get_docs(&file, line, &symbol).await?;
-            Ok(serde_json::json!({ "docs": result }))
+        Method::Docs { file, line, column, symbol } => {
+            let result = deadline.within(service.get_docs(&file, line, column, &symbol)).await.transpose()?.flatten();
+            payload(DocsResult { docs: result, location: None, flags: ResultFlags::default() })
+        }
+        Method::DocsRange { file, start_line, start_column, end_line, end_column } => {
+            let range = service.hover_range(&file, (start_line, start_column), (end_line, end_column));
+            let result = deadline.within(range).await.transpose()?.flatten();
+            payload(DocsResult { docs: result, location: None, flags: ResultFlags::default() })
+        }
+        Method::Definition { file, line, column, symbol } => {
+            let result = deadline.within(service.get_definition(&file, line, column, &symbol)).await.transpose()?.unwrap_or_default();
+            payload(DefResult::new(result))
+        }
+        Method::Impl { file, line, column, symbol } => {
+            let both = async {
+                tokio::try_join!(service.get_impls(&file, line, column, &symbol), service.get_definition(&file, line, column, &symbol))
+            };
+            let (implementations, definitions) = deadline.within(both).await.transpose()?.unwrap_or_default();
+            // `implementation` is what clients of protocol version 1 read
+            let implementation = definitions.into_iter().next();
+            payload(ImplResult { implementations, implementation, location: None, flags: ResultFlags::default() })
+        }
+        Method::TypeDefinition { file, line, column, symbol } => {
+            let result = deadline.within(service.get_type_definition(&file, line, column, &symbol)).await.transpose()?.unwrap_or_default();
+            payload(TypeResult { definitions: result, flags: ResultFlags::default() })
         }
-        Method::Impl { file, line, symbol } => {
-            let result = service.get_impl(&file, line, &symbol).await?;
-            Ok(serde_json::json!({ "implementation": result }))
+        Method::Refs { file, line, column, symbol, raw } => {
+            let result = if raw {
+                deadline.within(service.get_refs_raw(&file, line, column, &symbol)).await
+            } else {
+                deadline.within(service.get_refs(&file, line, column, &symbol)).await
+            };
+            let result = result.transpose()?.unwrap_or_default();
+            payload(RefsResult { references: result, location: None, flags: ResultFlags::default() })
         }
-        Method::Refs { file, line, symbol } => {
-            let result = service.get_refs(&file, line, &symbol).await?;
-            Ok(serde_json::json!({ "references": result }))
+        Method::AtPath { path, query, raw } => {
+            let Some(location) = deadline.within(service.locate_path(&path)).await.transpose()? else {
+                let flags = ResultFlags::default();
+                return match query {
+                    PathQuery::Docs => payload(DocsResult { docs: None, location: None, flags }),
+                    PathQuery::Def => payload(DefResult::new(Vec::new())),
+                    PathQuery::Impl => payload(ImplResult { implementations: Vec::new(), implementation: None, location: None, flags }),
+                    PathQuery::Refs => payload(RefsResult { references: Vec::new(), location: None, flags }),
+                };
+            };
+            let mut result = Box::pin(answer(id, query.at(&location, raw), deadline, state)).await?;
+            result["location"] = serde_json::json!(location);
+            Ok(result)
         }
         Method::Resolve { file, symbol } => {
-            let result = service.resolve_symbol(&file, &symbol).await?;
-            Ok(serde_json::json!({ "resolved": result }))
+            let result = deadline.within(service.resolve_symbol(&file, &symbol)).await.transpose()?.flatten();
+            payload(ResolveResult { resolved: result, flags: ResultFlags::default() })
+        }
+        Method::Callers { file, line, column, symbol, depth } => {
+            let result = service.call_hierarchy(&file, line, column, &symbol, CallDirection::Incoming, depth, deadline).await?;
+            payload(CallsResult { calls: result, flags: ResultFlags::default() })
+        }
+        Method::Callees { file, line, column, symbol, depth } => {
+            let result = service.call_hierarchy(&file, line, column, &symbol, CallDirection::Outgoing, depth, deadline).await?;
+            payload(CallsResult { calls: result, flags: ResultFlags::default() })
+        }
+        Method::Outline { file } => {
+            let result = deadline.within(service.outline(&file)).await.transpose()?.unwrap_or_default();
+            payload(OutlineResult { outline: result, flags: ResultFlags::default() })
+        }
+        Method::Hints { file, start_line, end_line } => {
+            let lines = start_line.map(|start| (start, end_line.unwrap_or(start)));
+            let result = deadline.within(service.inlay_hints(&file, lines)).await.transpose()?.unwrap_or_default();
+            payload(HintsResult { hints: result, flags: ResultFlags::default() })
+        }
+        Method::Search { query, limit } => {
+            let result = deadline.within(service.search(&query, limit)).await.transpose()?.unwrap_or_default();
+            payload(SearchResult { symbols: result, flags: ResultFlags::default() })
+        }
+        Method::Diag { file, severity } => {
+            let result = deadline.within(service.diagnostics(file.as_deref(), severity)).await.transpose()?.unwrap_or_default();
+            payload(DiagResult { diagnostics: result, flags: ResultFlags::default() })
+        }
+        Method::Rename { file, line, column, symbol, new_name } => {
+            // A partial edit would misstate what the rename touches, so it ignores the deadline
+            let result = service.rename(&file, line, column, &symbol, &new_name).await?;
+            payload(RenameResult { rename: result, applied: None, flags: ResultFlags::default() })
+        }
+        Method::Actions { file, line, column, symbol } => {
+            // Like a rename, an action list cut short could hide the fix being looked for
+            let result = service.code_actions(&file, line, column, symbol.as_deref()).await?;
+            payload(ActionsResult { actions: result, applied: None, flags: ResultFlags::default() })
+        }
+        Method::Expand { file, line, column, symbol } => {
+            let result = deadline.within(service.expand_macro(&file, line, column, symbol.as_deref())).await.transpose()?.flatten();
+            payload(ExpandResult { expansion: result, flags: ResultFlags::default() })
+        }
+        Method::Hir { file, line } => {
+            let result = deadline.within(service.view_hir(&file, line)).await.transpose()?.flatten();
+            payload(HirResult { hir: result, flags: ResultFlags::default() })
         }
-        Method::Status => {
-            Ok(serde_json::json!({
-                "status": "ready",
-                "workspace": service.workspace_path().display().to_string(),
-                "indexing": false,
-            }))
+        Method::Syntax { file, line } => {
+            let result = deadline.within(service.syntax_tree(&file, line)).await.transpose()?.flatten();
+            payload(SyntaxResult { syntax: result, flags: ResultFlags::default() })
         }
-        Method::Shutdown => {
-            Ok(serde_json::json!({ "shutdown": true }))
+        Method::Runnables { file } => {
+            let result = match file {
+                Some(file) => deadline.within(service.runnables(Some(&file), deadline)).await.transpose()?.unwrap_or_default(),
+                None => service.runnables(None, deadline).await?,
+            };
+            payload(RunnablesResult { runnables: result, flags: ResultFlags::default() })
+        }
+        Method::Ssr { rule, file, start_line, end_line } => {
+            // Like a rename, a partial rewrite would misstate what the rule touches
+            let lines = start_line.map(|start| (start, end_line.unwrap_or(start)));
+            let result = service.structural_replace(&rule, file.as_deref(), lines).await?;
+            payload(SsrResult { ssr: result, applied: None, flags: ResultFlags::default() })
+        }
+        Method::Hierarchy { file, line, column, symbol } => {
+            let result = match deadline.within(service.type_hierarchy(&file, line, column, &symbol)).await {
+                Some(result) => result?,
+                None => TypeHierarchy {
+                    path: service.relative_path(&file),
+                    name: symbol,
+                    line,
+                    supertypes: Vec::new(),
+                    subtypes: Vec::new(),
+                },
+            };
+            payload(HierarchyResult { hierarchy: result, flags: ResultFlags::default() })
+        }
+        Method::GenericHotspots { top } => {
+            let result = service.generic_hotspots(top, deadline).await?;
+            payload(GenericHotspotsResult { hotspots: result, flags: ResultFlags::default() })
+        }
+        Method::CoverageMap => {
+            let result = service.coverage_map(deadline).await?;
+            payload(CoverageMapResult { functions: Some(result), untested: None, flags: ResultFlags::default() })
+        }
+        Method::Hotspots { top, sample, concurrency } => {
+            let result = service.hotspots(top, sample, concurrency, deadline).await?;
+            payload(HotspotsResult { hotspots: result, flags: ResultFlags::default() })
+        }
+        Method::Tour { top } => {
+            let result = service.tour(top, deadline).await?;
+            payload(TourResult { tour: result, flags: ResultFlags::default() })
+        }
+        Method::Churn { top, since, sort } => {
+            let result = service.churn_report(top, since.as_deref(), sort).await?;
+            payload(ChurnResult { entries: result })
+        }
+        Method::DiffRefs { a, b, revs } => {
+            let diff = match (b, revs.as_slice()) {
+                (Some(b), []) => service.diff_refs(&a, &b).await?,
+                (None, [rev]) => service.diff_refs_across(&a, rev, None).await?,
+                (None, [rev_a, rev_b]) => service.diff_refs_across(&a, rev_a, Some(rev_b)).await?,
+                _ => bail!("diff-refs compares two items, or one item at one or two revisions"),
+            };
+            payload(DiffRefsResult { diff, flags: ResultFlags::default() })
+        }
+        Method::AsyncCallers { file, line, column, symbol, depth } => {
+            let report = service.async_callers(&file, line, column, &symbol, depth, deadline).await?;
+            payload(AsyncCallersResult { report, flags: ResultFlags::default() })
+        }
+        Method::TransitiveRefs { file, line, column, symbol, depth } => {
+            let report = service.ref_closure(&file, line, column, &symbol, depth, deadline).await?;
+            payload(TransitiveRefsResult { report, flags: ResultFlags::default() })
+        }
+        Method::Strings { pattern, log } => {
+            let strings = service.string_matches(&pattern, log, deadline).await?;
+            payload(StringsResult { strings, flags: ResultFlags::default() })
+        }
+        Method::Bounds { file, line, column } => {
+            let report = service.call_bounds(&file, line, column, deadline).await?;
+            payload(BoundsResult { report, flags: ResultFlags::default() })
+        }
+        Method::ErrorFlow { file, line, column, symbol } => {
+            let report = service.error_flow(&file, line, column, &symbol, deadline).await?;
+            payload(ErrorFlowResult { report, flags: ResultFlags::default() })
+        }
+        Method::Status | Method::Ping | Method::Stats | Method::Cancel { .. } | Method::Codec { .. } | Method::Compress { .. } | Method::Shutdown => unreachable!("answered above"),
+    }
+}
+
+/// Whether the language server is up, and how long it takes to answer a hover
+/// at the top of the crate root, or of the first source file when there is none.
+/// A server that has not started is not started for this.
+async fn ping(state: &DaemonState, deadline: Deadline) -> PingResult {
+    let service = state.service.get();
+    let server_pid = service.and_then(LanguageQueryService::server_pid);
+    let mut result = PingResult {
+        status: status(state).status,
+        server_alive: server_pid.is_some() && !service.is_some_and(LanguageQueryService::is_degraded),
+        server_pid,
+        hover_ms: None,
+        hover_error: None,
+        round_trip_ms: None,
+    };
+    let Some(service) = service.filter(|_| result.server_alive) else {
+        return result;
+    };
+    let file = ["src/lib.rs", "src/main.rs"]
+        .iter()
+        .map(|root| state.workspace.join(root))
+        .find(|root| root.is_file())
+        .or_else(|| rust_source_files(&state.workspace).into_iter().next());
+    let Some(file) = file else {
+        result.hover_error = Some("no Rust source file to hover in".to_string());
+        return result;
+    };
+    let started = Instant::now();
+    let hover = tokio::time::timeout(PING_HOVER_TIMEOUT, service.hover_at(&file, 1, 1));
+    match deadline.within(hover).await {
+        Some(Ok(Ok(_))) => result.hover_ms = Some(started.elapsed().as_millis() as u64),
+        Some(Ok(Err(e))) => result.hover_error = Some(format!("{:#}", e)),
+        Some(Err(_)) | None => {
+            result.hover_error = Some(format!("no answer after {}ms", started.elapsed().as_millis()));
         }
     }
-}
\ No newline at end of file
+    result
+}
+
+fn status(state: &DaemonState) -> DaemonStatus {
+    let pid = std::process::id();
+    let service = state.service.get();
+    let server_pid = service.and_then(LanguageQueryService::server_pid);
+    let indexing = match service {
+        Some(service) => service.indexing_status(),
+        None => IndexingStatus { indexing: true, ..state.progress.borrow().status() },
+    };
+    let health = service.map(LanguageQueryService::server_health).unwrap_or_default();
+    let status = if service.is_none() {
+        "starting"
+    } else if health.is_degraded() {
+        "degraded"
+    } else if indexing.indexing {
+        "indexing"
+    } else {
+        "ready"
+    };
+    DaemonStatus {
+        status: status.to_string(),
+        workspace: state.workspace.clone(),
+        indexing: indexing.indexing,
+        phase: indexing.phase,
+        percentage: indexing.percentage,
+        message: indexing.message,
+        pid,
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        memory_bytes: process_memory(pid),
+        server_pid,
+        server_memory_bytes: server_pid.and_then(process_memory),
+        next_restart_secs: health.next_restart.map(|at| at.saturating_duration_since(Instant::now()).as_millis().div_ceil(1000) as u64),
+        crash_reason: health.crash_reason,
+        restart_attempts: health.restart_attempts,
+        server_restarts: health.restarts,
+        memory_restarts: state.memory_restarts.load(Ordering::Relaxed),
+        paused_for_build: state.is_server_paused(),
+        prefetch: state.prefetcher.stats(state.cache.prefetch_hits()),
+        members: Vec::new(),
+        protocol_version: PROTOCOL_VERSION,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
@@ -0,0 +1,86 @@
+//! The startup handshake between a daemon and the client that spawned it.
+//!
+//! Once it is listening, the daemon writes one line to its stdout: `ready`, or
+//! `failed: <reason>` if it cannot start. It writes nothing to stdout after that.
+//! The spawning client reads the line from a pipe instead of polling the socket,
+//! so it learns right away why a daemon failed to start.
+//!
+//! A spawned daemon then points its stdout and stderr at its log file, as the
+//! client stops reading the pipe once it has the line.
+
+use std::io::{IsTerminal, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartupStatus {
+    Ready,
+    Failed(String),
+}
+
+impl StartupStatus {
+    /// The handshake line, without its newline
+    pub fn line(&self) -> String {
+        match self {
+            Self::Ready => "ready".to_string(),
+            // The reason must stay on one line
+            Self::Failed(reason) => format!("failed: {}", reason.replace('\n', " ")),
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        match line.trim_end() {
+            "ready" => Some(Self::Ready),
+            line => line.strip_prefix("failed: ").map(|reason| Self::Failed(reason.to_string())),
+        }
+    }
+}
+
+/// Tell the spawning client how startup went; a daemon started by hand just prints it
+pub fn report_startup(status: &StartupStatus) {
+    let mut stdout = std::io::stdout().lock();
+    // Nobody may be reading, which is fine
+    let _ = writeln!(stdout, "{}", status.line());
+    let _ = stdout.flush();
+}
+
+/// Send stdout and stderr to `log_path` from now on, as for panics and anything
+/// else printed outside of tracing; a daemon started by hand in a terminal keeps it
+pub fn redirect_output(log_path: &Path) -> Result<()> {
+    if std::io::stdout().is_terminal() {
+        return Ok(());
+    }
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .with_context(|| format!("Failed to open log file {}", log_path.display()))?;
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both descriptors are open, and dup2 replaces the standard one atomically
+        if unsafe { libc::dup2(log.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error()).context("Failed to redirect output to the log file");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_status_line() {
+        assert_eq!(StartupStatus::parse(&StartupStatus::Ready.line()), Some(StartupStatus::Ready));
+
+        let failed = StartupStatus::Failed("Workspace is not trusted\nrun lq trust".to_string());
+        assert_eq!(failed.line(), "failed: Workspace is not trusted run lq trust");
+        assert_eq!(
+            StartupStatus::parse("failed: Workspace is not trusted run lq trust\n"),
+            Some(StartupStatus::Failed("Workspace is not trusted run lq trust".to_string()))
+        );
+
+        assert_eq!(StartupStatus::parse("thread 'main' panicked"), None);
+    }
+}
@@ -0,0 +1,236 @@
+//! How a daemon has performed since it started, for `lq stats`: per method, how
+//! many requests it answered, how many failed, how long they took, and how
+//! often the cache answered them.
+//!
+//! Unlike usage metrics these are always kept, in memory only, and are gone
+//! when the daemon exits. Latencies are counted in buckets rather than kept, so
+//! a daemon that runs for weeks uses no more memory for them than a new one;
+//! percentiles are therefore within a bucket's width, about 19%, of the truth.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ipc::results::{MethodStats, StatsResult};
+
+/// Latency buckets per doubling of milliseconds
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+
+/// How the cache took part in answering a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheOutcome {
+    /// Answered from a fresh cached answer
+    Hit,
+    /// Answered from a cached answer computed before the workspace last changed
+    Stale,
+    /// Computed, as nothing usable was cached
+    Miss,
+}
+
+/// The requests of one method
+#[derive(Debug, Clone, Default)]
+struct Counters {
+    count: u64,
+    errors: u64,
+    total_ms: u64,
+    max_ms: u64,
+    /// Requests per latency bucket; see [`bucket`]
+    latencies: Vec<u64>,
+    cache_hits: u64,
+    cache_stale: u64,
+    cache_misses: u64,
+}
+
+impl Counters {
+    fn record(&mut self, ms: u64, succeeded: bool) {
+        self.count += 1;
+        self.errors += u64::from(!succeeded);
+        self.total_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+        let bucket = bucket(ms);
+        if self.latencies.len() <= bucket {
+            self.latencies.resize(bucket + 1, 0);
+        }
+        self.latencies[bucket] += 1;
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.count += other.count;
+        self.errors += other.errors;
+        self.total_ms += other.total_ms;
+        self.max_ms = self.max_ms.max(other.max_ms);
+        if self.latencies.len() < other.latencies.len() {
+            self.latencies.resize(other.latencies.len(), 0);
+        }
+        for (mine, theirs) in self.latencies.iter_mut().zip(&other.latencies) {
+            *mine += theirs;
+        }
+        self.cache_hits += other.cache_hits;
+        self.cache_stale += other.cache_stale;
+        self.cache_misses += other.cache_misses;
+    }
+
+    /// The latency that `fraction` of requests took at most, as the upper bound of its bucket
+    fn percentile(&self, fraction: f64) -> Option<u64> {
+        let total: u64 = self.latencies.iter().sum();
+        let rank = ((total as f64 * fraction).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.latencies.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(upper_bound(bucket).min(self.max_ms));
+            }
+        }
+        None
+    }
+}
+
+/// The bucket of a latency of `ms` milliseconds
+fn bucket(ms: u64) -> usize {
+    ((ms as f64 + 1.0).log2() * BUCKETS_PER_DOUBLING) as usize
+}
+
+/// The longest latency in `bucket`, in milliseconds: the last below where the next bucket starts
+fn upper_bound(bucket: usize) -> u64 {
+    let next = 2f64.powf((bucket + 1) as f64 / BUCKETS_PER_DOUBLING) - 1.0;
+    (next.ceil() as u64).saturating_sub(1)
+}
+
+/// A daemon's counters, by method name as the protocol spells it
+#[derive(Debug)]
+pub struct Stats {
+    started_at: Instant,
+    methods: Mutex<BTreeMap<String, Counters>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self { started_at: Instant::now(), methods: Mutex::new(BTreeMap::new()) }
+    }
+}
+
+impl Stats {
+    /// Start timing a request for `method`, recorded once the returned timer finishes or is dropped
+    pub fn start(&self, method: &str) -> RequestTimer<'_> {
+        RequestTimer { stats: self, method: method.to_string(), started: Instant::now(), recorded: false }
+    }
+
+    pub fn record(&self, method: &str, elapsed: Duration, succeeded: bool) {
+        self.with(method, |counters| counters.record(elapsed.as_millis() as u64, succeeded));
+    }
+
+    pub fn record_cache(&self, method: &str, outcome: CacheOutcome) {
+        self.with(method, |counters| match outcome {
+            CacheOutcome::Hit => counters.cache_hits += 1,
+            CacheOutcome::Stale => counters.cache_stale += 1,
+            CacheOutcome::Miss => counters.cache_misses += 1,
+        });
+    }
+
+    fn with(&self, method: &str, update: impl FnOnce(&mut Counters)) {
+        let mut methods = self.methods.lock().unwrap_or_else(|e| e.into_inner());
+        update(methods.entry(method.to_string()).or_default());
+    }
+
+    /// Add the counts of `other`, as for the several workspaces of one daemon
+    pub fn merge(&mut self, other: &Stats) {
+        self.started_at = self.started_at.min(other.started_at);
+        let theirs = other.methods.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let mine = self.methods.get_mut().unwrap_or_else(|e| e.into_inner());
+        for (method, counters) in &theirs {
+            mine.entry(method.clone()).or_default().add(counters);
+        }
+    }
+
+    /// The counts so far, as a `Stats` request answers them
+    pub fn result(&self) -> StatsResult {
+        let methods = self.methods.lock().unwrap_or_else(|e| e.into_inner());
+        StatsResult {
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            methods: methods
+                .iter()
+                .map(|(method, counters)| MethodStats {
+                    method: method.clone(),
+                    count: counters.count,
+                    errors: counters.errors,
+                    mean_ms: counters.total_ms.checked_div(counters.count).unwrap_or(0),
+                    p50_ms: counters.percentile(0.5),
+                    p90_ms: counters.percentile(0.9),
+                    p99_ms: counters.percentile(0.99),
+                    max_ms: counters.max_ms,
+                    cache_hits: counters.cache_hits,
+                    cache_stale: counters.cache_stale,
+                    cache_misses: counters.cache_misses,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A request being answered; dropped before [`RequestTimer::finish`], as when
+/// the request is cancelled or times out, it counts as failed
+pub struct RequestTimer<'a> {
+    stats: &'a Stats,
+    method: String,
+    started: Instant,
+    recorded: bool,
+}
+
+impl RequestTimer<'_> {
+    pub fn finish(mut self, succeeded: bool) {
+        self.stats.record(&self.method, self.started.elapsed(), succeeded);
+        self.recorded = true;
+    }
+}
+
+impl Drop for RequestTimer<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.stats.record(&self.method, self.started.elapsed(), false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn method<'a>(result: &'a StatsResult, name: &str) -> &'a MethodStats {
+        result.methods.iter().find(|stats| stats.method == name).unwrap()
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let stats = Stats::default();
+        for ms in 1..=100 {
+            stats.record("Refs", Duration::from_millis(ms), ms != 100);
+        }
+        let result = stats.result();
+        let refs = method(&result, "Refs");
+        assert_eq!((refs.count, refs.errors, refs.mean_ms, refs.max_ms), (100, 1, 50, 100));
+        let within = |estimate: Option<u64>, actual: f64| (estimate.unwrap() as f64 - actual).abs() <= actual * 0.2;
+        assert!(within(refs.p50_ms, 50.0), "{:?}", refs.p50_ms);
+        assert!(within(refs.p90_ms, 90.0), "{:?}", refs.p90_ms);
+        assert!(within(refs.p99_ms, 99.0) && refs.p99_ms <= Some(100), "{:?}", refs.p99_ms);
+        assert_eq!(bucket(upper_bound(bucket(37))), bucket(37));
+        assert_eq!(bucket(upper_bound(bucket(37)) + 1), bucket(37) + 1);
+    }
+
+    #[test]
+    fn test_timer_and_cache() {
+        let stats = Stats::default();
+        stats.start("Docs").finish(true);
+        // A request dropped unanswered, as when cancelled
+        drop(stats.start("Docs"));
+        stats.record_cache("Docs", CacheOutcome::Hit);
+        stats.record_cache("Docs", CacheOutcome::Miss);
+
+        let mut merged = Stats::default();
+        merged.merge(&stats);
+        merged.merge(&stats);
+        let result = merged.result();
+        let docs = method(&result, "Docs");
+        assert_eq!((docs.count, docs.errors, docs.cache_hits, docs.cache_stale, docs.cache_misses), (4, 2, 2, 0, 2));
+        assert_eq!(docs.p50_ms, Some(0));
+    }
+}
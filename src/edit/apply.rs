@@ -0,0 +1,241 @@
+//! Writing a previewed workspace edit to disk.
+//!
+//! New contents are first staged in temporary files next to their targets and
+//! only then renamed into place, so a failure while writing leaves every file
+//! untouched. In a git repository the affected files must have no uncommitted
+//! changes, making the edit revertible with git; elsewhere the originals are
+//! copied to `.lq-backup/<timestamp>/` first.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::edit::{replay, unified_diff, EditPreview, EditedFile};
+
+/// What applying an edit wrote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AppliedEdit {
+    /// Paths relative to the workspace root of the files written, moved or deleted
+    pub files: Vec<PathBuf>,
+    /// Where the originals were copied, outside git repositories
+    pub backup: Option<PathBuf>,
+}
+
+/// Write `preview` to disk; `force` skips the check for uncommitted changes
+pub fn apply(preview: &EditPreview, workspace: &Path, force: bool) -> Result<AppliedEdit> {
+    let files = replay(&preview.changes)?;
+    if unified_diff(&files) != preview.diff {
+        bail!("Files changed since the edit was computed; run the command again");
+    }
+    let files: Vec<EditedFile> = files.into_iter().filter(|f| !f.is_unchanged()).collect();
+
+    for file in &files {
+        let replaces_other = files.iter().all(|other| other.original_file != file.file);
+        if file.file != file.original_file && replaces_other && file.file.exists() {
+            bail!("{} already exists", file.path.display());
+        }
+    }
+
+    let backup = if is_git_repository(workspace) {
+        if !force {
+            ensure_committed(workspace, &files)?;
+        }
+        None
+    } else {
+        Some(back_up(workspace, &files)?)
+    };
+
+    write_files(&files)?;
+    Ok(AppliedEdit {
+        files: files.iter().map(|f| f.path.clone()).collect(),
+        backup,
+    })
+}
+
+fn is_git_repository(workspace: &Path) -> bool {
+    std::process::Command::new("git")
+        .arg("-C")
+        .arg(workspace)
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Fail if any file about to change has modifications git could not restore
+fn ensure_committed(workspace: &Path, files: &[EditedFile]) -> Result<()> {
+    let existing: Vec<&PathBuf> = files.iter().filter(|f| f.before.is_some()).map(|f| &f.original_file).collect();
+    if existing.is_empty() {
+        return Ok(());
+    }
+
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(workspace)
+        .args(["status", "--porcelain", "--"])
+        .args(existing)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("git status failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let dirty: Vec<&str> = std::str::from_utf8(&output.stdout)?
+        .lines()
+        .filter_map(|line| line.get(3..))
+        .collect();
+    if !dirty.is_empty() {
+        bail!(
+            "Uncommitted changes in {}; commit or stash them first, or pass --force",
+            dirty.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Copy the original of every changed file below a new backup directory
+fn back_up(workspace: &Path, files: &[EditedFile]) -> Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let backup = workspace.join(".lq-backup").join(timestamp.to_string());
+    for file in files.iter().filter(|f| f.before.is_some()) {
+        let target = backup.join(&file.original_path);
+        if let Some(dir) = target.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create backup directory {:?}", dir))?;
+        }
+        std::fs::copy(&file.original_file, &target)
+            .with_context(|| format!("Failed to back up {}", file.original_path.display()))?;
+    }
+    Ok(backup)
+}
+
+/// Stage every new file's contents, then move them into place and remove what moved or was deleted
+fn write_files(files: &[EditedFile]) -> Result<()> {
+    let mut staged = Vec::new();
+    let result = files
+        .iter()
+        .filter_map(|file| file.after.as_ref().map(|after| (file, after)))
+        .try_for_each(|(file, after)| {
+            let temp = stage(file, after)?;
+            staged.push((temp, file));
+            Ok::<_, anyhow::Error>(())
+        });
+    if let Err(e) = result {
+        for (temp, _) in &staged {
+            let _ = std::fs::remove_file(temp);
+        }
+        return Err(e);
+    }
+
+    for (temp, file) in &staged {
+        std::fs::rename(temp, &file.file).with_context(|| format!("Failed to write {}", file.path.display()))?;
+    }
+    for file in files {
+        let moved_away = file.original_file != file.file || file.after.is_none();
+        let replaced = files.iter().any(|other| other.after.is_some() && other.file == file.original_file);
+        if file.before.is_some() && moved_away && !replaced {
+            std::fs::remove_file(&file.original_file)
+                .with_context(|| format!("Failed to remove {}", file.original_path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write `contents` to a temporary file beside `file`'s destination, keeping its permissions
+fn stage(file: &EditedFile, contents: &str) -> Result<PathBuf> {
+    let dir = file.file.parent().context("File without a parent directory")?;
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let name = file.file.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(".{}.lq-{}.tmp", name, std::process::id()));
+
+    std::fs::write(&temp, contents).with_context(|| format!("Failed to write {:?}", temp))?;
+    if file.before.is_some() {
+        if let Ok(metadata) = std::fs::metadata(&file.original_file) {
+            std::fs::set_permissions(&temp, metadata.permissions())?;
+        }
+    }
+    Ok(temp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::{FileChange, TextEdit};
+
+    fn rename_module(workspace: &Path) -> EditPreview {
+        let file = |name: &str| (workspace.join(name), PathBuf::from(name));
+        let (lib, lib_path) = file("lib.rs");
+        let (shapes, shapes_path) = file("shapes.rs");
+        let (geometry, geometry_path) = file("geometry.rs");
+        EditPreview::new(vec![
+            FileChange::Edit {
+                file: lib,
+                path: lib_path,
+                edits: vec![TextEdit {
+                    start_line: 1,
+                    start_column: 5,
+                    end_line: 1,
+                    end_column: 11,
+                    new_text: "geometry".to_string(),
+                }],
+            },
+            FileChange::Rename { file: shapes, path: shapes_path, new_file: geometry, new_path: geometry_path },
+        ])
+        .unwrap()
+    }
+
+    fn git(workspace: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(workspace)
+            .args(["-c", "user.name=lq", "-c", "user.email=lq@example.com"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn test_apply_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "mod shapes;\n").unwrap();
+        std::fs::write(dir.path().join("shapes.rs"), "pub struct Square;\n").unwrap();
+        let preview = rename_module(dir.path());
+
+        let applied = apply(&preview, dir.path(), false).unwrap();
+        assert_eq!(applied.files, vec![PathBuf::from("lib.rs"), PathBuf::from("geometry.rs")]);
+        assert_eq!(std::fs::read_to_string(dir.path().join("lib.rs")).unwrap(), "mod geometry;\n");
+        assert_eq!(std::fs::read_to_string(dir.path().join("geometry.rs")).unwrap(), "pub struct Square;\n");
+        assert!(!dir.path().join("shapes.rs").exists());
+
+        let backup = applied.backup.unwrap();
+        assert_eq!(std::fs::read_to_string(backup.join("lib.rs")).unwrap(), "mod shapes;\n");
+        assert!(backup.join("shapes.rs").exists());
+
+        // The files no longer match what the preview was computed from
+        assert!(apply(&preview, dir.path(), false).is_err());
+    }
+
+    #[test]
+    fn test_apply_refuses_uncommitted_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "mod shapes;\n").unwrap();
+        std::fs::write(dir.path().join("shapes.rs"), "pub struct Square;\n").unwrap();
+        git(dir.path(), &["init", "--quiet"]);
+        git(dir.path(), &["add", "."]);
+        git(dir.path(), &["commit", "--quiet", "-m", "init"]);
+
+        std::fs::write(dir.path().join("shapes.rs"), "pub struct Circle;\n").unwrap();
+        let preview = rename_module(dir.path());
+        let error = apply(&preview, dir.path(), false).unwrap_err();
+        assert!(error.to_string().contains("shapes.rs"), "{}", error);
+        assert_eq!(std::fs::read_to_string(dir.path().join("lib.rs")).unwrap(), "mod shapes;\n");
+
+        let applied = apply(&preview, dir.path(), true).unwrap();
+        assert_eq!(applied.backup, None);
+        assert_eq!(std::fs::read_to_string(dir.path().join("geometry.rs")).unwrap(), "pub struct Circle;\n");
+    }
+}
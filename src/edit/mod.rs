@@ -0,0 +1,259 @@
+//! Workspace edits returned by refactorings such as rename and code actions.
+//!
+//! Changes are replayed in order against in-memory copies of the files they
+//! touch, so an edit to a file created or renamed earlier in the same workspace
+//! edit sees its new contents. The result renders as a unified diff, and
+//! [`apply`] writes it to disk.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+
+use crate::lsp::{FileChange, TextEdit};
+
+mod apply;
+
+pub use apply::{apply, AppliedEdit};
+
+/// The changes a refactoring would make, and the diff they amount to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EditPreview {
+    pub changes: Vec<FileChange>,
+    /// Unified diff of every affected file; empty when nothing changes
+    pub diff: String,
+}
+
+impl EditPreview {
+    pub fn new(changes: Vec<FileChange>) -> Result<Self> {
+        let diff = unified_diff(&replay(&changes)?);
+        Ok(Self { changes, diff })
+    }
+}
+
+/// A file's contents before and after a workspace edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditedFile {
+    /// Where the file was before the edit, absolute and relative to the workspace
+    pub original_file: PathBuf,
+    pub original_path: PathBuf,
+    /// Where the file is after the edit
+    pub file: PathBuf,
+    pub path: PathBuf,
+    /// `None` when the file did not exist before
+    pub before: Option<String>,
+    /// `None` when the edit deletes the file
+    pub after: Option<String>,
+}
+
+impl EditedFile {
+    fn load(file: &Path, path: &Path) -> Result<Self> {
+        let before = match std::fs::read_to_string(file) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", file)),
+        };
+        Ok(Self {
+            original_file: file.to_path_buf(),
+            original_path: path.to_path_buf(),
+            file: file.to_path_buf(),
+            path: path.to_path_buf(),
+            after: before.clone(),
+            before,
+        })
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        self.before == self.after && self.original_file == self.file
+    }
+}
+
+/// Replay `changes` in memory, returning every file they touch in order of first touch
+pub fn replay(changes: &[FileChange]) -> Result<Vec<EditedFile>> {
+    // The index of the file currently at `file`, loading it on first touch
+    fn touch(files: &mut Vec<EditedFile>, file: &Path, path: &Path) -> Result<usize> {
+        if let Some(index) = files.iter().position(|f| f.file == file) {
+            return Ok(index);
+        }
+        files.push(EditedFile::load(file, path)?);
+        Ok(files.len() - 1)
+    }
+
+    let mut files = Vec::new();
+
+    for change in changes {
+        match change {
+            FileChange::Edit { file, path, edits } => {
+                let index = touch(&mut files, file, path)?;
+                let Some(contents) = &files[index].after else {
+                    bail!("Edit to {} which does not exist", path.display());
+                };
+                files[index].after = Some(apply_text_edits(contents, edits).with_context(|| format!("Failed to edit {}", path.display()))?);
+            }
+            FileChange::Create { file, path } => {
+                let index = touch(&mut files, file, path)?;
+                files[index].after.get_or_insert_with(String::new);
+            }
+            FileChange::Rename { file, path, new_file, new_path } => {
+                let index = touch(&mut files, file, path)?;
+                if files[index].after.is_none() {
+                    bail!("Rename of {} which does not exist", path.display());
+                }
+                files[index].file = new_file.clone();
+                files[index].path = new_path.clone();
+            }
+            FileChange::Delete { file, path } => {
+                let index = touch(&mut files, file, path)?;
+                files[index].after = None;
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Apply edits positioned against `contents` all at once
+pub fn apply_text_edits(contents: &str, edits: &[TextEdit]) -> Result<String> {
+    let mut ranges = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let start = byte_offset(contents, edit.start_line, edit.start_column)?;
+        let end = byte_offset(contents, edit.end_line, edit.end_column)?;
+        if end < start {
+            bail!("Edit ends before it starts at line {}", edit.start_line);
+        }
+        ranges.push((start, end, edit.new_text.as_str()));
+    }
+    // Stable, so inserts at one position keep their order once applied back to front
+    ranges.sort_by_key(|&(start, end, _)| (start, end));
+    if ranges.windows(2).any(|pair| pair[1].0 < pair[0].1) {
+        bail!("Overlapping edits");
+    }
+
+    let mut result = contents.to_string();
+    for (start, end, new_text) in ranges.into_iter().rev() {
+        result.replace_range(start..end, new_text);
+    }
+    Ok(result)
+}
+
+/// The byte offset of a 1-based line and UTF-16 column
+fn byte_offset(contents: &str, line: u32, column: u32) -> Result<usize> {
+    let mut line_start = 0;
+    for _ in 1..line {
+        match contents[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => bail!("Line {} is past the end of the file", line),
+        }
+    }
+
+    let text = &contents[line_start..];
+    let text = &text[..text.find('\n').unwrap_or(text.len())];
+    let mut units = 0;
+    for (index, c) in text.char_indices() {
+        if units >= column.saturating_sub(1) as usize {
+            return Ok(line_start + index);
+        }
+        units += c.len_utf16();
+    }
+    Ok(line_start + text.len())
+}
+
+/// A git-style unified diff of the files that changed
+pub fn unified_diff(files: &[EditedFile]) -> String {
+    let mut out = String::new();
+    for file in files.iter().filter(|f| !f.is_unchanged()) {
+        let old_name = match &file.before {
+            Some(_) => format!("a/{}", file.original_path.display()),
+            None => "/dev/null".to_string(),
+        };
+        let new_name = match &file.after {
+            Some(_) => format!("b/{}", file.path.display()),
+            None => "/dev/null".to_string(),
+        };
+
+        out.push_str(&format!("diff --git a/{} b/{}\n", file.original_path.display(), file.path.display()));
+        if file.original_path != file.path {
+            out.push_str(&format!("rename from {}\nrename to {}\n", file.original_path.display(), file.path.display()));
+            if file.before == file.after {
+                continue;
+            }
+        }
+        let before = file.before.as_deref().unwrap_or_default();
+        let after = file.after.as_deref().unwrap_or_default();
+        out.push_str(&TextDiff::from_lines(before, after).unified_diff().header(&old_name, &new_name).to_string());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(start: (u32, u32), end: (u32, u32), new_text: &str) -> TextEdit {
+        TextEdit {
+            start_line: start.0,
+            start_column: start.1,
+            end_line: end.0,
+            end_column: end.1,
+            new_text: new_text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_apply_text_edits() {
+        let contents = "fn old() {}\nlet s = \"é\"; old();\n";
+        let edits = [edit((2, 14), (2, 17), "new"), edit((1, 4), (1, 7), "new")];
+        assert_eq!(apply_text_edits(contents, &edits).unwrap(), "fn new() {}\nlet s = \"é\"; new();\n");
+
+        // Inserts at the same position keep their order
+        let inserts = [edit((1, 1), (1, 1), "a"), edit((1, 1), (1, 1), "b")];
+        assert_eq!(apply_text_edits("x", &inserts).unwrap(), "abx");
+
+        assert!(apply_text_edits("x", &[edit((1, 1), (1, 2), "a"), edit((1, 1), (1, 2), "b")]).is_err());
+        assert!(apply_text_edits("x", &[edit((5, 1), (5, 1), "a")]).is_err());
+    }
+
+    #[test]
+    fn test_rename_preview() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "mod shapes;\nuse shapes::Old;\n").unwrap();
+        std::fs::write(dir.path().join("shapes.rs"), "pub struct Old;\n").unwrap();
+
+        let file = |name: &str| (dir.path().join(name), PathBuf::from(name));
+        let (lib, lib_path) = file("lib.rs");
+        let (shapes, shapes_path) = file("shapes.rs");
+        let (geometry, geometry_path) = file("geometry.rs");
+        let changes = vec![
+            FileChange::Edit {
+                file: lib,
+                path: lib_path,
+                edits: vec![edit((1, 5), (1, 11), "geometry"), edit((2, 5), (2, 11), "geometry"), edit((2, 13), (2, 16), "New")],
+            },
+            FileChange::Rename { file: shapes, path: shapes_path, new_file: geometry.clone(), new_path: geometry_path.clone() },
+            FileChange::Edit { file: geometry, path: geometry_path, edits: vec![edit((1, 12), (1, 15), "New")] },
+        ];
+
+        let preview = EditPreview::new(changes).unwrap();
+        assert_eq!(
+            preview.diff,
+            "diff --git a/lib.rs b/lib.rs\n\
+             --- a/lib.rs\n\
+             +++ b/lib.rs\n\
+             @@ -1,2 +1,2 @@\n\
+             -mod shapes;\n\
+             -use shapes::Old;\n\
+             +mod geometry;\n\
+             +use geometry::New;\n\
+             diff --git a/shapes.rs b/geometry.rs\n\
+             rename from shapes.rs\n\
+             rename to geometry.rs\n\
+             --- a/shapes.rs\n\
+             +++ b/geometry.rs\n\
+             @@ -1 +1 @@\n\
+             -pub struct Old;\n\
+             +pub struct New;\n"
+        );
+    }
+}
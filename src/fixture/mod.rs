@@ -0,0 +1,206 @@
+//! Synthetic Cargo workspaces for `lq fixture`, benchmarks and tests.
+//!
+//! A fixture has a given number of crates, modules per crate and items per
+//! module, and is the same every time for the same shape, so that a
+//! performance problem reproduced on one can be shared as the command that
+//! generated it. Every item has docs, and the items refer to each other the
+//! way real code does: each module calls into the one before it, the first
+//! module of each crate calls into the crate before it, and every struct
+//! implements a trait the first crate defines.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+/// The shape of a generated workspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixtureSpec {
+    pub crates: usize,
+    /// Modules in each crate
+    pub modules: usize,
+    /// Items in each module, each a struct with its impl and a function
+    pub items: usize,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self { crates: 3, modules: 4, items: 5 }
+    }
+}
+
+/// What [`write_fixture`] wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixtureSummary {
+    pub files: usize,
+    pub lines: usize,
+}
+
+/// The files of the workspace shaped as `spec`, by path relative to its root
+pub fn fixture_files(spec: FixtureSpec) -> Vec<(PathBuf, String)> {
+    let mut files = vec![(PathBuf::from("Cargo.toml"), workspace_manifest(spec))];
+    for krate in 0..spec.crates {
+        let dir = PathBuf::from("crates").join(crate_name(krate));
+        files.push((dir.join("Cargo.toml"), crate_manifest(krate)));
+        files.push((dir.join("src/lib.rs"), crate_root(spec, krate)));
+        for module in 0..spec.modules {
+            files.push((dir.join(format!("src/module_{}.rs", module)), module_source(spec, krate, module)));
+        }
+    }
+    files
+}
+
+/// Write the workspace shaped as `spec` to `dir`, which must be empty or
+/// missing unless `force` allows overwriting the files it shares with it
+pub fn write_fixture(dir: &Path, spec: FixtureSpec, force: bool) -> Result<FixtureSummary> {
+    if spec.crates == 0 || spec.modules == 0 {
+        bail!("A fixture needs at least one crate and one module");
+    }
+    let occupied = std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+    if occupied && !force {
+        bail!("{} is not empty; pass --force to write the fixture into it anyway", dir.display());
+    }
+    let files = fixture_files(spec);
+    let mut summary = FixtureSummary { files: 0, lines: 0 };
+    for (path, contents) in &files {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+        }
+        std::fs::write(&path, contents).with_context(|| format!("Failed to write {:?}", path))?;
+        summary.files += 1;
+        summary.lines += contents.lines().count();
+    }
+    Ok(summary)
+}
+
+fn crate_name(krate: usize) -> String {
+    format!("fixture_{}", krate)
+}
+
+fn workspace_manifest(spec: FixtureSpec) -> String {
+    let mut manifest = String::from("[workspace]\nresolver = \"2\"\nmembers = [\n");
+    for krate in 0..spec.crates {
+        let _ = writeln!(manifest, "    \"crates/{}\",", crate_name(krate));
+    }
+    manifest.push_str("]\n");
+    manifest
+}
+
+fn crate_manifest(krate: usize) -> String {
+    let mut manifest = format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n", crate_name(krate));
+    if krate > 0 {
+        let _ = writeln!(manifest, "{0} = {{ path = \"../{0}\" }}", crate_name(krate - 1));
+    }
+    manifest
+}
+
+fn crate_root(spec: FixtureSpec, krate: usize) -> String {
+    let mut source = format!("//! Generated crate {} of {}.\n\n", krate + 1, spec.crates);
+    for module in 0..spec.modules {
+        let _ = writeln!(source, "pub mod module_{};", module);
+    }
+    source.push('\n');
+    if krate == 0 {
+        source.push_str(
+            "/// Something that can say what it holds\n\
+             pub trait Describe {\n    \
+                 /// A one-line description\n    \
+                 fn describe(&self) -> String;\n\
+             }\n",
+        );
+    } else {
+        let _ = writeln!(source, "pub use {}::Describe;", crate_name(krate - 1));
+    }
+    source
+}
+
+fn module_source(spec: FixtureSpec, krate: usize, module: usize) -> String {
+    let mut source = format!("//! Generated module {} of crate {}.\n", module, crate_name(krate));
+    for item in 0..spec.items {
+        // The function this one calls, in the module or crate before it
+        let previous = match (krate, module) {
+            (0, 0) => None,
+            (_, 0) => Some(format!("{}::module_{}", crate_name(krate - 1), spec.modules - 1)),
+            _ => Some(format!("super::module_{}", module - 1)),
+        };
+        let body = match previous {
+            Some(previous) => format!("{0}::process_{1}(&{0}::Record{1}::new(record.id + 1)) + record.value.len() as u64", previous, item),
+            None => "record.id * 2 + record.value.len() as u64".to_string(),
+        };
+        let _ = write!(
+            source,
+            "
+/// A record of kind {item} in module {module}, with an id and a value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record{item} {{
+    /// Identifies the record
+    pub id: u64,
+    /// What the record holds
+    pub value: String,
+}}
+
+impl Record{item} {{
+    /// A record with `id` and an empty value
+    pub fn new(id: u64) -> Self {{
+        Self {{ id, value: String::new() }}
+    }}
+}}
+
+impl crate::Describe for Record{item} {{
+    fn describe(&self) -> String {{
+        format!(\"record {item} #{{}}: {{}}\", self.id, self.value)
+    }}
+}}
+
+/// Fold `record` into a checksum, through the records before it
+pub fn process_{item}(record: &Record{item}) -> u64 {{
+    {body}
+}}
+",
+        );
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_files() {
+        let spec = FixtureSpec { crates: 2, modules: 2, items: 3 };
+        let files = fixture_files(spec);
+        let paths: Vec<String> = files.iter().map(|(path, _)| path.display().to_string()).collect();
+        assert_eq!(
+            paths,
+            [
+                "Cargo.toml",
+                "crates/fixture_0/Cargo.toml",
+                "crates/fixture_0/src/lib.rs",
+                "crates/fixture_0/src/module_0.rs",
+                "crates/fixture_0/src/module_1.rs",
+                "crates/fixture_1/Cargo.toml",
+                "crates/fixture_1/src/lib.rs",
+                "crates/fixture_1/src/module_0.rs",
+                "crates/fixture_1/src/module_1.rs",
+            ]
+        );
+        let file = |path: &str| &files.iter().find(|(file, _)| file == Path::new(path)).unwrap().1;
+        assert!(file("crates/fixture_1/Cargo.toml").contains("fixture_0 = { path = \"../fixture_0\" }"));
+        assert!(file("crates/fixture_1/src/module_0.rs").contains("fixture_0::module_1::process_2(&fixture_0::module_1::Record2::new("));
+        assert!(file("crates/fixture_0/src/module_1.rs").contains("super::module_0::process_0("));
+        assert_eq!(file("crates/fixture_0/src/module_0.rs").matches("pub struct Record").count(), 3);
+        assert_eq!(fixture_files(spec), files);
+    }
+
+    #[test]
+    fn test_write_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let summary = write_fixture(dir.path(), FixtureSpec::default(), false).unwrap();
+        assert_eq!(summary.files, 1 + 3 * (2 + 4));
+        assert!(dir.path().join("crates/fixture_2/src/module_3.rs").exists());
+        assert!(write_fixture(dir.path(), FixtureSpec::default(), false).is_err());
+        assert_eq!(write_fixture(dir.path(), FixtureSpec::default(), true).unwrap(), summary);
+    }
+}
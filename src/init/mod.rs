@@ -0,0 +1,303 @@
+//! Setting up a workspace for lq with `lq init`.
+//!
+//! Every step is safe to repeat: an existing `.lq.toml` is kept unless forced,
+//! and git hooks that lq did not write are left alone.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::lsp::rust_analyzer::find_rust_analyzer;
+
+/// A `.lq.toml` documenting every setting, all commented out
+pub const STARTER_CONFIG: &str = r#"# Settings for the lq daemon serving this workspace. The global config
+# (see `lq trust`) overrides anything set here.
+
+# A wrapper confining rust-analyzer, which runs the workspace's build scripts
+# and proc macros; {workspace} is replaced by the workspace root.
+# [sandbox]
+# command = ["firejail", "--quiet", "--net=none", "--whitelist={workspace}"]
+
+# CPU and memory ceilings for rust-analyzer (Linux only).
+# [limits]
+# memory = "4G"
+# cpu = "200%"
+
+# The rust-analyzer to run, instead of the one on PATH, and its arguments.
+# [servers.rust]
+# command = "~/.cargo/bin/rust-analyzer"
+# args = ["--log-file", "/tmp/rust-analyzer.log"]
+# The features and target the workspace builds with, and whether proc macros
+# are expanded; other rust-analyzer settings go under [servers.rust.settings].
+# features = ["foo"]
+# target = "wasm32-unknown-unknown"
+# proc_macros = false
+# check_command = "clippy"
+
+# How long the daemon waits for rust-analyzer and lives without requests;
+# the daemon's own flags and environment variables win over these.
+# [daemon]
+# idle_timeout_minutes = 120
+# ready_timeout_secs = 900
+# max_restarts = 5
+# log_level = "info"
+
+# Running the daemon and rust-analyzer in the background, and stopping
+# rust-analyzer while it indexes during a cargo build of the workspace.
+# [priority]
+# nice = 10
+# idle_io = true
+# pause_during_builds = true
+
+# Answering the def and refs queries that usually follow a docs query in the
+# background, so they come from the cache.
+# [prefetch]
+# enabled = true
+# max_concurrent = 2
+
+# Asking once more when docs or refs come back empty right after rust-analyzer
+# finished indexing, while its caches may still be warming.
+# [retry_empty]
+# enabled = true
+
+# Commands run on daemon events, each given the event as JSON on stdin.
+# [hooks]
+# on_ready = ["notify-send", "lq is ready"]
+# on_diagnostics_changed = ["./scripts/post-diagnostics.sh"]
+# on_crash = ["sh", "-c", "cat >> ~/lq-crashes.jsonl"]
+
+# Named queries, run as `lq run <name> <more args>`.
+# [preset.api-refs]
+# method = "refs"
+# exclude = ["tests/**"]
+# format = "jsonl"
+
+# Flags added to every run of a command; flags on the command line win.
+# [defaults]
+# diag = ["--severity", "error"]
+
+# Links from results to the lines on the repository host, unless
+# `--link-template` is given.
+# [links]
+# template = "https://github.com/org/repo/blob/{rev}/{path}#L{line}"
+
+# Source lines masked in results before they are printed: every line of
+# files matching `paths`, and lines matching one of the `patterns` regexes.
+# [redact]
+# paths = ["config/secrets/**"]
+# patterns = ['(?i)(api_key|password|token)\s*=']
+"#;
+
+/// First line after the shebang of every hook lq installs
+const HOOK_MARKER: &str = "# Installed by lq init";
+
+/// Hooks that start the daemon in the background, so it re-indexes while the user works
+const GIT_HOOKS: &[&str] = &["post-checkout", "post-merge"];
+
+/// Write [`STARTER_CONFIG`] to `.lq.toml`; returns false if one exists and `force` is not set
+pub fn write_starter_config(workspace: &Path, force: bool) -> Result<bool> {
+    let path = workspace.join(".lq.toml");
+    if path.exists() && !force {
+        return Ok(false);
+    }
+    std::fs::write(&path, STARTER_CONFIG).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(true)
+}
+
+/// A tool a detected language needs, and whether it is installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCheck {
+    pub language: &'static str,
+    pub tool: &'static str,
+    /// First line of the tool's `--version` output; `None` when it is missing
+    pub version: Option<String>,
+    /// How to install the tool when it is missing
+    pub install_hint: &'static str,
+}
+
+/// Check the tools needed by each language found in the workspace root.
+///
+/// Rust, recognized by a `Cargo.toml`, is the only language lq serves so far.
+/// The rust-analyzer checked is the one at `server_path` when given.
+pub fn check_toolchains(workspace: &Path, server_path: Option<&Path>) -> Vec<ToolCheck> {
+    if !workspace.join("Cargo.toml").exists() {
+        return Vec::new();
+    }
+    vec![
+        ToolCheck {
+            language: "Rust",
+            tool: "cargo",
+            version: tool_version(Path::new("cargo")),
+            install_hint: "install Rust with rustup from https://rustup.rs",
+        },
+        ToolCheck {
+            language: "Rust",
+            tool: "rust-analyzer",
+            version: match server_path {
+                Some(path) => tool_version(path),
+                None => find_rust_analyzer().ok().and_then(|path| tool_version(&path)),
+            },
+            install_hint: "run `rustup component add rust-analyzer`",
+        },
+    ]
+}
+
+fn tool_version(program: &Path) -> Option<String> {
+    let output = std::process::Command::new(program).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().next().map(|line| line.trim().to_string())
+}
+
+/// A shell `lq init` can install completions for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Where `shell` loads completions for `lq` from, per user
+pub fn completion_path(shell: Shell) -> Result<PathBuf> {
+    let dirs = directories::BaseDirs::new().context("Failed to find the home directory")?;
+    Ok(match shell {
+        Shell::Bash => dirs.data_dir().join("bash-completion/completions/lq"),
+        // Not on zsh's default `fpath`; `lq init` says how to add it
+        Shell::Zsh => dirs.home_dir().join(".zfunc/_lq"),
+        Shell::Fish => dirs.config_dir().join("fish/completions/lq.fish"),
+    })
+}
+
+/// Write a completion script where `shell` finds it, returning the path
+pub fn install_completions(shell: Shell, script: &[u8]) -> Result<PathBuf> {
+    let path = completion_path(shell)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+    std::fs::write(&path, script).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(path)
+}
+
+/// What happened to one git hook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookInstall {
+    Installed(PathBuf),
+    /// A hook lq did not write is already there
+    Skipped(PathBuf),
+}
+
+/// Install hooks that start the daemon after checkouts and merges
+pub fn install_git_hooks(workspace: &Path) -> Result<Vec<HookInstall>> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(workspace)
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("{} is not in a git repository", workspace.display());
+    }
+    let hooks_dir = workspace.join(String::from_utf8_lossy(&output.stdout).trim());
+    std::fs::create_dir_all(&hooks_dir).with_context(|| format!("Failed to create {:?}", hooks_dir))?;
+
+    let script = format!(
+        "#!/bin/sh\n{}: start the lq daemon so it re-indexes in the background\n\
+         command -v lq >/dev/null 2>&1 && (lq status >/dev/null 2>&1 &)\nexit 0\n",
+        HOOK_MARKER
+    );
+    let mut installs = Vec::new();
+    for hook in GIT_HOOKS {
+        let path = hooks_dir.join(hook);
+        let ours = std::fs::read_to_string(&path).map(|existing| existing.contains(HOOK_MARKER));
+        if matches!(ours, Ok(false)) {
+            installs.push(HookInstall::Skipped(path));
+            continue;
+        }
+        std::fs::write(&path, &script).with_context(|| format!("Failed to write {:?}", path))?;
+        make_executable(&path)?;
+        installs.push(HookInstall::Installed(path));
+    }
+    Ok(installs)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to make {:?} executable", path))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorkspaceConfig;
+
+    #[test]
+    fn test_starter_config() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(write_starter_config(dir.path(), false).unwrap());
+        // Everything is commented out, so it changes nothing until edited
+        assert_eq!(WorkspaceConfig::load(dir.path()).unwrap(), WorkspaceConfig::default());
+
+        std::fs::write(dir.path().join(".lq.toml"), "[limits]\nmemory = \"1G\"\n").unwrap();
+        assert!(!write_starter_config(dir.path(), false).unwrap());
+        assert!(write_starter_config(dir.path(), true).unwrap());
+        assert_eq!(std::fs::read_to_string(dir.path().join(".lq.toml")).unwrap(), STARTER_CONFIG);
+    }
+
+    #[test]
+    fn test_check_toolchains_at_server_path() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_toolchains(dir.path(), None).is_empty());
+        std::fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"a\"\n").unwrap();
+        let server = dir.path().join("my-rust-analyzer");
+        std::fs::write(&server, "#!/bin/sh\necho 'rust-analyzer 1.2.3 (custom)'\n").unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let checks = check_toolchains(dir.path(), Some(&server));
+        let rust_analyzer = checks.iter().find(|check| check.tool == "rust-analyzer").unwrap();
+        assert_eq!(rust_analyzer.version.as_deref(), Some("rust-analyzer 1.2.3 (custom)"));
+        let missing = check_toolchains(dir.path(), Some(&dir.path().join("missing")));
+        assert_eq!(missing.iter().find(|check| check.tool == "rust-analyzer").unwrap().version, None);
+    }
+
+    #[test]
+    fn test_install_git_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(install_git_hooks(dir.path()).is_err());
+
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["init", "--quiet"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let hooks = dir.path().join(".git/hooks");
+        std::fs::create_dir_all(&hooks).unwrap();
+        std::fs::write(hooks.join("post-merge"), "#!/bin/sh\nmake\n").unwrap();
+
+        let installs = install_git_hooks(dir.path()).unwrap();
+        assert_eq!(
+            installs,
+            vec![
+                HookInstall::Installed(hooks.join("post-checkout")),
+                HookInstall::Skipped(hooks.join("post-merge")),
+            ]
+        );
+        assert!(std::fs::read_to_string(hooks.join("post-checkout")).unwrap().contains("lq status"));
+        assert_eq!(std::fs::read_to_string(hooks.join("post-merge")).unwrap(), "#!/bin/sh\nmake\n");
+
+        // Running it again rewrites its own hooks
+        assert_eq!(install_git_hooks(dir.path()).unwrap(), installs);
+    }
+}
@@ -0,0 +1,281 @@
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// How the messages of a connection are encoded inside their length prefix.
+///
+/// Every connection starts out in JSON; a `Codec` request switches both sides
+/// to another encoding once it has been answered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    Json,
+    /// MessagePack, more compact and quicker to encode for large answers
+    Msgpack,
+}
+
+/// How messages larger than a connection's threshold are compressed.
+///
+/// A `Compress` request asks the daemon to compress its later responses; a
+/// compressed message has the top bit of its length prefix set. Either side
+/// refuses compressed messages on a connection that has not switched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    /// Gzip, which shrinks large JSON answers several times over
+    Gzip,
+    /// Zstandard, about as small as gzip and several times quicker
+    Zstd,
+}
+
+/// Encoded size above which messages are compressed, unless a `Compress` request names another
+pub const DEFAULT_COMPRESS_MIN_BYTES: u32 = 16 * 1024;
+
+/// The bit of the length prefix marking a compressed message
+const COMPRESSED: u32 = 1 << 31;
+
+/// Largest message, in bytes, as its length prefix holds it beside the compression bit
+pub const MAX_MESSAGE_BYTES: usize = (COMPRESSED - 1) as usize;
+
+/// Largest size, in bytes, a compressed message may expand to; far below
+/// [`MAX_MESSAGE_BYTES`], so that a tiny message cannot make its reader allocate gigabytes
+pub const MAX_DECOMPRESSED_BYTES: usize = 128 * 1024 * 1024;
+
+/// Write a message as a 4-byte big-endian length prefix followed by JSON
+pub async fn write_message<W, T>(writer: &mut W, message: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    write_message_as(writer, message, Codec::Json).await
+}
+
+/// Write a message as a 4-byte big-endian length prefix followed by its `codec` encoding
+pub async fn write_message_as<W, T>(writer: &mut W, message: &T, codec: Codec) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    write_message_compressed(writer, message, codec, Compression::None, 0).await
+}
+
+/// Write a message as [`write_message_as`] does, compressed with `compression`
+/// when its encoding is larger than `min_bytes`
+pub async fn write_message_compressed<W, T>(writer: &mut W, message: &T, codec: Codec, compression: Compression, min_bytes: u32) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut bytes = match codec {
+        Codec::Json => serde_json::to_vec(message)?,
+        Codec::Msgpack => rmp_serde::to_vec_named(message)?,
+    };
+    let mut len = frame_len(bytes.len())?;
+    if compression != Compression::None && len > min_bytes {
+        bytes = compress(&bytes, compression)?;
+        len = frame_len(bytes.len())? | COMPRESSED;
+    }
+    let len_bytes = len.to_be_bytes();
+    writer.write_all(&len_bytes).await?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read one length-prefixed JSON message.
+///
+/// Returns `Ok(None)` if the peer closed the connection before sending anything.
+pub async fn read_message<R, T>(reader: &mut R) -> Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    read_message_as(reader, Codec::Json).await
+}
+
+/// Read one length-prefixed message in the `codec` encoding, as [`read_message`]
+pub async fn read_message_as<R, T>(reader: &mut R, codec: Codec) -> Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    read_message_compressed(reader, codec, Compression::None).await
+}
+
+/// Read one length-prefixed message as [`read_message_as`] does, on a connection
+/// switched to `compression`, decompressing it if it was compressed
+pub async fn read_message_compressed<R, T>(reader: &mut R, codec: Codec, compression: Compression) -> Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut len_buf = [0u8; 4];
+    match reader.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    
+    let len = u32::from_be_bytes(len_buf);
+    let mut buffer = vec![0; (len & !COMPRESSED) as usize];
+    reader.read_exact(&mut buffer).await?;
+    if len & COMPRESSED != 0 {
+        buffer = decompress(&buffer, compression, MAX_DECOMPRESSED_BYTES).context("Failed to decompress message")?;
+    }
+    
+    let message = match codec {
+        Codec::Json => serde_json::from_slice(&buffer).context("Failed to parse message")?,
+        Codec::Msgpack => rmp_serde::from_slice(&buffer).context("Failed to decode MessagePack message")?,
+    };
+    Ok(Some(message))
+}
+
+/// `len` as a length prefix, unless the message is too large for one
+fn frame_len(len: usize) -> Result<u32> {
+    if len > MAX_MESSAGE_BYTES {
+        bail!("Message of {} bytes is too large to send; the limit is {} bytes", len, MAX_MESSAGE_BYTES);
+    }
+    Ok(len as u32)
+}
+
+fn compress(bytes: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    Ok(match compression {
+        Compression::None => bytes.to_vec(),
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(bytes)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL)?,
+    })
+}
+
+/// `bytes` decompressed as `compression`, failing beyond `limit` bytes rather
+/// than let a small message expand without bound
+fn decompress(bytes: &[u8], compression: Compression, limit: usize) -> Result<Vec<u8>> {
+    let mut decoder: Box<dyn Read + '_> = match compression {
+        Compression::None => bail!("Received a compressed message on a connection that did not switch to compression"),
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(bytes)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(bytes)?),
+    };
+    let mut decompressed = Vec::new();
+    decoder.by_ref().take(limit as u64 + 1).read_to_end(&mut decompressed)?;
+    if decompressed.len() > limit {
+        bail!("Compressed message expands beyond {} bytes", limit);
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{Method, Request, Response, ResponseResult};
+    
+    #[tokio::test]
+    async fn test_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let request = Request {
+            id: "1".to_string(),
+            method: Method::Status,
+            deadline_ms: None,
+            allow_stale: false,
+            timeout_ms: None,
+            workspace: None,
+        };
+        
+        write_message(&mut client, &request).await.unwrap();
+        let received: Request = read_message(&mut server).await.unwrap().unwrap();
+        assert_eq!(received.id, "1");
+        assert!(matches!(received.method, Method::Status));
+        
+        drop(client);
+        let eof: Option<Request> = read_message(&mut server).await.unwrap();
+        assert!(eof.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_msgpack_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let request = Request {
+            id: "2".to_string(),
+            method: Method::Cancel { id: "1".to_string() },
+            deadline_ms: Some(500),
+            allow_stale: true,
+            timeout_ms: None,
+            workspace: None,
+        };
+
+        write_message_as(&mut client, &request, Codec::Msgpack).await.unwrap();
+        let received: Request = read_message_as(&mut server, Codec::Msgpack).await.unwrap().unwrap();
+        assert_eq!((received.id.as_str(), received.deadline_ms, received.allow_stale), ("2", Some(500), true));
+        assert!(matches!(received.method, Method::Cancel { id } if id == "1"));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_round_trip() {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let response = Response {
+            id: "3".to_string(),
+            result: ResponseResult::Success { result: serde_json::json!({ "files": vec!["src/daemon/server.rs"; 100] }) },
+        };
+        write_message_compressed(&mut server, &response, Codec::Msgpack, Compression::Gzip, 256).await.unwrap();
+        write_message_compressed(&mut server, &response, Codec::Msgpack, Compression::Gzip, 64 * 1024).await.unwrap();
+        drop(server);
+
+        let mut len = [0; 4];
+        client.read_exact(&mut len).await.unwrap();
+        let len = u32::from_be_bytes(len);
+        assert!(len & COMPRESSED != 0 && len & !COMPRESSED < 256);
+        let mut compressed = vec![0; (len & !COMPRESSED) as usize];
+        client.read_exact(&mut compressed).await.unwrap();
+        let mut framed = len.to_be_bytes().to_vec();
+        framed.extend(compressed);
+
+        // Refused on a connection that has not switched
+        assert!(read_message_as::<_, Response>(&mut framed.as_slice(), Codec::Msgpack).await.is_err());
+        let received: Response = read_message_compressed(&mut framed.as_slice(), Codec::Msgpack, Compression::Gzip).await.unwrap().unwrap();
+        assert_eq!(received.id, "3");
+        let uncompressed: Response = read_message_compressed(&mut client, Codec::Msgpack, Compression::Gzip).await.unwrap().unwrap();
+        assert!(matches!(uncompressed.result, ResponseResult::Success { result } if result["files"][99] == "src/daemon/server.rs"));
+    }
+
+    #[tokio::test]
+    async fn test_decompression_bomb() {
+        // A few hundred KiB of zeros on the wire that would expand past the limit
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        let zeros = vec![0; 1024 * 1024];
+        for _ in 0..MAX_DECOMPRESSED_BYTES / zeros.len() + 1 {
+            encoder.write_all(&zeros).unwrap();
+        }
+        let bomb = encoder.finish().unwrap();
+        assert!(bomb.len() < 1024 * 1024);
+
+        let mut framed = (frame_len(bomb.len()).unwrap() | COMPRESSED).to_be_bytes().to_vec();
+        framed.extend(bomb);
+        let error = read_message_compressed::<_, Response>(&mut framed.as_slice(), Codec::Json, Compression::Gzip)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("expands beyond"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_compression() {
+        let bytes = serde_json::to_vec(&serde_json::json!({ "files": vec!["src/daemon/server.rs"; 1000] })).unwrap();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let compressed = compress(&bytes, compression).unwrap();
+            assert!(compressed.len() < bytes.len() / 10, "{:?}", compression);
+            assert_eq!(decompress(&compressed, compression, bytes.len()).unwrap(), bytes);
+            let error = decompress(&compressed, compression, bytes.len() - 1).unwrap_err().to_string();
+            assert!(error.contains("expands beyond"), "{}", error);
+        }
+        assert!(decompress(&bytes, Compression::None, bytes.len()).is_err());
+        assert!(frame_len(MAX_MESSAGE_BYTES + 1).is_err());
+        assert_eq!(frame_len(MAX_MESSAGE_BYTES).unwrap(), COMPRESSED - 1);
+    }
+}
@@ -1,3 +1,8 @@
+pub mod framing;
 pub mod protocol;
+pub mod results;
+pub mod schema;
 
-pub use protocol::{Request, Response, ResponseResult, Method};
\ No newline at end of file
+pub use framing::{read_message, read_message_as, read_message_compressed, write_message, write_message_as, write_message_compressed, Codec, Compression, DEFAULT_COMPRESS_MIN_BYTES, MAX_DECOMPRESSED_BYTES, MAX_MESSAGE_BYTES};
+pub use protocol::{DaemonStatus, PathQuery, PrefetchStats, Request, Response, ResponseResult, Method, PROTOCOL_VERSION};
+pub use schema::{command_schema, method_schema, SCHEMA_COMMANDS};
@@ -1,38 +1,333 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::core::reports::ChurnSort;
+use crate::ipc::framing::{Codec, Compression};
+use crate::lsp::{Severity, SymbolMatch};
+
+/// Version of the request/response protocol and the JSON output schemas.
+/// Bump whenever a change could break an existing client or integration.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Request {
     pub id: String,
     pub method: Method,
+    /// Answer with whatever is available after this many milliseconds instead of
+    /// waiting for a complete result; such answers carry `"partial": true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    /// Answer from the cache even if the workspace changed since, marked `"stale": true`,
+    /// while a fresh answer is computed in the background
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_stale: bool,
+    /// Fail with an error instead of answering after this many milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// The workspace the client runs in, which answers a request naming no file
+    /// when the daemon serves several
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A request to the daemon.
+///
+/// Lines and columns are 1-based, columns counted in UTF-16 code units as in
+/// every response. A `symbol` is looked for on or near `line`; with a `column`,
+/// it is the occurrence on `line` that covers the column.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, strum::IntoStaticStr)]
 #[serde(tag = "method", content = "params")]
 pub enum Method {
     Docs {
         file: PathBuf,
         line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+    },
+    /// Docs for the expression or statement a selection covers, such as its type
+    DocsRange {
+        file: PathBuf,
+        start_line: u32,
+        start_column: u32,
+        end_line: u32,
+        /// The last column of the selection, inclusive
+        end_column: u32,
+    },
+    /// Where the item is defined, with its source
+    Definition {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
         symbol: String,
     },
+    /// The implementations of a trait, trait method or type. Before protocol
+    /// version 2 this answered the definition, which the answer still carries
+    /// as the deprecated `implementation` field; `Impls` is accepted as an alias.
+    #[serde(alias = "Impls")]
     Impl {
         file: PathBuf,
         line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+    },
+    TypeDefinition {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
         symbol: String,
     },
     Refs {
         file: PathBuf,
         line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
         symbol: String,
+        /// Skip merging and annotation of references
+        #[serde(default)]
+        raw: bool,
     },
     Resolve {
         file: PathBuf,
         symbol: String,
     },
+    /// `Docs`, `Definition`, `Impl` or `Refs` for the item a qualified path names, answered
+    /// with a `location` field for where it was found
+    AtPath {
+        /// Such as `my_crate::module::Type::method`; `crate::` anchors it at a crate root,
+        /// otherwise leading segments can be left out
+        path: String,
+        query: PathQuery,
+        /// Skip merging and annotation of references
+        #[serde(default)]
+        raw: bool,
+    },
+    Callers {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+        /// Levels of the call hierarchy to walk
+        depth: usize,
+    },
+    Callees {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+        /// Levels of the call hierarchy to walk
+        depth: usize,
+    },
+    Outline {
+        file: PathBuf,
+    },
+    Hints {
+        file: PathBuf,
+        /// First 1-based line to annotate; the whole file when absent
+        #[serde(default)]
+        start_line: Option<u32>,
+        /// Last line to annotate; `start_line` when absent
+        #[serde(default)]
+        end_line: Option<u32>,
+    },
+    Search {
+        query: String,
+        /// Maximum number of matches to return
+        limit: usize,
+    },
+    Diag {
+        /// Only this file; the whole workspace when absent
+        file: Option<PathBuf>,
+        /// Least severe level to include
+        severity: Severity,
+    },
+    Rename {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+        new_name: String,
+    },
+    Actions {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        /// Only the actions at this symbol; the whole line when absent
+        #[serde(default)]
+        symbol: Option<String>,
+    },
+    Expand {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        /// The macro to expand; the first macro on the line when absent
+        #[serde(default)]
+        symbol: Option<String>,
+    },
+    Hir {
+        file: PathBuf,
+        line: u32,
+    },
+    Syntax {
+        file: PathBuf,
+        line: u32,
+    },
+    Runnables {
+        /// Only this file; the whole workspace when absent
+        file: Option<PathBuf>,
+    },
+    Ssr {
+        /// `<pattern> ==>> <replacement>`
+        rule: String,
+        /// Only matches in this file, whose scope paths in the rule resolve from;
+        /// the whole workspace, resolving from the crate root, when absent
+        #[serde(default)]
+        file: Option<PathBuf>,
+        /// First 1-based line of `file` to match in; all of it when absent
+        #[serde(default)]
+        start_line: Option<u32>,
+        /// Last line to match in; `start_line` when absent
+        #[serde(default)]
+        end_line: Option<u32>,
+    },
+    Hierarchy {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+    },
+    GenericHotspots {
+        top: usize,
+    },
+    CoverageMap,
+    Hotspots {
+        top: usize,
+        sample: Option<usize>,
+        concurrency: usize,
+    },
+    Tour {
+        top: usize,
+    },
+    Churn {
+        top: usize,
+        since: Option<String>,
+        sort: ChurnSort,
+    },
+    /// References to item `a` on lines not referencing item `b`, and the other
+    /// way round; with `revs` instead of `b`, of item `a` at the first revision
+    /// and at the second, or the working tree
+    DiffRefs {
+        a: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        b: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        revs: Vec<String>,
+    },
+    /// The chains of callers of a function, and where they cross between async and sync code
+    AsyncCallers {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+        /// Levels of the call hierarchy to walk
+        depth: usize,
+    },
+    /// The functions referring to a function, and those referring to them in turn
+    TransitiveRefs {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+        /// Levels of referring functions to follow
+        depth: usize,
+    },
+    /// The trait bounds of the generic function called at a position, and the impls satisfying them
+    Bounds {
+        file: PathBuf,
+        line: u32,
+        /// The column of the function's name at the call
+        column: u32,
+    },
+    /// The error type a function returns, the conversions into it, and how its callers handle it
+    ErrorFlow {
+        file: PathBuf,
+        line: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        symbol: String,
+    },
+    /// The string literals matching a pattern, with their module and function
+    Strings {
+        pattern: String,
+        /// Treat `pattern` as a line of output, and find the literals that could have printed it
+        #[serde(default)]
+        log: bool,
+    },
     Status,
+    /// Check the daemon answers, and time a trivial hover by its language server
+    Ping,
+    /// How the daemon has answered each method since it started
+    Stats,
+    /// Stop answering the request with this id, if it is still in flight
+    Cancel {
+        id: String,
+    },
+    /// Encode the rest of this connection's messages, after the answer to this one, with `codec`
+    Codec {
+        codec: Codec,
+    },
+    /// Compress this connection's later responses whose encoding is larger than
+    /// `min_bytes`, by default [`DEFAULT_COMPRESS_MIN_BYTES`](crate::ipc::DEFAULT_COMPRESS_MIN_BYTES), with `compression`
+    Compress {
+        compression: Compression,
+        #[serde(default)]
+        min_bytes: Option<u32>,
+    },
     Shutdown,
 }
 
+impl Method {
+    /// The method's name as it goes over the wire, such as `Refs`
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+}
+
+/// What an `AtPath` request asks about the item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PathQuery {
+    Docs,
+    Def,
+    Impl,
+    Refs,
+}
+
+impl PathQuery {
+    /// The request for this query at `location`
+    pub fn at(self, location: &SymbolMatch, raw: bool) -> Method {
+        let (file, line, column, symbol) = (location.file.clone(), location.line, Some(location.column), location.name.clone());
+        match self {
+            PathQuery::Docs => Method::Docs { file, line, column, symbol },
+            PathQuery::Def => Method::Definition { file, line, column, symbol },
+            PathQuery::Impl => Method::Impl { file, line, column, symbol },
+            PathQuery::Refs => Method::Refs { file, line, column, symbol, raw },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Response {
     pub id: String,
@@ -45,4 +340,102 @@ pub struct Response {
 pub enum ResponseResult {
     Success { result: serde_json::Value },
     Error { error: String },
-}
\ No newline at end of file
+}
+
+/// The result of a `Status` request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DaemonStatus {
+    /// `starting` until the language server runs, then `indexing`, `ready`, or
+    /// `degraded` while it is down after a crash
+    pub status: String,
+    pub workspace: PathBuf,
+    pub indexing: bool,
+    pub phase: Option<String>,
+    pub percentage: Option<u32>,
+    pub message: Option<String>,
+    /// PID of the daemon
+    pub pid: u32,
+    pub uptime_secs: u64,
+    pub memory_bytes: Option<u64>,
+    /// PID of the language server the daemon drives
+    pub server_pid: Option<u32>,
+    pub server_memory_bytes: Option<u64>,
+    /// Why the language server stopped, while the daemon is degraded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crash_reason: Option<String>,
+    /// Restarts of a crashing language server since it last stayed up
+    #[serde(default)]
+    pub restart_attempts: u32,
+    /// Times the language server came back up after a crash, or a restart for
+    /// its memory, since the daemon started
+    #[serde(default)]
+    pub server_restarts: u32,
+    /// Of those, restarts for going over the `restart_above` memory limit
+    #[serde(default)]
+    pub memory_restarts: u32,
+    /// Seconds until the next restart; absent once restarts are exhausted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_restart_secs: Option<u64>,
+    /// Whether the language server is stopped while a cargo build runs
+    #[serde(default)]
+    pub paused_for_build: bool,
+    #[serde(default)]
+    pub prefetch: PrefetchStats,
+    /// The [`PROTOCOL_VERSION`] the daemon speaks; 0 from daemons older than this field
+    #[serde(default)]
+    pub protocol_version: u32,
+    /// Version of the `lq` build running the daemon; empty from daemons older than this field
+    #[serde(default)]
+    pub version: String,
+    /// The status of each workspace, when the daemon serves several; the fields
+    /// above are then those of the one furthest from ready
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<DaemonStatus>,
+}
+
+impl DaemonStatus {
+    /// Whether the daemon that answered `Status` with `status` runs the same `lq`
+    /// build as this process, so it understands every request.
+    ///
+    /// Read from the answer as JSON, since a daemon of an older build may leave
+    /// out fields a `DaemonStatus` requires: a missing version counts as 0 or
+    /// empty, and an answer that is not a status at all as another build.
+    pub fn is_current(status: &serde_json::Value) -> bool {
+        status.is_object()
+            && status["protocol_version"].as_u64().unwrap_or(0) == u64::from(PROTOCOL_VERSION)
+            && status["version"].as_str().unwrap_or_default() == env!("CARGO_PKG_VERSION")
+    }
+}
+
+/// How the answers prefetched after `docs` requests have been used
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PrefetchStats {
+    pub enabled: bool,
+    /// Prefetches started
+    pub issued: u64,
+    /// Prefetched answers later asked for
+    pub hits: u64,
+    /// Prefetches not started because the concurrency limit was reached
+    pub skipped: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_impls_is_an_alias_of_impl() {
+        let request = r#"{"method": "Impls", "params": {"file": "/ws/src/lib.rs", "line": 3, "symbol": "Shape"}}"#;
+        let method: Method = serde_json::from_str(request).unwrap();
+        assert!(matches!(method, Method::Impl { line: 3, column: None, .. }));
+    }
+
+    #[test]
+    fn test_method_name() {
+        assert_eq!(Method::Status.name(), "Status");
+        assert_eq!(Method::Search { query: "Config".to_string(), limit: 5 }.name(), "Search");
+        // The same as the tag the method goes over the wire with
+        let method = Method::Compress { compression: Compression::Zstd, min_bytes: None };
+        assert_eq!(serde_json::to_value(&method).unwrap()["method"], method.name());
+    }
+}
@@ -0,0 +1,391 @@
+//! Typed response payloads, one per method.
+//!
+//! The daemon builds these and sends them as the `result` of a [`Response`];
+//! clients read them back with the same types, so both sides agree on every
+//! field and the `--format json` schemas are derived from them too. Fields
+//! added after protocol version 1 default when missing, so a client can still
+//! read answers from an older daemon.
+//!
+//! [`Response`]: crate::ipc::Response
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::core::reports::{AsyncCallers, CallBounds, ChurnEntry, ErrorFlow, FunctionCoverage, GenericHotspot, RankedSymbol, RefClosure, RefsDiff, StringMatch, Tour};
+use crate::edit::{AppliedEdit, EditPreview};
+use crate::ipc::framing::{Codec, Compression};
+use crate::lsp::{Call, CodeAction, Definition, Diagnostic, HintedLine, ItemView, MacroExpansion, OutlineItem, Reference, Runnable, SymbolMatch, TypeHierarchy};
+
+// Caveats on an answer; a plain comment since flattened docs leak into the parent schema
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ResultFlags {
+    /// Present and true when the `--best-effort` deadline passed before the answer was complete
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// Present and true when the answer was cached before the workspace last changed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    /// Present and true when the language server was down and the answer comes from plain-text search
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub degraded: bool,
+    /// Present and true when the first answer was empty, right after the language server became ready,
+    /// and this is the answer to asking again
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retried: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DocsResult {
+    /// Hover documentation, or null when the server has none
+    pub docs: Option<String>,
+    /// Where the item was found, when it was named by a qualified path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SymbolMatch>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DefResult {
+    /// Every candidate, e.g. both the item and a re-export, in server order
+    #[serde(default)]
+    pub definitions: Vec<Definition>,
+    /// The first of `definitions`, for clients that predate multiple candidates
+    pub definition: Option<Definition>,
+    /// Where the item was found, when it was named by a qualified path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SymbolMatch>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+impl DefResult {
+    pub fn new(definitions: Vec<Definition>) -> Self {
+        Self {
+            definition: definitions.first().cloned(),
+            definitions,
+            location: None,
+            flags: ResultFlags::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ImplResult {
+    #[serde(default)]
+    pub implementations: Vec<Definition>,
+    /// Deprecated: the definition, which `impl` answered before protocol version 2; use `def`
+    #[serde(default)]
+    pub implementation: Option<Definition>,
+    /// Where the item was found, when it was named by a qualified path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SymbolMatch>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TypeResult {
+    /// One per named type, e.g. both `Option` and `Config` for an `Option<Config>`
+    pub definitions: Vec<Definition>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RefsResult {
+    pub references: Vec<Reference>,
+    /// Where the item was found, when it was named by a qualified path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SymbolMatch>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResolveResult {
+    pub resolved: Option<String>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CallsResult {
+    pub calls: Vec<Call>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OutlineResult {
+    pub outline: Vec<OutlineItem>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HintsResult {
+    /// Source lines with their inlay hints written in
+    pub hints: Vec<HintedLine>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SearchResult {
+    /// Best matches first
+    pub symbols: Vec<SymbolMatch>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DiagResult {
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RenameResult {
+    pub rename: EditPreview,
+    /// Present with `--apply`, once the edit has been written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied: Option<AppliedEdit>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ActionsResult {
+    /// In the order the server offers them
+    pub actions: Vec<CodeAction>,
+    /// Present with `--apply`, once the selected action has been written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied: Option<AppliedEdit>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ExpandResult {
+    /// `null` when there is no macro at the location
+    pub expansion: Option<MacroExpansion>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HirResult {
+    /// `null` when the line is in no function, const or static body
+    pub hir: Option<ItemView>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SyntaxResult {
+    /// `null` when the line is in no item
+    pub syntax: Option<ItemView>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RunnablesResult {
+    /// In file order, each cargo command listed once
+    pub runnables: Vec<Runnable>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SsrResult {
+    pub ssr: EditPreview,
+    /// Present with `--apply`, once the edit has been written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied: Option<AppliedEdit>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HierarchyResult {
+    pub hierarchy: TypeHierarchy,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GenericHotspotsResult {
+    pub hotspots: Vec<GenericHotspot>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CoverageMapResult {
+    /// Every public function with the tests that reference it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub functions: Option<Vec<FunctionCoverage>>,
+    /// With `--check`, only the functions no test references
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub untested: Option<Vec<FunctionCoverage>>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HotspotsResult {
+    pub hotspots: Vec<RankedSymbol>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TourResult {
+    pub tour: Tour,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DiffRefsResult {
+    // Flattened so that `--exclude` reaches both lists of references
+    #[serde(flatten)]
+    pub diff: RefsDiff,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AsyncCallersResult {
+    #[serde(flatten)]
+    pub report: AsyncCallers,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TransitiveRefsResult {
+    #[serde(flatten)]
+    pub report: RefClosure,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StringsResult {
+    pub strings: Vec<StringMatch>,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BoundsResult {
+    #[serde(flatten)]
+    pub report: CallBounds,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorFlowResult {
+    #[serde(flatten)]
+    pub report: ErrorFlow,
+    #[serde(flatten)]
+    pub flags: ResultFlags,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChurnResult {
+    pub entries: Vec<ChurnEntry>,
+}
+
+/// The result of a `Ping` request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PingResult {
+    /// The daemon's status, as `Status` reports it
+    pub status: String,
+    /// Whether the language server process runs and is not down after a crash
+    pub server_alive: bool,
+    pub server_pid: Option<u32>,
+    /// Milliseconds the language server took to answer a hover at the top of a source file
+    pub hover_ms: Option<u64>,
+    /// Why the hover failed, or that it took too long
+    pub hover_error: Option<String>,
+    /// Milliseconds from sending the request to reading the answer, measured by the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round_trip_ms: Option<u64>,
+}
+
+/// The result of a `Stats` request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StatsResult {
+    /// Seconds since the daemon started, over which the counts go
+    pub uptime_secs: u64,
+    /// One entry per method asked for, by name
+    pub methods: Vec<MethodStats>,
+}
+
+/// How the daemon has answered one method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MethodStats {
+    /// The method's name as it goes over the wire, such as `Refs`
+    pub method: String,
+    pub count: u64,
+    /// Requests answered with an error, cancelled or timed out
+    pub errors: u64,
+    pub mean_ms: u64,
+    /// Latency percentiles, each within about 19%
+    pub p50_ms: Option<u64>,
+    pub p90_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+    pub max_ms: u64,
+    /// Requests answered from a fresh cached answer
+    pub cache_hits: u64,
+    /// Requests answered from a stale cached answer, as `--allow-stale` or a passed deadline allows
+    pub cache_stale: u64,
+    /// Requests of a cacheable method that were computed
+    pub cache_misses: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StopResult {
+    pub shutdown: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CancelResult {
+    /// The request was still in flight and has been stopped
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CodecResult {
+    /// The encoding of the connection's later messages
+    pub codec: Codec,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CompressResult {
+    /// How the connection's later responses are compressed
+    pub compression: Compression,
+    /// Encoded size above which they are
+    pub min_bytes: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_fields_of_older_daemons_default() {
+        let result: DefResult = serde_json::from_value(serde_json::json!({ "definition": null, "partial": true })).unwrap();
+        assert!(result.definitions.is_empty());
+        assert!(result.flags.partial && !result.flags.stale);
+
+        let result: ImplResult = serde_json::from_value(serde_json::json!({ "implementations": [] })).unwrap();
+        assert!(result.implementation.is_none());
+    }
+}
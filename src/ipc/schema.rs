@@ -0,0 +1,169 @@
+//! JSON Schemas for the `--format json` output of each command.
+//!
+//! Schemas are derived from the [result types](crate::ipc::results) the daemon
+//! answers with, plus the few outputs the client builds itself. They carry
+//! [`PROTOCOL_VERSION`] so integrators can tell when the shape of the output
+//! changed.
+
+// The client-side output types are never constructed; they exist only to derive schemas from
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
+
+use crate::core::reports::{Badge, Migration};
+use crate::ipc::protocol::{DaemonStatus, Method, PROTOCOL_VERSION};
+use crate::ipc::results::{
+    ActionsResult, AsyncCallersResult, BoundsResult, CallsResult, ChurnResult, CoverageMapResult, DefResult, DiagResult, DiffRefsResult, DocsResult, ErrorFlowResult, ExpandResult, GenericHotspotsResult,
+    HierarchyResult, HintsResult, HirResult, HotspotsResult, ImplResult, OutlineResult, PingResult, RefsResult, RenameResult, ResolveResult, StatsResult,
+    RunnablesResult, SearchResult, SsrResult, StopResult, StringsResult, SyntaxResult, TourResult, TypeResult,
+};
+
+/// Commands with a JSON schema, in the order `lq schema` lists them
+pub const SCHEMA_COMMANDS: &[&str] = &[
+    "docs",
+    "def",
+    "impl",
+    "impls",
+    "type",
+    "refs",
+    "resolve",
+    "callers",
+    "callees",
+    "outline",
+    "hints",
+    "search",
+    "diag",
+    "rename",
+    "actions",
+    "expand",
+    "hir",
+    "syntax",
+    "runnables",
+    "ssr",
+    "hierarchy",
+    "generic-hotspots",
+    "coverage-map",
+    "hotspots",
+    "tour",
+    "churn",
+    "diff-refs",
+    "migrate",
+    "badge",
+    "async-callers",
+    "error-flow",
+    "bounds",
+    "strings",
+    "status",
+    "ping",
+    "stats",
+    "stop",
+    "daemons",
+    "error",
+];
+
+#[derive(JsonSchema)]
+struct DaemonsOutput {
+    daemons: Vec<DaemonEntry>,
+}
+
+/// A daemon found on this machine; status fields are present only if it responded
+#[derive(JsonSchema)]
+struct DaemonEntry {
+    socket: PathBuf,
+    responding: bool,
+    #[serde(flatten)]
+    status: Option<DaemonStatus>,
+}
+
+/// Printed instead of the command's output when a request fails
+#[derive(JsonSchema)]
+struct ErrorOutput {
+    error: String,
+}
+
+/// The JSON Schema for a command's `--format json` output
+pub fn command_schema(command: &str) -> Option<RootSchema> {
+    let generator = SchemaSettings::draft07().into_generator();
+    let mut schema = match command {
+        "docs" => generator.into_root_schema_for::<DocsResult>(),
+        "def" => generator.into_root_schema_for::<DefResult>(),
+        // `impls` is the deprecated name of `impl`
+        "impl" | "impls" => generator.into_root_schema_for::<ImplResult>(),
+        "type" => generator.into_root_schema_for::<TypeResult>(),
+        "refs" => generator.into_root_schema_for::<RefsResult>(),
+        "resolve" => generator.into_root_schema_for::<ResolveResult>(),
+        "callers" | "callees" => generator.into_root_schema_for::<CallsResult>(),
+        "outline" => generator.into_root_schema_for::<OutlineResult>(),
+        "hints" => generator.into_root_schema_for::<HintsResult>(),
+        "search" => generator.into_root_schema_for::<SearchResult>(),
+        "diag" => generator.into_root_schema_for::<DiagResult>(),
+        "rename" => generator.into_root_schema_for::<RenameResult>(),
+        "actions" => generator.into_root_schema_for::<ActionsResult>(),
+        "expand" => generator.into_root_schema_for::<ExpandResult>(),
+        "hir" => generator.into_root_schema_for::<HirResult>(),
+        "syntax" => generator.into_root_schema_for::<SyntaxResult>(),
+        "runnables" => generator.into_root_schema_for::<RunnablesResult>(),
+        "ssr" => generator.into_root_schema_for::<SsrResult>(),
+        "hierarchy" => generator.into_root_schema_for::<HierarchyResult>(),
+        "generic-hotspots" => generator.into_root_schema_for::<GenericHotspotsResult>(),
+        "coverage-map" => generator.into_root_schema_for::<CoverageMapResult>(),
+        "hotspots" => generator.into_root_schema_for::<HotspotsResult>(),
+        "tour" => generator.into_root_schema_for::<TourResult>(),
+        "churn" => generator.into_root_schema_for::<ChurnResult>(),
+        "diff-refs" => generator.into_root_schema_for::<DiffRefsResult>(),
+        "migrate" => generator.into_root_schema_for::<Migration>(),
+        "badge" => generator.into_root_schema_for::<Badge>(),
+        "async-callers" => generator.into_root_schema_for::<AsyncCallersResult>(),
+        "error-flow" => generator.into_root_schema_for::<ErrorFlowResult>(),
+        "bounds" => generator.into_root_schema_for::<BoundsResult>(),
+        "strings" => generator.into_root_schema_for::<StringsResult>(),
+        "status" => generator.into_root_schema_for::<DaemonStatus>(),
+        "ping" => generator.into_root_schema_for::<PingResult>(),
+        "stats" => generator.into_root_schema_for::<StatsResult>(),
+        "stop" => generator.into_root_schema_for::<StopResult>(),
+        "daemons" => generator.into_root_schema_for::<DaemonsOutput>(),
+        "error" => generator.into_root_schema_for::<ErrorOutput>(),
+        _ => return None,
+    };
+
+    schema.schema.metadata().title = Some(format!("lq {} output", command));
+    schema.schema.extensions.insert(
+        "x-lq-protocol-version".to_string(),
+        serde_json::json!(PROTOCOL_VERSION),
+    );
+    Some(schema)
+}
+
+/// The JSON Schema for the `method` and `params` of a request
+pub fn method_schema() -> RootSchema {
+    let mut schema = SchemaSettings::draft07().into_generator().into_root_schema_for::<Method>();
+    schema.schema.extensions.insert(
+        "x-lq-protocol-version".to_string(),
+        serde_json::json!(PROTOCOL_VERSION),
+    );
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_command_has_a_schema() {
+        for command in SCHEMA_COMMANDS {
+            let schema = command_schema(command).unwrap_or_else(|| panic!("no schema for {}", command));
+            let value = serde_json::to_value(&schema).unwrap();
+            assert_eq!(value["x-lq-protocol-version"], PROTOCOL_VERSION);
+        }
+        assert!(command_schema("unknown").is_none());
+    }
+
+    #[test]
+    fn test_refs_schema() {
+        insta::assert_yaml_snapshot!(command_schema("refs").unwrap());
+    }
+}
@@ -1,7 +1,41 @@
+//! Query rust-analyzer about a Rust workspace through a long-lived daemon.
+//!
+//! The `lq` binary is one client of the daemon; [`LanguageQueryClient`] is the
+//! same client for other Rust tools. It finds the daemon serving a workspace,
+//! starts one if none is running, and offers a typed method per query that
+//! returns the structured results `lq --format json` prints:
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use language_query::LanguageQueryClient;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = LanguageQueryClient::connect(Path::new("/src/my-project")).await?;
+//! for reference in client.references(Path::new("src/lib.rs"), 42, "Config", false).await? {
+//!     println!("{}:{}: {}", reference.path.display(), reference.line, reference.text);
+//! }
+//! println!("{} top-level items", client.outline(Path::new("src/lib.rs")).await?.len());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The daemon is started with the `lq` on `PATH` unless
+//! [`ClientOptions::executable`] says otherwise, and only for a workspace the
+//! user trusts (see [`config`]).
+
+pub mod client;
+pub mod config;
 pub mod core;
 pub mod daemon;
+pub mod edit;
+pub mod fixture;
+pub mod init;
 pub mod ipc;
 pub mod lsp;
+pub mod output;
+
+pub use client::{Client as LanguageQueryClient, ClientOptions};
 
 #[cfg(test)]
 pub mod test_utils;
\ No newline at end of file
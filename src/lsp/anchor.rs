@@ -0,0 +1,120 @@
+//! Content anchors for finding a location again after its file changes.
+//!
+//! Line and column numbers go stale as soon as a file is edited or
+//! reformatted, so results that point into source also carry an [`Anchor`]:
+//! a hash of the target line and its nearest non-blank neighbours with all
+//! whitespace removed, and the position of the target among the line's
+//! non-whitespace characters. Reindenting, rewrapping spaces, and adding or
+//! removing blank lines leave the anchor intact, which lets a consumer map an
+//! earlier result onto the current file with [`Anchor::locate`].
+//!
+//! The hash is 64-bit FNV-1a over the three normalized lines joined by `\n`
+//! (an empty string standing in for a missing neighbour), written as 16
+//! lowercase hex digits, so clients in other languages can compute it too.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Where a result points, in a form that survives formatting changes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Anchor {
+    /// FNV-1a hash of the whitespace-free target line and its non-blank neighbours
+    pub hash: String,
+    /// Non-whitespace characters on the target line before the target
+    pub offset: u32,
+}
+
+impl Anchor {
+    /// The anchor of the 1-based `line` and UTF-16 `column` in `lines`
+    pub fn at(lines: &[&str], line: u32, column: u32) -> Self {
+        let index = line.saturating_sub(1) as usize;
+        let target = lines.get(index).copied().unwrap_or_default();
+        let mut units = 0;
+        let offset = target
+            .chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units < column as usize
+            })
+            .filter(|c| !c.is_whitespace())
+            .count();
+        Self {
+            hash: context_hash(lines, index),
+            offset: offset as u32,
+        }
+    }
+
+    /// The 1-based line and UTF-16 column the anchor points to in `contents`.
+    ///
+    /// When several lines match, the one closest to `near_line` wins.
+    pub fn locate(&self, contents: &str, near_line: u32) -> Option<(u32, u32)> {
+        let lines: Vec<&str> = contents.lines().collect();
+        let index = (0..lines.len())
+            .filter(|&index| context_hash(&lines, index) == self.hash)
+            .min_by_key(|&index| (index as i64 + 1 - near_line as i64).abs())?;
+
+        let mut remaining = self.offset;
+        let mut units = 0;
+        for c in lines[index].chars() {
+            if !c.is_whitespace() {
+                if remaining == 0 {
+                    break;
+                }
+                remaining -= 1;
+            }
+            units += c.len_utf16();
+        }
+        Some((index as u32 + 1, units as u32 + 1))
+    }
+}
+
+fn context_hash(lines: &[&str], index: usize) -> String {
+    let normalize = |line: &str| line.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let non_blank = |line: &&&str| !line.trim().is_empty();
+    let before = lines[..index.min(lines.len())].iter().rev().find(non_blank).map(|line| normalize(line));
+    let after = lines.get(index + 1..).unwrap_or_default().iter().find(non_blank).map(|line| normalize(line));
+    let target = lines.get(index).map(|line| normalize(line));
+
+    let text = [before, target, after].map(Option::unwrap_or_default).join("\n");
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anchor_survives_reformatting() {
+        let before = "fn area(s: &Square) -> f64 {\n    let side = s.0;\n    side * side\n}\n";
+        let lines: Vec<&str> = before.lines().collect();
+        let anchor = Anchor::at(&lines, 2, 16);
+        assert_eq!(anchor.offset, 8);
+        assert_eq!(anchor.locate(before, 2), Some((2, 16)));
+
+        let after = "// Area\nfn area(s:&Square)->f64{\n\n        let side=s.0;\n  side*side\n}\n";
+        assert_eq!(anchor.locate(after, 2), Some((4, 18)));
+
+        let edited = "fn area(s: &Square) -> f64 {\n    let side = s.1;\n    side * side\n}\n";
+        assert_eq!(anchor.locate(edited, 2), None);
+    }
+
+    #[test]
+    fn test_locate_prefers_nearest_match() {
+        let contents = "a\nb\na\nb\na\n";
+        let lines: Vec<&str> = contents.lines().collect();
+        let anchor = Anchor::at(&lines, 3, 1);
+        // Lines 1 and 5 differ in context from line 3, so only line 3 matches
+        assert_eq!(anchor.locate(contents, 5), Some((3, 1)));
+
+        let repeated = "x\na\nb\na\nx\na\nb\na\nx\n";
+        let lines: Vec<&str> = repeated.lines().collect();
+        let anchor = Anchor::at(&lines, 7, 1);
+        assert_eq!(anchor.locate(repeated, 6), Some((7, 1)));
+        assert_eq!(anchor.locate(repeated, 2), Some((3, 1)));
+    }
+}
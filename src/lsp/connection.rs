@@ -1,11 +1,93 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use crate::lsp::{Call, CodeAction, Definition, Diagnostic, FileChange, IndexingStatus, InlayHint, ItemView, MacroExpansion, OutlineItem, Reference, Runnable, ServerHealth, SymbolMatch, TypeHierarchy};
+
+/// A language server as lq queries it.
+///
+/// Lines and columns are 1-based, columns counted in UTF-16 code units. A
+/// `symbol` is looked for on `line`, or a line or two around it; with a
+/// `column`, it must be the occurrence on `line` that covers the column.
 #[async_trait]
 pub trait LspConnection: Send + Sync {
-    async fn hover(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<String>>;
-    async fn implementation(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<String>>;
-    async fn references(&self, file: &Path, line: u32, symbol: &str) -> Result<Vec<String>>;
+    async fn hover(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Option<String>>;
+    /// Hover at an exact 1-based line and column
+    async fn hover_at(&self, file: &Path, line: u32, column: u32) -> Result<Option<String>>;
+    /// Hover over the selection from `start` to `end`, both `(line, column)` and
+    /// inclusive, describing the expression or statement it covers
+    async fn hover_range(&self, file: &Path, start: (u32, u32), end: (u32, u32)) -> Result<Option<String>>;
+    /// Where the item named by `symbol` is defined, with its source; re-exports and
+    /// trait items can have several candidates, in server order
+    async fn definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>>;
+    async fn references(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Reference>>;
     async fn resolve_symbol(&self, file: &Path, symbol: &str) -> Result<Option<String>>;
-}
\ No newline at end of file
+    /// Functions that call the function named by `symbol`, one level deep
+    async fn incoming_calls(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Call>>;
+    /// Functions called by the function named by `symbol`, one level deep
+    async fn outgoing_calls(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Call>>;
+    /// The items declared in a file, nested by containment
+    async fn document_symbols(&self, file: &Path) -> Result<Vec<OutlineItem>>;
+    /// Every implementation of a trait or trait method, each with its source
+    async fn implementations(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>>;
+    /// The declarations of the type of the expression named by `symbol`; compound
+    /// types such as `Option<Config>` yield one per named type
+    async fn type_definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>>;
+    /// The workspace edit renaming the symbol to `new_name`, without applying it
+    async fn rename(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str, new_name: &str) -> Result<Vec<FileChange>>;
+    /// Code actions offered at `symbol` on `line`, or for the whole line when no
+    /// symbol is given, in server order
+    async fn code_actions(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Vec<CodeAction>>;
+    /// The recursive expansion of the macro at `symbol` on `line`, or of the first
+    /// macro on the line when no symbol is given; `None` when there is no macro there
+    async fn expand_macro(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Option<MacroExpansion>>;
+    /// The lowered HIR of the innermost function, const or static body on `line`;
+    /// `None` when the line is in no body
+    async fn view_hir(&self, file: &Path, line: u32) -> Result<Option<ItemView>>;
+    /// The syntax tree of the innermost item on `line`; `None` when the line is in no item
+    async fn syntax_tree(&self, file: &Path, line: u32) -> Result<Option<ItemView>>;
+    /// The tests, benchmarks and binaries declared in `file`, with their cargo commands
+    async fn runnables(&self, file: &Path) -> Result<Vec<Runnable>>;
+    /// The workspace edit applying the structural search and replace `rule`, without
+    /// applying it: across the workspace, or within lines `start..=end` of `file`
+    /// (all of it when no range is given)
+    async fn structural_replace(&self, rule: &str, file: Option<&Path>, lines: Option<(u32, u32)>) -> Result<Vec<FileChange>>;
+    /// Inlay hints for lines `start_line..=end_line` of `file`, or the whole file
+    /// when no range is given, in position order
+    async fn inlay_hints(&self, file: &Path, lines: Option<(u32, u32)>) -> Result<Vec<InlayHint>>;
+    /// Symbols anywhere in the workspace whose names match `query`, in server order
+    async fn workspace_symbols(&self, query: &str) -> Result<Vec<SymbolMatch>>;
+    /// The latest diagnostics for `file`, or for the whole workspace when `None`
+    async fn diagnostics(&self, file: Option<&Path>) -> Result<Vec<Diagnostic>>;
+    /// Supertypes and subtypes of the type or trait named by `symbol`
+    async fn type_hierarchy(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<TypeHierarchy>;
+    /// Process id of the language server, if it runs as a child process
+    fn server_pid(&self) -> Option<u32> {
+        None
+    }
+    /// Current indexing progress reported by the server
+    fn indexing_status(&self) -> IndexingStatus {
+        IndexingStatus::default()
+    }
+    /// Whether the server is up, and why not if it is down
+    fn health(&self) -> ServerHealth {
+        ServerHealth::default()
+    }
+    /// Resolves with the reason once the server has stopped; never, for servers that do not stop
+    async fn wait_for_exit(&self) -> String {
+        std::future::pending().await
+    }
+    /// The files opened on the server so far, as queries about them do
+    async fn opened_files(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+    /// Open `file` on the server ahead of any query about it
+    async fn open(&self, _file: &Path) -> Result<()> {
+        Ok(())
+    }
+    /// Stop the server so that it is started afresh, for `reason`; false if it
+    /// is not running or cannot be restarted
+    fn restart(&self, _reason: &str) -> bool {
+        false
+    }
+}
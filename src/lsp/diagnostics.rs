@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use lsp_types::{DiagnosticSeverity, NumberOrString, PublishDiagnosticsParams};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How serious a diagnostic is, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    #[value(alias = "info")]
+    Information,
+    Hint,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Information => "info",
+            Severity::Hint => "hint",
+        })
+    }
+}
+
+/// An error, warning, or hint the language server reported for a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Diagnostic {
+    /// Absolute path of the file
+    pub file: PathBuf,
+    /// Path relative to the workspace root, used for display
+    pub path: PathBuf,
+    /// 1-based line where the diagnostic starts
+    pub line: u32,
+    /// 1-based column where the diagnostic starts
+    pub column: u32,
+    pub severity: Severity,
+    /// Lint or error code, e.g. `E0308` or `unused_variables`
+    pub code: Option<String>,
+    /// What produced the diagnostic, e.g. `rustc`, `clippy`, or `rust-analyzer`
+    pub source: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path.display(), self.line, self.column, self.severity)?;
+        if let Some(code) = &self.code {
+            write!(f, "[{}]", code)?;
+        }
+        // Continuation lines of multi-line messages are indented under the first
+        write!(f, ": {}", self.message.trim_end().replace('\n', "\n    "))
+    }
+}
+
+/// The latest diagnostics published via `textDocument/publishDiagnostics`
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsState {
+    /// Published diagnostics keyed by file; each notification replaces a file's list
+    files: BTreeMap<PathBuf, Vec<lsp_types::Diagnostic>>,
+}
+
+impl DiagnosticsState {
+    /// Apply a `textDocument/publishDiagnostics` notification
+    pub fn update(&mut self, params: PublishDiagnosticsParams) {
+        let Ok(file) = params.uri.to_file_path() else {
+            return;
+        };
+        // Empty lists are kept: they record that the file was checked and is clean
+        self.files.insert(file, params.diagnostics);
+    }
+
+    /// Whether the server has published diagnostics for `file`
+    pub fn has_file(&self, file: &Path) -> bool {
+        self.files.contains_key(file)
+    }
+
+    /// The diagnostics as published for `file` that touch 0-based `line`, as code
+    /// action requests pass them back
+    pub fn published_on_line(&self, file: &Path, line: u32) -> Vec<lsp_types::Diagnostic> {
+        self.files
+            .get(file)
+            .map(|published| {
+                published
+                    .iter()
+                    .filter(|d| d.range.start.line <= line && line <= d.range.end.line)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Diagnostics for `file`, or for every file when `None`, in file and line order
+    pub fn diagnostics(&self, file: Option<&Path>, workspace: &Path) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for (path, published) in &self.files {
+            if file.is_some_and(|file| file != path) {
+                continue;
+            }
+            for diagnostic in published {
+                diagnostics.push(Diagnostic {
+                    file: path.clone(),
+                    path: path.strip_prefix(workspace).unwrap_or(path).to_path_buf(),
+                    line: diagnostic.range.start.line + 1,
+                    column: diagnostic.range.start.character + 1,
+                    severity: severity(diagnostic.severity),
+                    code: diagnostic.code.as_ref().map(|code| match code {
+                        NumberOrString::String(code) => code.clone(),
+                        NumberOrString::Number(code) => code.to_string(),
+                    }),
+                    source: diagnostic.source.clone(),
+                    message: diagnostic.message.clone(),
+                });
+            }
+        }
+        diagnostics.sort_by(|a, b| a.path.cmp(&b.path).then(a.line.cmp(&b.line)).then(a.column.cmp(&b.column)));
+        diagnostics
+    }
+}
+
+/// Servers may leave severity out; the spec says clients then decide, and errors are the safe reading
+fn severity(severity: Option<DiagnosticSeverity>) -> Severity {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => Severity::Warning,
+        Some(DiagnosticSeverity::INFORMATION) => Severity::Information,
+        Some(DiagnosticSeverity::HINT) => Severity::Hint,
+        _ => Severity::Error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{Position, Range};
+
+    fn publish(file: &str, diagnostics: Vec<(u32, DiagnosticSeverity, &str)>) -> PublishDiagnosticsParams {
+        PublishDiagnosticsParams {
+            uri: url::Url::from_file_path(file).unwrap(),
+            diagnostics: diagnostics
+                .into_iter()
+                .map(|(line, severity, message)| lsp_types::Diagnostic {
+                    range: Range::new(Position::new(line, 4), Position::new(line, 9)),
+                    severity: Some(severity),
+                    code: Some(NumberOrString::String("E0308".to_string())),
+                    source: Some("rustc".to_string()),
+                    message: message.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_latest_diagnostics_per_file() {
+        let mut state = DiagnosticsState::default();
+        state.update(publish("/ws/src/main.rs", vec![(9, DiagnosticSeverity::WARNING, "unused")]));
+        state.update(publish("/ws/src/lib.rs", vec![(2, DiagnosticSeverity::ERROR, "old")]));
+        state.update(publish("/ws/src/lib.rs", vec![(4, DiagnosticSeverity::ERROR, "mismatched types\nexpected u32")]));
+
+        let all = state.diagnostics(None, Path::new("/ws"));
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].to_string(), "src/lib.rs:5:5: error[E0308]: mismatched types\n    expected u32");
+        assert_eq!(all[1].severity, Severity::Warning);
+
+        let lib = state.diagnostics(Some(Path::new("/ws/src/lib.rs")), Path::new("/ws"));
+        assert_eq!(lib.len(), 1);
+        assert_eq!(state.published_on_line(Path::new("/ws/src/lib.rs"), 4)[0].message, "mismatched types\nexpected u32");
+        assert!(state.published_on_line(Path::new("/ws/src/lib.rs"), 2).is_empty());
+
+        state.update(publish("/ws/src/lib.rs", Vec::new()));
+        assert!(state.has_file(Path::new("/ws/src/lib.rs")));
+        assert_eq!(state.diagnostics(None, Path::new("/ws")).len(), 1);
+    }
+}
@@ -0,0 +1,88 @@
+//! rust-analyzer's `rust-analyzer/expandMacro` extension request.
+//!
+//! The request expands the macro call, attribute or derive under the cursor
+//! recursively, so the result contains no further macro calls. Without a symbol
+//! to put the cursor on, the first macro on the line is picked from its source text.
+
+use lsp_types::request::Request;
+use lsp_types::{Position, TextDocumentIdentifier};
+use serde::{Deserialize, Serialize};
+
+pub enum ExpandMacro {}
+
+impl Request for ExpandMacro {
+    type Params = ExpandMacroParams;
+    type Result = Option<ExpandedMacro>;
+    const METHOD: &'static str = "rust-analyzer/expandMacro";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpandMacroParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpandedMacro {
+    pub name: String,
+    pub expansion: String,
+}
+
+/// The 0-based UTF-16 column of the first macro on `line`: the first derive in
+/// a `#[derive(...)]`, the name of another attribute, or the name of a `name!` call
+pub fn macro_call_column(line: &str) -> Option<u32> {
+    let code = line.split("//").next().unwrap_or_default();
+    let index = match code.find("#[") {
+        Some(attribute) => {
+            let name = attribute + 2 + leading_space(&code[attribute + 2..]);
+            match code[name..].strip_prefix("derive") {
+                Some(args) if args.trim_start().starts_with('(') => {
+                    let open = name + "derive".len() + args.find('(')? + 1;
+                    open + leading_space(&code[open..])
+                }
+                _ => name,
+            }
+        }
+        None => bang_call(code)?,
+    };
+    if !code[index..].starts_with(is_identifier_char) {
+        return None;
+    }
+    Some(code[..index].encode_utf16().count() as u32)
+}
+
+/// Byte index of the name in the first `name!` on `code` that is not `!=`
+fn bang_call(code: &str) -> Option<usize> {
+    code.match_indices('!').find_map(|(bang, _)| {
+        if code[bang + 1..].starts_with('=') {
+            return None;
+        }
+        let name_len = code[..bang].chars().rev().take_while(|&c| is_identifier_char(c)).map(char::len_utf8).sum::<usize>();
+        (name_len > 0).then(|| bang - name_len)
+    })
+}
+
+fn leading_space(text: &str) -> usize {
+    text.len() - text.trim_start().len()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_call_column() {
+        assert_eq!(macro_call_column("    let v = vec![1, 2];"), Some(12));
+        assert_eq!(macro_call_column("#[derive(Debug, Clone)]"), Some(9));
+        assert_eq!(macro_call_column("#[derive( Debug)]"), Some(10));
+        assert_eq!(macro_call_column("    #[tokio::main]"), Some(6));
+        assert_eq!(macro_call_column("if a != b { panic!(\"é\") }"), Some(12));
+        assert_eq!(macro_call_column("let s = \"é\"; println!()"), Some(13));
+        assert_eq!(macro_call_column("if !done {} // todo!()"), None);
+    }
+}
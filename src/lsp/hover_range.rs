@@ -0,0 +1,25 @@
+//! rust-analyzer's hover over a range, its `hoverRange` extension of `textDocument/hover`.
+//!
+//! In place of a position the request takes a selection, and the server
+//! describes the expression or pattern covering it: its type, and with a
+//! statement or block selected, the types of what it evaluates to.
+
+use lsp_types::request::Request;
+use lsp_types::{Hover, Range, TextDocumentIdentifier};
+use serde::{Deserialize, Serialize};
+
+pub enum HoverRange {}
+
+impl Request for HoverRange {
+    type Params = HoverRangeParams;
+    type Result = Option<Hover>;
+    const METHOD: &'static str = "textDocument/hover";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HoverRangeParams {
+    pub text_document: TextDocumentIdentifier,
+    /// A range where the standard request has a position
+    pub position: Range,
+}
@@ -0,0 +1,205 @@
+//! CPU and memory limits for the language server process on Linux.
+//!
+//! By default the server runs in a transient `systemd-run --user --scope` unit
+//! with `MemoryMax` and `CPUQuota` set. When `cgroup` names a delegated cgroup v2
+//! directory, the server is instead moved into a fresh child cgroup of it with
+//! `memory.max` and `cpu.max` written directly.
+//!
+//! `restart_above` is a softer limit that works anywhere: the daemon restarts
+//! a server whose resident memory has grown past it, rather than the kernel
+//! killing it mid-query.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Period `cpu.max` quotas are expressed against, in microseconds
+const CPU_PERIOD_US: u64 = 100_000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// Memory ceiling such as `4G` or `512M`; binary units, plain numbers are bytes
+    pub memory: Option<String>,
+    /// CPU ceiling as a percentage of one core, such as `200%` for two cores
+    pub cpu: Option<String>,
+    /// Delegated cgroup v2 directory to create the server's cgroup in, instead of
+    /// using `systemd-run`
+    pub cgroup: Option<PathBuf>,
+    /// Resident memory, such as `6G`, above which the daemon restarts the server
+    pub restart_above: Option<String>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu.is_none()
+    }
+
+    /// [`ResourceLimits::restart_above`] in bytes, if set
+    pub fn restart_above_bytes(&self) -> Result<Option<u64>> {
+        self.restart_above.as_deref().map(parse_memory).transpose()
+    }
+
+    /// The `systemd-run` wrapper enforcing the limits; empty when there are none
+    /// or they are applied through `cgroup`
+    pub fn command_prefix(&self) -> Result<Vec<String>> {
+        if self.is_empty() || self.cgroup.is_some() {
+            return Ok(Vec::new());
+        }
+        ensure_linux()?;
+
+        let mut command: Vec<String> = ["systemd-run", "--user", "--scope", "--quiet", "--collect"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if let Some(memory) = &self.memory {
+            command.push(format!("--property=MemoryMax={}", parse_memory(memory)?));
+        }
+        if let Some(cpu) = &self.cpu {
+            command.push(format!("--property=CPUQuota={}%", parse_cpu(cpu)?));
+        }
+        command.push("--".to_string());
+        Ok(command)
+    }
+
+    /// Move process `pid` into a new cgroup below `cgroup` carrying the limits.
+    ///
+    /// Empty cgroups left behind by earlier servers are removed first.
+    pub fn apply_to_process(&self, pid: u32) -> Result<()> {
+        let Some(parent) = self.cgroup.as_ref().filter(|_| !self.is_empty()) else {
+            return Ok(());
+        };
+        ensure_linux()?;
+
+        if let Ok(entries) = std::fs::read_dir(parent) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with("lq-") {
+                    // Fails harmlessly for cgroups that still have processes
+                    let _ = std::fs::remove_dir(entry.path());
+                }
+            }
+        }
+
+        let cgroup = parent.join(format!("lq-{}", pid));
+        std::fs::create_dir(&cgroup).with_context(|| format!("Failed to create cgroup {:?}", cgroup))?;
+        let write = |file: &str, value: String| {
+            std::fs::write(cgroup.join(file), value)
+                .with_context(|| format!("Failed to write {} in cgroup {:?}; is the memory and cpu controller delegated to it?", file, cgroup))
+        };
+        if let Some(memory) = &self.memory {
+            write("memory.max", parse_memory(memory)?.to_string())?;
+        }
+        if let Some(cpu) = &self.cpu {
+            write("cpu.max", format!("{} {}", parse_cpu(cpu)? * CPU_PERIOD_US / 100, CPU_PERIOD_US))?;
+        }
+        write("cgroup.procs", pid.to_string())
+    }
+}
+
+fn ensure_linux() -> Result<()> {
+    if cfg!(target_os = "linux") {
+        Ok(())
+    } else {
+        bail!("Resource limits for the language server are only supported on Linux")
+    }
+}
+
+/// `4G` as bytes
+fn parse_memory(memory: &str) -> Result<u64> {
+    let memory = memory.trim();
+    let (number, multiplier) = match memory.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier = match unit.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => bail!("Unknown memory unit in {:?}; use K, M, G or T", memory),
+            };
+            (&memory[..index], multiplier)
+        }
+        _ => (memory, 1),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid memory limit {:?}", memory))?;
+    Ok(number * multiplier)
+}
+
+/// `200%` as 200
+fn parse_cpu(cpu: &str) -> Result<u64> {
+    let percent: u64 = cpu
+        .trim()
+        .trim_end_matches('%')
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid CPU limit {:?}; use a percentage of one core such as 200%", cpu))?;
+    if percent == 0 {
+        bail!("CPU limit must be above 0%");
+    }
+    Ok(percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        assert_eq!(parse_memory("4G").unwrap(), 4 << 30);
+        assert_eq!(parse_memory("512m").unwrap(), 512 << 20);
+        assert_eq!(parse_memory("1000").unwrap(), 1000);
+        assert!(parse_memory("4X").is_err());
+        assert!(parse_memory("lots").is_err());
+
+        assert_eq!(parse_cpu("200%").unwrap(), 200);
+        assert_eq!(parse_cpu("50").unwrap(), 50);
+        assert!(parse_cpu("0%").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_command_prefix() {
+        let limits = ResourceLimits {
+            memory: Some("2G".to_string()),
+            cpu: Some("150%".to_string()),
+            cgroup: None,
+            restart_above: Some("3G".to_string()),
+        };
+        assert_eq!(limits.restart_above_bytes().unwrap(), Some(3 << 30));
+        assert_eq!(
+            limits.command_prefix().unwrap(),
+            vec![
+                "systemd-run", "--user", "--scope", "--quiet", "--collect",
+                "--property=MemoryMax=2147483648", "--property=CPUQuota=150%", "--",
+            ]
+        );
+
+        assert!(ResourceLimits::default().command_prefix().unwrap().is_empty());
+        let delegated = ResourceLimits { cgroup: Some(PathBuf::from("/sys/fs/cgroup/lq")), ..limits };
+        assert!(delegated.command_prefix().unwrap().is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_apply_to_delegated_cgroup() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("lq-1")).unwrap();
+        let limits = ResourceLimits {
+            memory: Some("1G".to_string()),
+            cpu: Some("50%".to_string()),
+            cgroup: Some(dir.path().to_path_buf()),
+            restart_above: None,
+        };
+        limits.apply_to_process(42).unwrap();
+
+        assert!(!dir.path().join("lq-1").exists());
+        let cgroup = dir.path().join("lq-42");
+        let read = |file| std::fs::read_to_string(cgroup.join(file)).unwrap();
+        assert_eq!(read("memory.max"), "1073741824");
+        assert_eq!(read("cpu.max"), "50000 100000");
+        assert_eq!(read("cgroup.procs"), "42");
+    }
+}
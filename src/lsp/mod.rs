@@ -1,5 +1,29 @@
+pub mod anchor;
 pub mod connection;
+pub mod diagnostics;
+pub mod expand_macro;
+pub mod hover_range;
+pub mod limits;
+pub mod options;
+pub mod position;
+pub mod priority;
+pub mod progress;
+pub mod render;
+pub mod runnables;
 pub mod rust_analyzer;
+pub mod ssr;
+pub mod supervisor;
+pub mod syntax_tree;
+pub mod type_hierarchy;
+pub mod types;
 
+pub use anchor::Anchor;
 pub use connection::LspConnection;
-pub use rust_analyzer::RustAnalyzerConnection;
\ No newline at end of file
+pub use diagnostics::{Diagnostic, DiagnosticsState, Severity};
+pub use limits::ResourceLimits;
+pub use options::ServerOptions;
+pub use priority::Priority;
+pub use progress::{IndexingStatus, ProgressState};
+pub use rust_analyzer::RustAnalyzerConnection;
+pub use supervisor::{ServerHealth, SupervisedConnection};
+pub use types::{enclosing_item, normalize_references, rank_symbols, Call, CallDirection, CallSite, CodeAction, Definition, FileChange, HintedLine, InlayHint, InlayHintKind, ItemView, MacroExpansion, OutlineItem, Reference, Runnable, RunnableKind, SymbolMatch, TextEdit, TypeHierarchy, TypeRelation};
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::lsp::{ProgressState, ResourceLimits};
+
+/// Default upper bound on the language server's initialization and first indexing
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Environment variable, read by the daemon, that overrides [`DEFAULT_READY_TIMEOUT`] in seconds
+pub const READY_TIMEOUT_ENV: &str = "LQ_READY_TIMEOUT";
+
+/// Environment variable naming the rust-analyzer executable, which wins over the config and `PATH`
+pub const RUST_ANALYZER_ENV: &str = "LQ_RUST_ANALYZER";
+
+/// Default number of times a crashed language server is restarted before giving up
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+
+/// Options controlling how the language server is started
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// The rust-analyzer executable; found on `PATH` or in a common install location when unset
+    pub executable: Option<PathBuf>,
+    /// Arguments the language server is started with
+    pub args: Vec<String>,
+    /// Settings sent as the server's `initializationOptions`
+    pub initialization_options: Option<serde_json::Value>,
+    /// Fail to start a server that has not initialized and finished indexing after this long
+    pub ready_timeout: Duration,
+    /// Command prefix the language server runs under, e.g. `["firejail", "--net=none"]`;
+    /// empty runs it directly
+    pub sandbox: Vec<String>,
+    /// CPU and memory ceilings for the language server
+    pub limits: ResourceLimits,
+    /// Consecutive restarts of a crashing server to attempt before giving up
+    pub max_restarts: u32,
+    /// Where the server's work-done progress is published, starting over with
+    /// each restart, so it can be followed before the server is up to ask
+    pub progress: Option<Arc<watch::Sender<ProgressState>>>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            executable: None,
+            args: Vec::new(),
+            initialization_options: None,
+            ready_timeout: DEFAULT_READY_TIMEOUT,
+            sandbox: Vec::new(),
+            limits: ResourceLimits::default(),
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            progress: None,
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Finding a symbol on a source line.
+//!
+//! A symbol only matches where it stands as a whole identifier, so `Test`
+//! is not found inside `TestStruct` or `MyTest`. Columns are UTF-16 code
+//! units, as the language server counts them.
+
+/// 0-based UTF-16 columns where `symbol` appears on `line` as a whole identifier
+pub fn symbol_columns(line: &str, symbol: &str) -> Vec<u32> {
+    if symbol.is_empty() {
+        return Vec::new();
+    }
+    line.match_indices(symbol)
+        .filter(|&(start, _)| {
+            let before = line[..start].chars().next_back();
+            let after = line[start + symbol.len()..].chars().next();
+            !before.is_some_and(is_identifier_char) && !after.is_some_and(is_identifier_char)
+        })
+        .map(|(start, _)| line[..start].encode_utf16().count() as u32)
+        .collect()
+}
+
+/// The 0-based UTF-16 column where the occurrence of `symbol` covering the
+/// 1-based `column` of `line` starts
+pub fn symbol_at_column(line: &str, symbol: &str, column: u32) -> Option<u32> {
+    let cursor = column.checked_sub(1)?;
+    let width = symbol.encode_utf16().count() as u32;
+    symbol_columns(line, symbol)
+        .into_iter()
+        .find(|&start| start <= cursor && cursor < start + width)
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_columns() {
+        let line = "let t: Test = MyTest::new(Test, TestStruct, test_Test);";
+        assert_eq!(symbol_columns(line, "Test"), vec![7, 26]);
+        assert_eq!(symbol_columns("\u{e9}(area)", "area"), vec![2]);
+        assert_eq!(symbol_columns(line, ""), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_symbol_at_column() {
+        let line = "let area = area(\u{e9}, area);";
+        assert_eq!(symbol_at_column(line, "area", 5), Some(4));
+        assert_eq!(symbol_at_column(line, "area", 14), Some(11));
+        assert_eq!(symbol_at_column(line, "area", 21), Some(19));
+        assert_eq!(symbol_at_column(line, "area", 10), None);
+        assert_eq!(symbol_at_column(line, "area", 0), None);
+        assert_eq!(symbol_at_column("let areas = 1;", "area", 5), None);
+    }
+}
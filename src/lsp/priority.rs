@@ -0,0 +1,63 @@
+//! Background scheduling priority for the daemon and its language server.
+//!
+//! The daemon lowers its own priority before it starts the server, which
+//! inherits it, as do the cargo processes the server runs. `nice` is applied
+//! with `renice`, and `idle_io` puts disk access in the idle class with
+//! `ionice -c 3`, which only exists on Linux.
+
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Priority {
+    /// Niceness from 0 (normal) to 19 (lowest)
+    pub nice: Option<i32>,
+    /// Only read and write the disk when nothing else does (Linux)
+    pub idle_io: bool,
+    /// Pause the language server while it indexes and a cargo build of the
+    /// workspace is running, so the build gets the machine to itself
+    pub pause_during_builds: bool,
+}
+
+impl Priority {
+    /// Lower the priority of process `pid` as configured
+    pub fn apply_to_process(&self, pid: u32) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(0..=19).contains(&nice) {
+                bail!("Priority nice must be between 0 and 19, not {}", nice);
+            }
+            run(Command::new("renice").args(["-n", &nice.to_string(), "-p", &pid.to_string()]))?;
+        }
+        if self.idle_io {
+            if !cfg!(target_os = "linux") {
+                bail!("Idle I/O priority is only supported on Linux");
+            }
+            run(Command::new("ionice").args(["-c", "3", "-p", &pid.to_string()]))?;
+        }
+        Ok(())
+    }
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        bail!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nice_out_of_range() {
+        let priority = Priority { nice: Some(20), ..Default::default() };
+        assert!(priority.apply_to_process(std::process::id()).is_err());
+        assert!(Priority::default().apply_to_process(std::process::id()).is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use lsp_types::{NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How long an initialized server must go without any progress task before it
+/// counts as ready without having indexed, as with `cachePriming.enable = false`
+pub const QUIET_PERIOD: Duration = Duration::from_secs(3);
+
+/// A work-done progress task the language server is currently reporting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressTask {
+    pub title: String,
+    pub message: Option<String>,
+    pub percentage: Option<u32>,
+}
+
+/// A snapshot of indexing progress for status reporting
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct IndexingStatus {
+    /// Whether an indexing task is running
+    pub indexing: bool,
+    /// Title of the most relevant running task, e.g. `Indexing` or `Loading`
+    pub phase: Option<String>,
+    pub percentage: Option<u32>,
+    pub message: Option<String>,
+}
+
+/// Work-done progress reported by the language server via `$/progress`
+#[derive(Debug, Clone, Default)]
+pub struct ProgressState {
+    /// Tasks that have begun but not yet ended, keyed by progress token
+    active: BTreeMap<String, ProgressTask>,
+    /// Whether an indexing task has run to completion at least once
+    indexing_finished: bool,
+    /// When indexing last finished
+    ready_since: Option<Instant>,
+    /// Whether the server has answered `initialize`
+    initialized: bool,
+    /// Since when an initialized server has run no task at all
+    quiet_since: Option<Instant>,
+}
+
+impl ProgressState {
+    /// Apply a `$/progress` notification
+    pub fn update(&mut self, params: ProgressParams) {
+        let token = match params.token {
+            NumberOrString::String(token) => token,
+            NumberOrString::Number(token) => token.to_string(),
+        };
+        let ProgressParamsValue::WorkDone(progress) = params.value;
+
+        match progress {
+            WorkDoneProgress::Begin(begin) => {
+                self.quiet_since = None;
+                self.active.insert(token, ProgressTask {
+                    title: begin.title,
+                    message: begin.message,
+                    percentage: begin.percentage,
+                });
+            }
+            WorkDoneProgress::Report(report) => {
+                if let Some(task) = self.active.get_mut(&token) {
+                    if report.message.is_some() {
+                        task.message = report.message;
+                    }
+                    if report.percentage.is_some() {
+                        task.percentage = report.percentage;
+                    }
+                }
+            }
+            WorkDoneProgress::End(_) => {
+                if self.active.remove(&token).is_some() && is_indexing_token(&token) {
+                    self.indexing_finished = true;
+                    if !self.is_indexing() {
+                        self.ready_since = Some(Instant::now());
+                    }
+                }
+                if self.initialized && self.active.is_empty() {
+                    self.quiet_since = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    /// Note that the server has answered `initialize`, from when on going quiet makes it ready
+    pub fn mark_initialized(&mut self) {
+        self.initialized = true;
+        if self.active.is_empty() {
+            self.quiet_since = Some(Instant::now());
+        }
+    }
+
+    /// When the server became ready: when indexing last finished, or for a server
+    /// that has not indexed, once it has been quiet for [`QUIET_PERIOD`]
+    fn ready_at(&self) -> Option<Instant> {
+        if self.is_indexing() {
+            return None;
+        }
+        if self.indexing_finished {
+            return self.ready_since;
+        }
+        self.quiet_since.map(|since| since + QUIET_PERIOD).filter(|at| *at <= Instant::now())
+    }
+
+    /// How much longer the server must stay quiet to become ready without indexing,
+    /// if it is quiet now
+    pub fn quiet_remaining(&self) -> Option<Duration> {
+        self.quiet_since.map(|since| QUIET_PERIOD.saturating_sub(since.elapsed()))
+    }
+
+    /// Whether any indexing-related task is still running
+    pub fn is_indexing(&self) -> bool {
+        self.active.keys().any(|token| is_indexing_token(token))
+    }
+
+    /// Indexing has completed and no indexing task is running, or the server was
+    /// initialized and has gone quiet without starting one
+    pub fn is_ready(&self) -> bool {
+        self.ready_at().is_some()
+    }
+
+    /// How long the server has been ready; none while it is not
+    pub fn ready_for(&self) -> Option<Duration> {
+        self.ready_at().map(|at| at.elapsed())
+    }
+
+    /// The currently running tasks, keyed by progress token
+    pub fn active_tasks(&self) -> &BTreeMap<String, ProgressTask> {
+        &self.active
+    }
+
+    /// Summarize progress, preferring an indexing task over other running tasks
+    pub fn status(&self) -> IndexingStatus {
+        let task = self
+            .active
+            .iter()
+            .find(|(token, _)| is_indexing_token(token))
+            .or_else(|| self.active.iter().next())
+            .map(|(_, task)| task);
+
+        IndexingStatus {
+            indexing: self.is_indexing(),
+            phase: task.map(|t| t.title.clone()),
+            percentage: task.and_then(|t| t.percentage),
+            message: task.and_then(|t| t.message.clone()),
+        }
+    }
+}
+
+/// rust-analyzer reports indexing as `rustAnalyzer/Indexing` (older versions)
+/// or `rustAnalyzer/cachePriming`
+fn is_indexing_token(token: &str) -> bool {
+    let token = token.to_ascii_lowercase();
+    token.contains("indexing") || token.contains("cachepriming")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{WorkDoneProgressBegin, WorkDoneProgressEnd, WorkDoneProgressReport};
+
+    fn progress(token: &str, value: WorkDoneProgress) -> ProgressParams {
+        ProgressParams {
+            token: NumberOrString::String(token.to_string()),
+            value: ProgressParamsValue::WorkDone(value),
+        }
+    }
+
+    #[test]
+    fn test_ready_after_indexing_ends() {
+        let mut state = ProgressState::default();
+        assert!(!state.is_ready());
+
+        state.update(progress("rustAnalyzer/Fetching", WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Fetching".to_string(),
+            ..Default::default()
+        })));
+        state.update(progress("rustAnalyzer/Fetching", WorkDoneProgress::End(WorkDoneProgressEnd::default())));
+        assert!(!state.is_ready(), "non-indexing tasks don't make the server ready");
+
+        state.update(progress("rustAnalyzer/cachePriming", WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Indexing".to_string(),
+            percentage: Some(0),
+            ..Default::default()
+        })));
+        state.update(progress("rustAnalyzer/cachePriming", WorkDoneProgress::Report(WorkDoneProgressReport {
+            percentage: Some(40),
+            message: Some("3/7 (core)".to_string()),
+            ..Default::default()
+        })));
+        assert!(state.is_indexing());
+        let status = state.status();
+        assert_eq!(status.phase.as_deref(), Some("Indexing"));
+        assert_eq!(status.percentage, Some(40));
+        let task = &state.active_tasks()["rustAnalyzer/cachePriming"];
+        assert_eq!(task.percentage, Some(40));
+        assert_eq!(task.message.as_deref(), Some("3/7 (core)"));
+
+        assert_eq!(state.ready_for(), None);
+
+        state.update(progress("rustAnalyzer/cachePriming", WorkDoneProgress::End(WorkDoneProgressEnd::default())));
+        assert!(state.is_ready());
+        assert_eq!(state.status(), IndexingStatus::default());
+        assert!(state.ready_for().unwrap() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_ready_when_quiet_without_indexing() {
+        let mut state = ProgressState::default();
+        // Not before the server is initialized, however long it has been quiet
+        assert_eq!(state.quiet_remaining(), None);
+
+        state.update(progress("rustAnalyzer/Fetching", WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Fetching".to_string(),
+            ..Default::default()
+        })));
+        state.mark_initialized();
+        assert_eq!(state.quiet_remaining(), None, "not quiet while a task runs");
+
+        state.update(progress("rustAnalyzer/Fetching", WorkDoneProgress::End(WorkDoneProgressEnd::default())));
+        assert!(!state.is_ready());
+        assert!(state.quiet_remaining().unwrap() > QUIET_PERIOD - Duration::from_secs(1));
+
+        // As if the quiet period had passed
+        state.quiet_since = Some(Instant::now() - QUIET_PERIOD);
+        assert!(state.is_ready());
+        assert!(state.ready_for().unwrap() < Duration::from_secs(1));
+
+        state.update(progress("rustAnalyzer/cachePriming", WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Indexing".to_string(),
+            ..Default::default()
+        })));
+        assert!(!state.is_ready());
+    }
+}
@@ -0,0 +1,118 @@
+//! Source as lq quotes it in answers: the code of an item, the line a result
+//! is on and the lines around it, fenced code, and paths relative to the
+//! workspace root.
+//!
+//! The language server and the plain-text fallback both quote through here,
+//! as does `-A`/`-B`/`-C` on the client, so an answer reads the same whichever
+//! produced it. Sizes are parameters rather than constants, so that each
+//! command can ask for as much source as it shows.
+
+use std::path::{Path, PathBuf};
+
+/// Lines [`item_excerpt`] stops at unless a command asks for more or less
+pub const DEFAULT_EXCERPT_LINES: usize = 12;
+
+/// `file` relative to `workspace`, for display; `file` itself when it lies outside
+pub fn relative_path(workspace: &Path, file: &Path) -> PathBuf {
+    file.strip_prefix(workspace).unwrap_or(file).to_path_buf()
+}
+
+/// `code` in a Markdown code fence tagged `language`
+pub fn fenced(language: &str, code: &str) -> String {
+    format!("```{}\n{}\n```", language, code)
+}
+
+/// The 0-based line `index` trimmed, as results quote the line they are on;
+/// empty past the end of the file
+pub fn line_text<S: AsRef<str>>(lines: &[S], index: usize) -> String {
+    lines.get(index).map(|line| line.as_ref().trim().to_string()).unwrap_or_default()
+}
+
+/// 1-based lines `start` to `end` inclusive, as the source of an item whose span is known
+pub fn span<S: AsRef<str>>(lines: &[S], start: u32, end: u32) -> String {
+    let start = start.saturating_sub(1) as usize;
+    let end = (end as usize).min(lines.len());
+    lines.get(start..end).unwrap_or_default().iter().map(AsRef::as_ref).collect::<Vec<_>>().join("\n")
+}
+
+/// The source of the item starting at 0-based line `start` when its span is
+/// not known: up to the line closing its first brace, or ending in `;`,
+/// capped at `max_lines`
+pub fn item_excerpt<S: AsRef<str>>(lines: &[S], start: usize, max_lines: usize) -> String {
+    let mut depth = 0i32;
+    let mut opened = false;
+    let mut excerpt = Vec::new();
+
+    for line in lines.iter().skip(start).take(max_lines) {
+        let line = line.as_ref();
+        excerpt.push(line);
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        if (opened && depth <= 0) || (!opened && line.trim_end().ends_with(';')) {
+            break;
+        }
+    }
+
+    excerpt.join("\n")
+}
+
+/// Up to `before` and `after` lines around 1-based `line`, dedented by its
+/// indentation, since the line itself is quoted trimmed; none if it is past the end
+pub fn context_window<S: AsRef<str>>(lines: &[S], line: usize, before: usize, after: usize) -> Option<(Vec<String>, Vec<String>)> {
+    let index = line.checked_sub(1).filter(|&index| index < lines.len())?;
+    let target = lines[index].as_ref();
+    let indent = &target[..target.len() - target.trim_start().len()];
+    let dedent = |line: &S| {
+        let line = line.as_ref().trim_end();
+        line.strip_prefix(indent).unwrap_or(line.trim_start()).to_string()
+    };
+    let context_before = lines[index.saturating_sub(before)..index].iter().map(dedent).collect();
+    let context_after = lines[index + 1..(index + 1 + after).min(lines.len())].iter().map(dedent).collect();
+    Some((context_before, context_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_excerpt() {
+        let source = "impl Shape for Square {\n    fn area(&self) -> f64 {\n        self.0 * self.0\n    }\n}\n\nfn next() {}";
+        let lines: Vec<&str> = source.lines().collect();
+        assert_eq!(item_excerpt(&lines, 0, DEFAULT_EXCERPT_LINES), lines[..5].join("\n"));
+        assert_eq!(item_excerpt(&lines, 1, DEFAULT_EXCERPT_LINES), lines[1..4].join("\n"));
+        assert_eq!(item_excerpt(&lines, 0, 2), lines[..2].join("\n"));
+        assert_eq!(item_excerpt(&["impl Send for Handle {}", "struct Next;"], 0, DEFAULT_EXCERPT_LINES), "impl Send for Handle {}");
+    }
+
+    #[test]
+    fn test_lines() {
+        let lines = ["fn main() {", "    let x = 1;", "}"];
+        assert_eq!(line_text(&lines, 1), "let x = 1;");
+        assert_eq!(line_text(&lines, 3), "");
+        assert_eq!(span(&lines, 1, 2), "fn main() {\n    let x = 1;");
+        assert_eq!(span(&lines, 2, 9), "    let x = 1;\n}");
+        assert_eq!(fenced("rust", "struct A;"), "```rust\nstruct A;\n```");
+        assert_eq!(relative_path(Path::new("/ws"), Path::new("/ws/src/lib.rs")), Path::new("src/lib.rs"));
+        assert_eq!(relative_path(Path::new("/ws"), Path::new("/elsewhere/lib.rs")), Path::new("/elsewhere/lib.rs"));
+    }
+
+    #[test]
+    fn test_context_window() {
+        let lines = ["fn main() {", "    let x = 1;", "    x", "        .abs()", "}"];
+        let (before, after) = context_window(&lines, 3, 2, 1).unwrap();
+        assert_eq!(before, ["fn main() {", "let x = 1;"]);
+        assert_eq!(after, ["    .abs()"]);
+        assert_eq!(context_window(&lines, 1, 3, 0).unwrap(), (Vec::new(), Vec::new()));
+        assert!(context_window(&lines, 6, 1, 1).is_none());
+        assert!(context_window(&lines, 0, 1, 1).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+//! rust-analyzer's `experimental/runnables` extension request.
+//!
+//! For a file, rust-analyzer lists the tests, test modules, benchmarks,
+//! doctests and binaries declared in it, each with the cargo invocation its
+//! editor integrations would run. Labels are the only place the server says
+//! what a runnable is, so [`RunnableKind`] is read from the label's first word.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use lsp_types::request::Request;
+use lsp_types::{LocationLink, Position, TextDocumentIdentifier};
+use serde::{Deserialize, Serialize};
+
+use crate::lsp::{Runnable, RunnableKind};
+
+pub enum Runnables {}
+
+impl Request for Runnables {
+    type Params = RunnablesParams;
+    type Result = Vec<ServerRunnable>;
+    const METHOD: &'static str = "experimental/runnables";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunnablesParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Only the runnables around this position; every runnable in the file when absent
+    pub position: Option<Position>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerRunnable {
+    pub label: String,
+    pub location: Option<LocationLink>,
+    pub args: RunnableArgs,
+}
+
+/// Arguments of a `cargo` runnable, or of a `shell` one, which has `program` and `args`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RunnableArgs {
+    pub cwd: Option<PathBuf>,
+    pub workspace_root: Option<PathBuf>,
+    pub override_cargo: Option<String>,
+    pub cargo_args: Vec<String>,
+    pub executable_args: Vec<String>,
+    pub program: Option<String>,
+    pub args: Vec<String>,
+    pub environment: BTreeMap<String, String>,
+}
+
+impl ServerRunnable {
+    /// The runnable as lq reports it, with paths relative to `workspace`
+    pub fn into_runnable(self, workspace: &Path) -> Runnable {
+        let (first, rest) = self.label.split_once(' ').unwrap_or((&self.label, ""));
+        let (kind, name) = match first {
+            "test" => (RunnableKind::Test, rest),
+            "test-mod" => (RunnableKind::TestModule, rest),
+            "bench" => (RunnableKind::Bench, rest),
+            "doctest" => (RunnableKind::Doctest, rest),
+            "run" => (RunnableKind::Binary, rest),
+            _ => (RunnableKind::Other, self.label.as_str()),
+        };
+        let name = name.to_string();
+
+        let args = self.args;
+        let command = match args.program {
+            Some(program) => std::iter::once(program).chain(args.args).collect(),
+            None => {
+                let mut command = vec![args.override_cargo.unwrap_or_else(|| "cargo".to_string())];
+                command.extend(args.cargo_args);
+                if !args.executable_args.is_empty() {
+                    command.push("--".to_string());
+                    command.extend(args.executable_args);
+                }
+                command
+            }
+        };
+        let location = self.location.and_then(|link| {
+            let file = link.target_uri.to_file_path().ok()?;
+            let path = file.strip_prefix(workspace).unwrap_or(&file).to_path_buf();
+            Some((path, link.target_selection_range.start.line + 1))
+        });
+
+        Runnable {
+            kind,
+            name,
+            path: location.as_ref().map(|(path, _)| path.clone()),
+            line: location.map(|(_, line)| line),
+            command,
+            cwd: args.cwd.or(args.workspace_root).unwrap_or_else(|| workspace.to_path_buf()),
+            environment: args.environment,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_runnable() {
+        let runnable: ServerRunnable = serde_json::from_value(serde_json::json!({
+            "label": "test tests::adds",
+            "kind": "cargo",
+            "location": {
+                "targetUri": "file:///ws/src/lib.rs",
+                "targetRange": { "start": { "line": 9, "character": 4 }, "end": { "line": 12, "character": 5 } },
+                "targetSelectionRange": { "start": { "line": 10, "character": 7 }, "end": { "line": 10, "character": 11 } }
+            },
+            "args": {
+                "cwd": "/ws",
+                "workspaceRoot": "/ws",
+                "cargoArgs": ["test", "--package", "calc", "--lib"],
+                "executableArgs": ["tests::adds", "--exact", "--show-output"],
+                "environment": { "RUST_BACKTRACE": "short" }
+            }
+        }))
+        .unwrap();
+        let runnable = runnable.into_runnable(Path::new("/ws"));
+        assert_eq!(runnable.kind, RunnableKind::Test);
+        assert_eq!(runnable.name, "tests::adds");
+        assert_eq!((runnable.path, runnable.line), (Some(PathBuf::from("src/lib.rs")), Some(11)));
+        assert_eq!(
+            runnable.command,
+            ["cargo", "test", "--package", "calc", "--lib", "--", "tests::adds", "--exact", "--show-output"]
+        );
+
+        let check: ServerRunnable = serde_json::from_value(serde_json::json!({
+            "label": "cargo check -p calc --all-targets",
+            "kind": "cargo",
+            "args": { "cwd": "/ws", "cargoArgs": ["check", "-p", "calc", "--all-targets"], "executableArgs": [] }
+        }))
+        .unwrap();
+        let check = check.into_runnable(Path::new("/ws"));
+        assert_eq!((check.kind, check.name.as_str()), (RunnableKind::Other, "cargo check -p calc --all-targets"));
+        assert_eq!(check.command, ["cargo", "check", "-p", "calc", "--all-targets"]);
+    }
+}
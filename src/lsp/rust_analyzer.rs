@@ -2,7 +2,8 @@ use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
 use std::ops::ControlFlow;
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::task::Poll;
 use std::time::Duration;
 
 use anyhow::{Result, Context, bail};
@@ -10,53 +11,157 @@ use async_lsp::concurrency::ConcurrencyLayer;
 use async_lsp::panic::CatchUnwindLayer;
 use async_lsp::server::LifecycleLayer;
 use async_lsp::tracing::TracingLayer;
-use async_lsp::{LanguageServer, MainLoop, ServerSocket};
+use async_lsp::{ErrorCode, LanguageServer, MainLoop, ServerSocket};
 use async_lsp::router::Router;
 use async_process::Command;
 use async_trait::async_trait;
+use futures::future::BoxFuture;
+use lsp_types::notification::Cancel;
+use lsp_types::request::Request;
 use lsp_types::{
-    ClientCapabilities, DidOpenTextDocumentParams, GotoDefinitionParams,
-    GotoDefinitionResponse, Hover, HoverClientCapabilities, HoverParams, InitializeParams,
-    InitializedParams, MarkupKind, Position, ReferenceContext, ReferenceParams,
+    CallHierarchyIncomingCallsParams, CancelParams, CallHierarchyItem, CallHierarchyOutgoingCallsParams,
+    CallHierarchyPrepareParams, ClientCapabilities, CodeActionClientCapabilities, CodeActionContext, CodeActionKindLiteralSupport,
+    CodeActionLiteralSupport, CodeActionOrCommand, CodeActionParams, DocumentChangeOperation, DocumentChanges, DocumentSymbolClientCapabilities,
+    DocumentSymbolParams, DocumentSymbolResponse, DidOpenTextDocumentParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverClientCapabilities, HoverParams, InitializeParams, InlayHintLabel, InlayHintParams,
+    InitializedParams, MarkupKind, NumberOrString, OneOf, Position, Range, ReferenceContext, ReferenceParams, RenameParams,
+    ResourceOp, ResourceOperationKind,
     TextDocumentClientCapabilities, TextDocumentIdentifier, TextDocumentItem,
-    TextDocumentPositionParams, WindowClientCapabilities, WorkDoneProgressParams,
-    WorkspaceFolder,
+    TextDocumentPositionParams, TypeHierarchyItem, TypeHierarchyPrepareParams,
+    TypeHierarchySubtypesParams, TypeHierarchySupertypesParams, WindowClientCapabilities,
+    WorkDoneProgressParams, WorkspaceClientCapabilities, WorkspaceEdit, WorkspaceEditClientCapabilities,
+    WorkspaceFolder, WorkspaceSymbolParams, WorkspaceSymbolResponse,
 };
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{watch, Mutex, RwLock};
 use tokio::task;
 use tower::ServiceBuilder;
-use tracing::{info, error};
+use tracing::{debug, info, error};
 
-use crate::lsp::LspConnection;
+use crate::lsp::expand_macro::{macro_call_column, ExpandMacro, ExpandMacroParams};
+use crate::lsp::hover_range::{HoverRange, HoverRangeParams};
+use crate::lsp::runnables::{Runnables, RunnablesParams};
+use crate::lsp::syntax_tree::{position_at, SyntaxElement, ViewHir, ViewSyntaxTree, ViewSyntaxTreeParams, NOT_LOWERABLE};
+use crate::lsp::position::{symbol_at_column, symbol_columns};
+use crate::lsp::ssr::{default_context, line_selection, Ssr, SsrParams};
+use crate::lsp::render::{fenced, item_excerpt, line_text, relative_path, span, DEFAULT_EXCERPT_LINES};
+use crate::lsp::types::enclosing_item;
+use crate::lsp::type_hierarchy::{last_segment, parse_impl_header, supertraits, ImplHeader};
+use crate::lsp::options::{READY_TIMEOUT_ENV, RUST_ANALYZER_ENV};
+use crate::lsp::{
+    Anchor, Call, CallSite, CodeAction, Definition, Diagnostic, DiagnosticsState, FileChange, IndexingStatus, InlayHint, InlayHintKind, ItemView, LspConnection, MacroExpansion, OutlineItem, ProgressState, Reference, Runnable, ServerOptions,
+    SymbolMatch, TextEdit, TypeHierarchy, TypeRelation,
+};
+
+/// Lines of the server's stderr kept to explain a crash
+const STDERR_TAIL_LINES: usize = 5;
+/// Times a request is sent again after rust-analyzer answers that the content was modified
+const CONTENT_MODIFIED_RETRIES: u32 = 3;
+/// Wait before sending such a request again
+const CONTENT_MODIFIED_DELAY: Duration = Duration::from_millis(500);
+
+/// A request sent to the server, cancelled there if dropped before its answer arrives,
+/// as when the daemon stops answering a client that went away
+struct PendingRequest {
+    server: ServerSocket,
+    id: i32,
+    answered: bool,
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if !self.answered {
+            debug!("Cancelling request {} to rust-analyzer", self.id);
+            let _ = self.server.notify::<Cancel>(CancelParams { id: NumberOrString::Number(self.id) });
+        }
+    }
+}
 
 pub struct RustAnalyzerConnection {
     workspace: PathBuf,
-    server: Arc<Mutex<ServerSocket>>,
+    server: ServerSocket,
+    /// Requests sent so far, which is also the number async-lsp gives the next one
+    requests_sent: Mutex<i32>,
     opened_files: Arc<Mutex<HashSet<PathBuf>>>,
     is_ready: Arc<RwLock<bool>>,
+    progress: watch::Receiver<ProgressState>,
+    diagnostics: watch::Receiver<DiagnosticsState>,
+    /// Why the server stopped, once it has
+    exit: watch::Receiver<Option<String>>,
+    options: ServerOptions,
     #[allow(dead_code)]
     _mainloop_handle: tokio::task::JoinHandle<()>,
-    #[allow(dead_code)]
-    _child: async_process::Child,
+    child: async_process::Child,
 }
 
 impl RustAnalyzerConnection {
     pub async fn new(workspace: &Path) -> Result<Self> {
-        let rust_analyzer_path = find_rust_analyzer()?;
+        Self::with_options(workspace, ServerOptions::default()).await
+    }
+    
+    pub async fn with_options(workspace: &Path, options: ServerOptions) -> Result<Self> {
+        let rust_analyzer_path = match &options.executable {
+            Some(executable) => executable.clone(),
+            None => find_rust_analyzer()?,
+        };
         
         info!("Starting rust-analyzer at: {:?}", rust_analyzer_path);
         
-        let mut child = Command::new(&rust_analyzer_path)
+        // Resource limits wrap the sandbox so that they also cover the sandbox itself
+        let mut wrapper = options.limits.command_prefix()?;
+        wrapper.extend(options.sandbox.iter().cloned());
+        let mut command = match wrapper.split_first() {
+            Some((program, args)) => {
+                info!("Running rust-analyzer under: {}", wrapper.join(" "));
+                let mut command = Command::new(program);
+                command.args(args).arg(&rust_analyzer_path);
+                command
+            }
+            None => Command::new(&rust_analyzer_path),
+        };
+        command.args(&options.args);
+        let mut child = command
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .current_dir(workspace)
             .kill_on_drop(true)
             .spawn()
-            .context("Failed to spawn rust-analyzer")?;
+            .with_context(|| match wrapper.first() {
+                Some(program) => format!("Failed to spawn rust-analyzer under `{}`", program),
+                None => format!("Failed to spawn rust-analyzer at {:?}", rust_analyzer_path),
+            })?;
+        options.limits.apply_to_process(child.id())?;
         
         let stdout = child.stdout.take().unwrap();
         let stdin = child.stdin.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        
+        // Keep the end of stderr, where a crashing server explains itself
+        let stderr_tail = Arc::new(std::sync::Mutex::new(VecDeque::new()));
+        let tail = stderr_tail.clone();
+        task::spawn(async move {
+            use futures::{AsyncBufReadExt, StreamExt};
+            let mut lines = futures::io::BufReader::new(stderr).lines();
+            while let Some(Ok(line)) = lines.next().await {
+                debug!("rust-analyzer: {}", line);
+                let mut tail = tail.lock().unwrap();
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+        });
+        
+        let progress_tx = match &options.progress {
+            Some(progress) => {
+                progress.send_replace(ProgressState::default());
+                progress.clone()
+            }
+            None => Arc::new(watch::channel(ProgressState::default()).0),
+        };
+        let progress = progress_tx.subscribe();
+        let (diagnostics_tx, diagnostics) = watch::channel(DiagnosticsState::default());
+        let (exit_tx, exit) = watch::channel(None);
         
         // Create the main loop for LSP communication
         let (mainloop, server) = MainLoop::new_client(|_server| {
@@ -65,16 +170,26 @@ impl RustAnalyzerConnection {
                 .layer(LifecycleLayer::default())
                 .layer(CatchUnwindLayer::default())
                 .layer(ConcurrencyLayer::default())
-                .service(ClientState::new_router())
+                .service(ClientState::new_router(progress_tx.clone(), diagnostics_tx))
         });
         
-        let server = Arc::new(Mutex::new(server));
-        
         // Run the main loop in a background task
         let mainloop_handle = task::spawn(async move {
-            if let Err(e) = mainloop.run_buffered(stdout, stdin).await {
-                error!("Language server mainloop error: {}", e);
+            let mut reason = match mainloop.run_buffered(stdout, stdin).await {
+                Ok(()) | Err(async_lsp::Error::Eof) => "rust-analyzer exited".to_string(),
+                Err(async_lsp::Error::Io(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => "rust-analyzer exited".to_string(),
+                Err(e) => {
+                    error!("Language server mainloop error: {}", e);
+                    format!("rust-analyzer connection failed: {}", e)
+                }
+            };
+            // Give the stderr reader a moment to catch the last words
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let tail: Vec<String> = stderr_tail.lock().unwrap().iter().filter(|l| !l.trim().is_empty()).cloned().collect();
+            if !tail.is_empty() {
+                reason = format!("{} (stderr: {})", reason, tail.join(" | "));
             }
+            let _ = exit_tx.send(Some(reason));
         });
         
         let is_ready = Arc::new(RwLock::new(false));
@@ -82,21 +197,102 @@ impl RustAnalyzerConnection {
         let connection = Self {
             workspace: workspace.to_path_buf(),
             server,
+            requests_sent: Mutex::new(0),
             opened_files: Arc::new(Mutex::new(HashSet::new())),
             is_ready: is_ready.clone(),
+            progress,
+            diagnostics,
+            exit,
+            options,
             _mainloop_handle: mainloop_handle,
-            _child: child,
+            child,
         };
         
-        // Initialize the LSP server
-        connection.initialize().await?;
-        
-        // Wait for the server to be ready
-        connection.wait_until_ready().await?;
+        // Initialize the LSP server and wait for it to be ready, dropping (and so
+        // killing) a server that takes too long
+        let timeout = connection.options.ready_timeout;
+        let started = async {
+            if let Err(e) = connection.initialize().await {
+                // A server that dies on startup says why on stderr
+                if let Ok(reason) = tokio::time::timeout(Duration::from_secs(1), connection.wait_for_exit()).await {
+                    return Err(e.context(reason));
+                }
+                return Err(e);
+            }
+            progress_tx.send_modify(ProgressState::mark_initialized);
+            connection.wait_until_ready().await
+        };
+        match tokio::time::timeout(timeout, started).await {
+            Ok(started) => started?,
+            Err(_) => bail!(
+                "rust-analyzer did not initialize and index the workspace within {}s; to allow it longer, run `lq stop` \
+                 and start the daemon again with {}=<seconds> set",
+                timeout.as_secs(),
+                READY_TIMEOUT_ENV
+            ),
+        }
         
         Ok(connection)
     }
     
+    /// Send a request and wait for the answer, cancelling the request on the server
+    /// if the caller stops waiting first.
+    ///
+    /// async-lsp numbers outgoing requests from 0 in the order they are sent and
+    /// keeps the numbers to itself. Every request is numbered and sent here with
+    /// the count locked, so counting them gives the number `$/cancelRequest` needs.
+    /// The lock is let go once the request is on its way, and the answers of
+    /// different clients' queries are awaited side by side.
+    async fn request<T>(&self, send: impl FnOnce(&mut ServerSocket) -> BoxFuture<'static, async_lsp::Result<T>>) -> async_lsp::Result<T> {
+        let mut server = self.server.clone();
+        let (id, answer, first_poll) = {
+            let mut sent = self.requests_sent.lock().await;
+            let id = *sent;
+            *sent += 1;
+            let mut answer = send(&mut server);
+            // Some requests only go out when first polled, which must happen before the next is numbered
+            let first_poll = futures::poll!(&mut answer);
+            (id, answer, first_poll)
+        };
+        let mut pending = PendingRequest { server, id, answered: false };
+        let result = match first_poll {
+            Poll::Ready(result) => result,
+            Poll::Pending => answer.await,
+        };
+        pending.answered = true;
+        result
+    }
+    
+    /// Send a request as [`Self::request`] does, sending it again a few times while
+    /// rust-analyzer answers that the content was modified as it worked on it
+    async fn request_with_retry<T>(&self, what: &str, send: impl Fn(&mut ServerSocket) -> BoxFuture<'static, async_lsp::Result<T>>) -> Result<T> {
+        let mut attempts = 0;
+        loop {
+            match self.request(&send).await {
+                Ok(response) => return Ok(response),
+                Err(e) if e.to_string().contains("content modified") && attempts < CONTENT_MODIFIED_RETRIES => {
+                    attempts += 1;
+                    info!("Retrying {} request due to content modified error (attempt {})", what, attempts);
+                    tokio::time::sleep(CONTENT_MODIFIED_DELAY).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    
+    /// Send a request rust-analyzer added to the protocol, retrying as [`Self::request_with_retry`] does
+    async fn custom_request_with_retry<R: Request>(&self, what: &str, params: R::Params) -> Result<R::Result>
+    where
+        R::Params: Clone,
+    {
+        self.request_with_retry(what, |server| {
+            let server = server.clone();
+            let params = params.clone();
+            Box::pin(async move { server.request::<R>(params).await })
+        })
+        .await
+    }
+    
     async fn initialize(&self) -> Result<()> {
         info!("Initializing rust-analyzer for workspace: {:?}", self.workspace);
         
@@ -115,6 +311,36 @@ impl RustAnalyzerConnection {
                         content_format: Some(vec![MarkupKind::Markdown]),
                         ..Default::default()
                     }),
+                    document_symbol: Some(DocumentSymbolClientCapabilities {
+                        hierarchical_document_symbol_support: Some(true),
+                        ..Default::default()
+                    }),
+                    // Without resolve support, actions arrive with their edits computed
+                    code_action: Some(CodeActionClientCapabilities {
+                        code_action_literal_support: Some(CodeActionLiteralSupport {
+                            code_action_kind: CodeActionKindLiteralSupport {
+                                value_set: ["", "quickfix", "refactor", "refactor.extract", "refactor.inline", "refactor.rewrite", "source"]
+                                    .into_iter()
+                                    .map(String::from)
+                                    .collect(),
+                            },
+                        }),
+                        is_preferred_support: Some(true),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                workspace: Some(WorkspaceClientCapabilities {
+                    // Lets renames of modules move their files too
+                    workspace_edit: Some(WorkspaceEditClientCapabilities {
+                        document_changes: Some(true),
+                        resource_operations: Some(vec![
+                            ResourceOperationKind::Create,
+                            ResourceOperationKind::Rename,
+                            ResourceOperationKind::Delete,
+                        ]),
+                        ..Default::default()
+                    }),
                     ..Default::default()
                 }),
                 window: Some(WindowClientCapabilities {
@@ -123,37 +349,44 @@ impl RustAnalyzerConnection {
                 }),
                 ..Default::default()
             },
+            initialization_options: self.options.initialization_options.clone(),
             ..Default::default()
         };
         
-        let mut server = self.server.lock().await;
-        let _response = server.initialize(initialize_params).await?;
-        server.initialized(InitializedParams {})?;
+        let _response = self.request(|server| server.initialize(initialize_params)).await?;
+        self.server.clone().initialized(InitializedParams {})?;
         
         Ok(())
     }
     
     async fn wait_until_ready(&self) -> Result<()> {
-        info!("Waiting for rust-analyzer to be ready...");
-        
-        // For rust-analyzer, we need to wait for it to index the project
-        // The best approach is to wait a reasonable amount of time based on project size
-        // For test projects, 2-3 seconds should be enough
-        
-        // In a production system, we could:
-        // 1. Use workspace/symbol requests to check if symbols are indexed
-        // 2. Monitor progress notifications from the server
-        // 3. Use experimental/serverStatus if available
-        
-        // For now, use a simple time-based approach
-        let wait_time = if cfg!(test) {
-            Duration::from_secs(3) // Longer wait in tests
-        } else {
-            Duration::from_secs(2) // Normal wait
-        };
+        info!("Waiting for rust-analyzer to finish indexing...");
         
-        info!("Waiting {:?} for rust-analyzer to initialize...", wait_time);
-        tokio::time::sleep(wait_time).await;
+        // rust-analyzer reports indexing through `$/progress`; ClientState folds
+        // those notifications into the watched ProgressState. One that does not
+        // index, as with cache priming off, is ready once it has been quiet a while.
+        let mut progress = self.progress.clone();
+        loop {
+            let quiet_remaining = {
+                let state = progress.borrow_and_update();
+                if state.is_ready() {
+                    break;
+                }
+                state.quiet_remaining()
+            };
+            let quiet = async {
+                match quiet_remaining {
+                    Some(remaining) => tokio::time::sleep(remaining).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = progress.changed() => {}
+                _ = quiet => {}
+                reason = self.wait_for_exit() => bail!("rust-analyzer exited before it finished indexing: {}", reason),
+            }
+        }
+        info!("rust-analyzer finished indexing");
         
         *self.is_ready.write().await = true;
         info!("rust-analyzer marked as ready");
@@ -204,8 +437,7 @@ impl RustAnalyzerConnection {
             },
         };
         
-        let mut server = self.server.lock().await;
-        server.did_open(params)?;
+        self.server.clone().did_open(params)?;
         
         // Mark file as opened
         opened.insert(canonical_path);
@@ -213,8 +445,34 @@ impl RustAnalyzerConnection {
         Ok(())
     }
     
+    /// Send a hover request for an exact LSP position
+    async fn hover_position(&self, file: &Path, position: Position) -> Result<Option<String>> {
+        // Make the path absolute if it's relative
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+        
+        let response = self.request_with_retry("hover", |server| server.hover(params.clone())).await?;
+        Ok(response.map(|hover| format_hover_content(&hover)))
+    }
+    
     /// Find the position of a symbol in a file starting from the given line
-    async fn find_symbol_position(&self, file: &Path, line: u32, symbol: &str) -> Result<Position> {
+    async fn find_symbol_position(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Position> {
         let contents = tokio::fs::read_to_string(file)
             .await
             .context("Failed to read file")?;
@@ -228,55 +486,35 @@ impl RustAnalyzerConnection {
             bail!("Line {} is out of bounds (file has {} lines)", line, lines.len());
         }
         
-        // Search for the symbol in the specified line
-        if let Some(char_index) = lines[line_index].find(symbol) {
-            Ok(Position {
-                line: line_index as u32,
-                character: char_index as u32,
-            })
-        } else {
-            // If not found on the exact line, search nearby lines
-            for offset in 1..=2 {
-                // Check lines before
-                if line_index >= offset {
-                    let check_line = line_index - offset;
-                    if let Some(char_index) = lines[check_line].find(symbol) {
-                        return Ok(Position {
-                            line: check_line as u32,
-                            character: char_index as u32,
-                        });
-                    }
-                }
-                
-                // Check lines after
-                let check_line = line_index + offset;
-                if check_line < lines.len() {
-                    if let Some(char_index) = lines[check_line].find(symbol) {
-                        return Ok(Position {
-                            line: check_line as u32,
-                            character: char_index as u32,
-                        });
-                    }
-                }
+        // A column pins down one occurrence, where the line alone could hold several
+        if let Some(column) = column {
+            let character = symbol_at_column(lines[line_index], symbol, column)
+                .with_context(|| format!("Symbol '{}' not found at {}:{}", symbol, line, column))?;
+            return Ok(Position { line: line_index as u32, character });
+        }
+        
+        // Search the specified line first, then nearby lines
+        let mut candidates = vec![line_index];
+        for offset in 1..=2 {
+            candidates.extend(line_index.checked_sub(offset));
+            candidates.push(line_index + offset);
+        }
+        for index in candidates.into_iter().filter(|&index| index < lines.len()) {
+            if let Some(&character) = symbol_columns(lines[index], symbol).first() {
+                return Ok(Position { line: index as u32, character });
             }
-            
-            bail!("Symbol '{}' not found near line {}", symbol, line);
         }
+        
+        bail!("Symbol '{}' not found near line {}", symbol, line);
     }
-}
-
-#[async_trait]
-impl LspConnection for RustAnalyzerConnection {
-    async fn hover(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<String>> {
-        // Ensure server is ready
+    
+    /// Resolve the call hierarchy item for the function named by `symbol`
+    async fn prepare_call_hierarchy(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<CallHierarchyItem> {
         self.ensure_ready().await?;
-        
-        // Ensure file is open
         self.open_file(file).await?;
         
-        let position = self.find_symbol_position(file, line, symbol).await?;
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
         
-        // Make the path absolute if it's relative
         let absolute_path = if file.is_absolute() {
             file.to_path_buf()
         } else {
@@ -286,7 +524,7 @@ impl LspConnection for RustAnalyzerConnection {
         let uri = url::Url::from_file_path(&absolute_path)
             .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
         
-        let params = HoverParams {
+        let params = CallHierarchyPrepareParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier { uri },
                 position,
@@ -296,38 +534,343 @@ impl LspConnection for RustAnalyzerConnection {
             },
         };
         
-        // Retry logic for content modified errors
-        let mut attempts = 0;
-        loop {
-            let mut server = self.server.lock().await;
-            match server.hover(params.clone()).await {
-                Ok(response) => {
-                    if let Some(hover) = response {
-                        let content = format_hover_content(&hover);
-                        return Ok(Some(content));
-                    } else {
-                        return Ok(None);
-                    }
+        let items = self.request_with_retry("call hierarchy", |server| server.prepare_call_hierarchy(params.clone())).await?;
+        items
+            .and_then(|items| items.into_iter().next())
+            .with_context(|| format!("'{}' near line {} is not a function", symbol, line))
+    }
+    
+    /// Convert a call hierarchy item, with the call ranges found in `sites_file`
+    async fn call_from_item(&self, item: CallHierarchyItem, sites_file: &Path, ranges: &[lsp_types::Range]) -> Result<Call> {
+        let file = item.uri.to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+        let path = relative_path(&self.workspace, &file);
+        
+        let contents = tokio::fs::read_to_string(sites_file).await?;
+        let lines: Vec<&str> = contents.lines().collect();
+        let sites_path = relative_path(&self.workspace, sites_file);
+        
+        let sites = ranges
+            .iter()
+            .map(|range| CallSite {
+                path: sites_path.clone(),
+                line: range.start.line + 1,
+                column: range.start.character + 1,
+                text: line_text(&lines, range.start.line as usize),
+            })
+            .collect();
+        
+        Ok(Call {
+            name: item.name,
+            kind: format!("{:?}", item.kind),
+            detail: item.detail,
+            file,
+            path,
+            line: item.selection_range.start.line + 1,
+            column: item.selection_range.start.character + 1,
+            sites,
+            calls: Vec::new(),
+        })
+    }
+    
+    /// The steps of a workspace edit, in the order the server listed them
+    /// The absolute path, contents and syntax tree of `file`
+    async fn file_syntax_tree(&self, file: &Path) -> Result<(PathBuf, String, SyntaxElement)> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        let contents = std::fs::read_to_string(&absolute_path)
+            .with_context(|| format!("Failed to read {:?}", absolute_path))?;
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        let params = ViewSyntaxTreeParams {
+            text_document: TextDocumentIdentifier { uri },
+        };
+        
+        let tree = self.custom_request_with_retry::<ViewSyntaxTree>("viewSyntaxTree", params).await?;
+        
+        let tree = serde_json::from_str(&tree).context("Invalid syntax tree from rust-analyzer")?;
+        Ok((absolute_path, contents, tree))
+    }
+    
+    fn item_view(&self, file: &Path, item: &SyntaxElement, text: String) -> ItemView {
+        ItemView {
+            kind: item.kind.clone(),
+            path: relative_path(&self.workspace, file),
+            start_line: item.start[1] + 1,
+            end_line: item.end[1] + 1,
+            text,
+        }
+    }
+    
+    fn file_changes(&self, edit: WorkspaceEdit) -> Result<Vec<FileChange>> {
+        let paths = |uri: &url::Url| -> Result<(PathBuf, PathBuf)> {
+            let file = uri.to_file_path().map_err(|_| anyhow::anyhow!("Invalid URI: {}", uri))?;
+            let path = relative_path(&self.workspace, &file);
+            Ok((file, path))
+        };
+        let text_edit = |edit: &lsp_types::TextEdit| TextEdit {
+            start_line: edit.range.start.line + 1,
+            start_column: edit.range.start.character + 1,
+            end_line: edit.range.end.line + 1,
+            end_column: edit.range.end.character + 1,
+            new_text: edit.new_text.clone(),
+        };
+        let document_edit = |edit: lsp_types::TextDocumentEdit| -> Result<FileChange> {
+            let (file, path) = paths(&edit.text_document.uri)?;
+            let edits = edit
+                .edits
+                .iter()
+                .map(|e| match e {
+                    OneOf::Left(e) => text_edit(e),
+                    OneOf::Right(annotated) => text_edit(&annotated.text_edit),
+                })
+                .collect();
+            Ok(FileChange::Edit { file, path, edits })
+        };
+        
+        let mut changes = Vec::new();
+        match edit.document_changes {
+            Some(DocumentChanges::Edits(edits)) => {
+                for edit in edits {
+                    changes.push(document_edit(edit)?);
                 }
-                Err(e) if e.to_string().contains("content modified") && attempts < 3 => {
-                    drop(server); // Release the lock
-                    attempts += 1;
-                    info!("Retrying hover request due to content modified error (attempt {})", attempts);
-                    tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+            Some(DocumentChanges::Operations(operations)) => {
+                for operation in operations {
+                    changes.push(match operation {
+                        DocumentChangeOperation::Edit(edit) => document_edit(edit)?,
+                        DocumentChangeOperation::Op(ResourceOp::Create(create)) => {
+                            let (file, path) = paths(&create.uri)?;
+                            FileChange::Create { file, path }
+                        }
+                        DocumentChangeOperation::Op(ResourceOp::Rename(rename)) => {
+                            let (file, path) = paths(&rename.old_uri)?;
+                            let (new_file, new_path) = paths(&rename.new_uri)?;
+                            FileChange::Rename { file, path, new_file, new_path }
+                        }
+                        DocumentChangeOperation::Op(ResourceOp::Delete(delete)) => {
+                            let (file, path) = paths(&delete.uri)?;
+                            FileChange::Delete { file, path }
+                        }
+                    });
+                }
+            }
+            None => {
+                // Servers may still answer with the plain map of edits per file
+                let mut files: Vec<_> = edit.changes.unwrap_or_default().into_iter().collect();
+                files.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+                for (uri, edits) in files {
+                    let (file, path) = paths(&uri)?;
+                    changes.push(FileChange::Edit { file, path, edits: edits.iter().map(text_edit).collect() });
+                }
+            }
+        }
+        Ok(changes)
+    }
+    
+    /// The items at `locations`, each with the source of the item as its code
+    /// The first line and full source of the innermost item of `file` that
+    /// spans 0-based `line`, including its doc comments and attributes; falls
+    /// back to an excerpt when the server lists no such item
+    async fn item_source(&self, file: &Path, lines: &[&str], line: u32) -> (u32, String) {
+        let outline = self.document_symbols(file).await.unwrap_or_default();
+        match enclosing_item(&outline, line + 1) {
+            Some(item) => (item.start_line, span(lines, item.start_line, item.end_line)),
+            None => (line + 1, item_excerpt(lines, line as usize, DEFAULT_EXCERPT_LINES)),
+        }
+    }
+    
+    async fn item_definitions(&self, locations: Vec<lsp_types::Location>) -> Result<Vec<Definition>> {
+        let mut definitions = Vec::new();
+        for location in locations {
+            let item_path = location.uri.to_file_path()
+                .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+            let contents = tokio::fs::read_to_string(&item_path).await?;
+            let lines: Vec<&str> = contents.lines().collect();
+            let (code_start_line, code) = self.item_source(&item_path, &lines, location.range.start.line).await;
+            
+            definitions.push(Definition {
+                path: relative_path(&self.workspace, &item_path),
+                file: item_path.clone(),
+                start_line: location.range.start.line + 1,
+                start_column: location.range.start.character + 1,
+                end_line: location.range.end.line + 1,
+                end_column: location.range.end.character + 1,
+                code_start_line,
+                code,
+                anchor: Anchor::at(&lines, location.range.start.line + 1, location.range.start.character + 1),
+                url: None,
+            });
+        }
+        Ok(definitions)
+    }
+    
+    fn symbol_match(
+        &self,
+        name: String,
+        kind: lsp_types::SymbolKind,
+        container: Option<String>,
+        location: lsp_types::Location,
+    ) -> Result<SymbolMatch> {
+        let file = location.uri.to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+        Ok(SymbolMatch {
+            name,
+            kind: format!("{:?}", kind),
+            container,
+            path: relative_path(&self.workspace, &file),
+            file,
+            line: location.range.start.line + 1,
+            column: location.range.start.character + 1,
+            url: None,
+        })
+    }
+    
+    fn type_relation(&self, item: TypeHierarchyItem) -> Result<TypeRelation> {
+        let file = item.uri.to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+        Ok(TypeRelation {
+            name: item.name,
+            path: relative_path(&self.workspace, &file),
+            line: item.selection_range.start.line + 1,
+        })
+    }
+    
+    /// Derive a type hierarchy from goto-implementation results, for servers
+    /// without type hierarchy support (rust-analyzer among them)
+    async fn type_hierarchy_from_impls(&self, params: TextDocumentPositionParams, symbol: &str) -> Result<TypeHierarchy> {
+        let goto_params = GotoDefinitionParams {
+            text_document_position_params: params,
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        
+        let implementations = self.request_with_retry("implementation", |server| server.implementation(goto_params.clone())).await?;
+        let definition = self.request(|server| server.definition(goto_params.clone())).await?;
+        
+        let query_file = goto_params.text_document_position_params.text_document.uri.to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+        let mut hierarchy = TypeHierarchy {
+            name: symbol.to_string(),
+            path: relative_path(&self.workspace, &query_file),
+            line: goto_params.text_document_position_params.position.line + 1,
+            supertypes: Vec::new(),
+            subtypes: Vec::new(),
+        };
+        
+        // Supertraits are spelled out on the trait's declaration line
+        if let Some(declaration) = definition.map(response_locations).and_then(|l| l.into_iter().next()) {
+            let file = declaration.uri.to_file_path()
+                .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+            let contents = tokio::fs::read_to_string(&file).await?;
+            let path = relative_path(&self.workspace, &file);
+            hierarchy.path = path.clone();
+            hierarchy.line = declaration.range.start.line + 1;
+            if let Some(text) = contents.lines().nth(declaration.range.start.line as usize) {
+                for name in supertraits(text, symbol) {
+                    hierarchy.supertypes.push(TypeRelation { name, path: path.clone(), line: hierarchy.line });
+                }
+            }
+        }
+        
+        for location in implementations.map(response_locations).unwrap_or_default() {
+            let file = location.uri.to_file_path()
+                .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+            let contents = tokio::fs::read_to_string(&file).await?;
+            let Some(text) = contents.lines().nth(location.range.start.line as usize) else {
+                continue;
+            };
+            let relation = |name: String| TypeRelation {
+                name,
+                path: relative_path(&self.workspace, &file),
+                line: location.range.start.line + 1,
+            };
+            
+            if text.trim_start().starts_with("#[derive") {
+                // The location names the derived trait itself
+                let start = location.range.start.character as usize;
+                let end = location.range.end.character as usize;
+                let derived: String = text.chars().skip(start).take(end.saturating_sub(start)).collect();
+                hierarchy.supertypes.push(relation(derived));
+                continue;
+            }
+            
+            if let Some(ImplHeader { trait_name: Some(trait_name), self_type }) = parse_impl_header(text) {
+                if last_segment(&trait_name) == symbol {
+                    hierarchy.subtypes.push(relation(self_type));
+                } else {
+                    hierarchy.supertypes.push(relation(trait_name));
                 }
-                Err(e) => return Err(e.into()),
             }
         }
+        
+        Ok(hierarchy)
+    }
+}
+
+#[async_trait]
+impl LspConnection for RustAnalyzerConnection {
+    async fn hover(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Option<String>> {
+        // Ensure server is ready
+        self.ensure_ready().await?;
+        
+        // Ensure file is open
+        self.open_file(file).await?;
+        
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
+        self.hover_position(file, position).await
+    }
+    
+    async fn hover_at(&self, file: &Path, line: u32, column: u32) -> Result<Option<String>> {
+        // Ensure server is ready
+        self.ensure_ready().await?;
+        
+        // Ensure file is open
+        self.open_file(file).await?;
+        
+        let position = Position {
+            line: line.saturating_sub(1),
+            character: column.saturating_sub(1),
+        };
+        self.hover_position(file, position).await
+    }
+    
+    async fn hover_range(&self, file: &Path, start: (u32, u32), end: (u32, u32)) -> Result<Option<String>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let uri = url::Url::from_file_path(file)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", file))?;
+        let params = HoverRangeParams {
+            text_document: TextDocumentIdentifier { uri },
+            // The end column is inclusive, so 1-based it is the exclusive 0-based one
+            position: Range {
+                start: Position { line: start.0.saturating_sub(1), character: start.1.saturating_sub(1) },
+                end: Position { line: end.0.saturating_sub(1), character: end.1 },
+            },
+        };
+        
+        let hover = self.custom_request_with_retry::<HoverRange>("hover range", params).await?;
+        Ok(hover.map(|hover| format_hover_content(&hover)))
     }
     
-    async fn implementation(&self, file: &Path, line: u32, symbol: &str) -> Result<Option<String>> {
+    async fn definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
         // Ensure server is ready
         self.ensure_ready().await?;
         
         // Ensure file is open
         self.open_file(file).await?;
         
-        let position = self.find_symbol_position(file, line, symbol).await?;
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
         
         // Make the path absolute if it's relative
         let absolute_path = if file.is_absolute() {
@@ -350,52 +893,42 @@ impl LspConnection for RustAnalyzerConnection {
             partial_result_params: Default::default(),
         };
         
-        let mut server = self.server.lock().await;
+        let response = self.request(|server| server.definition(params)).await?;
         
-        // Try to get the definition first
-        let response = server.definition(params).await?;
-        
-        if let Some(GotoDefinitionResponse::Scalar(location)) = response {
-            // Read the implementation from the file
-            let impl_path = location.uri.to_file_path()
+        let mut definitions = Vec::new();
+        for location in response.map(response_locations).unwrap_or_default() {
+            // Read the definition from the file
+            let def_path = location.uri.to_file_path()
                 .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
-            let contents = tokio::fs::read_to_string(&impl_path).await?;
+            let contents = tokio::fs::read_to_string(&def_path).await?;
             let lines: Vec<&str> = contents.lines().collect();
             
-            let start_line = location.range.start.line as usize;
-            let end_line = location.range.end.line as usize;
-            
-            // Extract more context around the definition
-            let context_start = start_line.saturating_sub(1);
-            let context_end = (end_line + 10).min(lines.len() - 1);
-            
-            let impl_lines: Vec<String> = lines[context_start..=context_end]
-                .iter()
-                .map(|s| s.to_string())
-                .collect();
-            
-            let relative_path = impl_path.strip_prefix(&self.workspace).unwrap_or(&impl_path);
+            let (code_start_line, code) = self.item_source(&def_path, &lines, location.range.start.line).await;
             
-            Ok(Some(format!(
-                "{}:{}:{}:\n```rust\n{}\n```",
-                relative_path.display(),
-                location.range.start.line + 1,
-                location.range.end.line + 1,
-                impl_lines.join("\n")
-            )))
-        } else {
-            Ok(None)
+            definitions.push(Definition {
+                file: def_path.clone(),
+                path: relative_path(&self.workspace, &def_path),
+                start_line: location.range.start.line + 1,
+                start_column: location.range.start.character + 1,
+                end_line: location.range.end.line + 1,
+                end_column: location.range.end.character + 1,
+                code_start_line,
+                code,
+                anchor: Anchor::at(&lines, location.range.start.line + 1, location.range.start.character + 1),
+                url: None,
+            });
         }
+        Ok(definitions)
     }
     
-    async fn references(&self, file: &Path, line: u32, symbol: &str) -> Result<Vec<String>> {
+    async fn references(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Reference>> {
         // Ensure server is ready
         self.ensure_ready().await?;
         
         // Ensure file is open
         self.open_file(file).await?;
         
-        let position = self.find_symbol_position(file, line, symbol).await?;
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
         
         // Make the path absolute if it's relative
         let absolute_path = if file.is_absolute() {
@@ -421,51 +954,41 @@ impl LspConnection for RustAnalyzerConnection {
             },
         };
         
-        // Retry logic for content modified errors
-        let mut attempts = 0;
-        loop {
-            let mut server = self.server.lock().await;
-            match server.references(params.clone()).await {
-                Ok(response) => {
-                    drop(server); // Release lock before doing I/O
-                    
-                    let mut results = Vec::new();
-                    
-                    if let Some(locations) = response {
-                        for location in locations {
-                            let ref_path = location.uri.to_file_path()
-                                .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
-                            
-                            let relative_path = ref_path.strip_prefix(&self.workspace).unwrap_or(&ref_path);
-                            
-                            // Read the line to show context
-                            let contents = tokio::fs::read_to_string(&ref_path).await?;
-                            let lines: Vec<&str> = contents.lines().collect();
-                            let line_num = location.range.start.line as usize;
-                            
-                            if line_num < lines.len() {
-                                let line_content = lines[line_num].trim();
-                                results.push(format!(
-                                    "{}:{}: {}",
-                                    relative_path.display(),
-                                    line_num + 1,
-                                    line_content
-                                ));
-                            }
-                        }
-                    }
-                    
-                    return Ok(results);
-                }
-                Err(e) if e.to_string().contains("content modified") && attempts < 3 => {
-                    drop(server); // Release the lock
-                    attempts += 1;
-                    info!("Retrying references request due to content modified error (attempt {})", attempts);
-                    tokio::time::sleep(Duration::from_millis(500)).await;
+        let response = self.request_with_retry("references", |server| server.references(params.clone())).await?;
+        let mut results = Vec::new();
+        
+        if let Some(locations) = response {
+            for location in locations {
+                let ref_path = location.uri.to_file_path()
+                    .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+                
+                // Read the line to show context
+                let contents = tokio::fs::read_to_string(&ref_path).await?;
+                let lines: Vec<&str> = contents.lines().collect();
+                let line_num = location.range.start.line as usize;
+                
+                if line_num < lines.len() {
+                    let macro_generated = !range_names_symbol(lines[line_num], &location.range, symbol);
+                    results.push(Reference {
+                        file: ref_path.clone(),
+                        path: relative_path(&self.workspace, &ref_path),
+                        line: line_num as u32 + 1,
+                        column: location.range.start.character + 1,
+                        end_line: location.range.end.line + 1,
+                        end_column: location.range.end.character + 1,
+                        text: line_text(&lines, line_num),
+                        occurrences: 1,
+                        macro_generated,
+                        anchor: Anchor::at(&lines, line_num as u32 + 1, location.range.start.character + 1),
+                        url: None,
+                        context_before: Vec::new(),
+                        context_after: Vec::new(),
+                    });
                 }
-                Err(e) => return Err(e.into()),
             }
         }
+        
+        Ok(results)
     }
     
     async fn resolve_symbol(&self, file: &Path, symbol: &str) -> Result<Option<String>> {
@@ -481,7 +1004,7 @@ impl LspConnection for RustAnalyzerConnection {
         // Find the first occurrence of the symbol
         for (line_num, line) in contents.lines().enumerate() {
             if line.contains(symbol) {
-                if let Ok(Some(hover)) = self.hover(file, (line_num + 1) as u32, symbol).await {
+                if let Ok(Some(hover)) = self.hover(file, (line_num + 1) as u32, None, symbol).await {
                     return Ok(Some(format!(
                         "Found symbol `{}` in {}:\n\n{}",
                         symbol,
@@ -494,17 +1017,644 @@ impl LspConnection for RustAnalyzerConnection {
         
         Ok(None)
     }
-}
-
-fn find_rust_analyzer() -> Result<PathBuf> {
-    // Try to find rust-analyzer in PATH
-    if let Ok(output) = std::process::Command::new("which")
-        .arg("rust-analyzer")
-        .output()
-    {
-        if output.status.success() {
-            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            return Ok(PathBuf::from(path));
+    
+    async fn incoming_calls(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Call>> {
+        let item = self.prepare_call_hierarchy(file, line, column, symbol).await?;
+        
+        let params = CallHierarchyIncomingCallsParams {
+            item,
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        let response = self.request(|server| server.incoming_calls(params)).await?;
+        
+        let mut calls = Vec::new();
+        for incoming in response.unwrap_or_default() {
+            // Call sites lie inside the caller
+            let caller_file = incoming.from.uri.to_file_path()
+                .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+            calls.push(self.call_from_item(incoming.from, &caller_file, &incoming.from_ranges).await?);
+        }
+        
+        Ok(calls)
+    }
+    
+    async fn outgoing_calls(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Call>> {
+        let item = self.prepare_call_hierarchy(file, line, column, symbol).await?;
+        // Call sites lie inside the function we started from
+        let caller_file = item.uri.to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid URI"))?;
+        
+        let params = CallHierarchyOutgoingCallsParams {
+            item,
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        let response = self.request(|server| server.outgoing_calls(params)).await?;
+        
+        let mut calls = Vec::new();
+        for outgoing in response.unwrap_or_default() {
+            calls.push(self.call_from_item(outgoing.to, &caller_file, &outgoing.from_ranges).await?);
+        }
+        
+        Ok(calls)
+    }
+    
+    async fn document_symbols(&self, file: &Path) -> Result<Vec<OutlineItem>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri },
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        
+        let response = self.request_with_retry("document symbol", |server| server.document_symbol(params.clone())).await?;
+        Ok(match response {
+            Some(DocumentSymbolResponse::Nested(symbols)) => symbols.into_iter().map(outline_item).collect(),
+            // Servers without hierarchical support report a flat list
+            #[allow(deprecated)]
+            Some(DocumentSymbolResponse::Flat(symbols)) => symbols
+                .into_iter()
+                .map(|symbol| OutlineItem {
+                    name: symbol.name,
+                    kind: format!("{:?}", symbol.kind),
+                    detail: symbol.container_name,
+                    start_line: symbol.location.range.start.line + 1,
+                    end_line: symbol.location.range.end.line + 1,
+                    children: Vec::new(),
+                })
+                .collect(),
+            None => Vec::new(),
+        })
+    }
+    
+    async fn implementations(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        
+        let response = self.request_with_retry("implementation", |server| server.implementation(params.clone())).await?;
+        
+        self.item_definitions(response.map(response_locations).unwrap_or_default()).await
+    }
+    
+    async fn type_definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        
+        let response = self.request_with_retry("type definition", |server| server.type_definition(params.clone())).await?;
+        
+        self.item_definitions(response.map(response_locations).unwrap_or_default()).await
+    }
+    
+    async fn rename(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str, new_name: &str) -> Result<Vec<FileChange>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position,
+            },
+            new_name: new_name.to_string(),
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+        
+        let response = self.request_with_retry("rename", |server| server.rename(params.clone())).await?;
+        
+        match response {
+            Some(edit) => self.file_changes(edit),
+            None => Ok(Vec::new()),
+        }
+    }
+    
+    async fn code_actions(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Vec<CodeAction>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        // Which actions apply depends on the selection: the symbol or column as a cursor, or the whole line
+        let range = match (symbol, column) {
+            (Some(symbol), _) => {
+                let position = self.find_symbol_position(file, line, column, symbol).await?;
+                lsp_types::Range { start: position, end: position }
+            }
+            (None, Some(column)) => {
+                let position = Position { line: line - 1, character: column.saturating_sub(1) };
+                lsp_types::Range { start: position, end: position }
+            }
+            (None, None) => {
+                let contents = std::fs::read_to_string(&absolute_path)?;
+                let text = contents.lines().nth(line.saturating_sub(1) as usize).unwrap_or_default();
+                lsp_types::Range {
+                    start: Position { line: line - 1, character: 0 },
+                    end: Position { line: line - 1, character: text.encode_utf16().count() as u32 },
+                }
+            }
+        };
+        // Quick fixes for compiler diagnostics are matched against the diagnostics sent along
+        let canonical_path = absolute_path.canonicalize().unwrap_or_else(|_| absolute_path.clone());
+        let diagnostics = self.diagnostics.borrow().published_on_line(&canonical_path, line - 1);
+        
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier { uri },
+            range,
+            context: CodeActionContext {
+                diagnostics,
+                only: None,
+                trigger_kind: None,
+            },
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        
+        let response = self.request_with_retry("code action", |server| server.code_action(params.clone())).await?;
+        
+        let mut actions = Vec::new();
+        for (index, action) in response.unwrap_or_default().into_iter().enumerate() {
+            actions.push(match action {
+                CodeActionOrCommand::CodeAction(action) => CodeAction {
+                    index: index + 1,
+                    title: action.title,
+                    kind: action.kind.map(|kind| kind.as_str().to_string()),
+                    preferred: action.is_preferred.unwrap_or(false),
+                    changes: action.edit.map(|edit| self.file_changes(edit)).transpose()?.unwrap_or_default(),
+                },
+                CodeActionOrCommand::Command(command) => CodeAction {
+                    index: index + 1,
+                    title: command.title,
+                    kind: None,
+                    preferred: false,
+                    changes: Vec::new(),
+                },
+            });
+        }
+        Ok(actions)
+    }
+    
+    async fn expand_macro(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Option<MacroExpansion>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let position = match (symbol, column) {
+            (Some(symbol), _) => self.find_symbol_position(file, line, column, symbol).await?,
+            (None, Some(column)) => Position { line: line - 1, character: column.saturating_sub(1) },
+            (None, None) => {
+                let contents = std::fs::read_to_string(&absolute_path)?;
+                let text = contents.lines().nth(line.saturating_sub(1) as usize).unwrap_or_default();
+                let Some(character) = macro_call_column(text) else {
+                    return Ok(None);
+                };
+                Position { line: line - 1, character }
+            }
+        };
+        
+        let params = ExpandMacroParams {
+            text_document: TextDocumentIdentifier { uri },
+            position,
+        };
+        
+        let expanded = self.custom_request_with_retry::<ExpandMacro>("expand macro", params).await?;
+        
+        Ok(expanded.map(|expanded| MacroExpansion {
+            name: expanded.name,
+            path: relative_path(&self.workspace, &absolute_path),
+            line: position.line + 1,
+            expansion: expanded.expansion,
+        }))
+    }
+    
+    async fn view_hir(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        let (absolute_path, contents, tree) = self.file_syntax_tree(file).await?;
+        let Some((item, body)) = tree.items_at(line - 1).into_iter().rev().find_map(|item| Some((item, item.body_offset()?))) else {
+            return Ok(None);
+        };
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: position_at(&contents, body),
+        };
+        
+        let hir = self.custom_request_with_retry::<ViewHir>("viewHir", params).await?;
+        
+        if hir == NOT_LOWERABLE {
+            return Ok(None);
+        }
+        Ok(Some(self.item_view(&absolute_path, item, hir)))
+    }
+    
+    async fn syntax_tree(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        let (absolute_path, contents, tree) = self.file_syntax_tree(file).await?;
+        Ok(tree.items_at(line - 1).last().map(|item| self.item_view(&absolute_path, item, item.render(&contents))))
+    }
+    
+    async fn runnables(&self, file: &Path) -> Result<Vec<Runnable>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = RunnablesParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: None,
+        };
+        
+        let runnables = self.custom_request_with_retry::<Runnables>("runnables", params).await?;
+        
+        Ok(runnables.into_iter().map(|runnable| runnable.into_runnable(&self.workspace)).collect())
+    }
+    
+    async fn structural_replace(&self, rule: &str, file: Option<&Path>, lines: Option<(u32, u32)>) -> Result<Vec<FileChange>> {
+        self.ensure_ready().await?;
+        
+        let absolute_path = match file {
+            Some(file) if file.is_absolute() => file.to_path_buf(),
+            Some(file) => std::env::current_dir()?.join(file),
+            None => default_context(&self.workspace).context("No Rust files in the workspace")?,
+        };
+        self.open_file(&absolute_path).await?;
+        
+        let selections = match file {
+            Some(_) => {
+                let contents = std::fs::read_to_string(&absolute_path)
+                    .with_context(|| format!("Failed to read {:?}", absolute_path))?;
+                vec![line_selection(&contents, lines)]
+            }
+            None => Vec::new(),
+        };
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        let params = SsrParams {
+            query: rule.to_string(),
+            parse_only: false,
+            position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: selections.first().map_or(Position::default(), |selection| selection.start),
+            },
+            selections,
+        };
+        
+        let edit = self.custom_request_with_retry::<Ssr>("ssr", params).await?;
+        
+        self.file_changes(edit)
+    }
+    
+    async fn inlay_hints(&self, file: &Path, lines: Option<(u32, u32)>) -> Result<Vec<InlayHint>> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        
+        // The range ends at the start of the line after the last one wanted
+        let (start_line, end_line) = match lines {
+            Some(lines) => lines,
+            None => (1, std::fs::read_to_string(&absolute_path)?.lines().count() as u32),
+        };
+        let params = InlayHintParams {
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            text_document: TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: Position { line: start_line.saturating_sub(1), character: 0 },
+                end: Position { line: end_line, character: 0 },
+            },
+        };
+        
+        let response = self.request_with_retry("inlay hint", |server| server.inlay_hint(params.clone())).await?;
+        
+        let mut hints: Vec<InlayHint> = response
+            .unwrap_or_default()
+            .into_iter()
+            .map(|hint| InlayHint {
+                line: hint.position.line + 1,
+                column: hint.position.character + 1,
+                label: match hint.label {
+                    InlayHintLabel::String(label) => label,
+                    InlayHintLabel::LabelParts(parts) => parts.into_iter().map(|part| part.value).collect(),
+                },
+                kind: match hint.kind {
+                    Some(lsp_types::InlayHintKind::TYPE) => InlayHintKind::Type,
+                    Some(lsp_types::InlayHintKind::PARAMETER) => InlayHintKind::Parameter,
+                    _ => InlayHintKind::Other,
+                },
+                padding_left: hint.padding_left.unwrap_or(false),
+                padding_right: hint.padding_right.unwrap_or(false),
+            })
+            .collect();
+        hints.sort_by_key(|hint| (hint.line, hint.column));
+        Ok(hints)
+    }
+    
+    async fn workspace_symbols(&self, query: &str) -> Result<Vec<SymbolMatch>> {
+        self.ensure_ready().await?;
+        
+        let params = WorkspaceSymbolParams {
+            // rust-analyzer searches only types unless the query ends in `#`
+            query: format!("{}#", query.trim_end_matches('#')),
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        };
+        
+        let response = self.request_with_retry("workspace symbol", |server| server.symbol(params.clone())).await?;
+        
+        let mut symbols = Vec::new();
+        match response {
+            #[allow(deprecated)]
+            Some(WorkspaceSymbolResponse::Flat(found)) => {
+                for symbol in found {
+                    symbols.push(self.symbol_match(symbol.name, symbol.kind, symbol.container_name, symbol.location)?);
+                }
+            }
+            Some(WorkspaceSymbolResponse::Nested(found)) => {
+                for symbol in found {
+                    // Locations without a range would need a resolve round trip; skip them
+                    if let lsp_types::OneOf::Left(location) = symbol.location {
+                        symbols.push(self.symbol_match(symbol.name, symbol.kind, symbol.container_name, location)?);
+                    }
+                }
+            }
+            None => {}
+        }
+        
+        Ok(symbols)
+    }
+    
+    async fn diagnostics(&self, file: Option<&Path>) -> Result<Vec<Diagnostic>> {
+        self.ensure_ready().await?;
+        
+        let Some(file) = file else {
+            return Ok(self.diagnostics.borrow().diagnostics(None, &self.workspace));
+        };
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        let canonical_path = absolute_path.canonicalize()
+            .unwrap_or_else(|_| absolute_path.clone());
+        
+        // rust-analyzer publishes its own diagnostics only for open files, shortly after opening
+        let newly_opened = !self.opened_files.lock().await.contains(&canonical_path);
+        self.open_file(&canonical_path).await?;
+        if newly_opened {
+            let mut diagnostics = self.diagnostics.clone();
+            let _ = tokio::time::timeout(
+                Duration::from_secs(5),
+                diagnostics.wait_for(|state| state.has_file(&canonical_path)),
+            ).await;
+        }
+        
+        Ok(self.diagnostics.borrow().diagnostics(Some(&canonical_path), &self.workspace))
+    }
+    
+    async fn type_hierarchy(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<TypeHierarchy> {
+        self.ensure_ready().await?;
+        self.open_file(file).await?;
+        
+        let position = self.find_symbol_position(file, line, column, symbol).await?;
+        
+        let absolute_path = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(file)
+        };
+        
+        let uri = url::Url::from_file_path(&absolute_path)
+            .map_err(|_| anyhow::anyhow!("Invalid file path: {:?}", absolute_path))?;
+        let text_document_position = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position,
+        };
+        
+        let params = TypeHierarchyPrepareParams {
+            text_document_position_params: text_document_position.clone(),
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+        let prepared = self.request(|server| server.prepare_type_hierarchy(params)).await;
+        
+        let item = match prepared {
+            Ok(items) => items
+                .and_then(|items| items.into_iter().next())
+                .with_context(|| format!("'{}' near line {} is not a type", symbol, line))?,
+            Err(async_lsp::Error::Response(e)) if e.code == ErrorCode::METHOD_NOT_FOUND => {
+                return self.type_hierarchy_from_impls(text_document_position, symbol).await;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        
+        let supertypes = self.request(|server| server.supertypes(TypeHierarchySupertypesParams {
+            item: item.clone(),
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        })).await?;
+        let subtypes = self.request(|server| server.subtypes(TypeHierarchySubtypesParams {
+            item: item.clone(),
+            work_done_progress_params: WorkDoneProgressParams {
+                work_done_token: None,
+            },
+            partial_result_params: Default::default(),
+        })).await?;
+        
+        let root = self.type_relation(item)?;
+        Ok(TypeHierarchy {
+            name: root.name,
+            path: root.path,
+            line: root.line,
+            supertypes: supertypes.unwrap_or_default().into_iter().map(|i| self.type_relation(i)).collect::<Result<_>>()?,
+            subtypes: subtypes.unwrap_or_default().into_iter().map(|i| self.type_relation(i)).collect::<Result<_>>()?,
+        })
+    }
+    
+    fn server_pid(&self) -> Option<u32> {
+        Some(self.child.id())
+    }
+    
+    fn indexing_status(&self) -> IndexingStatus {
+        self.progress.borrow().status()
+    }
+    
+    async fn opened_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.opened_files.lock().await.iter().cloned().collect();
+        files.sort();
+        files
+    }
+    
+    async fn open(&self, file: &Path) -> Result<()> {
+        self.open_file(file).await
+    }
+    
+    async fn wait_for_exit(&self) -> String {
+        let mut exit = self.exit.clone();
+        let reason = match exit.wait_for(Option::is_some).await {
+            Ok(reason) => reason.clone().unwrap_or_default(),
+            Err(_) => "rust-analyzer exited".to_string(),
+        };
+        reason
+    }
+}
+
+fn outline_item(symbol: lsp_types::DocumentSymbol) -> OutlineItem {
+    OutlineItem {
+        name: symbol.name,
+        kind: format!("{:?}", symbol.kind),
+        detail: symbol.detail,
+        start_line: symbol.range.start.line + 1,
+        end_line: symbol.range.end.line + 1,
+        children: symbol.children.unwrap_or_default().into_iter().map(outline_item).collect(),
+    }
+}
+
+/// Flatten the three shapes of a definition-style response into locations
+fn response_locations(response: GotoDefinitionResponse) -> Vec<lsp_types::Location> {
+    match response {
+        GotoDefinitionResponse::Scalar(location) => vec![location],
+        GotoDefinitionResponse::Array(locations) => locations,
+        GotoDefinitionResponse::Link(links) => links
+            .into_iter()
+            .map(|link| lsp_types::Location {
+                uri: link.target_uri,
+                range: link.target_selection_range,
+            })
+            .collect(),
+    }
+}
+
+/// The rust-analyzer executable: the one `LQ_RUST_ANALYZER` names, or else
+/// the one on `PATH` or in a common install location
+pub fn find_rust_analyzer() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os(RUST_ANALYZER_ENV).filter(|path| !path.is_empty()) {
+        let path = PathBuf::from(shellexpand::tilde(&path.to_string_lossy()).as_ref());
+        if !path.exists() {
+            bail!("{} names {:?}, which does not exist", RUST_ANALYZER_ENV, path);
+        }
+        return Ok(path);
+    }
+    
+    // Try to find rust-analyzer in PATH
+    if let Ok(output) = std::process::Command::new("which")
+        .arg("rust-analyzer")
+        .output()
+    {
+        if output.status.success() {
+            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            return Ok(PathBuf::from(path));
         }
     }
     
@@ -527,6 +1677,25 @@ fn find_rust_analyzer() -> Result<PathBuf> {
     bail!("Could not find rust-analyzer. Please ensure it is installed and in your PATH.")
 }
 
+/// Whether the source covered by a reference range spells out the symbol.
+///
+/// References produced by macro expansion point at the macro call or derive
+/// attribute instead, so their range text doesn't name the symbol.
+fn range_names_symbol(line: &str, range: &lsp_types::Range, symbol: &str) -> bool {
+    if range.start.line != range.end.line {
+        return false;
+    }
+    
+    let token: String = line
+        .chars()
+        .skip(range.start.character as usize)
+        .take(range.end.character.saturating_sub(range.start.character) as usize)
+        .collect();
+    
+    // `Self` and `self` are genuine references to the enclosing type or module
+    token.contains(symbol) || token == "Self" || token == "self"
+}
+
 fn format_hover_content(hover: &Hover) -> String {
     use lsp_types::HoverContents;
     use lsp_types::MarkedString;
@@ -534,18 +1703,14 @@ fn format_hover_content(hover: &Hover) -> String {
     match &hover.contents {
         HoverContents::Scalar(marked) => match marked {
             MarkedString::String(s) => s.clone(),
-            MarkedString::LanguageString(ls) => {
-                format!("```{}\n{}\n```", ls.language, ls.value)
-            }
+            MarkedString::LanguageString(ls) => fenced(&ls.language, &ls.value),
         },
         HoverContents::Array(marked_strings) => {
             marked_strings
                 .iter()
                 .map(|ms| match ms {
                     MarkedString::String(s) => s.clone(),
-                    MarkedString::LanguageString(ls) => {
-                        format!("```{}\n{}\n```", ls.language, ls.value)
-                    }
+                    MarkedString::LanguageString(ls) => fenced(&ls.language, &ls.value),
                 })
                 .collect::<Vec<_>>()
                 .join("\n\n")
@@ -556,11 +1721,17 @@ fn format_hover_content(hover: &Hover) -> String {
 
 // Minimal client state to handle LSP notifications
 #[derive(Clone)]
-struct ClientState;
+struct ClientState {
+    progress: Arc<watch::Sender<ProgressState>>,
+    diagnostics: Arc<watch::Sender<DiagnosticsState>>,
+}
 
 impl ClientState {
-    fn new_router() -> Router<Self> {
-        let mut router = Router::new(ClientState);
+    fn new_router(progress: Arc<watch::Sender<ProgressState>>, diagnostics: watch::Sender<DiagnosticsState>) -> Router<Self> {
+        let mut router = Router::new(ClientState {
+            progress,
+            diagnostics: Arc::new(diagnostics),
+        });
         
         router.notification::<lsp_types::notification::ShowMessage>(|_state, _params| {
             ControlFlow::Continue(())
@@ -570,14 +1741,21 @@ impl ClientState {
             ControlFlow::Continue(())
         });
         
-        router.notification::<lsp_types::notification::PublishDiagnostics>(|_state, _params| {
+        router.notification::<lsp_types::notification::PublishDiagnostics>(|state, params| {
+            state.diagnostics.send_modify(|diagnostics| diagnostics.update(params));
             ControlFlow::Continue(())
         });
         
-        router.notification::<lsp_types::notification::Progress>(|_state, _params| {
+        router.notification::<lsp_types::notification::Progress>(|state, params| {
+            state.progress.send_modify(|progress| progress.update(params));
             ControlFlow::Continue(())
         });
         
+        // The server must be allowed to create progress tokens before reporting on them
+        router.request::<lsp_types::request::WorkDoneProgressCreate, _>(|_state, _params| async {
+            Ok(())
+        });
+        
         router
     }
 }
@@ -601,4 +1779,4 @@ impl async_lsp::LanguageClient for ClientState {
     fn show_message(&mut self, _params: lsp_types::ShowMessageParams) -> Self::NotifyResult {
         ControlFlow::Continue(())
     }
-}
\ No newline at end of file
+}
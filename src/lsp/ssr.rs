@@ -0,0 +1,90 @@
+//! rust-analyzer's `experimental/ssr` extension request.
+//!
+//! Structural search and replace takes a rule such as
+//! `foo($a, $b) ==>> bar($b, $a)` and rewrites every match of the pattern,
+//! comparing syntax trees and resolved paths rather than text: `foo` matches
+//! however it is imported, and method calls match their UFCS form. Paths in the
+//! rule resolve from a context file, the crate root unless the search is
+//! confined to one file. The server returns the rewrite as a workspace edit.
+
+use std::path::{Path, PathBuf};
+
+use lsp_types::request::Request;
+use lsp_types::{Position, Range, TextDocumentPositionParams, WorkspaceEdit};
+use serde::{Deserialize, Serialize};
+
+use crate::core::workspace::rust_source_files;
+
+pub enum Ssr {}
+
+impl Request for Ssr {
+    type Params = SsrParams;
+    type Result = WorkspaceEdit;
+    const METHOD: &'static str = "experimental/ssr";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsrParams {
+    pub query: String,
+    /// Only check that the rule parses
+    pub parse_only: bool,
+    /// Where paths in the rule resolve from
+    #[serde(flatten)]
+    pub position: TextDocumentPositionParams,
+    /// Only matches within these ranges of the context file; the whole workspace when empty
+    pub selections: Vec<Range>,
+}
+
+/// The file paths in a rule resolve from when no file is given: the crate root,
+/// or the first Rust file of a workspace without one
+pub fn default_context(workspace: &Path) -> Option<PathBuf> {
+    ["src/lib.rs", "src/main.rs"]
+        .iter()
+        .map(|root| workspace.join(root))
+        .find(|root| root.is_file())
+        .or_else(|| rust_source_files(workspace).into_iter().next())
+}
+
+/// The range covering 1-based lines `start..=end` of `contents`, or all of it
+pub fn line_selection(contents: &str, lines: Option<(u32, u32)>) -> Range {
+    let line_count = contents.lines().count().max(1) as u32;
+    let (start, end) = lines.unwrap_or((1, line_count));
+    let end = end.min(line_count);
+    let end_column = contents.lines().nth(end as usize - 1).map_or(0, |line| line.encode_utf16().count() as u32);
+    Range {
+        start: Position { line: start.saturating_sub(1), character: 0 },
+        end: Position { line: end - 1, character: end_column },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_context() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(default_context(dir.path()), None);
+
+        std::fs::create_dir_all(dir.path().join("src/bin")).unwrap();
+        std::fs::write(dir.path().join("src/bin/tool.rs"), "fn main() {}\n").unwrap();
+        assert_eq!(default_context(dir.path()), Some(dir.path().join("src/bin/tool.rs")));
+
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        assert_eq!(default_context(dir.path()), Some(dir.path().join("src/main.rs")));
+    }
+
+    #[test]
+    fn test_line_selection() {
+        let contents = "fn a() {}\nfn b\u{e9}() {}\nfn c() {}";
+        let range = |start: (u32, u32), end: (u32, u32)| Range {
+            start: Position { line: start.0, character: start.1 },
+            end: Position { line: end.0, character: end.1 },
+        };
+        assert_eq!(line_selection(contents, None), range((0, 0), (2, 9)));
+        assert_eq!(line_selection(contents, Some((2, 2))), range((1, 0), (1, 10)));
+        assert_eq!(line_selection(contents, Some((2, 9))), range((1, 0), (2, 9)));
+        assert_eq!(line_selection("", None), range((0, 0), (0, 0)));
+    }
+}
@@ -0,0 +1,339 @@
+//! Restarting the language server when it crashes.
+//!
+//! A crashed server is restarted after a delay that doubles with each failed
+//! attempt, so a deterministic crash (say, a panicking proc macro) does not turn
+//! into a busy loop. While the server is down the daemon is degraded: queries
+//! without a text-search fallback fail with the crash reason, which `lq status`
+//! also shows. After
+//! `max_restarts` failed attempts in a row it stops trying. A server that cannot
+//! start at all, for example because rust-analyzer is not installed, is handled
+//! like one that crashed right away. A restarted server has the files the
+//! crashed one had open opened again, so that it publishes their diagnostics
+//! as before.
+//!
+//! The daemon can also ask for a restart, as when the server uses more memory
+//! than it is allowed: the server is killed and started again right away, and
+//! as the server was not at fault this does not count towards `max_restarts`.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, Signal, System};
+use tracing::{error, info, warn};
+
+use crate::lsp::{
+    Call, CodeAction, Definition, Diagnostic, FileChange, IndexingStatus, InlayHint, ItemView, LspConnection, MacroExpansion, OutlineItem, Reference, Runnable,
+    RustAnalyzerConnection, ServerOptions, SymbolMatch, TypeHierarchy,
+};
+
+/// Delay before the first restart; each further attempt doubles it
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A server that stays up this long has recovered, and later crashes start the backoff over
+const HEALTHY_UPTIME: Duration = Duration::from_secs(300);
+
+/// Whether the language server is up, and if not, why
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerHealth {
+    /// Why the server last stopped, while it is down
+    pub crash_reason: Option<String>,
+    /// Restarts since the server last stayed up for a while
+    pub restart_attempts: u32,
+    /// When the next restart is due; `None` while running or once restarts are exhausted
+    pub next_restart: Option<Instant>,
+    /// Successful restarts since the server first started
+    pub restarts: u32,
+}
+
+impl ServerHealth {
+    pub fn is_degraded(&self) -> bool {
+        self.crash_reason.is_some()
+    }
+}
+
+/// The delay before restart number `attempt`, counting from 0
+pub fn restart_delay(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+struct Supervised {
+    workspace: PathBuf,
+    options: ServerOptions,
+    server: RwLock<Option<Arc<dyn LspConnection>>>,
+    health: Mutex<ServerHealth>,
+    /// Why the daemon asked for the running server to be restarted, until it has stopped
+    restart_requested: Mutex<Option<String>>,
+}
+
+/// A rust-analyzer connection that is restarted with backoff when the server exits
+pub struct SupervisedConnection {
+    inner: Arc<Supervised>,
+}
+
+impl SupervisedConnection {
+    /// Start the server, degraded from the outset if it fails to start
+    pub async fn start(workspace: &Path, options: ServerOptions) -> Self {
+        let inner = Arc::new(Supervised {
+            workspace: workspace.to_path_buf(),
+            options: options.clone(),
+            server: RwLock::new(None),
+            health: Mutex::new(ServerHealth::default()),
+            restart_requested: Mutex::new(None),
+        });
+        match RustAnalyzerConnection::with_options(workspace, options).await {
+            Ok(server) => {
+                let server: Arc<dyn LspConnection> = Arc::new(server);
+                *inner.server.write().unwrap() = Some(server.clone());
+                tokio::spawn(supervise(inner.clone(), Some(server)));
+            }
+            Err(e) => {
+                error!("Language server failed to start: {:#}", e);
+                inner.health.lock().unwrap().crash_reason = Some(format!("{:#}", e));
+                tokio::spawn(supervise(inner.clone(), None));
+            }
+        }
+        Self { inner }
+    }
+
+    fn server(&self) -> Result<Arc<dyn LspConnection>> {
+        if let Some(server) = self.inner.server.read().unwrap().clone() {
+            return Ok(server);
+        }
+        let health = self.inner.health.lock().unwrap().clone();
+        let reason = health.crash_reason.unwrap_or_default();
+        Err(match health.next_restart {
+            Some(_) => anyhow!("Language server unavailable while it restarts: {}", reason),
+            None => anyhow!("Language server unavailable after {} failed restarts: {}", health.restart_attempts, reason),
+        })
+    }
+}
+
+/// Wait for the server to exit, then restart it until one stays up or attempts run out.
+///
+/// Without a running server to start with, restarting begins immediately.
+async fn supervise(inner: Arc<Supervised>, server: Option<Arc<dyn LspConnection>>) {
+    let mut started_at = Instant::now();
+    let mut reason = inner.health.lock().unwrap().crash_reason.clone().unwrap_or_default();
+    let mut server = server;
+    let mut opened = Vec::new();
+    let mut requested = None;
+    loop {
+        if let Some(running) = server.take() {
+            reason = running.wait_for_exit().await;
+            *inner.server.write().unwrap() = None;
+            opened = running.opened_files().await;
+            drop(running);
+            requested = inner.restart_requested.lock().unwrap().take();
+            match &requested {
+                Some(requested) => {
+                    warn!("Language server stopped to restart it: {}", requested);
+                    reason = requested.clone();
+                }
+                None => error!("Language server stopped: {}", reason),
+            }
+
+            if started_at.elapsed() >= HEALTHY_UPTIME {
+                inner.health.lock().unwrap().restart_attempts = 0;
+            }
+        }
+
+        let restarted: Arc<dyn LspConnection> = loop {
+            // A requested restart is tried once straight away, before any backoff
+            if requested.take().is_some() {
+                {
+                    let mut health = inner.health.lock().unwrap();
+                    health.crash_reason = Some(reason.clone());
+                    health.next_restart = Some(Instant::now());
+                }
+                match RustAnalyzerConnection::with_options(&inner.workspace, inner.options.clone()).await {
+                    Ok(restarted) => break Arc::new(restarted),
+                    Err(e) => reason = format!("{:#}", e),
+                }
+            }
+            let attempt = inner.health.lock().unwrap().restart_attempts;
+            if attempt >= inner.options.max_restarts {
+                error!("Giving up on the language server after {} failed restarts", attempt);
+                let mut health = inner.health.lock().unwrap();
+                health.crash_reason = Some(reason);
+                health.next_restart = None;
+                return;
+            }
+
+            let delay = restart_delay(attempt);
+            {
+                let mut health = inner.health.lock().unwrap();
+                health.crash_reason = Some(reason.clone());
+                health.restart_attempts = attempt;
+                health.next_restart = Some(Instant::now() + delay);
+            }
+            warn!("Restarting the language server in {:?} (attempt {} of {})", delay, attempt + 1, inner.options.max_restarts);
+            tokio::time::sleep(delay).await;
+
+            inner.health.lock().unwrap().restart_attempts = attempt + 1;
+            match RustAnalyzerConnection::with_options(&inner.workspace, inner.options.clone()).await {
+                Ok(restarted) => break Arc::new(restarted),
+                Err(e) => reason = format!("{:#}", e),
+            }
+        };
+
+        info!("Language server restarted, reopening {} file(s)", opened.len());
+        for file in opened.drain(..) {
+            if let Err(e) = restarted.open(&file).await {
+                warn!("Failed to reopen {:?} after the restart: {:#}", file, e);
+            }
+        }
+        started_at = Instant::now();
+        *inner.server.write().unwrap() = Some(restarted.clone());
+        {
+            let mut health = inner.health.lock().unwrap();
+            health.crash_reason = None;
+            health.next_restart = None;
+            health.restarts += 1;
+        }
+        server = Some(restarted);
+    }
+}
+
+#[async_trait]
+impl LspConnection for SupervisedConnection {
+    async fn hover(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Option<String>> {
+        self.server()?.hover(file, line, column, symbol).await
+    }
+
+    async fn hover_at(&self, file: &Path, line: u32, column: u32) -> Result<Option<String>> {
+        self.server()?.hover_at(file, line, column).await
+    }
+
+    async fn hover_range(&self, file: &Path, start: (u32, u32), end: (u32, u32)) -> Result<Option<String>> {
+        self.server()?.hover_range(file, start, end).await
+    }
+
+    async fn definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        self.server()?.definition(file, line, column, symbol).await
+    }
+
+    async fn references(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Reference>> {
+        self.server()?.references(file, line, column, symbol).await
+    }
+
+    async fn resolve_symbol(&self, file: &Path, symbol: &str) -> Result<Option<String>> {
+        self.server()?.resolve_symbol(file, symbol).await
+    }
+
+    async fn incoming_calls(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Call>> {
+        self.server()?.incoming_calls(file, line, column, symbol).await
+    }
+
+    async fn outgoing_calls(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Call>> {
+        self.server()?.outgoing_calls(file, line, column, symbol).await
+    }
+
+    async fn document_symbols(&self, file: &Path) -> Result<Vec<OutlineItem>> {
+        self.server()?.document_symbols(file).await
+    }
+
+    async fn implementations(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        self.server()?.implementations(file, line, column, symbol).await
+    }
+
+    async fn type_definition(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<Vec<Definition>> {
+        self.server()?.type_definition(file, line, column, symbol).await
+    }
+
+    async fn rename(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str, new_name: &str) -> Result<Vec<FileChange>> {
+        self.server()?.rename(file, line, column, symbol, new_name).await
+    }
+
+    async fn code_actions(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Vec<CodeAction>> {
+        self.server()?.code_actions(file, line, column, symbol).await
+    }
+
+    async fn expand_macro(&self, file: &Path, line: u32, column: Option<u32>, symbol: Option<&str>) -> Result<Option<MacroExpansion>> {
+        self.server()?.expand_macro(file, line, column, symbol).await
+    }
+
+    async fn view_hir(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        self.server()?.view_hir(file, line).await
+    }
+
+    async fn syntax_tree(&self, file: &Path, line: u32) -> Result<Option<ItemView>> {
+        self.server()?.syntax_tree(file, line).await
+    }
+
+    async fn runnables(&self, file: &Path) -> Result<Vec<Runnable>> {
+        self.server()?.runnables(file).await
+    }
+
+    async fn structural_replace(&self, rule: &str, file: Option<&Path>, lines: Option<(u32, u32)>) -> Result<Vec<FileChange>> {
+        self.server()?.structural_replace(rule, file, lines).await
+    }
+
+    async fn inlay_hints(&self, file: &Path, lines: Option<(u32, u32)>) -> Result<Vec<InlayHint>> {
+        self.server()?.inlay_hints(file, lines).await
+    }
+
+    async fn workspace_symbols(&self, query: &str) -> Result<Vec<SymbolMatch>> {
+        self.server()?.workspace_symbols(query).await
+    }
+
+    async fn diagnostics(&self, file: Option<&Path>) -> Result<Vec<Diagnostic>> {
+        self.server()?.diagnostics(file).await
+    }
+
+    async fn type_hierarchy(&self, file: &Path, line: u32, column: Option<u32>, symbol: &str) -> Result<TypeHierarchy> {
+        self.server()?.type_hierarchy(file, line, column, symbol).await
+    }
+
+    fn server_pid(&self) -> Option<u32> {
+        self.server().ok()?.server_pid()
+    }
+
+    fn indexing_status(&self) -> IndexingStatus {
+        self.server().map(|server| server.indexing_status()).unwrap_or_default()
+    }
+
+    fn health(&self) -> ServerHealth {
+        self.inner.health.lock().unwrap().clone()
+    }
+
+    fn restart(&self, reason: &str) -> bool {
+        let Some(pid) = self.server_pid() else {
+            return false;
+        };
+        *self.inner.restart_requested.lock().unwrap() = Some(reason.to_string());
+        if kill(pid) {
+            return true;
+        }
+        self.inner.restart_requested.lock().unwrap().take();
+        false
+    }
+}
+
+/// Kill process `pid`; false if the signal could not be sent
+fn kill(pid: u32) -> bool {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), ProcessRefreshKind::new());
+    system
+        .process(pid)
+        .and_then(|process| process.kill_with(Signal::Kill))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_delay() {
+        let delays: Vec<u64> = (0..8).map(|attempt| restart_delay(attempt).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(restart_delay(u32::MAX), MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,165 @@
+//! rust-analyzer's `rust-analyzer/viewHir` and `rust-analyzer/viewSyntaxTree`
+//! extension requests.
+//!
+//! `viewSyntaxTree` returns the syntax tree of a whole file as JSON, with each
+//! element's start and end as `[offset, line, column]`; the item at a line is
+//! picked from it and rendered in rust-analyzer's classic indented form, with
+//! byte offsets into the file. `viewHir` lowers the body around the cursor, and
+//! only when the cursor is inside the body, so it is placed just after the start
+//! of the body of the item found in the syntax tree.
+
+use lsp_types::request::Request;
+use lsp_types::{Position, TextDocumentIdentifier, TextDocumentPositionParams};
+use serde::{Deserialize, Serialize};
+
+pub enum ViewHir {}
+
+impl Request for ViewHir {
+    type Params = TextDocumentPositionParams;
+    type Result = String;
+    const METHOD: &'static str = "rust-analyzer/viewHir";
+}
+
+pub enum ViewSyntaxTree {}
+
+impl Request for ViewSyntaxTree {
+    type Params = ViewSyntaxTreeParams;
+    /// A serialized [`SyntaxElement`]
+    type Result = String;
+    const METHOD: &'static str = "rust-analyzer/viewSyntaxTree";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewSyntaxTreeParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// What `viewHir` answers outside a body
+pub const NOT_LOWERABLE: &str = "Not inside a lowerable item";
+
+/// Syntax kinds of items, the elements a line is looked up by
+const ITEM_KINDS: &[&str] = &[
+    "FN", "CONST", "STATIC", "STRUCT", "ENUM", "UNION", "TRAIT", "TRAIT_ALIAS", "IMPL", "MODULE", "TYPE_ALIAS",
+    "MACRO_RULES", "MACRO_DEF", "USE", "EXTERN_CRATE", "EXTERN_BLOCK",
+];
+
+/// A node or token of a syntax tree
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SyntaxElement {
+    /// `Node` or `Token`
+    #[serde(rename = "type")]
+    pub element: String,
+    pub kind: String,
+    /// Byte offset, 0-based line and column of the first character
+    pub start: [u32; 3],
+    /// Byte offset, 0-based line and column just past the last character
+    pub end: [u32; 3],
+    #[serde(default)]
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxElement {
+    /// The items whose lines include the 0-based `line`, outermost first
+    pub fn items_at(&self, line: u32) -> Vec<&SyntaxElement> {
+        let mut items = Vec::new();
+        let mut element = self;
+        // Whitespace tokens span lines too, so only nodes are followed
+        let contains = |child: &&SyntaxElement| child.element == "Node" && child.start[1] <= line && line <= child.end[1];
+        while let Some(child) = element.children.iter().find(contains) {
+            if ITEM_KINDS.contains(&child.kind.as_str()) {
+                items.push(child);
+            }
+            element = child;
+        }
+        items
+    }
+
+    /// The byte offset just inside the body of a function, const or static
+    pub fn body_offset(&self) -> Option<u32> {
+        let body = match self.kind.as_str() {
+            "FN" => self.children.iter().find(|child| child.kind == "BLOCK_EXPR"),
+            "CONST" | "STATIC" => self.children.iter().find(|child| child.element == "Node" && child.kind.ends_with("_EXPR")),
+            _ => None,
+        }?;
+        Some((body.start[0] + 1).min(body.end[0]))
+    }
+
+    /// The element and everything below it, one per line and indented by depth,
+    /// tokens followed by their text in `contents`
+    pub fn render(&self, contents: &str) -> String {
+        let mut out = String::new();
+        self.render_into(contents, 0, &mut out);
+        out
+    }
+
+    fn render_into(&self, contents: &str, depth: usize, out: &mut String) {
+        let (start, end) = (self.start[0] as usize, self.end[0] as usize);
+        out.push_str(&format!("{:indent$}{}@{}..{}", "", self.kind, start, end, indent = depth * 2));
+        if self.element == "Token" {
+            out.push_str(&format!(" {:?}", contents.get(start..end).unwrap_or_default()));
+        }
+        out.push('\n');
+        for child in &self.children {
+            child.render_into(contents, depth + 1, out);
+        }
+    }
+}
+
+/// The LSP position, with a UTF-16 column, of byte `offset` in `contents`
+pub fn position_at(contents: &str, offset: u32) -> Position {
+    let before = contents.get(..offset as usize).unwrap_or(contents);
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "impl S {\n    fn f() { 1 }\n}\n";
+
+    fn tree() -> SyntaxElement {
+        serde_json::from_value(serde_json::json!({
+            "type": "Node", "kind": "SOURCE_FILE", "start": [0, 0, 0], "end": [28, 3, 0],
+            "children": [{
+                "type": "Node", "kind": "IMPL", "start": [0, 0, 0], "end": [27, 2, 1],
+                "children": [{
+                    "type": "Node", "kind": "ASSOC_ITEM_LIST", "start": [7, 0, 7], "end": [27, 2, 1],
+                    "children": [{ "type": "Token", "kind": "WHITESPACE", "start": [8, 0, 8], "end": [13, 1, 4] }, {
+                        "type": "Node", "kind": "FN", "start": [13, 1, 4], "end": [25, 1, 16],
+                        "children": [
+                            { "type": "Token", "kind": "FN_KW", "start": [13, 1, 4], "end": [15, 1, 6] },
+                            { "type": "Node", "kind": "BLOCK_EXPR", "start": [20, 1, 11], "end": [25, 1, 16] }
+                        ]
+                    }]
+                }]
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_items_at() {
+        let tree = tree();
+        let kinds = |line| tree.items_at(line).iter().map(|item| item.kind.as_str()).collect::<Vec<_>>();
+        assert_eq!(kinds(0), ["IMPL"]);
+        assert_eq!(kinds(1), ["IMPL", "FN"]);
+        assert!(kinds(3).is_empty());
+
+        let function = *tree.items_at(1).last().unwrap();
+        assert_eq!(function.body_offset(), Some(21));
+        assert_eq!(position_at(SOURCE, 21), Position { line: 1, character: 12 });
+        assert_eq!(tree.items_at(0)[0].body_offset(), None);
+        assert_eq!(function.render(SOURCE), "FN@13..25\n  FN_KW@13..15 \"fn\"\n  BLOCK_EXPR@20..25\n");
+    }
+
+    #[test]
+    fn test_position_at() {
+        assert_eq!(position_at("a\u{e9}b\nc", 3), Position { line: 0, character: 2 });
+        assert_eq!(position_at("a\u{e9}b\nc", 5), Position { line: 1, character: 0 });
+    }
+}
@@ -0,0 +1,146 @@
+//! Source-text helpers for deriving a type hierarchy from `impl` blocks.
+//!
+//! rust-analyzer does not implement `textDocument/prepareTypeHierarchy`, but
+//! goto-implementation lists the impl blocks and derives of a type or trait.
+//! The source lines at those locations tell which trait links which type.
+
+/// An `impl` header split into the implemented trait and the self type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImplHeader {
+    /// `None` for an inherent impl
+    pub trait_name: Option<String>,
+    pub self_type: String,
+}
+
+/// Parse a line such as `impl<T: Shape> Shape for Box<T> {`
+pub fn parse_impl_header(line: &str) -> Option<ImplHeader> {
+    let code = line.split("//").next().unwrap_or_default().trim();
+    let code = code.strip_prefix("unsafe ").unwrap_or(code);
+    let rest = code.strip_prefix("impl")?;
+    if !rest.starts_with([' ', '<']) {
+        return None;
+    }
+
+    let rest = skip_generics(rest.trim_start()).trim_start();
+    let header = rest
+        .split(['{', ';'])
+        .next()
+        .unwrap_or_default();
+    let header = split_top_level(header, " where ").0.trim();
+
+    let (trait_name, self_type) = match split_top_level(header, " for ") {
+        (trait_name, Some(self_type)) => (Some(trait_name.trim().to_string()), self_type.trim()),
+        (self_type, None) => (None, self_type.trim()),
+    };
+    if self_type.is_empty() {
+        return None;
+    }
+
+    Some(ImplHeader {
+        trait_name,
+        self_type: self_type.to_string(),
+    })
+}
+
+/// The supertraits declared on a `trait symbol: A + B {` line
+pub fn supertraits(line: &str, symbol: &str) -> Vec<String> {
+    let Some(start) = line.find(&format!("trait {}", symbol)) else {
+        return Vec::new();
+    };
+    let rest = skip_generics(line[start + "trait ".len() + symbol.len()..].trim_start());
+    let Some(bounds) = rest.trim_start().strip_prefix(':') else {
+        return Vec::new();
+    };
+    let bounds = bounds.split('{').next().unwrap_or_default();
+    let bounds = split_top_level(bounds, " where ").0;
+
+    bounds
+        .split('+')
+        .map(str::trim)
+        .filter(|bound| !bound.is_empty() && !bound.starts_with('\''))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The final path segment without generic arguments: `std::fmt::Debug` becomes `Debug`
+pub fn last_segment(path: &str) -> &str {
+    let without_generics = path.split('<').next().unwrap_or(path);
+    without_generics.rsplit("::").next().unwrap_or(without_generics).trim()
+}
+
+/// Skip a leading `<...>` generic parameter list, if any
+fn skip_generics(text: &str) -> &str {
+    if !text.starts_with('<') {
+        return text;
+    }
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &text[index + 1..];
+                }
+            }
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// Split at the first `separator` outside angle brackets
+fn split_top_level<'a>(text: &'a str, separator: &str) -> (&'a str, Option<&'a str>) {
+    let mut depth = 0i32;
+    for (index, c) in text.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            _ if depth == 0 && text[index..].starts_with(separator) => {
+                return (&text[..index], Some(&text[index + separator.len()..]));
+            }
+            _ => {}
+        }
+    }
+    (text, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_impl_header() {
+        let header = |line| parse_impl_header(line).map(|h| (h.trait_name, h.self_type));
+        assert_eq!(
+            header("impl Shape for Square {"),
+            Some((Some("Shape".to_string()), "Square".to_string()))
+        );
+        assert_eq!(
+            header("impl<T: Into<f64>> std::fmt::Display for Wrapper<T> where T: Copy {"),
+            Some((Some("std::fmt::Display".to_string()), "Wrapper<T>".to_string()))
+        );
+        assert_eq!(header("    impl Circle {"), Some((None, "Circle".to_string())));
+        assert_eq!(
+            header("unsafe impl Send for Handle {}"),
+            Some((Some("Send".to_string()), "Handle".to_string()))
+        );
+        assert_eq!(header("let implementation = 1;"), None);
+    }
+
+    #[test]
+    fn test_supertraits() {
+        assert_eq!(
+            supertraits("pub trait Shape: std::fmt::Debug + Clone {", "Shape"),
+            vec!["std::fmt::Debug".to_string(), "Clone".to_string()]
+        );
+        assert_eq!(supertraits("trait Parse<'a>: Sized + 'a where Self: Copy {", "Parse"), vec!["Sized".to_string()]);
+        assert!(supertraits("pub trait Plain {", "Plain").is_empty());
+    }
+
+    #[test]
+    fn test_last_segment() {
+        assert_eq!(last_segment("std::fmt::Debug"), "Debug");
+        assert_eq!(last_segment("Into<f64>"), "Into");
+    }
+}
@@ -0,0 +1,700 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::lsp::Anchor;
+
+/// A single reference to a symbol, with the line it occurs on for context
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Reference {
+    /// Absolute path of the file containing the reference
+    pub file: PathBuf,
+    /// Path relative to the workspace root, used for display
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column number
+    pub column: u32,
+    /// 1-based line where the reference ends
+    pub end_line: u32,
+    /// 1-based column where the reference ends
+    pub end_column: u32,
+    /// The trimmed source line containing the reference
+    pub text: String,
+    /// Number of references merged into this one (same file and line)
+    #[serde(default = "default_occurrences")]
+    pub occurrences: usize,
+    /// The location comes from a macro expansion rather than the symbol's name
+    #[serde(default)]
+    pub macro_generated: bool,
+    /// Finds the reference again after the file is edited or reformatted
+    #[serde(default)]
+    pub anchor: Anchor,
+    /// Link to the line on the repository host, with `--link-template`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Lines before this one, with `-B` or `-C`, less its indentation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context_before: Vec<String>,
+    /// Lines after this one, with `-A` or `-C`, less its indentation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context_after: Vec<String>,
+}
+
+fn default_occurrences() -> usize {
+    1
+}
+
+/// The alternate form, `{:#}`, leaves out the path, for listings grouped by file
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if f.alternate() { String::new() } else { self.path.display().to_string() };
+        // Context lines are marked with `-` where the reference has `:`, as grep does
+        let (context, matched) = if f.alternate() { ("", "") } else { ("-", ":") };
+        let first = self.line as usize - self.context_before.len();
+        for (offset, line) in self.context_before.iter().enumerate() {
+            writeln!(f, "{}{}{}- {}", path, context, first + offset, line)?;
+        }
+        write!(f, "{}{}{}: {}", path, matched, self.line, self.text)?;
+        if self.occurrences > 1 {
+            write!(f, " ({} occurrences)", self.occurrences)?;
+        }
+        if self.macro_generated {
+            write!(f, " [macro]")?;
+        }
+        if let Some(url) = &self.url {
+            write!(f, " <{}>", url)?;
+        }
+        for (offset, line) in self.context_after.iter().enumerate() {
+            write!(f, "\n{}{}{}- {}", path, context, self.line as usize + 1 + offset, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Merge references that share a file and line, keeping the first one's position.
+///
+/// Order of first appearance is preserved.
+pub fn normalize_references(references: Vec<Reference>) -> Vec<Reference> {
+    let mut merged: Vec<Reference> = Vec::with_capacity(references.len());
+    
+    for reference in references {
+        match merged.iter_mut().find(|r| r.file == reference.file && r.line == reference.line) {
+            Some(existing) => {
+                existing.occurrences += reference.occurrences;
+                // A line counts as macro-generated only if every merged hit is
+                existing.macro_generated &= reference.macro_generated;
+                if reference.column < existing.column {
+                    existing.column = reference.column;
+                    existing.end_line = reference.end_line;
+                    existing.end_column = reference.end_column;
+                }
+            }
+            None => merged.push(reference),
+        }
+    }
+    
+    merged
+}
+
+/// The definition of a symbol together with the surrounding source code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Definition {
+    /// Absolute path of the file containing the definition
+    pub file: PathBuf,
+    /// Path relative to the workspace root, used for display
+    pub path: PathBuf,
+    /// 1-based line where the definition starts
+    pub start_line: u32,
+    /// 1-based column where the definition starts
+    pub start_column: u32,
+    /// 1-based line where the definition ends
+    pub end_line: u32,
+    /// 1-based column where the definition ends
+    pub end_column: u32,
+    /// The first line of `code` in the file (1-based)
+    pub code_start_line: u32,
+    /// Complete source of the item containing the definition, doc comments and attributes included
+    pub code: String,
+    /// Finds the start of the definition again after the file is edited or reformatted
+    #[serde(default)]
+    pub anchor: Anchor,
+    /// Link to the line on the repository host, with `--link-template`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}:", self.path.display(), self.start_line, self.end_line)?;
+        if let Some(url) = &self.url {
+            write!(f, " <{}>", url)?;
+        }
+        write!(f, "\n```rust\n{}\n```", self.code)
+    }
+}
+
+/// Which way a call hierarchy is walked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CallDirection {
+    /// Functions that call the symbol
+    Incoming,
+    /// Functions the symbol calls
+    Outgoing,
+}
+
+/// A function in a call hierarchy together with the call sites linking it to its parent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Call {
+    pub name: String,
+    /// Symbol kind reported by the server, e.g. `Function` or `Method`
+    pub kind: String,
+    /// Extra detail such as the signature, if the server provides it
+    pub detail: Option<String>,
+    /// Absolute path of the file declaring the function
+    pub file: PathBuf,
+    /// Path relative to the workspace root, used for display
+    pub path: PathBuf,
+    /// 1-based line of the function name
+    pub line: u32,
+    /// 1-based column of the function name
+    pub column: u32,
+    /// Where the call happens: inside this function for callers, inside the parent for callees
+    pub sites: Vec<CallSite>,
+    /// The next level of the hierarchy, when more than one level was requested
+    #[serde(default)]
+    pub calls: Vec<Call>,
+}
+
+/// A call expression linking two functions in a call hierarchy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CallSite {
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column number
+    pub column: u32,
+    /// The trimmed source line containing the call
+    pub text: String,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_call(f, self, 0)
+    }
+}
+
+/// Print a call and its descendants as an indented tree
+fn write_call(f: &mut fmt::Formatter<'_>, call: &Call, depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    writeln!(f, "{}{} ({}:{})", indent, call.name, call.path.display(), call.line)?;
+    for site in &call.sites {
+        writeln!(f, "{}  > {}:{}: {}", indent, site.path.display(), site.line, site.text)?;
+    }
+    for child in &call.calls {
+        write_call(f, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// The innermost item of `outline` spanning 1-based `line`
+pub fn enclosing_item(outline: &[OutlineItem], line: u32) -> Option<&OutlineItem> {
+    let item = outline.iter().find(|item| item.start_line <= line && line <= item.end_line)?;
+    enclosing_item(&item.children, line).or(Some(item))
+}
+
+/// A symbol found by a workspace-wide search
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SymbolMatch {
+    pub name: String,
+    /// Symbol kind reported by the server, e.g. `Struct` or `Function`
+    pub kind: String,
+    /// The module, type, or impl the symbol is declared in
+    pub container: Option<String>,
+    /// Absolute path of the declaring file
+    pub file: PathBuf,
+    /// Path relative to the workspace root, used for display
+    pub path: PathBuf,
+    /// 1-based line of the symbol name
+    pub line: u32,
+    /// 1-based column of the symbol name
+    pub column: u32,
+    /// Link to the line on the repository host, with `--link-template`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl fmt::Display for SymbolMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {} {}", self.path.display(), self.line, self.kind, self.name)?;
+        if let Some(container) = &self.container {
+            write!(f, " (in {})", container)?;
+        }
+        if let Some(url) = &self.url {
+            write!(f, " <{}>", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// Order search results by how well their name matches `query`.
+///
+/// Exact matches come first, then prefixes, substrings, and finally the fuzzy
+/// subsequence matches a server may add. Ties go to shorter names, then to
+/// position in the workspace.
+pub fn rank_symbols(query: &str, mut symbols: Vec<SymbolMatch>) -> Vec<SymbolMatch> {
+    symbols.sort_by_cached_key(|s| (match_rank(query, &s.name), s.name.len(), s.path.clone(), s.line));
+    symbols
+}
+
+fn match_rank(query: &str, name: &str) -> u8 {
+    let query_lower = query.to_lowercase();
+    let name_lower = name.to_lowercase();
+    if name == query {
+        0
+    } else if name_lower == query_lower {
+        1
+    } else if name_lower.starts_with(&query_lower) {
+        2
+    } else if name_lower.contains(&query_lower) {
+        3
+    } else {
+        4
+    }
+}
+
+/// An item in a file's outline, with the items nested inside it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct OutlineItem {
+    pub name: String,
+    /// Symbol kind reported by the server, e.g. `Struct` or `Function`
+    pub kind: String,
+    /// Extra detail such as a function signature, if the server provides it
+    pub detail: Option<String>,
+    /// 1-based first line of the item, including doc comments and attributes
+    pub start_line: u32,
+    /// 1-based last line of the item
+    pub end_line: u32,
+    #[serde(default)]
+    pub children: Vec<OutlineItem>,
+}
+
+impl fmt::Display for OutlineItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_outline(f, self, 0)
+    }
+}
+
+fn write_outline(f: &mut fmt::Formatter<'_>, item: &OutlineItem, depth: usize) -> fmt::Result {
+    writeln!(
+        f,
+        "{}{} {} [{}-{}]",
+        "  ".repeat(depth),
+        item.kind,
+        item.name,
+        item.start_line,
+        item.end_line
+    )?;
+    for child in &item.children {
+        write_outline(f, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// The types a type or trait is related to by implementation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TypeHierarchy {
+    pub name: String,
+    /// Path relative to the workspace root of the queried symbol's file
+    pub path: PathBuf,
+    /// 1-based line of the queried symbol
+    pub line: u32,
+    /// Traits the type implements, or supertraits of a trait
+    pub supertypes: Vec<TypeRelation>,
+    /// Types implementing the trait
+    pub subtypes: Vec<TypeRelation>,
+}
+
+/// One edge of a type hierarchy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TypeRelation {
+    pub name: String,
+    /// Path relative to the workspace root of the declaration linking the two types:
+    /// the related item itself, or the `impl` block or derive when only that is known
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: u32,
+}
+
+impl fmt::Display for TypeHierarchy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({}:{})", self.name, self.path.display(), self.line)?;
+        for (label, relations) in [("supertypes", &self.supertypes), ("subtypes", &self.subtypes)] {
+            if relations.is_empty() {
+                continue;
+            }
+            writeln!(f, "  {}:", label)?;
+            for relation in relations {
+                writeln!(f, "    {} ({}:{})", relation.name, relation.path.display(), relation.line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A code action or quick fix the server offers at a location
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CodeAction {
+    /// 1-based position in the server's list, used to pick an action to apply
+    pub index: usize,
+    pub title: String,
+    /// Kind such as `quickfix` or `refactor.extract`, if the server gives one
+    pub kind: Option<String>,
+    /// Whether the server recommends this fix for a diagnostic at the location
+    #[serde(default)]
+    pub preferred: bool,
+    /// What applying the action changes; empty for actions that only run a server command
+    #[serde(default)]
+    pub changes: Vec<FileChange>,
+}
+
+impl fmt::Display for CodeAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}. {}", self.index, self.title)?;
+        if let Some(kind) = &self.kind {
+            write!(f, " [{}]", kind)?;
+        }
+        if self.preferred {
+            write!(f, " (preferred)")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a runnable runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunnableKind {
+    Test,
+    /// Every test in a module
+    TestModule,
+    Bench,
+    Doctest,
+    Binary,
+    /// Anything else, such as checking or testing a whole package
+    Other,
+}
+
+impl fmt::Display for RunnableKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Test => "test",
+            Self::TestModule => "test-module",
+            Self::Bench => "bench",
+            Self::Doctest => "doctest",
+            Self::Binary => "binary",
+            Self::Other => "other",
+        })
+    }
+}
+
+/// A test, benchmark or binary and the cargo command that runs it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Runnable {
+    pub kind: RunnableKind,
+    /// The test path or target name; the server's whole label for `other`
+    pub name: String,
+    /// Path relative to the workspace root of where it is declared, if anywhere
+    pub path: Option<PathBuf>,
+    /// 1-based line of its name
+    pub line: Option<u32>,
+    /// Program and arguments, exactly as rust-analyzer would run them
+    pub command: Vec<String>,
+    /// Directory to run the command in
+    pub cwd: PathBuf,
+    /// Extra environment variables for the command
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environment: BTreeMap<String, String>,
+}
+
+impl Runnable {
+    /// The command as one line for a POSIX shell
+    pub fn shell_command(&self) -> String {
+        let quote = |arg: &String| {
+            if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@+,".contains(c)) {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        };
+        let env = self.environment.iter().map(|(name, value)| format!("{}={}", name, quote(value)));
+        env.chain(self.command.iter().map(quote)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl fmt::Display for Runnable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(path), Some(line)) = (&self.path, self.line) {
+            write!(f, "{}:{}: ", path.display(), line)?;
+        }
+        write!(f, "[{}] {}\n    {}", self.kind, self.name, self.shell_command())
+    }
+}
+
+/// What an inlay hint annotates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InlayHintKind {
+    /// An inferred type, e.g. `: Vec<String>` after a binding
+    Type,
+    /// The name of the parameter an argument is passed to
+    Parameter,
+    /// Anything else the server shows inline, such as closing-brace labels
+    Other,
+}
+
+/// A hint the server would show inline in an editor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct InlayHint {
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column the hint is shown before, counted in UTF-16 code units
+    pub column: u32,
+    pub label: String,
+    pub kind: InlayHintKind,
+    /// Whether the hint is set off from the code before it by a space
+    #[serde(default)]
+    pub padding_left: bool,
+    /// Whether the hint is set off from the code after it by a space
+    #[serde(default)]
+    pub padding_right: bool,
+}
+
+/// A source line with its inlay hints written into the text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HintedLine {
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: u32,
+    /// The line as an editor would show it, hints included
+    pub text: String,
+    pub hints: Vec<InlayHint>,
+}
+
+impl fmt::Display for HintedLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.text)
+    }
+}
+
+/// A macro call, attribute or derive expanded down to code without macros
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MacroExpansion {
+    /// The macro's name, such as `vec` or `Debug`
+    pub name: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based line of the expanded call
+    pub line: u32,
+    /// The generated code, formatted by rust-analyzer
+    pub expansion: String,
+}
+
+impl fmt::Display for MacroExpansion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "// Recursive expansion of `{}` at {}:{}", self.name, self.path.display(), self.line)?;
+        write!(f, "{}", self.expansion.trim_end())
+    }
+}
+
+/// A compiler-internal view of one item: its lowered HIR or its syntax tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ItemView {
+    /// rust-analyzer's syntax kind of the item, such as `FN` or `IMPL`
+    pub kind: String,
+    /// Path relative to the workspace root
+    pub path: PathBuf,
+    /// 1-based first and last lines of the item
+    pub start_line: u32,
+    pub end_line: u32,
+    /// The HIR as rust-analyzer prints it, or the syntax tree one element per line
+    pub text: String,
+}
+
+impl fmt::Display for ItemView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "// {} at {}:{}-{}", self.kind, self.path.display(), self.start_line, self.end_line)?;
+        write!(f, "{}", self.text.trim_end())
+    }
+}
+
+/// One replacement within a file, in the positions the server sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TextEdit {
+    /// 1-based line number
+    pub start_line: u32,
+    /// 1-based column, counted in UTF-16 code units
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+    pub new_text: String,
+}
+
+/// One step of a workspace edit; steps apply in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileChange {
+    /// Replacements in one file, all positioned against its contents before this step
+    Edit {
+        file: PathBuf,
+        /// Path relative to the workspace root
+        path: PathBuf,
+        edits: Vec<TextEdit>,
+    },
+    Create {
+        file: PathBuf,
+        path: PathBuf,
+    },
+    Rename {
+        file: PathBuf,
+        path: PathBuf,
+        new_file: PathBuf,
+        new_path: PathBuf,
+    },
+    Delete {
+        file: PathBuf,
+        path: PathBuf,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn reference(line: u32, column: u32, macro_generated: bool) -> Reference {
+        Reference {
+            file: PathBuf::from("/ws/src/lib.rs"),
+            path: PathBuf::from("src/lib.rs"),
+            line,
+            column,
+            end_line: line,
+            end_column: column + 3,
+            text: "text".to_string(),
+            occurrences: 1,
+            macro_generated,
+            anchor: Anchor::default(),
+            url: None,
+            context_before: Vec::new(),
+            context_after: Vec::new(),
+        }
+    }
+    
+    #[test]
+    fn test_normalize_merges_same_line() {
+        let merged = normalize_references(vec![
+            reference(3, 10, false),
+            reference(1, 1, true),
+            reference(3, 2, true),
+        ]);
+        
+        assert_eq!(merged.len(), 2);
+        assert_eq!((merged[0].line, merged[0].column, merged[0].occurrences), (3, 2, 2));
+        assert!(!merged[0].macro_generated);
+        assert_eq!((merged[1].line, merged[1].occurrences), (1, 1));
+        assert!(merged[1].macro_generated);
+    }
+    
+    #[test]
+    fn test_display_annotations() {
+        let mut r = reference(7, 1, true);
+        r.occurrences = 2;
+        assert_eq!(r.to_string(), "src/lib.rs:7: text (2 occurrences) [macro]");
+    }
+    
+    #[test]
+    fn test_display_call_tree() {
+        let call = |name: &str, line: u32, calls: Vec<Call>| Call {
+            name: name.to_string(),
+            kind: "Function".to_string(),
+            detail: None,
+            file: PathBuf::from("/ws/src/lib.rs"),
+            path: PathBuf::from("src/lib.rs"),
+            line,
+            column: 8,
+            sites: vec![CallSite {
+                path: PathBuf::from("src/lib.rs"),
+                line: line + 1,
+                column: 5,
+                text: "callee();".to_string(),
+            }],
+            calls,
+        };
+        
+        let tree = call("outer", 1, vec![call("inner", 10, Vec::new())]);
+        assert_eq!(
+            tree.to_string(),
+            "outer (src/lib.rs:1)\n  > src/lib.rs:2: callee();\n    inner (src/lib.rs:10)\n      > src/lib.rs:11: callee();\n"
+        );
+    }
+    
+    #[test]
+    fn test_display_outline() {
+        let item = |kind: &str, name: &str, start_line: u32, end_line: u32, children: Vec<OutlineItem>| OutlineItem {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            detail: None,
+            start_line,
+            end_line,
+            children,
+        };
+        
+        let outline = item("Object", "impl Circle", 21, 23, vec![item("Function", "new", 22, 22, Vec::new())]);
+        assert_eq!(outline.to_string(), "Object impl Circle [21-23]\n  Function new [22-22]\n");
+    }
+    
+    #[test]
+    fn test_rank_symbols() {
+        let symbol = |name: &str, line: u32| SymbolMatch {
+            name: name.to_string(),
+            kind: "Struct".to_string(),
+            container: None,
+            file: PathBuf::from("/ws/src/lib.rs"),
+            path: PathBuf::from("src/lib.rs"),
+            line,
+            column: 1,
+            url: None,
+        };
+        
+        let ranked = rank_symbols(
+            "config",
+            vec![symbol("AppConfigLoader", 1), symbol("cfg_options", 2), symbol("ConfigFile", 3), symbol("Config", 4), symbol("AppConfig", 5)],
+        );
+        let names: Vec<&str> = ranked.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Config", "ConfigFile", "AppConfig", "AppConfigLoader", "cfg_options"]);
+    }
+    
+    #[test]
+    fn test_enclosing_item() {
+        let item = |name: &str, start_line, end_line, children| OutlineItem {
+            name: name.to_string(),
+            kind: "Function".to_string(),
+            detail: None,
+            start_line,
+            end_line,
+            children,
+        };
+        let outline = vec![
+            item("Square", 1, 3, Vec::new()),
+            item("impl Shape for Square", 5, 30, vec![item("area", 6, 8, Vec::new()), item("scale", 10, 29, Vec::new())]),
+        ];
+        assert_eq!(enclosing_item(&outline, 2).unwrap().name, "Square");
+        assert_eq!(enclosing_item(&outline, 12).unwrap().name, "scale");
+        assert_eq!(enclosing_item(&outline, 9).unwrap().name, "impl Shape for Square");
+        assert!(enclosing_item(&outline, 4).is_none());
+    }
+}
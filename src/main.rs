@@ -1,50 +1,242 @@
-use anyhow::{Result, Context};
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
-use std::process::Command;
-use tokio::net::UnixStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use anyhow::{bail, Result, Context};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use language_query::{
-    daemon::{get_socket_path, is_daemon_running, DaemonServer},
-    ipc::{Request, Response, Method, ResponseResult},
+    errln, out, outln,
+    client::{absolute, generate::{generate_client, ClientLanguage}, mcp, picker::pick, resolve_file, stdio, watch::SourceWatcher, Client, ClientOptions},
+    config::{config_path, daemon_config, default_flags, ensure_trusted, formatters, hooks_config, link_template, metrics_config, monorepo_members, monorepo_root, prefetch_config, redact_config, presets, priority_config, resource_limits, retry_config, sandbox_command, server_config, Config, ServerConfig},
+    daemon::{get_log_path, get_socket_path, is_daemon_running, list_daemon_sockets, logs, metrics, pidfile, redirect_output, report_startup, DaemonServer, StartupStatus},
+    edit::{apply, EditPreview},
+    fixture::{write_fixture, FixtureSpec},
+    init::{check_toolchains, install_completions, install_git_hooks, write_starter_config, HookInstall, Shell},
+    ipc::{command_schema, Codec, Compression, DaemonStatus, Method, PathQuery, Response, ResponseResult, PROTOCOL_VERSION, SCHEMA_COMMANDS},
+    ipc::results::{
+        ActionsResult, AsyncCallersResult, BoundsResult, CallsResult, CancelResult, ChurnResult, CodecResult, CompressResult, CoverageMapResult, DefResult, DiagResult, DiffRefsResult, DocsResult, ErrorFlowResult, ExpandResult, GenericHotspotsResult,
+        HierarchyResult, HintsResult, HirResult, HotspotsResult, ImplResult, OutlineResult, PingResult, RefsResult, RenameResult, ResolveResult, StatsResult,
+        ResultFlags, RunnablesResult, SearchResult, SsrResult, StringsResult, SyntaxResult, TourResult, TransitiveRefsResult, TypeResult,
+    },
+    core::reports::{Badge, ChurnSort, FunctionCoverage, Migration},
+    core::workspace::find_workspace_root,
+    output::{add_context, add_links, call_graph, exclude_paths, is_ascii, redact, run_formatter, sort_results, set_ascii, to_json, to_json_line, to_json_lines, GraphFormat, Permalinks, Redactions, ResultOrder},
+    lsp::{options::{DEFAULT_MAX_RESTARTS, DEFAULT_READY_TIMEOUT, READY_TIMEOUT_ENV, RUST_ANALYZER_ENV}, position::symbol_columns, CallDirection, Reference, ServerOptions, Severity},
 };
 
 #[derive(Parser)]
 #[command(name = "lq")]
 #[command(about = "Language Query - Fast CLI for LSP code intelligence", long_about = None)]
 #[command(version)]
+#[command(args_override_self = true)]
 struct Cli {
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    format: FormatArg,
+    /// Save each request and the daemon's response to this directory
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// Answer requests from responses saved with `--record`, without a daemon
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<PathBuf>,
+    /// Answer with whatever is available after MS milliseconds instead of waiting for a complete result
+    #[arg(long, global = true, value_name = "MS")]
+    best_effort: Option<u64>,
+    /// Answer immediately from the daemon's cache even if files changed since, refreshing it in the background
+    #[arg(long, global = true)]
+    allow_stale: bool,
+    /// Fail with an error when the daemon has not answered after SECS seconds
+    #[arg(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+    /// Print on stderr how long the answer took, and whether an empty answer was retried
+    #[arg(long, global = true)]
+    timing: bool,
+    /// Start a daemon even if the workspace is not in the trust allowlist
+    #[arg(long, global = true)]
+    trust: bool,
+    /// The rust-analyzer a newly started daemon runs, instead of the one in the config or on PATH
+    #[arg(long, global = true, value_name = "PATH", env = RUST_ANALYZER_ENV)]
+    server_path: Option<PathBuf>,
+    /// Print pure ASCII with no control sequences, escaping anything else (for log scrapers and old CI)
+    #[arg(long, global = true, visible_alias = "no-emoji", env = "LQ_ASCII", value_parser = clap::builder::FalseyValueParser::new())]
+    ascii: bool,
+    /// Order of the results; by default located results are ordered by location and rankings by rank
+    #[arg(long, global = true, value_enum)]
+    sort: Option<SortArg>,
+    /// Drop results in files matching this glob, relative to the workspace root (repeatable)
+    #[arg(long, global = true, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Link each result to its line on the repository host, e.g. `https://github.com/org/repo/blob/{rev}/{path}#L{line}`
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    link_template: Option<String>,
+    /// Ask again and print the new answer whenever a source file in the workspace changes
+    #[arg(long, global = true)]
+    watch: bool,
+    /// Query the Nth whole-word occurrence of the symbol on the line, counting from 1
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    occurrence: Option<u32>,
+    /// Print every candidate of a search or definition instead of asking on the terminal which one to go on with
+    #[arg(long, global = true)]
+    no_interactive: bool,
+    /// The formatter configured for the command, set with the rest of the command line
+    #[arg(skip)]
+    formatter: Option<Vec<String>>,
     #[command(subcommand)]
     command: Commands,
 }
 
-#[derive(Subcommand)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FormatArg {
+    /// Human-readable text
+    Text,
+    /// The structured response payload as JSON
+    Json,
+    /// One line of JSON per result, or the whole payload on one line when it is not a list
+    Jsonl,
+    /// The shields.io endpoint payload of `lq badge`
+    ShieldsJson,
+    /// The call graph of `lq callers` or `lq callees` in Graphviz's dot language
+    Dot,
+    /// The call graph of `lq callers` or `lq callees` as a Mermaid flowchart
+    Mermaid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortArg {
+    /// Path by byte order, then line, then column, for rankings too
+    Location,
+    /// As the daemon answered
+    None,
+    /// Rank `lq churn` items by commits × references (its default)
+    Risk,
+    /// Rank `lq churn` items by the commits touching the declaring file
+    Churn,
+    /// Rank `lq churn` items by references
+    Refs,
+}
+
+/// The order of `command`'s results, for `--sort`; a churn ranking is set on the command itself
+fn result_order(sort: Option<SortArg>, command: &mut Commands) -> Result<ResultOrder> {
+    let ranking = match sort {
+        None => return Ok(ResultOrder::Default),
+        Some(SortArg::Location) => return Ok(ResultOrder::Location),
+        Some(SortArg::None) => return Ok(ResultOrder::None),
+        Some(SortArg::Risk) => ChurnSort::Risk,
+        Some(SortArg::Churn) => ChurnSort::Churn,
+        Some(SortArg::Refs) => ChurnSort::Refs,
+    };
+    match command {
+        Commands::Churn { sort, .. } => {
+            *sort = ranking;
+            Ok(ResultOrder::Default)
+        }
+        _ => bail!("--sort {} only applies to lq churn; use location or none", format!("{:?}", ranking).to_lowercase()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Jsonl,
+}
+
+impl FormatArg {
+    /// How to print the answer to `command`; a graph format is set on the command itself
+    fn for_command(self, command: &mut Commands) -> Result<OutputFormat> {
+        Ok(match self {
+            FormatArg::Text => OutputFormat::Text,
+            FormatArg::Json => OutputFormat::Json,
+            FormatArg::Jsonl => OutputFormat::Jsonl,
+            // A badge's JSON is the endpoint payload itself
+            FormatArg::ShieldsJson if matches!(command, Commands::Badge { .. }) => OutputFormat::Json,
+            FormatArg::ShieldsJson => bail!("--format shields-json only applies to lq badge"),
+            FormatArg::Dot | FormatArg::Mermaid => match command {
+                Commands::Callers { graph, .. } | Commands::Callees { graph, .. } => {
+                    *graph = Some(if self == FormatArg::Dot { GraphFormat::Dot } else { GraphFormat::Mermaid });
+                    OutputFormat::Text
+                }
+                _ => bail!("--format {} only applies to lq callers and lq callees", format!("{:?}", self).to_lowercase()),
+            },
+        })
+    }
+}
+
+#[derive(Clone, Subcommand)]
 enum Commands {
-    /// Get documentation/hover information for a symbol
+    /// Get documentation/hover information for a symbol, or an expression or statement
     Docs {
-        /// File path and line number (e.g., src/main.rs:42)
-        #[arg(value_parser = parse_file_location)]
-        location: FileLocation,
-        /// Symbol name to query
-        symbol: String,
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9),
+        /// or a qualified path such as my_crate::module::Type::method in place of both location and symbol,
+        /// or a selection from one line and column to another (e.g., src/main.rs:42:9-44:6) on its own
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Symbol name to query; left out after a qualified path or a selection
+        symbol: Option<String>,
     },
-    /// Show the implementation of a symbol
+    /// Show where a symbol is defined, with its source
+    #[command(visible_alias = "definition")]
+    Def {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9),
+        /// or a qualified path such as my_crate::module::Type::method in place of both location and symbol
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Symbol name to query; left out after a qualified path
+        symbol: Option<String>,
+    },
+    /// List every implementation of a trait, trait method or type, with its code
+    #[command(alias = "impls")]
     Impl {
-        /// File path and line number (e.g., src/main.rs:42)
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9),
+        /// or a qualified path such as my_crate::module::Type::method in place of both location and symbol
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Symbol name to query; left out after a qualified path
+        symbol: Option<String>,
+    },
+    /// Show the declaration of a variable's or expression's type
+    Type {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
         #[arg(value_parser = parse_file_location)]
         location: FileLocation,
-        /// Symbol name to query
+        /// Variable or expression name to query
         symbol: String,
     },
     /// Find all references to a symbol
     Refs {
-        /// File path and line number (e.g., src/main.rs:42)
-        #[arg(value_parser = parse_file_location)]
-        location: FileLocation,
-        /// Symbol name to query
-        symbol: String,
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9),
+        /// or a qualified path such as my_crate::module::Type::method in place of both location and symbol
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Symbol name to query; left out after a qualified path
+        symbol: Option<String>,
+        /// Print references exactly as the server reports them, without merging or annotations
+        #[arg(long)]
+        raw: bool,
+        /// Print N lines of source after each reference
+        #[arg(short = 'A', long, value_name = "N")]
+        after_context: Option<usize>,
+        /// Print N lines of source before each reference
+        #[arg(short = 'B', long, value_name = "N")]
+        before_context: Option<usize>,
+        /// Print N lines of source before and after each reference
+        #[arg(short = 'C', long, value_name = "N")]
+        context: Option<usize>,
+        /// Print the references under a header per file, with its number of hits
+        #[arg(long)]
+        group_by_file: bool,
+        /// Only print the number of hits per file
+        #[arg(long, conflicts_with = "group_by_file")]
+        count: bool,
+        /// Also follow the references to each function referring to the symbol, and print them as a tree
+        #[arg(long, conflicts_with_all = ["raw", "group_by_file", "count"])]
+        transitive: bool,
+        /// Levels of referring functions to follow with --transitive
+        #[arg(long, requires = "transitive", default_value = "3")]
+        depth: usize,
     },
     /// Search for symbols by name (fuzzy matching)
     Resolve {
@@ -53,15 +245,351 @@ enum Commands {
         /// File to search within
         file: PathBuf,
     },
+    /// Show the functions that call a function
+    Callers {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Function name to query
+        symbol: String,
+        /// Levels of callers to show
+        #[arg(long, default_value = "1")]
+        depth: usize,
+        /// Graph to print instead of the tree, set from the global `--format`
+        #[arg(skip)]
+        graph: Option<GraphFormat>,
+    },
+    /// Show the functions a function calls
+    Callees {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Function name to query
+        symbol: String,
+        /// Levels of callees to show
+        #[arg(long, default_value = "1")]
+        depth: usize,
+        /// Graph to print instead of the tree, set from the global `--format`
+        #[arg(skip)]
+        graph: Option<GraphFormat>,
+    },
+    /// Show the structure of a file: modules, types, impls, and functions with line ranges
+    Outline {
+        /// File to outline
+        file: PathBuf,
+    },
+    /// Show the types and parameter names rust-analyzer infers, written into the source lines
+    Hints {
+        /// File, optionally with a line or line range (e.g., src/main.rs:10-20); without one,
+        /// only the lines that have hints are shown
+        #[arg(value_parser = parse_file_range)]
+        range: FileRange,
+    },
+    /// Search symbols across the whole workspace by name, best matches first
+    Search {
+        /// Name or part of a name; matched fuzzily
+        query: String,
+        /// Maximum number of matches to show
+        #[arg(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Show compiler and rust-analyzer diagnostics for a file or the whole workspace
+    Diag {
+        /// File to check; all files with diagnostics when omitted
+        file: Option<PathBuf>,
+        /// Show diagnostics at least this severe
+        #[arg(long, value_enum, default_value = "warning")]
+        severity: Severity,
+    },
+    /// Preview renaming a symbol as a unified diff, or apply it with --apply
+    Rename {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Symbol name to rename
+        symbol: String,
+        /// The new name
+        new_name: String,
+        /// Write the changes to disk instead of only printing them
+        #[arg(long)]
+        apply: bool,
+        /// With --apply, write even over uncommitted changes in a git repository
+        #[arg(long, requires = "apply")]
+        force: bool,
+    },
+    /// List the code actions and quick fixes rust-analyzer offers on a line, or apply one with --apply
+    Actions {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Only the actions at this symbol; all actions on the line when omitted
+        symbol: Option<String>,
+        /// Write the changes of the action with this number to disk
+        #[arg(long, value_name = "N")]
+        apply: Option<usize>,
+        /// With --apply, write even over uncommitted changes in a git repository
+        #[arg(long, requires = "apply")]
+        force: bool,
+    },
+    /// Print the recursive expansion of a macro call, attribute or derive
+    Expand {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Macro to expand, such as `vec` or `Debug`; the first macro on the line when omitted
+        symbol: Option<String>,
+    },
+    /// Print rust-analyzer's lowered HIR of the function, const or static body on a line
+    Hir {
+        /// File path and line number (e.g., src/main.rs:42)
+        #[arg(value_parser = parse_file_line)]
+        location: FileLocation,
+    },
+    /// Print the syntax tree of the innermost item on a line
+    Syntax {
+        /// File path and line number (e.g., src/main.rs:42)
+        #[arg(value_parser = parse_file_line)]
+        location: FileLocation,
+    },
+    /// List tests, benchmarks and binaries with the cargo command that runs each
+    Runnables {
+        /// File to list; the whole workspace when omitted
+        file: Option<PathBuf>,
+    },
+    /// Preview a structural search and replace as a unified diff, or apply it with --apply
+    Ssr {
+        /// The rule, such as 'foo($a, $b) ==>> bar($b, $a)'
+        rule: String,
+        /// Only rewrite matches in this file or these of its lines (e.g., src/main.rs:10-20);
+        /// paths in the rule then resolve from this file instead of the crate root
+        #[arg(long = "in", value_name = "FILE[:LINES]", value_parser = parse_file_range)]
+        within: Option<FileRange>,
+        /// Write the changes to disk instead of only printing them
+        #[arg(long)]
+        apply: bool,
+        /// With --apply, write even over uncommitted changes in a git repository
+        #[arg(long, requires = "apply")]
+        force: bool,
+    },
+    /// Show the traits a type implements, or the types implementing a trait
+    Hierarchy {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Type or trait name to query
+        symbol: String,
+    },
+    /// Rank generic functions by likely monomorphization cost
+    GenericHotspots {
+        /// Number of functions to show
+        #[arg(long, default_value = "20")]
+        top: usize,
+    },
+    /// Report which public functions are referenced from tests
+    CoverageMap {
+        /// Only list untested functions and exit with status 1 if there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Rank public items by how often they are referenced
+    Hotspots {
+        /// Number of items to show
+        #[arg(long, default_value = "20")]
+        top: usize,
+        /// Only count references for this many items, sampled across the workspace
+        #[arg(long)]
+        sample: Option<usize>,
+        /// Maximum number of reference queries in flight
+        #[arg(long, default_value = "8")]
+        concurrency: usize,
+    },
+    /// Generate a markdown onboarding tour of the workspace
+    Tour {
+        /// Number of most-referenced items to include
+        #[arg(long, default_value = "10")]
+        top: usize,
+    },
+    /// Flag items that change often and are widely referenced
+    Churn {
+        /// Number of items to show
+        #[arg(long, default_value = "20")]
+        top: usize,
+        /// Only count commits since this date (passed to `git log --since`)
+        #[arg(long)]
+        since: Option<String>,
+        /// Ranking, set from the global `--sort`
+        #[arg(skip)]
+        sort: ChurnSort,
+    },
+    /// Compare the references of two items, or of one item at two revisions, to track a migration
+    DiffRefs {
+        /// Qualified path of the item, e.g. my_crate::old_api::connect
+        a: String,
+        /// Qualified path of the item to compare with, e.g. my_crate::new_api::connect
+        #[arg(required_unless_present = "rev", conflicts_with = "rev")]
+        b: Option<String>,
+        /// Revision to compare at instead; once to compare with the working tree, twice to compare the two
+        #[arg(long = "rev", num_args = 1)]
+        rev: Vec<String>,
+    },
+    /// Show the chains of callers of a function and where they cross between async and sync code
+    AsyncCallers {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Function name to query
+        symbol: String,
+        /// Levels of callers to walk
+        #[arg(long, default_value = "3")]
+        depth: usize,
+    },
+    /// Explain the trait bounds of the generic function called at a position: each trait and the impls satisfying it
+    Bounds {
+        /// File path, line number and column of the called function's name (e.g., src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+    },
+    /// Show the error type a function returns, the From conversions into it, and where its callers propagate it
+    ErrorFlow {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9)
+        #[arg(value_parser = parse_file_location)]
+        location: FileLocation,
+        /// Function name to query
+        symbol: String,
+    },
+    /// Find string literals matching a pattern, each with its module and enclosing function
+    Strings {
+        /// Regular expression matched against each literal's text
+        pattern: String,
+        /// Take the pattern as a line of log output, and find the format strings that could have printed it
+        #[arg(long)]
+        log: bool,
+    },
+    /// List the remaining usages of a deprecated item as a markdown checklist, grouped by crate and module
+    Migrate {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9),
+        /// or a qualified path such as my_crate::old_api::connect in place of both location and symbol
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Symbol name to query; left out after a qualified path
+        symbol: Option<String>,
+        /// Markdown file tracking the migration: written with the checklist, and on later runs
+        /// keeping the usages that are gone, checked off
+        #[arg(long)]
+        track: Option<PathBuf>,
+    },
+    /// Count the references to an item as a usage badge; `--format shields-json` prints the shields.io endpoint payload
+    Badge {
+        /// File path, line number and optional column (e.g., src/main.rs:42 or src/main.rs:42:9),
+        /// or a qualified path such as my_crate::client::Client in place of both location and symbol
+        #[arg(value_parser = parse_target)]
+        target: Target,
+        /// Symbol name to query; left out after a qualified path
+        symbol: Option<String>,
+        /// Text on the left of the badge
+        #[arg(long, default_value = "references")]
+        label: String,
+    },
+    /// Run a query preset from the config, or list the presets when no name is given
+    Run {
+        /// Preset name, as in `[preset.<name>]`
+        name: Option<String>,
+        /// Arguments added after the preset's own
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Answer queries read from stdin, one request per line as JSON like {"method": "Docs", "params": {...}}
+    /// (see `lq genclient`), over a single daemon connection; each response is written as a line of JSON
+    Batch {
+        /// Encoding of the messages to and from the daemon; stdin and stdout stay JSON either way
+        #[arg(long, value_enum, default_value = "json")]
+        ipc_codec: Codec,
+        /// Compression of the daemon's large responses, worth it when the socket is forwarded over a slow link
+        #[arg(long, value_enum, default_value = "none")]
+        ipc_compression: Compression,
+    },
+    /// Speak the daemon's protocol on stdin and stdout: length-prefixed JSON requests in, responses out as they are
+    /// answered, for editors and tools embedding lq in one long-lived process
+    Stdio,
+    /// Serve docs, impl, refs, outline and search as the tools of a Model Context Protocol server on stdin and stdout
+    Mcp,
     /// Check daemon status and indexing progress
     Status,
+    /// Time a round trip to the daemon and a trivial hover by its language server, and check the server is alive
+    Ping,
+    /// Show how the daemon has answered each method since it started: requests, errors, latency percentiles and
+    /// cache hits
+    Stats,
     /// Stop the daemon for current workspace
     Stop,
+    /// List all running daemons on this machine
+    Daemons,
+    /// Print the JSON Schema for a command's `--format json` output
+    Schema {
+        /// Command to describe; lists the available schemas when omitted
+        #[arg(value_parser = clap::builder::PossibleValuesParser::new(SCHEMA_COMMANDS))]
+        command: Option<String>,
+    },
+    /// Print a client library that talks to the daemon directly
+    Genclient {
+        #[arg(value_enum)]
+        language: ClientLanguage,
+    },
+    /// Print the completion script for a shell, completing subcommands, flags and their values
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Write a synthetic Cargo workspace of documented, cross-referencing items, the same every time for the same
+    /// shape, for benchmarks and for sharing a reproduction of a performance problem
+    Fixture {
+        /// Directory to write the workspace to, created if missing
+        dir: PathBuf,
+        #[arg(long, default_value_t = FixtureSpec::default().crates)]
+        crates: usize,
+        /// Modules in each crate
+        #[arg(long, default_value_t = FixtureSpec::default().modules)]
+        modules: usize,
+        /// Items in each module, each a struct with its impl and a function
+        #[arg(long, default_value_t = FixtureSpec::default().items)]
+        items: usize,
+        /// Write into a directory that is not empty, replacing files of the same name
+        #[arg(long)]
+        force: bool,
+    },
+    /// Allow daemons to start for a workspace (rust-analyzer runs its build scripts and proc macros)
+    Trust {
+        /// Workspace to trust, including everything beneath it; defaults to the workspace of the current directory
+        path: Option<PathBuf>,
+    },
+    /// Set up the current workspace: a starter .lq.toml, toolchain checks, and a warmed-up daemon
+    Init {
+        /// Also install completions for this shell
+        #[arg(long, value_enum)]
+        completions: Option<Shell>,
+        /// Also install git hooks that start the daemon after checkouts and merges
+        #[arg(long)]
+        git_hooks: bool,
+        /// Don't start a daemon and wait for the first indexing
+        #[arg(long)]
+        no_warmup: bool,
+        /// Replace an existing .lq.toml with the starter one
+        #[arg(long)]
+        force: bool,
+    },
     /// View daemon logs
     Logs {
         /// Number of lines to show (default: 50)
         #[arg(short = 'n', long = "lines", default_value = "50")]
         lines: usize,
+        /// Keep printing new log output as it is written
+        #[arg(short = 'f', long)]
+        follow: bool,
+    },
+    /// Usage metrics recorded on this machine, once enabled in the global config
+    Metrics {
+        #[command(subcommand)]
+        action: MetricsAction,
     },
     /// Start the daemon process (usually called automatically)
     #[command(hide = true)]
@@ -69,23 +597,87 @@ enum Commands {
         /// Workspace root directory
         #[arg(long)]
         workspace: PathBuf,
+        /// Seconds the language server has to initialize and finish indexing before it fails to start
+        /// [default: `ready_timeout_secs` in the config, or 600]
+        #[arg(long, env = READY_TIMEOUT_ENV)]
+        ready_timeout: Option<u64>,
+        /// Restarts of a crashing language server to attempt before giving up
+        /// [default: `max_restarts` in the config, or 5]
+        #[arg(long, env = "LQ_MAX_RESTARTS")]
+        max_restarts: Option<u32>,
+        /// Minutes without requests after which the daemon exits, 0 for never
+        /// [default: `idle_timeout_minutes` in the config, or never]
+        #[arg(long, env = "LQ_IDLE_TIMEOUT")]
+        idle_timeout: Option<u64>,
+        #[command(flatten)]
+        analyzer: AnalyzerFlags,
     },
 }
 
+/// rust-analyzer settings of the daemon, which win over `[servers.rust]` in the
+/// config. A daemon started on demand inherits their environment variables.
+#[derive(Clone, clap::Args)]
+struct AnalyzerFlags {
+    /// Cargo features to analyze the workspace with, comma-separated
+    #[arg(long, env = "LQ_FEATURES", value_delimiter = ',')]
+    features: Vec<String>,
+    /// Analyze with every Cargo feature enabled
+    #[arg(long, env = "LQ_ALL_FEATURES")]
+    all_features: bool,
+    #[arg(long, env = "LQ_NO_DEFAULT_FEATURES")]
+    no_default_features: bool,
+    /// The target triple to analyze for, such as wasm32-unknown-unknown
+    #[arg(long, env = "LQ_TARGET")]
+    target: Option<String>,
+    /// Leave proc macros unexpanded
+    #[arg(long, env = "LQ_NO_PROC_MACROS")]
+    no_proc_macros: bool,
+    /// The cargo command run for diagnostics, such as clippy
+    #[arg(long, env = "LQ_CHECK_COMMAND")]
+    check_command: Option<String>,
+}
+
+impl AnalyzerFlags {
+    /// `server` with the settings given as flags in place of its own
+    fn apply(self, server: ServerConfig) -> ServerConfig {
+        ServerConfig {
+            features: Some(self.features).filter(|features| !features.is_empty()).or(server.features),
+            all_features: self.all_features.then_some(true).or(server.all_features),
+            no_default_features: self.no_default_features.then_some(true).or(server.no_default_features),
+            target: self.target.or(server.target),
+            proc_macros: self.no_proc_macros.then_some(false).or(server.proc_macros),
+            check_command: self.check_command.or(server.check_command),
+            ..server
+        }
+    }
+}
+
+#[derive(Clone, Subcommand)]
+enum MetricsAction {
+    /// Print the requests per method and their latencies, summed over every daemon
+    Dump,
+}
+
 #[derive(Debug, Clone)]
 struct FileLocation {
     file: PathBuf,
     line: u32,
+    /// 1-based column in UTF-16 code units, as lq prints them
+    column: Option<u32>,
 }
 
 fn parse_file_location(s: &str) -> Result<FileLocation, String> {
-    let parts: Vec<&str> = s.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return Err("Expected format: file:line (e.g., src/main.rs:42)".to_string());
-    }
+    let Some((rest, last)) = s.rsplit_once(':') else {
+        return Err("Expected format: file:line or file:line:column (e.g., src/main.rs:42 or src/main.rs:42:9)".to_string());
+    };
+    // Numbers are split off the end, so a path may hold colons itself, as `C:\src\main.rs:42` does
+    let (file, line, column) = match rest.rsplit_once(':') {
+        Some((file, line)) if line.parse::<u32>().is_ok() => (file, line, Some(last)),
+        _ => (rest, last, None),
+    };
     
-    let file = PathBuf::from(parts[0]);
-    let line = parts[1]
+    let file = PathBuf::from(file);
+    let line = line
         .parse::<u32>()
         .map_err(|_| "Line number must be a positive integer".to_string())?;
     
@@ -93,236 +685,1982 @@ fn parse_file_location(s: &str) -> Result<FileLocation, String> {
         return Err("Line number must be greater than 0".to_string());
     }
     
-    Ok(FileLocation { file, line })
+    let column = match column {
+        Some(column) => match column.parse::<u32>() {
+            Ok(0) => return Err("Column must be greater than 0".to_string()),
+            Ok(column) => Some(column),
+            Err(_) => return Err("Column must be a positive integer".to_string()),
+        },
+        None => None,
+    };
+    
+    Ok(FileLocation { file, line, column })
+}
+
+impl Commands {
+    /// The location and symbol of a command that queries a symbol on a line
+    fn symbol_location(&mut self) -> Option<(&mut FileLocation, &str)> {
+        match self {
+            Commands::Docs { target: Target::Location(location), symbol: Some(symbol) }
+            | Commands::Def { target: Target::Location(location), symbol: Some(symbol) }
+            | Commands::Impl { target: Target::Location(location), symbol: Some(symbol) }
+            | Commands::Refs { target: Target::Location(location), symbol: Some(symbol), .. }
+            | Commands::Migrate { target: Target::Location(location), symbol: Some(symbol), .. }
+            | Commands::Badge { target: Target::Location(location), symbol: Some(symbol), .. }
+            | Commands::Type { location, symbol }
+            | Commands::Callers { location, symbol, .. }
+            | Commands::Callees { location, symbol, .. }
+            | Commands::AsyncCallers { location, symbol, .. }
+            | Commands::ErrorFlow { location, symbol }
+            | Commands::Rename { location, symbol, .. }
+            | Commands::Actions { location, symbol: Some(symbol), .. }
+            | Commands::Expand { location, symbol: Some(symbol) }
+            | Commands::Hierarchy { location, symbol } => Some((location, symbol)),
+            _ => None,
+        }
+    }
+}
+
+/// Pin the symbol `command` queries to a column: that of its `occurrence` on
+/// the line when given, noting on stderr when the line holds several otherwise
+fn pin_occurrence(command: &mut Commands, occurrence: Option<u32>) -> Result<()> {
+    let Some((location, symbol)) = command.symbol_location() else {
+        if occurrence.is_some() {
+            bail!("--occurrence needs a file:line location and a symbol to count");
+        }
+        return Ok(());
+    };
+    if location.column.is_some() {
+        if occurrence.is_some() {
+            bail!("Give either a column or --occurrence, not both");
+        }
+        return Ok(());
+    }
+    
+    let line = std::fs::read_to_string(&location.file)
+        .ok()
+        .and_then(|contents| contents.lines().nth(location.line as usize - 1).map(str::to_string));
+    let columns = line.as_deref().map(|line| symbol_columns(line, symbol)).unwrap_or_default();
+    match occurrence {
+        Some(n) => {
+            let column = n.checked_sub(1).and_then(|index| columns.get(index as usize)).with_context(|| {
+                format!("Symbol '{}' occurs {} time(s) on {}:{}, not {}", symbol, columns.len(), location.file.display(), location.line, n)
+            })?;
+            location.column = Some(column + 1);
+        }
+        None if columns.len() > 1 => {
+            errln!(
+                "Note: '{}' occurs {} times on {}:{}; querying the first (pick another with --occurrence N or file:line:column)",
+                symbol,
+                columns.len(),
+                location.file.display(),
+                location.line
+            );
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// What `docs`, `impl` and `refs` look up
+#[derive(Debug, Clone)]
+enum Target {
+    Location(FileLocation),
+    /// A qualified path, which names the symbol too
+    Path(String),
+    /// The code between two locations, for `docs`
+    Selection(Selection),
+}
+
+#[derive(Debug, Clone)]
+struct Selection {
+    file: PathBuf,
+    /// 1-based line and column of the first character
+    start: (u32, u32),
+    /// 1-based line and column of the last character
+    end: (u32, u32),
+}
+
+/// A location, a selection when `s` ends in `-line:column`, or a qualified path
+/// when `s` has a `::` or no `:` at all
+fn parse_target(s: &str) -> Result<Target, String> {
+    if s.contains("::") || !s.contains(':') {
+        Ok(Target::Path(s.to_string()))
+    } else if let Some(selection) = parse_selection(s)? {
+        Ok(Target::Selection(selection))
+    } else {
+        parse_file_location(s).map(Target::Location)
+    }
+}
+
+/// A selection such as `src/main.rs:42:9-44:6`; `None` when `s` does not end in `-line:column`
+fn parse_selection(s: &str) -> Result<Option<Selection>, String> {
+    let Some((start, end)) = s.rsplit_once('-') else {
+        return Ok(None);
+    };
+    let Some(end) = end.split_once(':').and_then(|(line, column)| Some((line.parse::<u32>().ok()?, column.parse::<u32>().ok()?))) else {
+        return Ok(None);
+    };
+    let location = parse_file_location(start)?;
+    let Some(column) = location.column else {
+        return Err("Expected format: file:line:column-line:column (e.g., src/main.rs:42:9-44:6)".to_string());
+    };
+    let start = (location.line, column);
+    if end.0 == 0 || end.1 == 0 {
+        return Err("Line and column numbers must be greater than 0".to_string());
+    }
+    if end < start {
+        return Err(format!("Selection {}:{}-{}:{} ends before it starts", start.0, start.1, end.0, end.1));
+    }
+    Ok(Some(Selection { file: location.file, start, end }))
+}
+
+/// Why a target and symbol do not go together
+fn target_mismatch(target: &Target) -> anyhow::Error {
+    match target {
+        Target::Location(location) => anyhow::anyhow!("Missing the symbol to query at {}:{}", location.file.display(), location.line),
+        Target::Path(path) => anyhow::anyhow!(
+            "Expected file:line before the symbol (e.g., src/main.rs:42), or a qualified path on its own; {:?} is neither",
+            path
+        ),
+        Target::Selection(_) => anyhow::anyhow!("A selection only works with `lq docs`, without a symbol"),
+    }
+}
+
+/// A location for commands that work on whole lines
+fn parse_file_line(s: &str) -> Result<FileLocation, String> {
+    let location = parse_file_location(s)?;
+    if location.column.is_some() {
+        return Err("Expected format: file:line (e.g., src/main.rs:42); this command takes no column".to_string());
+    }
+    Ok(location)
+}
+
+#[derive(Debug, Clone)]
+struct FileRange {
+    file: PathBuf,
+    /// Inclusive 1-based lines; the whole file when absent
+    lines: Option<(u32, u32)>,
+}
+
+fn parse_file_range(s: &str) -> Result<FileRange, String> {
+    let Some((file, range)) = s.rsplit_once(':') else {
+        return Ok(FileRange { file: PathBuf::from(s), lines: None });
+    };
+    
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let parse = |line: &str| match line.parse::<u32>() {
+        Ok(0) => Err("Line numbers must be greater than 0".to_string()),
+        Ok(line) => Ok(line),
+        Err(_) => Err("Expected format: file, file:line or file:start-end (e.g., src/main.rs:10-20)".to_string()),
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if end < start {
+        return Err(format!("Line range {} ends before it starts", range));
+    }
+    
+    Ok(FileRange { file: PathBuf::from(file), lines: Some((start, end)) })
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let cli = Cli::parse_from(&args);
+    set_ascii(cli.ascii);
+    
+    let result = match resolve_command_line(cli, args) {
+        Ok(cli) => {
+            set_ascii(cli.ascii);
+            run(cli).await
+        }
+        Err(e) => Err(e),
+    };
+    // The standard report would print the error unescaped
+    if let Err(e) = &result {
+        if is_ascii() {
+            errln!("Error: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+    result
+}
+
+/// What the command line `cli` was parsed from stands for, with a preset run by
+/// `lq run` expanded, the configured default flags of the command added, and its
+/// configured formatter
+fn resolve_command_line(cli: Cli, args: Vec<OsString>) -> Result<Cli> {
+    let workspace = current_workspace()?;
+    let (args, exclude) = match &cli.command {
+        Commands::Run { name: Some(name), args: trailing } => expand_preset(&workspace, name, trailing.len(), args)?,
+        _ => (args, Vec::new()),
+    };
+    let name = subcommand_index(&args).and_then(|index| args[index].to_str()).map(str::to_string);
+    let with_defaults = with_default_flags(&args, &default_flags(&workspace)?);
+    let mut cli = if with_defaults.is_none() && !matches!(cli.command, Commands::Run { name: Some(_), .. }) {
+        cli
+    } else {
+        let mut cli = Cli::parse_from(with_defaults.unwrap_or(args));
+        cli.exclude.extend(exclude);
+        cli
+    };
+    if let Some(name) = name {
+        cli.formatter = formatters(&workspace, cli.trust)?.remove(&name);
+    }
+    Ok(cli)
+}
+
+/// The command line `lq run <name> <trailing args>` stands for, and the globs to exclude.
+///
+/// The preset's method and arguments take the place of `run <name>`, and its
+/// format is put first, so one given on the command line wins. Its globs are
+/// returned to be added to any given with `--exclude`.
+fn expand_preset(workspace: &Path, name: &str, trailing: usize, args: Vec<OsString>) -> Result<(Vec<OsString>, Vec<String>)> {
+    let presets = presets(workspace)?;
+    let Some(preset) = presets.get(name) else {
+        bail!("No preset named {:?}; `lq run` lists the configured ones", name);
+    };
+    if preset.method.is_empty() || preset.method == "run" {
+        bail!("Preset {:?} needs a `method` naming another lq command", name);
+    }
     
+    // Trailing arguments are the end of the command line, right after the name
+    let name_index = args.len() - trailing - 1;
+    let run_index = args[..name_index].iter().rposition(|arg| arg == "run").context("Failed to find `run` on the command line")?;
+    
+    let mut expanded: Vec<OsString> = vec![args[0].clone()];
+    if let Some(format) = &preset.format {
+        expanded.extend(["--format".into(), format.into()]);
+    }
+    expanded.extend(args[1..run_index].iter().chain(&args[run_index + 1..name_index]).cloned());
+    expanded.push(preset.method.as_str().into());
+    expanded.extend(preset.args.iter().map(OsString::from));
+    expanded.extend(args[name_index + 1..].iter().cloned());
+    Ok((expanded, preset.exclude.clone()))
+}
+
+/// `args` with the default flags configured for its command right after the
+/// command name, and the flags that came before the name moved after them, so
+/// every flag on the command line overrides its default; `None` without defaults
+fn with_default_flags(args: &[OsString], defaults: &BTreeMap<String, Vec<String>>) -> Option<Vec<OsString>> {
+    let index = subcommand_index(args)?;
+    let flags = defaults.get(args[index].to_str()?).filter(|flags| !flags.is_empty())?;
+    let mut expanded = vec![args[0].clone(), args[index].clone()];
+    expanded.extend(flags.iter().map(OsString::from));
+    expanded.extend(args[1..index].iter().chain(&args[index + 1..]).cloned());
+    Some(expanded)
+}
+
+/// Where the command name is in `args`, past the global flags and their values
+fn subcommand_index(args: &[OsString]) -> Option<usize> {
+    let command = Cli::command();
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        let Some(flag) = arg.to_str()?.strip_prefix("--") else {
+            return Some(index);
+        };
+        let takes_value = command
+            .get_arguments()
+            .any(|argument| argument.get_long() == Some(flag) && argument.get_action().takes_values());
+        index += if takes_value { 2 } else { 1 };
+    }
+    None
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    let format = cli.format.for_command(&mut cli.command)?;
     match cli.command {
-        Commands::Daemon { workspace } => {
-            // Initialize logging for daemon
+        Commands::Daemon { workspace, ready_timeout, max_restarts, idle_timeout, analyzer } => {
+            // Initialize logging for daemon into the per-workspace log file
+            let log_path = get_log_path(&workspace)?;
+            let log_dir = log_path.parent().context("Invalid log path")?;
+            let log_name = log_path.file_name().context("Invalid log path")?;
+            let (log_writer, _log_guard) = tracing_appender::non_blocking(
+                tracing_appender::rolling::never(log_dir, log_name)
+            );
+            
             tracing_subscriber::registry()
                 .with(
                     tracing_subscriber::fmt::layer()
                         .with_target(false)
                         .with_thread_ids(true)
+                        .with_ansi(false)
+                        .with_writer(log_writer)
+                )
+                .with(
+                    tracing_subscriber::EnvFilter::try_from_default_env()
+                        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(daemon_log_level(&workspace)))
                 )
-                .with(tracing_subscriber::EnvFilter::from_default_env())
                 .init();
             
-            run_daemon(workspace).await
+            let server = start_daemon(&workspace, cli.trust, cli.server_path, ready_timeout, max_restarts, idle_timeout, analyzer).await;
+            match &server {
+                Ok(_) => report_startup(&StartupStatus::Ready),
+                Err(e) => {
+                    tracing::error!("Failed to start daemon: {:#}", e);
+                    report_startup(&StartupStatus::Failed(format!("{:#}", e)));
+                }
+            }
+            if let Err(e) = redirect_output(&log_path) {
+                tracing::warn!("{:#}", e);
+            }
+            server?.run().await
+        }
+        Commands::Daemons => list_daemons(format).await,
+        Commands::Run { .. } => list_presets(format),
+        Commands::Schema { command } => print_schema(command.as_deref(), format),
+        Commands::Genclient { language } => {
+            out!("{}", generate_client(language)?);
+            Ok(())
+        }
+        Commands::Completions { shell } => {
+            out!("{}", completion_script(shell));
+            Ok(())
+        }
+        Commands::Fixture { dir, crates, modules, items, force } => {
+            let summary = write_fixture(&dir, FixtureSpec { crates, modules, items }, force)?;
+            outln!("Wrote {} files, {} lines, to {}", summary.files, summary.lines, dir.display());
+            Ok(())
+        }
+        Commands::Trust { path } => {
+            let workspace = match path {
+                Some(path) => path,
+                None => current_workspace()?,
+            };
+            let mut config = Config::load()?;
+            config.trust(&workspace);
+            config.save()?;
+            outln!("Trusted {} (saved to {})", workspace.display(), config_path()?.display());
+            Ok(())
+        }
+        Commands::Init { completions, git_hooks, no_warmup, force } => {
+            init_workspace(completions, git_hooks, !no_warmup, force, cli.trust, cli.server_path).await
+        }
+        Commands::Logs { lines, follow } => {
+            // Logs are read straight from the file, no daemon needed
+            let workspace = current_workspace()?;
+            let log_path = get_log_path(&workspace)?;
+            
+            let offset = logs::print_log_tail(&log_path, lines)?;
+            if follow {
+                logs::follow_log(&log_path, offset).await?;
+            }
+            Ok(())
         }
+        Commands::Metrics { action: MetricsAction::Dump } => dump_metrics(format),
         _ => {
             // For client commands, find workspace and ensure daemon is running
-            let workspace = std::env::current_dir()
-                .context("Failed to get current directory")?;
+            let workspace = current_workspace()?;
+            // Relative files in batch, stdio and MCP requests are taken from here, as on the command line
+            let cwd = std::env::current_dir().context("Failed to get current directory")?;
+            let mut command = cli.command;
+            pin_occurrence(&mut command, cli.occurrence)?;
+            let order = result_order(cli.sort, &mut command)?;
+            
+            let needs_daemon = cli.replay.is_none() && !is_daemon_running(&get_socket_path(&workspace)?).await;
+            if needs_daemon && !cli.trust {
+                prompt_for_trust(&workspace)?;
+            }
             
-            let socket_path = get_socket_path(&workspace)?;
+            let options = ClientOptions {
+                executable: std::env::current_exe()
+                    .context("Failed to get current executable")?,
+                record: cli.record,
+                replay: cli.replay,
+                best_effort: cli.best_effort.map(std::time::Duration::from_millis),
+                allow_stale: cli.allow_stale,
+                timeout: cli.timeout.map(std::time::Duration::from_secs),
+                trust: cli.trust,
+                server_path: cli.server_path,
+                codec: match command {
+                    Commands::Batch { ipc_codec, .. } => ipc_codec,
+                    _ => Codec::Json,
+                },
+                compression: match command {
+                    Commands::Batch { ipc_compression, .. } => ipc_compression,
+                    _ => Compression::None,
+                },
+                progress: std::io::stderr().is_terminal(),
+                // Within a monorepo, the member the current directory lies in
+                member: Some(find_workspace_root(&cwd)).filter(|member| *member != workspace),
+                ..Default::default()
+            };
+            let client = Client::connect_with_options(&workspace, options).await?;
+            client.cancel_on_interrupt();
+            let template = match cli.link_template {
+                Some(template) => Some(template),
+                None => link_template(&workspace)?,
+            };
+            let links = template.map(|template| Permalinks::for_workspace(&workspace, &template)).transpose()?;
+            let redactions = Redactions::new(&redact_config(&workspace)?)?;
+            // Only a person at a terminal can pick a candidate
+            let interactive = !cli.no_interactive && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
             
-            // Start daemon if not running
-            if !is_daemon_running(&socket_path).await {
-                start_daemon(&workspace)?;
-                
-                // Wait for daemon to be ready
-                for _ in 0..50 {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    if is_daemon_running(&socket_path).await {
-                        break;
+            // Send request to daemon
+            match command {
+                Commands::Batch { .. } if cli.watch => bail!("--watch does not apply to lq batch"),
+                Commands::Batch { .. } => run_batch(&client, &cwd, &cli.exclude, links.as_ref(), &redactions).await,
+                Commands::Stdio if cli.watch => bail!("--watch does not apply to lq stdio"),
+                Commands::Stdio => {
+                    stdio::serve(&client, &cwd, |result| finish_json(result, &cli.exclude, links.as_ref(), &redactions)).await
+                }
+                Commands::Mcp if cli.watch => bail!("--watch does not apply to lq mcp"),
+                Commands::Mcp => {
+                    mcp::serve(&client, &cwd, |result| finish_json(result, &cli.exclude, links.as_ref(), &redactions)).await
+                }
+                Commands::Rename { apply: true, .. } | Commands::Ssr { apply: true, .. } | Commands::Actions { apply: Some(_), .. } if cli.watch => {
+                    // Each edit would set off the next run
+                    bail!("--watch cannot be combined with --apply")
+                }
+                command if cli.watch => {
+                    let mut watcher = SourceWatcher::new(&workspace)?;
+                    loop {
+                        if format == OutputFormat::Text && std::io::stdout().is_terminal() && !is_ascii() {
+                            // Clear the screen, so only the latest answer shows
+                            out!("\x1b[2J\x1b[H");
+                        }
+                        send_request_to_daemon(&client, command.clone(), format, cli.formatter.as_deref(), order, &cli.exclude, links.as_ref(), &redactions, false, cli.timing).await?;
+                        errln!("(watching for changes, Ctrl-C to stop)");
+                        if watcher.changed().await.is_empty() {
+                            return Ok(());
+                        }
                     }
                 }
-                
-                if !is_daemon_running(&socket_path).await {
-                    anyhow::bail!("Failed to start daemon");
+                command => {
+                    if !send_request_to_daemon(&client, command, format, cli.formatter.as_deref(), order, &cli.exclude, links.as_ref(), &redactions, interactive, cli.timing).await? {
+                        std::process::exit(1);
+                    }
+                    Ok(())
                 }
             }
-            
-            // Send request to daemon
-            send_request_to_daemon(&socket_path, cli.command).await
         }
     }
 }
 
-async fn run_daemon(workspace: PathBuf) -> Result<()> {
-    let socket_path = get_socket_path(&workspace)?;
-    let server = DaemonServer::new(&workspace, socket_path).await?;
-    server.run().await
+/// Answer the requests on stdin in order, writing each response as soon as it arrives.
+///
+/// Responses carry the line number of their request as `id`; a line that is
+/// not a valid request gets an error response rather than ending the batch.
+async fn run_batch(client: &Client, workspace: &Path, exclude: &[String], links: Option<&Permalinks>, redactions: &Redactions) -> Result<()> {
+    use tokio::io::AsyncBufReadExt;
+    
+    let mut session = client.session();
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut number = 0;
+    while let Some(line) = lines.next_line().await.context("Failed to read stdin")? {
+        number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match batch_method(&line, workspace) {
+            Ok(method) => session.request(method).await?,
+            Err(e) => ResponseResult::Error { error: format!("Invalid request: {:#}", e) },
+        };
+        let result = match result {
+            ResponseResult::Success { mut result } => {
+                finish_json(&mut result, exclude, links, redactions);
+                ResponseResult::Success { result }
+            }
+            error => error,
+        };
+        outln!("{}", to_json_line(&Response { id: number.to_string(), result })?);
+    }
+    Ok(())
+}
+
+/// The output steps of a command line that apply to answers passed on as JSON
+/// by `lq batch`, `lq stdio` and `lq mcp`
+fn finish_json(result: &mut serde_json::Value, exclude: &[String], links: Option<&Permalinks>, redactions: &Redactions) {
+    exclude_paths(result, exclude);
+    sort_results(result, ResultOrder::Default);
+    redact(result, redactions);
+    if let Some(links) = links {
+        add_links(result, links);
+    }
 }
 
-fn start_daemon(workspace: &PathBuf) -> Result<()> {
-    let exe = std::env::current_exe()
-        .context("Failed to get current executable")?;
-    
-    // Create log file for daemon
-    let log_path = std::env::temp_dir().join("lq-daemon.log");
-    let log_file = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_path)
-        .context("Failed to open log file")?;
-    
-    Command::new(&exe)
-        .arg("daemon")
-        .arg("--workspace")
-        .arg(workspace)
-        .stdin(std::process::Stdio::null())
-        .stdout(log_file.try_clone()?)
-        .stderr(log_file)
-        .spawn()
-        .context("Failed to spawn daemon")?;
+/// The request on a batch line, with a relative `file` taken relative to `dir`
+/// as on the command line
+fn batch_method(line: &str, dir: &Path) -> Result<Method> {
+    let mut request: serde_json::Value = serde_json::from_str(line)?;
+    resolve_file(&mut request, dir);
+    Ok(serde_json::from_value(request)?)
+}
+
+/// Ask on the terminal whether to trust an unknown workspace, remembering a yes.
+///
+/// Without a terminal nothing is asked; connecting then fails with instructions.
+fn prompt_for_trust(workspace: &Path) -> Result<()> {
+    let mut config = Config::load()?;
+    if config.is_trusted(workspace) || !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(());
+    }
     
+    eprint!(
+        "Workspace {} is not trusted. Starting a daemon runs rust-analyzer, which executes its build \
+         scripts and proc macros.\nTrust it and remember the choice? [y/N] ",
+        workspace.display()
+    );
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        config.trust(workspace);
+        config.save()?;
+    }
     Ok(())
 }
 
-async fn send_request_to_daemon(socket_path: &PathBuf, command: Commands) -> Result<()> {
-    let mut stream = UnixStream::connect(socket_path).await
-        .context("Failed to connect to daemon")?;
+/// The completion script for `lq` in `shell`
+fn completion_script(shell: clap_complete::Shell) -> String {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "lq", &mut script);
+    String::from_utf8_lossy(&script).into_owned()
+}
+
+/// The workspace the current directory lies in, whose daemon answers for it:
+/// the monorepo it is part of, if any, else its Cargo workspace
+fn current_workspace() -> Result<PathBuf> {
+    let dir = std::env::current_dir().context("Failed to get current directory")?;
+    Ok(monorepo_root(&dir).unwrap_or_else(|| find_workspace_root(&dir)))
+}
+
+/// Run the `lq init` steps for the workspace of the current directory, reporting each; the warmup
+/// is skipped when it could not succeed
+async fn init_workspace(completions: Option<Shell>, git_hooks: bool, warmup: bool, force: bool, trust: bool, server_path: Option<PathBuf>) -> Result<()> {
+    let workspace = current_workspace()?;
     
-    let request = match command {
-        Commands::Docs { location, symbol } => {
-            let absolute_file = if location.file.is_absolute() {
-                location.file
-            } else {
-                std::env::current_dir()
-                    .unwrap_or_default()
-                    .join(&location.file)
-            };
-            Request {
-                id: uuid::Uuid::new_v4().to_string(),
-                method: Method::Docs {
-                    file: absolute_file,
-                    line: location.line,
-                    symbol,
-                },
-            }
-        },
-        Commands::Impl { location, symbol } => {
-            let absolute_file = if location.file.is_absolute() {
-                location.file
-            } else {
-                std::env::current_dir()
-                    .unwrap_or_default()
-                    .join(&location.file)
-            };
-            Request {
-                id: uuid::Uuid::new_v4().to_string(),
-                method: Method::Impl {
-                    file: absolute_file,
-                    line: location.line,
-                    symbol,
-                },
+    if write_starter_config(&workspace, force)? {
+        outln!("Wrote a starter .lq.toml");
+    } else {
+        outln!("Kept the existing .lq.toml (--force replaces it)");
+    }
+    
+    let checks = check_toolchains(&workspace, server_path.as_deref());
+    if checks.is_empty() {
+        outln!("No Cargo.toml found; lq serves Rust workspaces only");
+    }
+    for check in &checks {
+        match &check.version {
+            Some(version) => outln!("{}: found {}", check.language, version),
+            None => outln!("{}: {} is missing; {}", check.language, check.tool, check.install_hint),
+        }
+    }
+    
+    if let Some(shell) = completions {
+        let generator = match shell {
+            Shell::Bash => clap_complete::Shell::Bash,
+            Shell::Zsh => clap_complete::Shell::Zsh,
+            Shell::Fish => clap_complete::Shell::Fish,
+        };
+        let path = install_completions(shell, completion_script(generator).as_bytes())?;
+        outln!("Installed {} completions to {}", generator, path.display());
+        if shell == Shell::Zsh {
+            outln!("  Load them by adding `fpath=(~/.zfunc $fpath)` before `compinit` in ~/.zshrc");
+        }
+    }
+    
+    if git_hooks {
+        for install in install_git_hooks(&workspace)? {
+            match install {
+                HookInstall::Installed(path) => outln!("Installed git hook {}", path.display()),
+                HookInstall::Skipped(path) => outln!("Left the existing git hook {} alone", path.display()),
             }
-        },
-        Commands::Refs { location, symbol } => {
-            let absolute_file = if location.file.is_absolute() {
-                location.file
-            } else {
-                std::env::current_dir()
-                    .unwrap_or_default()
-                    .join(&location.file)
+        }
+    }
+    
+    if !warmup {
+        return Ok(());
+    }
+    if checks.is_empty() || checks.iter().any(|check| check.version.is_none()) {
+        outln!("Skipping the warmup until the toolchain is in place");
+        return Ok(());
+    }
+    if !trust {
+        prompt_for_trust(&workspace)?;
+        if !Config::load()?.is_trusted(&workspace) {
+            outln!("Skipping the warmup: the workspace is not trusted yet (see `lq trust`)");
+            return Ok(());
+        }
+    }
+    
+    outln!("Warming up: starting the daemon and waiting for rust-analyzer to index the workspace...");
+    let started = std::time::Instant::now();
+    let options = ClientOptions {
+        executable: std::env::current_exe()
+            .context("Failed to get current executable")?,
+        trust,
+        server_path,
+        progress: std::io::stderr().is_terminal(),
+        ..Default::default()
+    };
+    let client = Client::connect_with_options(&workspace, options).await?;
+    // Like every query, a symbol search first waits for indexing to finish,
+    // unless it was answered before a restart and comes from the cache
+    client.search("main", 1).await?;
+    let mut status = client.status().await?;
+    while status.status == "starting" {
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        status = client.status().await?;
+    }
+    match status.crash_reason {
+        Some(reason) => outln!("The language server is down: {}", reason),
+        None => outln!("Ready after {}s", started.elapsed().as_secs()),
+    }
+    Ok(())
+}
+
+/// The daemon's log filter when `RUST_LOG` is unset; a config that fails to
+/// load is reported once logging is up, by [`start_daemon`]
+fn daemon_log_level(workspace: &Path) -> String {
+    daemon_config(workspace).ok().and_then(|config| config.log_level).unwrap_or_else(|| "info".to_string())
+}
+
+/// Check the workspace is trusted, load the server options and start listening.
+/// Each setting comes from its flag or environment variable, then the config,
+/// then the default.
+async fn start_daemon(
+    workspace: &Path,
+    trust: bool,
+    server_path: Option<PathBuf>,
+    ready_timeout: Option<u64>,
+    max_restarts: Option<u32>,
+    idle_timeout: Option<u64>,
+    analyzer: AnalyzerFlags,
+) -> Result<DaemonServer> {
+    if !trust {
+        ensure_trusted(workspace)?;
+    }
+    
+    let daemon = daemon_config(workspace)?;
+    let server = analyzer.apply(server_config(workspace)?);
+    let ready_timeout = ready_timeout.or(daemon.ready_timeout_secs).unwrap_or(DEFAULT_READY_TIMEOUT.as_secs());
+    let options = ServerOptions {
+        executable: server_path.or_else(|| server.executable()),
+        initialization_options: server.initialization_options()?,
+        args: server.args,
+        ready_timeout: std::time::Duration::from_secs(ready_timeout),
+        sandbox: sandbox_command(workspace)?,
+        limits: resource_limits(workspace)?,
+        max_restarts: max_restarts.or(daemon.max_restarts).unwrap_or(DEFAULT_MAX_RESTARTS),
+        progress: None,
+    };
+    let idle_timeout = idle_timeout.or(daemon.idle_timeout_minutes)
+        .filter(|minutes| *minutes > 0)
+        .map(|minutes| std::time::Duration::from_secs(minutes * 60));
+    let socket_path = get_socket_path(workspace)?;
+    let server = DaemonServer::new(workspace, &monorepo_members(workspace)?, socket_path, options, priority_config(workspace)?, prefetch_config(workspace)?, retry_config(workspace)?, hooks_config(workspace)?, metrics_config()?).await?;
+    Ok(server.with_idle_timeout(idle_timeout))
+}
+
+/// Ask the daemon and print its answer, as text through `formatter` when one is
+/// configured; false if it answered with an error, which is printed too. When
+/// `interactive`, an answer with several candidates asks which one to go on with.
+#[allow(clippy::too_many_arguments)]
+async fn send_request_to_daemon(client: &Client, command: Commands, format: OutputFormat, formatter: Option<&[String]>, order: ResultOrder, exclude: &[String], links: Option<&Permalinks>, redactions: &Redactions, interactive: bool, timing: bool) -> Result<bool> {
+    let check_coverage = matches!(command, Commands::CoverageMap { check: true });
+    let apply_edit = match command {
+        Commands::Rename { apply: true, force, .. } | Commands::Ssr { apply: true, force, .. } => Some(force),
+        _ => None,
+    };
+    let apply_action = match command {
+        Commands::Actions { apply: Some(index), force, .. } => Some((index, force)),
+        _ => None,
+    };
+    let graph = match command {
+        Commands::Callers { graph, .. } | Commands::Callees { graph, .. } => graph,
+        _ => None,
+    };
+    // References grouped by file; `Some(true)` for only the counts
+    let refs_by_file = match command {
+        Commands::Refs { count: true, .. } => Some(true),
+        Commands::Refs { group_by_file: true, .. } => Some(false),
+        _ => None,
+    };
+    let (before, after) = match command {
+        Commands::Refs { after_context, before_context, context, .. } => {
+            (before_context.or(context).unwrap_or(0), after_context.or(context).unwrap_or(0))
+        }
+        _ => (0, 0),
+    };
+    // A migration checklist is built from a plain refs answer
+    let (command, migrate) = match command {
+        Commands::Migrate { target, symbol, track } => {
+            let item = match (&target, &symbol) {
+                (_, Some(symbol)) => symbol.clone(),
+                (Target::Path(path), None) => path.clone(),
+                (Target::Location(_) | Target::Selection(_), None) => String::new(),
             };
-            Request {
-                id: uuid::Uuid::new_v4().to_string(),
-                method: Method::Refs {
-                    file: absolute_file,
-                    line: location.line,
-                    symbol,
-                },
+            (Commands::Refs { target, symbol, raw: false, after_context: None, before_context: None, context: None, group_by_file: false, count: false, transitive: false, depth: 0 }, Some((item, track)))
+        }
+        command => (command, None),
+    };
+    // So is a badge
+    let (command, badge) = match command {
+        Commands::Badge { target, symbol, label } => {
+            (Commands::Refs { target, symbol, raw: false, after_context: None, before_context: None, context: None, group_by_file: false, count: false, transitive: false, depth: 0 }, Some(label))
+        }
+        command => (command, None),
+    };
+    
+    let method = match command {
+        Commands::Docs { target: Target::Path(path), symbol: None } => Method::AtPath { path, query: PathQuery::Docs, raw: false },
+        Commands::Docs { target: Target::Location(location), symbol: Some(symbol) } => {
+            Method::Docs {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::Docs { target: Target::Selection(selection), symbol: None } => {
+            Method::DocsRange {
+                file: absolute(&selection.file),
+                start_line: selection.start.0,
+                start_column: selection.start.1,
+                end_line: selection.end.0,
+                end_column: selection.end.1,
+            }
+        },
+        Commands::Def { target: Target::Path(path), symbol: None } => Method::AtPath { path, query: PathQuery::Def, raw: false },
+        Commands::Def { target: Target::Location(location), symbol: Some(symbol) } => {
+            Method::Definition {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::Impl { target: Target::Path(path), symbol: None } => Method::AtPath { path, query: PathQuery::Impl, raw: false },
+        Commands::Impl { target: Target::Location(location), symbol: Some(symbol) } => {
+            Method::Impl {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::Type { location, symbol } => {
+            Method::TypeDefinition {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::Refs { target: Target::Location(location), symbol: Some(symbol), transitive: true, depth, .. } => {
+            Method::TransitiveRefs {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+                depth,
+            }
+        },
+        Commands::Refs { target: Target::Path(_), symbol: None, transitive: true, .. } => {
+            bail!("--transitive follows a function given by file:line and name (e.g., src/main.rs:42 parse), not a qualified path");
+        }
+        Commands::Refs { target: Target::Path(path), symbol: None, raw, .. } => Method::AtPath { path, query: PathQuery::Refs, raw },
+        Commands::Refs { target: Target::Location(location), symbol: Some(symbol), raw, .. } => {
+            Method::Refs {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+                raw,
+            }
+        },
+        Commands::Docs { target, .. } | Commands::Def { target, .. } | Commands::Impl { target, .. } | Commands::Refs { target, .. } => {
+            return Err(target_mismatch(&target));
+        }
+        Commands::Callers { location, symbol, depth, .. } => {
+            Method::Callers {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+                depth,
+            }
+        },
+        Commands::AsyncCallers { location, symbol, depth } => {
+            Method::AsyncCallers {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+                depth,
+            }
+        },
+        Commands::Bounds { location } => {
+            let column = location.column.context("lq bounds needs the column of the call, as in src/main.rs:42:9")?;
+            Method::Bounds {
+                file: absolute(&location.file),
+                line: location.line,
+                column,
+            }
+        },
+        Commands::ErrorFlow { location, symbol } => {
+            Method::ErrorFlow {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::Strings { pattern, log } => Method::Strings { pattern, log },
+        Commands::Callees { location, symbol, depth, .. } => {
+            Method::Callees {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+                depth,
             }
         },
         Commands::Resolve { symbol, file } => {
-            // Convert relative path to absolute
-            let absolute_file = if file.is_absolute() {
-                file
-            } else {
-                std::env::current_dir()
-                    .unwrap_or_default()
-                    .join(&file)
-            };
-            Request {
-                id: uuid::Uuid::new_v4().to_string(),
-                method: Method::Resolve { 
-                    file: absolute_file,
-                    symbol,
-                },
+            Method::Resolve { 
+                file: absolute(&file),
+                symbol,
             }
         },
-        Commands::Status => Request {
-            id: uuid::Uuid::new_v4().to_string(),
-            method: Method::Status,
+        Commands::Outline { file } => {
+            Method::Outline { file: absolute(&file) }
         },
-        Commands::Stop => Request {
-            id: uuid::Uuid::new_v4().to_string(),
-            method: Method::Shutdown,
+        Commands::Hints { range } => {
+            Method::Hints {
+                file: absolute(&range.file),
+                start_line: range.lines.map(|(start, _)| start),
+                end_line: range.lines.map(|(_, end)| end),
+            }
         },
-        Commands::Logs { lines } => {
-            eprintln!("Log viewing not yet implemented (would show {} lines)", lines);
-            return Ok(());
+        Commands::Search { query, limit } => Method::Search { query, limit },
+        Commands::Diag { file, severity } => {
+            Method::Diag { file: file.as_deref().map(absolute), severity }
+        },
+        Commands::Rename { location, symbol, new_name, .. } => {
+            Method::Rename {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+                new_name,
+            }
+        },
+        Commands::Actions { location, symbol, .. } => {
+            Method::Actions {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::Expand { location, symbol } => {
+            Method::Expand {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::Hir { location } => {
+            Method::Hir {
+                file: absolute(&location.file),
+                line: location.line,
+            }
+        },
+        Commands::Syntax { location } => {
+            Method::Syntax {
+                file: absolute(&location.file),
+                line: location.line,
+            }
+        },
+        Commands::Runnables { file } => {
+            Method::Runnables { file: file.as_deref().map(absolute) }
+        },
+        Commands::Ssr { rule, within, .. } => {
+            let file = within.as_ref().map(|within| absolute(&within.file));
+            let lines = within.and_then(|within| within.lines);
+            Method::Ssr {
+                rule,
+                file,
+                start_line: lines.map(|(start, _)| start),
+                end_line: lines.map(|(_, end)| end),
+            }
+        },
+        Commands::Hierarchy { location, symbol } => {
+            Method::Hierarchy {
+                file: absolute(&location.file),
+                line: location.line,
+                column: location.column,
+                symbol,
+            }
+        },
+        Commands::GenericHotspots { top } => Method::GenericHotspots { top },
+        Commands::CoverageMap { .. } => Method::CoverageMap,
+        Commands::Hotspots { top, sample, concurrency } => Method::Hotspots { top, sample, concurrency },
+        Commands::Tour { top } => Method::Tour { top },
+        Commands::Churn { top, since, sort } => Method::Churn { top, since, sort },
+        Commands::DiffRefs { a, b, rev } => {
+            if rev.len() > 2 {
+                bail!("--rev may be given at most twice");
+            }
+            Method::DiffRefs { a, b, revs: rev }
         }
-        _ => unreachable!(),
+        Commands::Status => Method::Status,
+        Commands::Ping => Method::Ping,
+        Commands::Stats => Method::Stats,
+        Commands::Stop => Method::Shutdown,
+        Commands::Daemon { .. } | Commands::Daemons | Commands::Logs { .. } | Commands::Metrics { .. } | Commands::Schema { .. } | Commands::Genclient { .. } | Commands::Completions { .. } | Commands::Fixture { .. } | Commands::Trust { .. } | Commands::Init { .. } | Commands::Run { .. } | Commands::Batch { .. } | Commands::Stdio | Commands::Mcp | Commands::Migrate { .. } | Commands::Badge { .. } => unreachable!(),
     };
     
-    // Send request
-    let request_bytes = serde_json::to_vec(&request)?;
-    let len_bytes = (request_bytes.len() as u32).to_be_bytes();
-    stream.write_all(&len_bytes).await?;
-    stream.write_all(&request_bytes).await?;
-    stream.flush().await?;
-    
-    // Read response
-    let mut len_buf = [0u8; 4];
-    stream.read_exact(&mut len_buf).await?;
-    let msg_len = u32::from_be_bytes(len_buf) as usize;
-    
-    let mut buffer = vec![0; msg_len];
-    stream.read_exact(&mut buffer).await?;
-    
-    let response: Response = serde_json::from_slice(&buffer)?;
-    
-    match response.result {
-        ResponseResult::Success { result } => {
-            // Format output based on method
-            match request.method {
-                Method::Docs { .. } => {
-                    if let Some(docs) = result.get("docs").and_then(|v| v.as_str()) {
-                        println!("{}", docs);
-                    }
+    let started = std::time::Instant::now();
+    let mut answer = client.request(method.clone()).await;
+    if let (Method::Ping, Ok(result)) = (&method, &mut answer) {
+        result["round_trip_ms"] = serde_json::json!(started.elapsed().as_millis() as u64);
+    }
+    if timing {
+        let retried = answer.as_ref().is_ok_and(|result| typed::<ResultFlags>(result).is_ok_and(|flags| flags.retried));
+        errln!(
+            "(timing: {} took {}ms{})",
+            method.name(),
+            started.elapsed().as_millis(),
+            if retried { ", including a retry after an empty answer right after the language server became ready" } else { "" }
+        );
+    }
+    match answer {
+        Ok(mut result) => {
+            exclude_paths(&mut result, exclude);
+            sort_results(&mut result, order);
+            add_context(&mut result, before, after);
+            redact(&mut result, redactions);
+            if let Some(links) = links {
+                add_links(&mut result, links);
+            }
+            if check_coverage {
+                check_coverage_map(&result, format)?;
+                return Ok(true);
+            }
+            if let Some((item, track)) = migrate {
+                migrate_result(&result, &item, track.as_deref(), links, format)?;
+                return Ok(true);
+            }
+            if let Some(label) = badge {
+                let badge = Badge::references(&label, &typed::<RefsResult>(&result)?.references);
+                match format {
+                    OutputFormat::Text => out!("{}", badge),
+                    _ => print_json(&serde_json::to_value(&badge)?, format)?,
                 }
-                Method::Impl { .. } => {
-                    if let Some(implementation) = result.get("implementation").and_then(|v| v.as_str()) {
-                        println!("{}", implementation);
-                    }
+                return Ok(true);
+            }
+            if let Some(force) = apply_edit {
+                apply_edit_result(&method, result, force, format)?;
+                return Ok(true);
+            }
+            if let Some((index, force)) = apply_action {
+                apply_action_result(result, index, force, format)?;
+                return Ok(true);
+            }
+            if let Some(count_only) = refs_by_file {
+                refs_by_file_result(&result, count_only, format)?;
+                return Ok(true);
+            }
+            if let (Some(graph), Method::Callers { symbol, .. } | Method::Callees { symbol, .. }) = (graph, &method) {
+                let direction = if matches!(method, Method::Callers { .. }) { CallDirection::Incoming } else { CallDirection::Outgoing };
+                out!("{}", call_graph(symbol, &typed::<CallsResult>(&result)?.calls, direction, graph));
+                return Ok(true);
+            }
+            if interactive && format == OutputFormat::Text && formatter.is_none() {
+                if let Some(follow_up) = pick_candidate(&method, &mut result)? {
+                    return Box::pin(send_request_to_daemon(client, follow_up, format, formatter, order, exclude, links, redactions, false, timing)).await;
                 }
-                Method::Refs { .. } => {
-                    if let Some(references) = result.get("references").and_then(|v| v.as_array()) {
-                        for reference in references {
-                            if let Some(ref_str) = reference.as_str() {
-                                println!("{}", ref_str);
-                            }
-                        }
+            }
+            match (format, formatter) {
+                (OutputFormat::Json | OutputFormat::Jsonl, _) => print_json(&result, format)?,
+                (OutputFormat::Text, Some(formatter)) => match run_formatter(formatter, &result).await {
+                    Ok(text) => out!("{}", text),
+                    Err(e) => {
+                        errln!("(formatter failed, printing the usual output: {:#})", e);
+                        print_text(&method, &result)?;
                     }
+                },
+                (OutputFormat::Text, None) => print_text(&method, &result)?,
+            }
+            // JSON carries these flags in the payload itself
+            if format != OutputFormat::Json {
+                let flags: ResultFlags = typed(&result)?;
+                if flags.partial {
+                    errln!("(partial result: the --best-effort deadline passed before the answer was complete)");
                 }
-                Method::Resolve { .. } => {
-                    if let Some(resolved) = result.get("resolved").and_then(|v| v.as_str()) {
-                        println!("{}", resolved);
-                    }
+                if flags.stale {
+                    errln!("(stale result: files changed since this answer was computed; it is being refreshed)");
                 }
-                Method::Status => {
-                    println!("Status: {}", result.get("status").and_then(|v| v.as_str()).unwrap_or("unknown"));
-                    println!("Workspace: {}", result.get("workspace").and_then(|v| v.as_str()).unwrap_or("unknown"));
-                    println!("Indexing: {}", result.get("indexing").and_then(|v| v.as_bool()).unwrap_or(false));
+                if flags.degraded {
+                    errln!("(degraded result: the language server is down, so this comes from plain-text search)");
                 }
-                Method::Shutdown => {
-                    println!("Daemon stopped");
+            }
+        }
+        Err(error) => {
+            match format {
+                OutputFormat::Json | OutputFormat::Jsonl => outln!("{}", serde_json::json!({ "error": format!("{:#}", error) })),
+                OutputFormat::Text => errln!("Error: {:#}", error),
+            }
+            return Ok(false);
+        }
+    }
+    
+    Ok(true)
+}
+
+/// Let the user pick one of an answer's candidates on the terminal: the docs of
+/// the search match picked are asked for next, and the definitions not picked
+/// are dropped. Giving up leaves every candidate in the answer.
+fn pick_candidate(method: &Method, result: &mut serde_json::Value) -> Result<Option<Commands>> {
+    match method {
+        Method::Search { .. } => {
+            let symbols = typed::<SearchResult>(result)?.symbols;
+            if symbols.len() < 2 {
+                return Ok(None);
+            }
+            let items: Vec<String> = symbols.iter().map(ToString::to_string).collect();
+            Ok(pick("docs for:", &items)?.map(|index| {
+                let symbol = &symbols[index];
+                let location = FileLocation { file: symbol.file.clone(), line: symbol.line, column: Some(symbol.column) };
+                Commands::Docs { target: Target::Location(location), symbol: Some(symbol.name.clone()) }
+            }))
+        }
+        Method::Definition { .. } | Method::AtPath { query: PathQuery::Def, .. } => {
+            let definitions = typed::<DefResult>(result)?.definitions;
+            if definitions.len() < 2 {
+                return Ok(None);
+            }
+            let items: Vec<String> = definitions
+                .iter()
+                .map(|definition| {
+                    let line = definition.code.lines().nth(definition.start_line.saturating_sub(definition.code_start_line) as usize);
+                    format!("{}:{}: {}", definition.path.display(), definition.start_line, line.unwrap_or_default().trim())
+                })
+                .collect();
+            if let Some(index) = pick("definition:", &items)? {
+                let picked = result["definitions"][index].take();
+                result["definition"] = picked.clone();
+                result["definitions"] = serde_json::json!([picked]);
+            }
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Print a JSON payload pretty-printed, or as JSON Lines
+fn print_json(value: &serde_json::Value, format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Jsonl => {
+            let lines = to_json_lines(value)?;
+            if !lines.is_empty() {
+                outln!("{}", lines);
+            }
+        }
+        _ => outln!("{}", to_json(value)?),
+    }
+    Ok(())
+}
+
+/// The configured presets, with what each one runs
+fn list_presets(format: OutputFormat) -> Result<()> {
+    let workspace = current_workspace()?;
+    let presets = presets(&workspace)?;
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl => print_json(&serde_json::json!({ "presets": presets }), format)?,
+        OutputFormat::Text => {
+            if presets.is_empty() {
+                outln!("No presets; define them as [preset.<name>] in .lq.toml or the global config");
+            }
+            for (name, preset) in &presets {
+                let command = std::iter::once(&preset.method).chain(&preset.args).cloned().collect::<Vec<_>>().join(" ");
+                let exclude = preset.exclude.iter().map(|glob| format!(" --exclude {}", glob)).collect::<String>();
+                let format = preset.format.as_ref().map(|format| format!(" --format {}", format)).unwrap_or_default();
+                outln!("{}: lq {}{}{}", name, command, exclude, format);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `result` read as the typed payload of the method it answers
+fn typed<T: DeserializeOwned>(result: &serde_json::Value) -> Result<T> {
+    serde_json::from_value(result.clone()).context("Malformed result in response")
+}
+
+/// Render a successful response payload as human-readable text
+fn print_text(method: &Method, result: &serde_json::Value) -> Result<()> {
+    match method {
+        Method::Docs { .. } | Method::DocsRange { .. } | Method::AtPath { query: PathQuery::Docs, .. } => {
+            if let Some(docs) = typed::<DocsResult>(result)?.docs {
+                outln!("{}", docs);
+            }
+        }
+        Method::Definition { .. } | Method::AtPath { query: PathQuery::Def, .. } => {
+            let definitions = typed::<DefResult>(result)?.definitions;
+            if definitions.is_empty() {
+                outln!("No definition found");
+            }
+            for definition in definitions {
+                outln!("{}\n", definition);
+            }
+        }
+        Method::Impl { .. } | Method::AtPath { query: PathQuery::Impl, .. } => {
+            let ImplResult { implementations, implementation, .. } = typed(result)?;
+            if implementations.is_empty() {
+                outln!("No implementations found");
+                if implementation.is_some() {
+                    errln!("Note: `lq impl` lists implementations now; `lq def` shows the definition it used to");
                 }
             }
+            for definition in implementations {
+                outln!("{}\n", definition);
+            }
         }
-        ResponseResult::Error { error } => {
-            eprintln!("Error: {}", error);
-            std::process::exit(1);
+        Method::TypeDefinition { .. } => {
+            let definitions = typed::<TypeResult>(result)?.definitions;
+            if definitions.is_empty() {
+                outln!("No type definition found");
+            }
+            for definition in definitions {
+                outln!("{}\n", definition);
+            }
+        }
+        Method::Refs { .. } | Method::AtPath { query: PathQuery::Refs, .. } => {
+            for (index, reference) in typed::<RefsResult>(result)?.references.iter().enumerate() {
+                let has_context = !reference.context_before.is_empty() || !reference.context_after.is_empty();
+                if index > 0 && has_context {
+                    outln!("--");
+                }
+                outln!("{}", reference);
+            }
+        }
+        Method::Resolve { .. } => {
+            if let Some(resolved) = typed::<ResolveResult>(result)?.resolved {
+                outln!("{}", resolved);
+            }
+        }
+        Method::Callers { symbol, .. } | Method::Callees { symbol, .. } => {
+            let calls = typed::<CallsResult>(result)?.calls;
+            if calls.is_empty() {
+                let relation = if matches!(method, Method::Callers { .. }) { "callers" } else { "callees" };
+                outln!("No {} found for {}", relation, symbol);
+            }
+            for call in calls {
+                out!("{}", call);
+            }
+        }
+        Method::Outline { .. } => {
+            for item in typed::<OutlineResult>(result)?.outline {
+                out!("{}", item);
+            }
+        }
+        Method::Hints { .. } => {
+            let hints = typed::<HintsResult>(result)?.hints;
+            if hints.is_empty() {
+                outln!("No inlay hints found");
+            }
+            for line in hints {
+                outln!("{}", line);
+            }
+        }
+        Method::Search { .. } => {
+            let symbols = typed::<SearchResult>(result)?.symbols;
+            if symbols.is_empty() {
+                outln!("No matching symbols found");
+            }
+            for symbol in symbols {
+                outln!("{}", symbol);
+            }
+        }
+        Method::Rename { .. } => {
+            let rename = typed::<RenameResult>(result)?.rename;
+            if rename.diff.is_empty() {
+                outln!("No changes");
+            }
+            out!("{}", rename.diff);
+        }
+        Method::Actions { .. } => {
+            let actions = typed::<ActionsResult>(result)?.actions;
+            if actions.is_empty() {
+                outln!("No code actions available");
+            }
+            for action in actions {
+                outln!("{}", action);
+            }
+        }
+        Method::Expand { .. } => {
+            match typed::<ExpandResult>(result)?.expansion {
+                Some(expansion) => outln!("{}", expansion),
+                None => outln!("No macro to expand here"),
+            }
+        }
+        Method::Hir { .. } => {
+            match typed::<HirResult>(result)?.hir {
+                Some(hir) => outln!("{}", hir),
+                None => outln!("No function, const or static body on this line"),
+            }
+        }
+        Method::Syntax { .. } => {
+            match typed::<SyntaxResult>(result)?.syntax {
+                Some(syntax) => outln!("{}", syntax),
+                None => outln!("No item on this line"),
+            }
+        }
+        Method::Ssr { .. } => {
+            let ssr = typed::<SsrResult>(result)?.ssr;
+            if ssr.diff.is_empty() {
+                outln!("No matches");
+            }
+            out!("{}", ssr.diff);
+        }
+        Method::Runnables { .. } => {
+            let runnables = typed::<RunnablesResult>(result)?.runnables;
+            if runnables.is_empty() {
+                outln!("No runnables found");
+            }
+            for runnable in runnables {
+                outln!("{}", runnable);
+            }
+        }
+        Method::Diag { .. } => {
+            let diagnostics = typed::<DiagResult>(result)?.diagnostics;
+            if diagnostics.is_empty() {
+                outln!("No diagnostics");
+            }
+            for diagnostic in &diagnostics {
+                outln!("{}", diagnostic);
+            }
+            let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+            if errors > 0 {
+                outln!("\n{} error(s), {} other diagnostic(s)", errors, diagnostics.len() - errors);
+            }
+        }
+        Method::Hierarchy { .. } => {
+            let hierarchy = typed::<HierarchyResult>(result)?.hierarchy;
+            out!("{}", hierarchy);
+            if hierarchy.supertypes.is_empty() && hierarchy.subtypes.is_empty() {
+                outln!("  no supertypes or subtypes found");
+            }
+        }
+        Method::GenericHotspots { .. } => {
+            let hotspots = typed::<GenericHotspotsResult>(result)?.hotspots;
+            if hotspots.is_empty() {
+                outln!("No generic functions with references found");
+            }
+            for hotspot in hotspots {
+                outln!(
+                    "{:>6}  {}:{}: {} ({} refs x {} instantiations)",
+                    hotspot.score,
+                    hotspot.path.display(),
+                    hotspot.line,
+                    hotspot.name,
+                    hotspot.references,
+                    hotspot.instantiations,
+                );
+            }
+        }
+        Method::CoverageMap => {
+            let functions = coverage_functions(result)?;
+            let untested = functions.iter().filter(|f| f.tests.is_empty()).count();
+            for function in &functions {
+                if function.tests.is_empty() {
+                    outln!("{}:{}: {} - UNTESTED", function.path.display(), function.line, function.name);
+                } else {
+                    let names: Vec<String> = function.tests.iter()
+                        .map(|t| t.name.clone().unwrap_or_else(|| t.path.display().to_string()))
+                        .collect();
+                    outln!(
+                        "{}:{}: {} - {} test(s): {}",
+                        function.path.display(),
+                        function.line,
+                        function.name,
+                        function.tests.len(),
+                        names.join(", ")
+                    );
+                }
+            }
+            outln!();
+            outln!("{} of {} public functions have no referencing test", untested, functions.len());
+        }
+        Method::Hotspots { .. } => {
+            for symbol in typed::<HotspotsResult>(result)?.hotspots {
+                outln!(
+                    "{:>6}  {}:{}: {} {}",
+                    symbol.references,
+                    symbol.path.display(),
+                    symbol.line,
+                    symbol.kind,
+                    symbol.name
+                );
+            }
+        }
+        Method::Tour { .. } => {
+            out!("{}", typed::<TourResult>(result)?.tour.to_markdown());
+        }
+        Method::Churn { .. } => {
+            outln!("{:>8} {:>8} {:>6}  ITEM", "RISK", "COMMITS", "REFS");
+            for entry in typed::<ChurnResult>(result)?.entries {
+                outln!(
+                    "{:>8} {:>8} {:>6}  {}:{}: {} {}",
+                    entry.risk,
+                    entry.commits,
+                    entry.references,
+                    entry.path.display(),
+                    entry.line,
+                    entry.kind,
+                    entry.name
+                );
+            }
+        }
+        Method::AsyncCallers { .. } => {
+            out!("{}", typed::<AsyncCallersResult>(result)?.report);
+        }
+        Method::TransitiveRefs { .. } => {
+            out!("{}", typed::<TransitiveRefsResult>(result)?.report);
+        }
+        Method::Bounds { .. } => {
+            out!("{}", typed::<BoundsResult>(result)?.report);
+        }
+        Method::ErrorFlow { .. } => {
+            out!("{}", typed::<ErrorFlowResult>(result)?.report);
+        }
+        Method::Strings { .. } => {
+            let strings = typed::<StringsResult>(result)?.strings;
+            if strings.is_empty() {
+                outln!("No matching string literals found");
+            }
+            for found in strings {
+                outln!("{}", found);
+            }
+        }
+        Method::DiffRefs { .. } => {
+            let diff = typed::<DiffRefsResult>(result)?.diff;
+            for (side, references) in [(&diff.a, &diff.only_a), (&diff.b, &diff.only_b)] {
+                outln!("Only {} ({}):", side, references.len());
+                for reference in references {
+                    outln!("  {}", reference);
+                }
+            }
+            outln!("Lines referencing both: {}", diff.common);
+        }
+        Method::Status => {
+            let status: DaemonStatus = typed(result)?;
+            outln!("Status: {}", status.status);
+            outln!("Workspace: {}", status.workspace.display());
+            outln!("Indexing: {}", status.indexing);
+            if status.paused_for_build {
+                outln!("Indexing paused while a cargo build runs");
+            }
+            if let Some(reason) = &status.crash_reason {
+                outln!("Language server down: {}", reason);
+                let attempts = status.restart_attempts;
+                match status.next_restart_secs {
+                    Some(secs) => outln!("Restarting in {}s (after {} attempt(s))", secs, attempts),
+                    None => outln!("Gave up after {} restart attempt(s); run `lq stop` and retry once fixed", attempts),
+                }
+            }
+            if let Some(phase) = &status.phase {
+                let mut progress = phase.to_string();
+                if let Some(percentage) = status.percentage {
+                    progress.push_str(&format!(" {}%", percentage));
+                }
+                if let Some(message) = &status.message {
+                    progress.push_str(&format!(" ({})", message));
+                }
+                outln!("Progress: {}", progress);
+            }
+            let crash_restarts = status.server_restarts.saturating_sub(status.memory_restarts);
+            if crash_restarts > 0 {
+                outln!("Language server restarted after a crash {} time(s)", crash_restarts);
+            }
+            if status.memory_restarts > 0 {
+                outln!("Language server restarted for its memory use {} time(s)", status.memory_restarts);
+            }
+            outln!("PID: {}", status.pid);
+            if !status.version.is_empty() {
+                outln!("Version: {} (protocol {})", status.version, status.protocol_version);
+            }
+            outln!("Uptime: {}", format_duration(status.uptime_secs));
+            if let Some(memory) = status.memory_bytes {
+                outln!("Memory: {}", format_bytes(memory));
+            }
+            if let Some(memory) = status.server_memory_bytes {
+                outln!("Server memory: {}", format_bytes(memory));
+            }
+            let prefetch = status.prefetch;
+            if prefetch.enabled {
+                outln!("Prefetch: {} of {} prefetched answers used, {} skipped", prefetch.hits, prefetch.issued, prefetch.skipped);
+            } else {
+                outln!("Prefetch: disabled");
+            }
+            if !status.members.is_empty() {
+                outln!("Workspaces:");
+            }
+            for member in &status.members {
+                let path = member.workspace.strip_prefix(&status.workspace).unwrap_or(&member.workspace);
+                match member.server_memory_bytes {
+                    Some(memory) => outln!("  {}: {} (server memory {})", path.display(), member.status, format_bytes(memory)),
+                    None => outln!("  {}: {}", path.display(), member.status),
+                }
+            }
+        }
+        Method::Ping => {
+            let ping: PingResult = typed(result)?;
+            match ping.round_trip_ms {
+                Some(ms) => outln!("Daemon: answered in {}ms ({})", ms, ping.status),
+                None => outln!("Daemon: answered ({})", ping.status),
+            }
+            let server = match ping.server_pid {
+                Some(pid) if ping.server_alive => format!("alive (pid {})", pid),
+                _ if ping.status == "starting" => "not started yet".to_string(),
+                _ => "down (see `lq status`)".to_string(),
+            };
+            match (ping.hover_ms, ping.hover_error) {
+                (Some(ms), _) => outln!("Language server: {}, hover answered in {}ms", server, ms),
+                (None, Some(error)) => outln!("Language server: {}, hover failed: {}", server, error),
+                (None, None) => outln!("Language server: {}", server),
+            }
+        }
+        Method::Stats => {
+            let stats: StatsResult = typed(result)?;
+            let requests: u64 = stats.methods.iter().map(|method| method.count).sum();
+            let errors: u64 = stats.methods.iter().map(|method| method.errors).sum();
+            outln!("Uptime: {}; {} request(s), {} failed", format_duration(stats.uptime_secs), requests, errors);
+            if stats.methods.is_empty() {
+                return Ok(());
+            }
+            let ms = |ms: Option<u64>| ms.map(|ms| format!("{}ms", ms)).unwrap_or_else(|| "-".to_string());
+            outln!("{:<16} {:>6} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8}  Cache hits", "Method", "Count", "Errors", "Mean", "p50", "p90", "p99", "Max");
+            for method in &stats.methods {
+                let answered = method.cache_hits + method.cache_stale;
+                let cache = match answered + method.cache_misses {
+                    0 => "-".to_string(),
+                    lookups => format!("{}/{} ({}%)", answered, lookups, answered * 100 / lookups),
+                };
+                outln!(
+                    "{:<16} {:>6} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8}  {}",
+                    method.method,
+                    method.count,
+                    method.errors,
+                    ms(Some(method.mean_ms)),
+                    ms(method.p50_ms),
+                    ms(method.p90_ms),
+                    ms(method.p99_ms),
+                    ms(Some(method.max_ms)),
+                    cache
+                );
+            }
+        }
+        Method::Codec { .. } => outln!("Connection encoded as {:?}", typed::<CodecResult>(result)?.codec),
+        Method::Compress { .. } => {
+            let result = typed::<CompressResult>(result)?;
+            outln!("Responses over {} bytes compressed as {:?}", result.min_bytes, result.compression);
+        }
+        Method::Cancel { .. } => {
+            if typed::<CancelResult>(result)?.cancelled {
+                outln!("Request cancelled");
+            } else {
+                outln!("No such request in flight");
+            }
+        }
+        Method::Shutdown => {
+            outln!("Daemon stopped");
+        }
+    }
+    
+    Ok(())
+}
+
+/// Print the JSON schema of one command's response, or list the commands that have one
+fn print_schema(command: Option<&str>, format: OutputFormat) -> Result<()> {
+    if let Some(command) = command {
+        let schema = command_schema(command).context("Unknown command")?;
+        outln!("{}", to_json(&schema)?);
+        return Ok(());
+    }
+    
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let schemas: serde_json::Map<String, serde_json::Value> = SCHEMA_COMMANDS
+                .iter()
+                .filter_map(|command| Some((command.to_string(), serde_json::to_value(command_schema(command)?).ok()?)))
+                .collect();
+            print_json(&serde_json::json!({
+                "protocol_version": PROTOCOL_VERSION,
+                "schemas": schemas,
+            }), format)?;
+        }
+        OutputFormat::Text => {
+            outln!("Protocol version {}", PROTOCOL_VERSION);
+            for command in SCHEMA_COMMANDS {
+                outln!("  {}", command);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Query every daemon socket on this machine for its status
+async fn list_daemons(format: OutputFormat) -> Result<()> {
+    let mut daemons = Vec::new();
+    
+    for socket in list_daemon_sockets()? {
+        // Also removes sockets left behind by daemons that have exited
+        if !is_daemon_running(&socket).await {
+            continue;
+        }
+        
+        let status = tokio::time::timeout(
+            std::time::Duration::from_secs(2),
+            Client::at_socket(&socket).request(Method::Status),
+        ).await;
+        
+        let entry = match status {
+            Ok(Ok(status)) => {
+                let mut entry = status;
+                entry["responding"] = serde_json::json!(true);
+                entry
+            }
+            _ => serde_json::json!({ "responding": false, "pid": pidfile::read_pid(&socket) }),
+        };
+        let mut entry = entry;
+        entry["socket"] = serde_json::json!(socket.display().to_string());
+        daemons.push(entry);
+    }
+    
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl => print_json(&serde_json::json!({ "daemons": daemons }), format)?,
+        OutputFormat::Text => {
+            if daemons.is_empty() {
+                outln!("No daemons running");
+                return Ok(());
+            }
+            outln!("{:<8} {:>8} {:>10} {:>10}  WORKSPACE", "PID", "UPTIME", "MEMORY", "SERVER");
+            for daemon in &daemons {
+                if daemon["responding"] != serde_json::json!(true) {
+                    let pid = daemon["pid"].as_u64().map(|p| p.to_string()).unwrap_or_else(|| "-".to_string());
+                    outln!("{:<8} {:>8} {:>10} {:>10}  (not responding: {})", pid, "-", "-", "-", daemon["socket"].as_str().unwrap_or("?"));
+                    continue;
+                }
+                outln!(
+                    "{:<8} {:>8} {:>10} {:>10}  {}",
+                    daemon["pid"].as_u64().map(|p| p.to_string()).unwrap_or_else(|| "?".to_string()),
+                    daemon["uptime_secs"].as_u64().map(format_duration).unwrap_or_else(|| "?".to_string()),
+                    daemon["memory_bytes"].as_u64().map(format_bytes).unwrap_or_else(|| "?".to_string()),
+                    daemon["server_memory_bytes"].as_u64().map(format_bytes).unwrap_or_else(|| "-".to_string()),
+                    daemon["workspace"].as_str().unwrap_or("?"),
+                );
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Print the metrics every daemon on this machine recorded, read from their files
+fn dump_metrics(format: OutputFormat) -> Result<()> {
+    let enabled = metrics_config()?.enabled;
+    let usage = metrics::collect(&metrics::metrics_dir()?)?;
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            print_json(&serde_json::json!({ "enabled": enabled, "since": usage.since, "methods": usage.methods }), format)?
+        }
+        OutputFormat::Text => {
+            if usage.is_empty() {
+                outln!("No requests recorded");
+            } else {
+                outln!("{:<20} {:>8} {:>8} {:>8} {:>8}", "METHOD", "COUNT", "ERRORS", "MEAN", "MAX");
+                for (method, usage) in &usage.methods {
+                    outln!(
+                        "{:<20} {:>8} {:>8} {:>8} {:>8}",
+                        method,
+                        usage.count,
+                        usage.errors,
+                        format!("{}ms", usage.mean_ms()),
+                        format!("{}ms", usage.max_ms),
+                    );
+                }
+            }
+            if !enabled {
+                errln!("(metrics are off; set `enabled = true` under [metrics] in {} to record them)", config_path()?.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m{:02}s", s / 60, s % 60),
+        s if s < 86400 => format!("{}h{:02}m", s / 3600, (s % 3600) / 60),
+        s => format!("{}d{:02}h", s / 86400, (s % 86400) / 3600),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= 1024.0 * MB {
+        format!("{:.1} GB", bytes as f64 / (1024.0 * MB))
+    } else {
+        format!("{:.1} MB", bytes as f64 / MB)
+    }
+}
+
+fn coverage_functions(result: &serde_json::Value) -> Result<Vec<FunctionCoverage>> {
+    Ok(typed::<CoverageMapResult>(result)?.functions.unwrap_or_default())
+}
+
+/// Write the edit previewed by a rename or SSR result to disk, then print what changed
+fn apply_edit_result(method: &Method, mut result: serde_json::Value, force: bool, format: OutputFormat) -> Result<()> {
+    let preview = match method {
+        Method::Rename { .. } => typed::<RenameResult>(&result)?.rename,
+        _ => typed::<SsrResult>(&result)?.ssr,
+    };
+    let workspace = current_workspace()?;
+    let applied = apply(&preview, &workspace, force)?;
+    
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            result["applied"] = serde_json::to_value(&applied)?;
+            print_json(&result, format)?;
+        }
+        OutputFormat::Text => {
+            out!("{}", preview.diff);
+            if applied.files.is_empty() {
+                outln!("No changes");
+            } else {
+                outln!("Applied to {} file(s)", applied.files.len());
+            }
+            if let Some(backup) = &applied.backup {
+                outln!("Originals backed up to {}", backup.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the changes of code action `index` to disk, then print what changed
+fn apply_action_result(mut result: serde_json::Value, index: usize, force: bool, format: OutputFormat) -> Result<()> {
+    let ActionsResult { actions, flags, .. } = typed(&result)?;
+    // Numbers refer to the list as offered now, not one cached before the files changed
+    if flags.stale {
+        bail!("The code actions are out of date; run the command again without stale answers");
+    }
+    let Some(action) = actions.into_iter().find(|action| action.index == index) else {
+        bail!("No code action {}", index);
+    };
+    if action.changes.is_empty() {
+        bail!("Code action {} (\"{}\") runs a server command, which lq cannot apply", index, action.title);
+    }
+    let preview = EditPreview::new(action.changes)?;
+    let workspace = current_workspace()?;
+    let applied = apply(&preview, &workspace, force)?;
+    
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            result["applied"] = serde_json::to_value(&applied)?;
+            print_json(&result, format)?;
+        }
+        OutputFormat::Text => {
+            outln!("Applying: {}", action.title);
+            out!("{}", preview.diff);
+            outln!("Applied to {} file(s)", applied.files.len());
+            if let Some(backup) = &applied.backup {
+                outln!("Originals backed up to {}", backup.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print references under a header per file, or with `count_only` just the
+/// number of hits per file, which JSON output carries too
+fn refs_by_file_result(result: &serde_json::Value, count_only: bool, format: OutputFormat) -> Result<()> {
+    let references = typed::<RefsResult>(result)?.references;
+    let mut files: Vec<(&Path, Vec<&Reference>)> = Vec::new();
+    for reference in &references {
+        match files.iter_mut().find(|(path, _)| *path == reference.path) {
+            Some((_, hits)) => hits.push(reference),
+            None => files.push((&reference.path, vec![reference])),
+        }
+    }
+    let hits = |references: &[&Reference]| references.iter().map(|reference| reference.occurrences).sum::<usize>();
+    
+    match (format, count_only) {
+        (OutputFormat::Json | OutputFormat::Jsonl, true) => {
+            let counts: Vec<_> = files
+                .iter()
+                .map(|(path, references)| serde_json::json!({ "path": path, "count": hits(references) }))
+                .collect();
+            let total: usize = files.iter().map(|(_, references)| hits(references)).sum();
+            print_json(&serde_json::json!({ "files": counts, "total": total }), format)?;
+        }
+        (OutputFormat::Json | OutputFormat::Jsonl, false) => print_json(result, format)?,
+        (OutputFormat::Text, true) => {
+            for (path, references) in &files {
+                outln!("{}: {}", path.display(), hits(references));
+            }
+            let total: usize = files.iter().map(|(_, references)| hits(references)).sum();
+            outln!("{} reference(s) in {} file(s)", total, files.len());
+        }
+        (OutputFormat::Text, false) => {
+            for (index, (path, references)) in files.iter().enumerate() {
+                if index > 0 {
+                    outln!();
+                }
+                outln!("{} ({})", path.display(), hits(references));
+                for reference in references {
+                    for line in format!("{:#}", reference).lines() {
+                        outln!("  {}", line);
+                    }
+                }
+            }
         }
     }
+    Ok(())
+}
+
+/// Turn the references to `item` into a migration checklist, printed or written
+/// to the tracking file `track`
+fn migrate_result(result: &serde_json::Value, item: &str, track: Option<&Path>, links: Option<&Permalinks>, format: OutputFormat) -> Result<()> {
+    let previous = match track {
+        Some(path) if path.exists() => Some(std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?),
+        _ => None,
+    };
+    let migration = Migration::new(item, typed::<RefsResult>(result)?.references, links, previous.as_deref());
     
+    if let Some(path) = track {
+        std::fs::write(path, migration.to_markdown()).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    match (format, track) {
+        (OutputFormat::Json | OutputFormat::Jsonl, _) => print_json(&serde_json::to_value(&migration)?, format)?,
+        (OutputFormat::Text, Some(path)) => outln!("{} usage(s) remaining; checklist written to {}", migration.remaining, path.display()),
+        (OutputFormat::Text, None) => out!("{}", migration.to_markdown()),
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Print only untested functions and fail if there are any (for CI)
+fn check_coverage_map(result: &serde_json::Value, format: OutputFormat) -> Result<()> {
+    let untested: Vec<FunctionCoverage> = coverage_functions(result)?
+        .into_iter()
+        .filter(|f| f.tests.is_empty())
+        .collect();
+    
+    match format {
+        OutputFormat::Json | OutputFormat::Jsonl => print_json(&serde_json::json!({ "untested": untested }), format)?,
+        OutputFormat::Text => {
+            for function in &untested {
+                outln!("{}:{}: {}", function.path.display(), function.line, function.name);
+            }
+        }
+    }
+    
+    if !untested.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<OsString> {
+        line.split_whitespace().map(OsString::from).collect()
+    }
+
+    /// `line` with the preset it runs expanded, as [`resolve_command_line`] does
+    fn expand(workspace: &Path, line: &str) -> Result<(Vec<OsString>, Vec<String>)> {
+        let Commands::Run { name: Some(name), args: trailing } = Cli::parse_from(args(line)).command else {
+            panic!("{:?} runs no preset", line);
+        };
+        expand_preset(workspace, &name, trailing.len(), args(line))
+    }
+
+    fn workspace_with_presets() -> tempfile::TempDir {
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(
+            workspace.path().join(".lq.toml"),
+            "[preset.lq-test-refs]\nmethod = \"refs\"\nargs = [\"--raw\"]\n\
+             [preset.lq-test-errors]\nmethod = \"diag\"\nargs = [\"--severity\", \"error\"]\nexclude = [\"tests/**\"]\nformat = \"jsonl\"\n",
+        )
+        .unwrap();
+        workspace
+    }
+
+    #[test]
+    fn test_expand_unknown_preset() {
+        let workspace = workspace_with_presets();
+        let error = expand(workspace.path(), "lq run lq-test-missing").unwrap_err();
+        assert!(error.to_string().contains("No preset named \"lq-test-missing\""), "{}", error);
+    }
+
+    #[test]
+    fn test_expand_preset_trailing_args() {
+        let workspace = workspace_with_presets();
+        let (expanded, exclude) = expand(workspace.path(), "lq run lq-test-refs src/lib.rs:3 Foo -C 2").unwrap();
+        assert_eq!(expanded, args("lq refs --raw src/lib.rs:3 Foo -C 2"));
+        assert!(exclude.is_empty());
+        assert!(matches!(Cli::parse_from(expanded).command, Commands::Refs { raw: true, context: Some(2), .. }));
+    }
+
+    #[test]
+    fn test_expand_preset_with_global_flags() {
+        let workspace = workspace_with_presets();
+        let (expanded, exclude) = expand(workspace.path(), "lq --timing run --allow-stale lq-test-errors src/lib.rs").unwrap();
+        assert_eq!(expanded, args("lq --format jsonl --timing --allow-stale diag --severity error src/lib.rs"));
+        assert_eq!(exclude, ["tests/**"]);
+
+        let cli = Cli::parse_from(expanded);
+        assert!(cli.timing && cli.allow_stale);
+        assert_eq!(cli.format, FormatArg::Jsonl);
+        assert!(matches!(cli.command, Commands::Diag { severity: Severity::Error, .. }));
+
+        // A format given on the command line wins over the preset's
+        let (expanded, _) = expand(workspace.path(), "lq --format json run lq-test-errors").unwrap();
+        assert_eq!(Cli::parse_from(expanded).format, FormatArg::Json);
+    }
+
+    #[test]
+    fn test_subcommand_index() {
+        assert_eq!(subcommand_index(&args("lq refs src/lib.rs:3 Foo")), Some(1));
+        // Global flags and their values come before the name
+        assert_eq!(subcommand_index(&args("lq --format json --timing --exclude tests/** refs --raw")), Some(6));
+        assert_eq!(subcommand_index(&args("lq --format=json refs")), Some(2));
+        assert_eq!(subcommand_index(&args("lq --timing")), None);
+        assert_eq!(subcommand_index(&args("lq")), None);
+    }
+
+    #[test]
+    fn test_with_default_flags() {
+        let defaults = BTreeMap::from([
+            ("refs".to_string(), vec!["--format".to_string(), "jsonl".to_string(), "-C".to_string(), "1".to_string()]),
+            ("diag".to_string(), Vec::new()),
+        ]);
+        assert_eq!(with_default_flags(&args("lq docs src/lib.rs:3 Foo"), &defaults), None);
+        assert_eq!(with_default_flags(&args("lq diag"), &defaults), None);
+        assert_eq!(with_default_flags(&args("lq --timing"), &defaults), None);
+
+        let expanded = with_default_flags(&args("lq --timing refs src/lib.rs:3 Foo"), &defaults).unwrap();
+        assert_eq!(expanded, args("lq refs --format jsonl -C 1 --timing src/lib.rs:3 Foo"));
+        let cli = Cli::parse_from(expanded);
+        assert!(cli.timing);
+        assert_eq!(cli.format, FormatArg::Jsonl);
+        assert!(matches!(cli.command, Commands::Refs { context: Some(1), .. }));
+    }
+
+    #[test]
+    fn test_default_flags_never_override_given_ones() {
+        let defaults = BTreeMap::from([(
+            "refs".to_string(),
+            vec!["--format".to_string(), "jsonl".to_string(), "-C".to_string(), "1".to_string()],
+        )]);
+        for line in ["lq --format json refs src/lib.rs:3 Foo -C 3", "lq refs --format json src/lib.rs:3 Foo -C 3"] {
+            let cli = Cli::parse_from(with_default_flags(&args(line), &defaults).unwrap());
+            assert_eq!(cli.format, FormatArg::Json, "{}", line);
+            assert!(matches!(cli.command, Commands::Refs { context: Some(3), .. }), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_parse_file_location() {
+        let location = parse_file_location("src/main.rs:42").unwrap();
+        assert_eq!((location.file, location.line, location.column), (PathBuf::from("src/main.rs"), 42, None));
+        let location = parse_file_location("src/main.rs:42:9").unwrap();
+        assert_eq!((location.file, location.line, location.column), (PathBuf::from("src/main.rs"), 42, Some(9)));
+
+        assert!(parse_file_location("src/main.rs").is_err());
+        assert!(parse_file_location("src/main.rs:0").is_err());
+        assert!(parse_file_location("src/main.rs:-3").is_err());
+        assert!(parse_file_location("src/main.rs:42:").is_err());
+        assert_eq!(parse_file_location("src/main.rs:42:0").unwrap_err(), "Column must be greater than 0");
+        assert_eq!(parse_file_location("src/main.rs:42:-1").unwrap_err(), "Column must be a positive integer");
+    }
+
+    #[test]
+    fn test_parse_file_location_with_colons_in_path() {
+        let location = parse_file_location(r"C:\src\main.rs:42").unwrap();
+        assert_eq!((location.file, location.line, location.column), (PathBuf::from(r"C:\src\main.rs"), 42, None));
+        let location = parse_file_location(r"C:\src\main.rs:42:9").unwrap();
+        assert_eq!((location.file, location.line, location.column), (PathBuf::from(r"C:\src\main.rs"), 42, Some(9)));
+        let location = parse_file_location("notes:v2/lib.rs:7").unwrap();
+        assert_eq!((location.file, location.line), (PathBuf::from("notes:v2/lib.rs"), 7));
+    }
+
+    #[test]
+    fn test_parse_selection() {
+        let selection = parse_selection("src/main.rs:42:9-44:6").unwrap().unwrap();
+        assert_eq!((selection.file, selection.start, selection.end), (PathBuf::from("src/main.rs"), (42, 9), (44, 6)));
+        let selection = parse_selection("src/main.rs:42:9-42:9").unwrap().unwrap();
+        assert_eq!((selection.start, selection.end), ((42, 9), (42, 9)));
+
+        // Not a selection at all
+        assert!(parse_selection("src/main.rs:42:9").unwrap().is_none());
+        assert!(parse_selection("src/main.rs:42:9-44").unwrap().is_none());
+        assert!(parse_selection("my-crate/src/lib.rs:3").unwrap().is_none());
+
+        // Malformed ones
+        assert!(parse_selection("src/main.rs:42-44:6").unwrap_err().starts_with("Expected format: file:line:column-line:column"));
+        assert!(parse_selection("src/main.rs:0:9-44:6").is_err());
+        assert!(parse_selection("src/main.rs:42:9-0:6").is_err());
+        assert!(parse_selection("src/main.rs:42:9-44:0").is_err());
+        assert!(parse_selection("src/main.rs:x:9-44:6").is_err());
+    }
+
+    #[test]
+    fn test_parse_selection_ending_before_start() {
+        assert_eq!(parse_selection("src/main.rs:44:6-42:9").unwrap_err(), "Selection 44:6-42:9 ends before it starts");
+        assert_eq!(parse_selection("src/main.rs:42:9-42:3").unwrap_err(), "Selection 42:9-42:3 ends before it starts");
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert!(matches!(parse_target("my_crate::module::Type::method"), Ok(Target::Path(path)) if path == "my_crate::module::Type::method"));
+        assert!(matches!(parse_target("HashMap"), Ok(Target::Path(path)) if path == "HashMap"));
+        assert!(matches!(parse_target("src/main.rs:42"), Ok(Target::Location(FileLocation { line: 42, column: None, .. }))));
+        assert!(matches!(parse_target("src/main.rs:42:9"), Ok(Target::Location(FileLocation { line: 42, column: Some(9), .. }))));
+        assert!(matches!(parse_target("my-crate/src/lib.rs:3"), Ok(Target::Location(FileLocation { line: 3, .. }))));
+        assert!(matches!(parse_target("src/main.rs:42:9-44:6"), Ok(Target::Selection(Selection { start: (42, 9), end: (44, 6), .. }))));
+        assert!(parse_target("src/main.rs:44:6-42:9").is_err());
+        assert!(parse_target("src/main.rs:forty").is_err());
+    }
+
+    /// The column `lq <line>` queries once `occurrence` is pinned, for a file holding `source`
+    fn pinned_column(source: &str, line: &str, occurrence: Option<u32>) -> Result<Option<u32>> {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lib.rs");
+        std::fs::write(&file, source).unwrap();
+        let mut command = Cli::parse_from(args(&line.replace("FILE", &file.display().to_string()))).command;
+        pin_occurrence(&mut command, occurrence)?;
+        Ok(command.symbol_location().unwrap().0.column)
+    }
+
+    #[test]
+    fn test_pin_occurrence() {
+        let source = "fn main() {}\nlet total = add(add(1, 2), add(3, 4));\n";
+        // Several matches on one line, counted as whole words from the left
+        assert_eq!(pinned_column(source, "lq type FILE:2 add", Some(1)).unwrap(), Some(13));
+        assert_eq!(pinned_column(source, "lq type FILE:2 add", Some(2)).unwrap(), Some(17));
+        assert_eq!(pinned_column(source, "lq type FILE:2 add", Some(3)).unwrap(), Some(28));
+        // Without --occurrence the first is queried, by the server's own lookup
+        assert_eq!(pinned_column(source, "lq type FILE:2 add", None).unwrap(), None);
+        // A column given on the command line is kept
+        assert_eq!(pinned_column(source, "lq type FILE:2:28 add", None).unwrap(), Some(28));
+        assert!(pinned_column(source, "lq type FILE:2:28 add", Some(1)).is_err());
+    }
+
+    #[test]
+    fn test_pin_occurrence_out_of_range() {
+        let source = "let total = add(add(1, 2), add(3, 4));\n";
+        let error = pinned_column(source, "lq type FILE:1 add", Some(4)).unwrap_err();
+        assert!(error.to_string().contains("occurs 3 time(s)"), "{}", error);
+        assert!(pinned_column(source, "lq type FILE:1 add", Some(0)).is_err());
+        assert!(pinned_column(source, "lq type FILE:1 total_add", Some(1)).is_err());
+        // Clap turns down a zeroth occurrence before it gets this far
+        assert!(Cli::try_parse_from(args("lq --occurrence 0 type src/lib.rs:1 add")).is_err());
+    }
+}
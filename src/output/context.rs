@@ -0,0 +1,64 @@
+//! `-A`/`-B`/`-C` on `lq refs`: source lines around each reference, as grep prints them.
+//!
+//! The lines are read from the files on disk after the answer arrives, so
+//! asking for context does not change the request and cached answers still
+//! apply. They lose the indentation of the reference's own line, which `text`
+//! is trimmed of, so they stay aligned with it.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::lsp::render::context_window;
+
+/// Set `context_before` and `context_after` on the results in `payload`'s lists
+/// that quote a line, with up to `before` and `after` lines
+pub fn add_context(payload: &mut Value, before: usize, after: usize) {
+    if before == 0 && after == 0 {
+        return;
+    }
+    let Some(fields) = payload.as_object_mut() else {
+        return;
+    };
+    let mut files: HashMap<String, Option<Vec<String>>> = HashMap::new();
+    for items in fields.values_mut().filter_map(Value::as_array_mut) {
+        for item in items.iter_mut().filter_map(Value::as_object_mut) {
+            let file = item.get("file").and_then(Value::as_str);
+            let line = item.get("line").and_then(Value::as_u64);
+            let (Some(file), Some(line), true) = (file, line, item.contains_key("text")) else {
+                continue;
+            };
+            let lines = files
+                .entry(file.to_string())
+                .or_insert_with(|| std::fs::read_to_string(file).ok().map(|contents| contents.lines().map(str::to_string).collect()));
+            let Some((context_before, context_after)) = lines.as_deref().and_then(|lines| context_window(lines, line as usize, before, after)) else {
+                continue;
+            };
+            item.insert("context_before".to_string(), context_before.into());
+            item.insert("context_after".to_string(), context_after.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lib.rs");
+        std::fs::write(&file, "fn main() {\n    let x = 1;\n    area(x);\n        .abs()\n}\n").unwrap();
+        let mut payload = serde_json::json!({
+            "references": [
+                { "file": file, "line": 3, "text": "area(x);" },
+                { "file": file, "line": 1, "text": "fn main() {" }
+            ]
+        });
+        add_context(&mut payload, 2, 2);
+        let first = &payload["references"][0];
+        assert_eq!(first["context_before"], serde_json::json!(["fn main() {", "let x = 1;"]));
+        assert_eq!(first["context_after"], serde_json::json!(["    .abs()", "}"]));
+        assert_eq!(payload["references"][1]["context_before"], serde_json::json!([]));
+    }
+}
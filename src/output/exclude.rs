@@ -0,0 +1,84 @@
+//! `--exclude`: dropping results in files that match a glob.
+//!
+//! Globs match paths relative to the workspace root, the form every result
+//! reports them in. `*` matches within one path component, `**` matches any
+//! number of components, and `?` matches one character, so `tests/**` covers
+//! everything under `tests/` and `src/*_test.rs` only files directly in `src/`.
+//! Only the items of a payload's lists are dropped; previews of edits are left
+//! whole, since the diff they carry would no longer match their list of changes.
+
+use serde_json::Value;
+
+/// Drop the items of `payload`'s lists whose `path` matches one of `patterns`
+pub fn exclude_paths(payload: &mut Value, patterns: &[String]) {
+    if patterns.is_empty() {
+        return;
+    }
+    let Some(fields) = payload.as_object_mut() else {
+        return;
+    };
+    for items in fields.values_mut().filter_map(Value::as_array_mut) {
+        items.retain(|item| {
+            let path = item.get("path").and_then(Value::as_str);
+            !path.is_some_and(|path| patterns.iter().any(|pattern| glob_matches(pattern, path)))
+        });
+    }
+}
+
+/// Whether `path`, with `/` separators, matches the glob `pattern`
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => path
+            .split_first()
+            .is_some_and(|(component, path)| match_component(first.as_bytes(), component.as_bytes()) && match_components(rest, path)),
+    }
+}
+
+fn match_component(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| match_component(rest, &text[skip..])),
+        Some((b'?', rest)) => {
+            // One character, however many bytes it takes
+            let width = text.iter().skip(1).take_while(|&&byte| byte & 0xc0 == 0x80).count() + 1;
+            !text.is_empty() && match_component(rest, &text[width.min(text.len())..])
+        }
+        Some((byte, rest)) => text.first() == Some(byte) && match_component(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("tests/**", "tests/api.rs"));
+        assert!(glob_matches("tests/**", "tests/fixtures/a.rs"));
+        assert!(!glob_matches("tests/**", "crates/core/tests/api.rs"));
+        assert!(glob_matches("**/tests/**", "crates/core/tests/api.rs"));
+        assert!(glob_matches("src/*_test.rs", "src/parse_test.rs"));
+        assert!(!glob_matches("src/*_test.rs", "src/parse/lex_test.rs"));
+        assert!(glob_matches("src/?.rs", "src/\u{e9}.rs"));
+        assert!(!glob_matches("src/?.rs", "src/ab.rs"));
+    }
+
+    #[test]
+    fn test_exclude_paths() {
+        let mut payload = serde_json::json!({
+            "references": [{ "path": "src/lib.rs" }, { "path": "tests/api.rs" }, { "line": 3 }],
+            "rename": { "changes": [{ "path": "tests/api.rs" }] }
+        });
+        exclude_paths(&mut payload, &["tests/**".to_string()]);
+        assert_eq!(payload["references"], serde_json::json!([{ "path": "src/lib.rs" }, { "line": 3 }]));
+        assert_eq!(payload["rename"]["changes"].as_array().unwrap().len(), 1);
+    }
+}
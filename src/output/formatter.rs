@@ -0,0 +1,73 @@
+//! Formatters: external commands that render a command's answer.
+//!
+//! A formatter configured for a command under `[formatter]` is given the answer
+//! as `--format json` prints it on stdin, and what it writes to stdout is printed
+//! in place of the usual text. It runs in the workspace root like any command the
+//! user types there, so it can be a `jq` filter or a script kept in the repository.
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::io::AsyncWriteExt;
+
+use super::to_json;
+
+/// How long a formatter may take before its output is given up on
+const FORMATTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the formatter `command`, a program and its arguments, makes of `payload`
+pub async fn run_formatter(command: &[String], payload: &serde_json::Value) -> Result<String> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("The formatter command is empty");
+    };
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", program))?;
+
+    let input = format!("{}\n", to_json(payload)?);
+    let mut stdin = child.stdin.take().context("Formatter started without stdin")?;
+    // Written alongside reading the output, so a formatter that streams cannot block on a full pipe
+    let write = async move {
+        // A formatter that exits without reading all of its input is fine
+        let _ = stdin.write_all(input.as_bytes()).await;
+    };
+    let (_, output) = tokio::time::timeout(FORMATTER_TIMEOUT, async { tokio::join!(write, child.wait_with_output()) })
+        .await
+        .with_context(|| format!("{:?} killed after {:?}", program, FORMATTER_TIMEOUT))?;
+    let output = output?;
+    if !output.status.success() {
+        bail!("{:?} exited with {}: {}", program, output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("{:?} printed invalid UTF-8", program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_run_formatter() {
+        let payload = serde_json::json!({ "references": [{ "path": "src/lib.rs", "line": 3 }] });
+        let output = run_formatter(&command(&["grep", "-c", "src/lib.rs"]), &payload).await.unwrap();
+        assert_eq!(output, "1\n");
+    }
+
+    #[tokio::test]
+    async fn test_run_formatter_failures() {
+        let payload = serde_json::json!({});
+        assert!(run_formatter(&[], &payload).await.is_err());
+        assert!(run_formatter(&command(&["lq-no-such-formatter"]), &payload).await.is_err());
+        let error = run_formatter(&command(&["sh", "-c", "echo bad filter >&2; exit 3"]), &payload).await.unwrap_err();
+        assert!(error.to_string().contains("bad filter"), "{}", error);
+    }
+}
@@ -0,0 +1,141 @@
+//! Call hierarchies as graphs, for `--format dot` and `--format mermaid`.
+//!
+//! The queried function is the root node, labelled with its name alone, and
+//! every caller or callee is a node labelled with its name and location. Edges
+//! point from caller to callee whichever way the hierarchy was walked, so a
+//! graph of callers and one of callees of the same function read alike. A
+//! function reached along several paths is drawn once, with an edge per path.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::lsp::{Call, CallDirection};
+
+/// A graph description language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz, rendered with `dot -Tsvg`
+    Dot,
+    /// A Mermaid flowchart, rendered inline by GitHub and many documentation tools
+    Mermaid,
+}
+
+/// The call hierarchy `calls` of the function `root`, walked in `direction`, in `format`
+pub fn call_graph(root: &str, calls: &[Call], direction: CallDirection, format: GraphFormat) -> String {
+    let mut graph = Graph::default();
+    graph.nodes.push(Node { name: root.to_string(), location: None });
+    graph.add_calls(0, calls, direction);
+
+    let mut out = String::new();
+    match format {
+        GraphFormat::Dot => {
+            let _ = writeln!(out, "digraph calls {{");
+            let _ = writeln!(out, "    rankdir=LR;");
+            let _ = writeln!(out, "    node [shape=box];");
+            for (id, node) in graph.nodes.iter().enumerate() {
+                let label = node.label().replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+                let _ = writeln!(out, "    n{} [label=\"{}\"];", id, label);
+            }
+            for (from, to) in &graph.edges {
+                let _ = writeln!(out, "    n{} -> n{};", from, to);
+            }
+            let _ = writeln!(out, "}}");
+        }
+        GraphFormat::Mermaid => {
+            let _ = writeln!(out, "flowchart LR");
+            for (id, node) in graph.nodes.iter().enumerate() {
+                // Quotes would end the label, and angle brackets read as HTML
+                let label = node.label().replace('"', "#quot;").replace('<', "#lt;").replace('>', "#gt;").replace('\n', "<br/>");
+                let _ = writeln!(out, "    n{}[\"{}\"]", id, label);
+            }
+            for (from, to) in &graph.edges {
+                let _ = writeln!(out, "    n{} --> n{}", from, to);
+            }
+        }
+    }
+    out
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    /// From caller to callee, by index into `nodes`
+    edges: BTreeSet<(usize, usize)>,
+}
+
+struct Node {
+    name: String,
+    /// Where the function is declared; the root's is not in the answer
+    location: Option<(PathBuf, u32)>,
+}
+
+impl Node {
+    fn label(&self) -> String {
+        match &self.location {
+            Some((path, line)) => format!("{}\n{}:{}", self.name, path.display(), line),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl Graph {
+    fn add_calls(&mut self, parent: usize, calls: &[Call], direction: CallDirection) {
+        for call in calls {
+            let node = self.node(call);
+            let edge = match direction {
+                CallDirection::Incoming => (node, parent),
+                CallDirection::Outgoing => (parent, node),
+            };
+            self.edges.insert(edge);
+            self.add_calls(node, &call.calls, direction);
+        }
+    }
+
+    /// The index of `call`'s function, added if it is new
+    fn node(&mut self, call: &Call) -> usize {
+        let location = Some((call.path.clone(), call.line));
+        if let Some(index) = self.nodes.iter().position(|node| node.name == call.name && node.location == location) {
+            return index;
+        }
+        self.nodes.push(Node { name: call.name.clone(), location });
+        self.nodes.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, line: u32, calls: Vec<Call>) -> Call {
+        Call {
+            name: name.to_string(),
+            kind: "Function".to_string(),
+            detail: None,
+            file: PathBuf::from("/ws/src/lib.rs"),
+            path: PathBuf::from("src/lib.rs"),
+            line,
+            column: 4,
+            sites: Vec::new(),
+            calls,
+        }
+    }
+
+    #[test]
+    fn test_dot() {
+        let calls = vec![call("load", 10, vec![call("main", 2, Vec::new())]), call("reload", 20, vec![call("main", 2, Vec::new())])];
+        let graph = call_graph("parse", &calls, CallDirection::Incoming, GraphFormat::Dot);
+        assert_eq!(
+            graph,
+            "digraph calls {\n    rankdir=LR;\n    node [shape=box];\n    n0 [label=\"parse\"];\n    n1 [label=\"load\\nsrc/lib.rs:10\"];\n    \
+             n2 [label=\"main\\nsrc/lib.rs:2\"];\n    n3 [label=\"reload\\nsrc/lib.rs:20\"];\n    n1 -> n0;\n    n2 -> n1;\n    n2 -> n3;\n    n3 -> n0;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_mermaid() {
+        let calls = vec![call("Vec<T>::push", 7, Vec::new())];
+        let graph = call_graph("fill", &calls, CallDirection::Outgoing, GraphFormat::Mermaid);
+        assert_eq!(graph, "flowchart LR\n    n0[\"fill\"]\n    n1[\"Vec#lt;T#gt;::push<br/>src/lib.rs:7\"]\n    n0 --> n1\n");
+    }
+}
@@ -0,0 +1,113 @@
+//! `--link-template`: links from results to their lines on the repository host.
+//!
+//! A template such as `https://github.com/org/repo/blob/{rev}/{path}#L{line}`
+//! is filled in with the commit checked out in the workspace, the result's path
+//! relative to the repository root, and its line. Like `--exclude`, links are
+//! added to the items of a payload's lists, and to objects it has in place of
+//! one, that have a `path` and a `line` or `start_line`.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// Links to lines at the checked-out commit
+#[derive(Debug, Clone)]
+pub struct Permalinks {
+    template: String,
+    rev: String,
+    /// The workspace's path within the repository, with a trailing `/` unless empty
+    prefix: String,
+}
+
+impl Permalinks {
+    /// Permalinks for `workspace` at its `HEAD` commit
+    pub fn for_workspace(workspace: &Path, template: &str) -> Result<Self> {
+        let git = |args: &[&str]| -> Result<String> {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(workspace)
+                .args(args)
+                .output()
+                .context("Failed to run git")?;
+            if !output.status.success() {
+                bail!(
+                    "git {} failed (links need a git repository): {}",
+                    args[0],
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+        Ok(Self {
+            template: template.to_string(),
+            rev: git(&["rev-parse", "HEAD"])?,
+            prefix: git(&["rev-parse", "--show-prefix"])?,
+        })
+    }
+
+    /// The link to `line` of `path`, relative to the workspace root
+    pub fn url(&self, path: &Path, line: u32) -> String {
+        let path = format!("{}{}", self.prefix, path.to_string_lossy().replace('\\', "/"));
+        self.template
+            .replace("{rev}", &self.rev)
+            .replace("{path}", &path)
+            .replace("{line}", &line.to_string())
+    }
+}
+
+/// Set `url` on the results in `payload` that point at a line
+pub fn add_links(payload: &mut Value, links: &Permalinks) {
+    let Some(fields) = payload.as_object_mut() else {
+        return;
+    };
+    for field in fields.values_mut() {
+        match field {
+            Value::Array(items) => items.iter_mut().for_each(|item| add_link(item, links)),
+            item => add_link(item, links),
+        }
+    }
+}
+
+fn add_link(item: &mut Value, links: &Permalinks) {
+    let Some(item) = item.as_object_mut() else {
+        return;
+    };
+    let path = item.get("path").and_then(Value::as_str);
+    let line = item.get("line").or_else(|| item.get("start_line")).and_then(Value::as_u64);
+    if let (Some(path), Some(line)) = (path, line) {
+        let url = links.url(Path::new(path), line as u32);
+        item.insert("url".to_string(), url.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links() -> Permalinks {
+        Permalinks {
+            template: "https://example.com/{rev}/{path}#L{line}".to_string(),
+            rev: "abc123".to_string(),
+            prefix: "crates/shapes/".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_url() {
+        assert_eq!(links().url(Path::new("src/lib.rs"), 9), "https://example.com/abc123/crates/shapes/src/lib.rs#L9");
+    }
+
+    #[test]
+    fn test_add_links() {
+        let mut payload = serde_json::json!({
+            "references": [{ "path": "src/lib.rs", "line": 3 }, { "line": 4 }],
+            "definition": { "path": "src/area.rs", "start_line": 12 },
+            "count": 2
+        });
+        add_links(&mut payload, &links());
+        assert_eq!(payload["references"][0]["url"], "https://example.com/abc123/crates/shapes/src/lib.rs#L3");
+        assert!(payload["references"][1].get("url").is_none());
+        assert_eq!(payload["definition"]["url"], "https://example.com/abc123/crates/shapes/src/area.rs#L12");
+    }
+}
@@ -0,0 +1,209 @@
+//! What lq prints, and the ASCII profile for consumers that cannot take anything else.
+//!
+//! Text lq writes itself is ASCII already, but much of what it prints comes from
+//! source files and the language server: doc comments, code excerpts, inlay hint
+//! labels. Under the ASCII profile (`--ascii`, or `LQ_ASCII=1`) every line
+//! printed is pure ASCII and contains no terminal control sequences, whatever the
+//! locale: common typographic and box-drawing characters are transliterated,
+//! anything else is escaped as `\u{...}`, and carriage returns are dropped so
+//! output stays one record per line. JSON keeps its content and escapes non-ASCII
+//! characters as `\uXXXX` instead. The only timestamps lq prints, in backup
+//! directory names, are Unix seconds in either profile.
+
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use serde::Serialize;
+
+mod context;
+mod exclude;
+mod formatter;
+mod graph;
+mod links;
+mod redact;
+mod sort;
+
+pub use context::add_context;
+pub use exclude::exclude_paths;
+pub use formatter::run_formatter;
+pub use graph::{call_graph, GraphFormat};
+pub use links::{add_links, Permalinks};
+pub use redact::{redact, Redactions};
+pub use sort::{sort_results, ResultOrder};
+
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+/// Select the ASCII profile for everything printed from now on
+pub fn set_ascii(ascii: bool) {
+    ASCII.store(ascii, Ordering::Relaxed);
+}
+
+pub fn is_ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+/// ASCII stand-ins for characters common in docs and terminal output
+const TRANSLITERATIONS: &[(char, &str)] = &[
+    ('\u{2018}', "'"),
+    ('\u{2019}', "'"),
+    ('\u{201c}', "\""),
+    ('\u{201d}', "\""),
+    ('\u{2013}', "-"),
+    ('\u{2014}', "--"),
+    ('\u{2026}', "..."),
+    ('\u{2022}', "*"),
+    ('\u{00a0}', " "),
+    ('\u{00d7}', "x"),
+    ('\u{2192}', "->"),
+    ('\u{2190}', "<-"),
+    ('\u{21d2}', "=>"),
+    ('\u{2500}', "-"),
+    ('\u{2502}', "|"),
+    ('\u{250c}', "+"),
+    ('\u{2510}', "+"),
+    ('\u{2514}', "`"),
+    ('\u{2518}', "+"),
+    ('\u{251c}', "|"),
+    ('\u{2524}', "|"),
+    ('\u{252c}', "+"),
+    ('\u{2534}', "+"),
+    ('\u{253c}', "+"),
+];
+
+/// `text` as the ASCII profile prints it
+pub fn ascii_text(text: &str) -> Cow<'_, str> {
+    let printable = |c: char| c.is_ascii() && (!c.is_ascii_control() || c == '\n' || c == '\t');
+    if text.chars().all(printable) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if printable(c) {
+            out.push(c);
+        } else if c == '\r' {
+            continue;
+        } else if let Some((_, ascii)) = TRANSLITERATIONS.iter().find(|(from, _)| *from == c) {
+            out.push_str(ascii);
+        } else {
+            let _ = write!(out, "\\u{{{:x}}}", c as u32);
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// `value` as pretty-printed JSON, with non-ASCII characters escaped under the ASCII profile
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    Ok(profile_json(serde_json::to_string_pretty(value)?))
+}
+
+/// `value` as JSON on a single line, escaped like [`to_json`]
+pub fn to_json_line<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    Ok(profile_json(serde_json::to_string(value)?))
+}
+
+/// A response payload as JSON Lines: each item of its one list on a line of its
+/// own, or the whole payload on one line when it has no single list
+pub fn to_json_lines(value: &serde_json::Value) -> Result<String> {
+    let lists: Vec<&Vec<serde_json::Value>> = value
+        .as_object()
+        .map(|fields| fields.values().filter_map(serde_json::Value::as_array).collect())
+        .unwrap_or_default();
+    let lines = match lists[..] {
+        [items] => items.iter().map(to_json_line).collect::<Result<Vec<_>>>()?,
+        _ => vec![to_json_line(value)?],
+    };
+    Ok(lines.join("\n"))
+}
+
+fn profile_json(json: String) -> String {
+    if !is_ascii() || json.is_ascii() {
+        return json;
+    }
+    // Serialized JSON has non-ASCII characters only inside strings, where escapes mean the same
+    let mut out = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                let _ = write!(out, "\\u{:04x}", unit);
+            }
+        }
+    }
+    out
+}
+
+/// Print `text` to stdout in the selected profile; see [`out!`](crate::out)
+pub fn write_stdout(text: &str) {
+    let text = if is_ascii() { ascii_text(text) } else { Cow::Borrowed(text) };
+    print!("{}", text);
+    let _ = std::io::stdout().flush();
+}
+
+/// Print `text` to stderr in the selected profile; see [`errln!`](crate::errln)
+pub fn write_stderr(text: &str) {
+    let text = if is_ascii() { ascii_text(text) } else { Cow::Borrowed(text) };
+    eprint!("{}", text);
+}
+
+/// `print!` in the selected output profile
+#[macro_export]
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::output::write_stdout(&format!($($arg)*))
+    };
+}
+
+/// `println!` in the selected output profile
+#[macro_export]
+macro_rules! outln {
+    () => {
+        $crate::output::write_stdout("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write_stdout(&format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// `eprintln!` in the selected output profile
+#[macro_export]
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        $crate::output::write_stderr(&format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_text() {
+        assert!(matches!(ascii_text("fn main() {\n\tok\n}"), Cow::Borrowed(_)));
+        assert_eq!(ascii_text("Les Mis\u{e9}rables \u{2014} \u{201c}caf\u{e9}\u{201d}\r\n"), "Les Mis\\u{e9}rables -- \"caf\\u{e9}\"\n");
+        assert_eq!(ascii_text("\u{251c}\u{2500} a \u{2192} b \u{1f680}"), "|- a -> b \\u{1f680}");
+        assert_eq!(ascii_text("\u{1b}[31mred\u{1b}[0m"), "\\u{1b}[31mred\\u{1b}[0m");
+    }
+
+    #[test]
+    fn test_to_json_escapes_under_ascii() {
+        let value = serde_json::json!({ "docs": "caf\u{e9} \u{1f680}" });
+        set_ascii(true);
+        let json = to_json(&value).unwrap();
+        set_ascii(false);
+        assert_eq!(json, "{\n  \"docs\": \"caf\\u00e9 \\ud83d\\ude80\"\n}");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn test_to_json_lines() {
+        let refs = serde_json::json!({ "references": [{ "line": 1 }, { "line": 2 }], "partial": true });
+        assert_eq!(to_json_lines(&refs).unwrap(), "{\"line\":1}\n{\"line\":2}");
+        assert_eq!(to_json_lines(&serde_json::json!({ "references": [] })).unwrap(), "");
+        let docs = serde_json::json!({ "docs": "text" });
+        assert_eq!(to_json_lines(&docs).unwrap(), "{\"docs\":\"text\"}");
+    }
+}
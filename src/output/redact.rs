@@ -0,0 +1,129 @@
+//! Redaction: masking source lines before lq prints them.
+//!
+//! Results quote the workspace's code, in a reference's `text` and context
+//! lines or a definition's `code`, and that output is often passed on to other tools and
+//! services. Configured rules mask it first: every line of a result in a file
+//! matching one of the `paths` globs, and any line matching one of the
+//! `patterns` regexes wherever it is. A masked line keeps its indentation, so
+//! code excerpts still line up with their line numbers.
+
+use anyhow::{Context, Result};
+use regex_automata::meta::Regex;
+use serde_json::Value;
+
+use super::exclude::glob_matches;
+use crate::config::RedactConfig;
+
+/// What a masked line reads instead
+pub const REDACTED: &str = "[redacted]";
+
+/// Fields of a result that quote source lines
+const QUOTED_FIELDS: &[&str] = &["text", "code", "context_before", "context_after"];
+
+/// Compiled redaction rules
+#[derive(Debug)]
+pub struct Redactions {
+    paths: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactions {
+    pub fn new(config: &RedactConfig) -> Result<Self> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern).with_context(|| format!("Invalid redaction pattern {:?}", pattern)))
+            .collect::<Result<_>>()?;
+        Ok(Self { paths: config.paths.clone(), patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.patterns.is_empty()
+    }
+
+    /// `text` with the lines to hide masked; all of them when `whole`
+    fn mask(&self, text: &str, whole: bool) -> String {
+        let lines: Vec<String> = text
+            .split('\n')
+            .map(|line| {
+                if line.trim().is_empty() || !(whole || self.patterns.iter().any(|pattern| pattern.is_match(line))) {
+                    return line.to_string();
+                }
+                let indent = &line[..line.len() - line.trim_start().len()];
+                format!("{}{}", indent, REDACTED)
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Mask the quoted source lines of the results in `payload` that `redactions` cover
+pub fn redact(payload: &mut Value, redactions: &Redactions) {
+    if redactions.is_empty() {
+        return;
+    }
+    let Some(fields) = payload.as_object_mut() else {
+        return;
+    };
+    for field in fields.values_mut() {
+        match field {
+            Value::Array(items) => items.iter_mut().for_each(|item| redact_item(item, redactions)),
+            item => redact_item(item, redactions),
+        }
+    }
+}
+
+fn redact_item(item: &mut Value, redactions: &Redactions) {
+    let Some(item) = item.as_object_mut() else {
+        return;
+    };
+    let path = item.get("path").and_then(Value::as_str);
+    let whole = path.is_some_and(|path| redactions.paths.iter().any(|glob| glob_matches(glob, path)));
+    for field in QUOTED_FIELDS {
+        match item.get_mut(*field) {
+            Some(Value::String(text)) => *text = redactions.mask(text, whole),
+            Some(Value::Array(lines)) => {
+                for line in lines {
+                    if let Value::String(text) = line {
+                        *text = redactions.mask(text, whole);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let config = RedactConfig {
+            paths: vec!["config/**".to_string()],
+            patterns: vec![r"(?i)api_key\s*=".to_string()],
+        };
+        let redactions = Redactions::new(&config).unwrap();
+        let mut payload = serde_json::json!({
+            "references": [
+                { "path": "src/lib.rs", "text": "let api_key = \"s3cret\";" },
+                { "path": "src/lib.rs", "text": "connect(api_key)" },
+                { "path": "config/prod.rs", "text": "connect(token)", "context_after": ["  retry(token)"] }
+            ],
+            "definition": { "path": "src/lib.rs", "code": "fn connect() {\n    let API_KEY = load();\n}" }
+        });
+        redact(&mut payload, &redactions);
+        assert_eq!(payload["references"][0]["text"], REDACTED);
+        assert_eq!(payload["references"][1]["text"], "connect(api_key)");
+        assert_eq!(payload["references"][2]["text"], REDACTED);
+        assert_eq!(payload["references"][2]["context_after"][0], "  [redacted]");
+        assert_eq!(payload["definition"]["code"], "fn connect() {\n    [redacted]\n}");
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let config = RedactConfig { paths: Vec::new(), patterns: vec!["(".to_string()] };
+        assert!(Redactions::new(&config).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Result order: the same answer lists its results in the same order on every platform.
+//!
+//! The language server reports results in whatever order it found them, which
+//! can change between runs and operating systems, so the items of a payload's
+//! lists are sorted by location: path by byte order, then line, then column.
+//! `Definition`-style items count their `start_line` and `start_column`, and
+//! items without a path, such as a runnable for the whole workspace, come first.
+//! Rankings keep the order of their rank unless `--sort location` asks for
+//! theirs too; ties in a rank are already broken by location. Like `--exclude`,
+//! this reaches the top-level lists of a payload, not the lists nested in them.
+
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+/// Lists ordered by rank rather than location
+const RANKED_FIELDS: &[&str] = &["hotspots", "entries", "symbols"];
+
+/// How to order the items of a payload's lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultOrder {
+    /// By location, except rankings, which stay in rank order
+    #[default]
+    Default,
+    /// Every list by location, rankings included
+    Location,
+    /// As the daemon answered
+    None,
+}
+
+/// Sort the items of `payload`'s lists in `order`
+pub fn sort_results(payload: &mut Value, order: ResultOrder) {
+    if order == ResultOrder::None {
+        return;
+    }
+    let Some(fields) = payload.as_object_mut() else {
+        return;
+    };
+    for (name, field) in fields.iter_mut() {
+        if order == ResultOrder::Default && RANKED_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+        if let Value::Array(items) = field {
+            // Stable, so items at the same location keep the order they came in
+            items.sort_by(compare_locations);
+        }
+    }
+}
+
+fn compare_locations(a: &Value, b: &Value) -> Ordering {
+    let number = |item: &Value, keys: [&str; 2]| keys.iter().find_map(|key| item.get(*key).and_then(Value::as_u64)).unwrap_or(0);
+    // Strings compare by their bytes
+    path(a)
+        .cmp(&path(b))
+        .then_with(|| number(a, ["line", "start_line"]).cmp(&number(b, ["line", "start_line"])))
+        .then_with(|| number(a, ["column", "start_column"]).cmp(&number(b, ["column", "start_column"])))
+}
+
+fn path(item: &Value) -> Option<&str> {
+    item.get("path").and_then(Value::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locations(items: &Value) -> Vec<String> {
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| format!("{}:{}", item["path"].as_str().unwrap_or("-"), item["line"]))
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_results() {
+        let payload = serde_json::json!({
+            "references": [
+                { "path": "src/a/b.rs", "line": 1, "column": 1 },
+                { "path": "src/a-b.rs", "line": 9, "column": 5 },
+                { "path": "src/a-b.rs", "line": 9, "column": 2 },
+                { "path": "src/a-b.rs", "line": 10, "column": 1 },
+                { "line": 4 }
+            ],
+            "hotspots": [{ "path": "src/z.rs", "line": 1 }, { "path": "src/a.rs", "line": 1 }],
+            "partial": false
+        });
+
+        let mut sorted = payload.clone();
+        sort_results(&mut sorted, ResultOrder::Default);
+        // Byte order puts `-` before `/`, where comparing components would not
+        assert_eq!(locations(&sorted["references"]), ["-:4", "src/a-b.rs:9", "src/a-b.rs:9", "src/a-b.rs:10", "src/a/b.rs:1"]);
+        assert_eq!(sorted["references"][1]["column"], 2);
+        assert_eq!(locations(&sorted["hotspots"]), ["src/z.rs:1", "src/a.rs:1"]);
+
+        let mut by_location = payload.clone();
+        sort_results(&mut by_location, ResultOrder::Location);
+        assert_eq!(locations(&by_location["hotspots"]), ["src/a.rs:1", "src/z.rs:1"]);
+
+        let mut unsorted = payload.clone();
+        sort_results(&mut unsorted, ResultOrder::None);
+        assert_eq!(unsorted, payload);
+    }
+
+    #[test]
+    fn test_definitions_sort_by_start() {
+        let mut payload = serde_json::json!({
+            "definitions": [{ "path": "src/lib.rs", "start_line": 30 }, { "path": "src/lib.rs", "start_line": 4 }]
+        });
+        sort_results(&mut payload, ResultOrder::Default);
+        assert_eq!(payload["definitions"][0]["start_line"], 4);
+    }
+}